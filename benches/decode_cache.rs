@@ -0,0 +1,62 @@
+//! How much the decode cache saves: run the same loop with the cache, and
+//! with a memory hook over the program, which makes every instruction get
+//! fetched and decoded again each time it runs. Run it with:
+//!
+//!     cargo bench
+
+use chip8::{
+    instruction::{Address, Instruction, Register},
+    interpreter::State,
+    memory::{self, MemoryHook},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{cell::RefCell, rc::Rc};
+
+/// Sees every read, but doesn't change anything.
+struct PassThrough;
+
+impl MemoryHook for PassThrough {}
+
+/// Count V1 up by V0 forever.
+fn counting_loop() -> Vec<u8> {
+    let v0 = Register::from_nibble(0x0);
+    let v1 = Register::from_nibble(0x1);
+    [
+        Instruction::LDByte(v0, 0x01),
+        Instruction::ADDRegister(v1, v0),
+        Instruction::SNEByte(v1, 0xFF),
+        Instruction::LDByte(v1, 0x00),
+        Instruction::JP(Address::new(0x202).unwrap()),
+    ]
+    .iter()
+    .flat_map(|instruction| instruction.to_bytes().unwrap())
+    .collect()
+}
+
+fn run(state: &mut State, rng: &mut StdRng) {
+    for _ in 0..1000 {
+        state.step(&mut *rng).unwrap();
+    }
+}
+
+fn decode_cache(c: &mut Criterion) {
+    let program = counting_loop();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let mut cached = State::with_program(&program).unwrap();
+    c.bench_function("1000 cycles, decoded once", |b| {
+        b.iter(|| run(&mut cached, &mut rng))
+    });
+
+    let mut uncached = State::with_program(&program).unwrap().with_memory_hook(
+        memory::PROGRAM_START..memory::PROGRAM_START + program.len(),
+        Rc::new(RefCell::new(PassThrough)),
+    );
+    c.bench_function("1000 cycles, decoded every time", |b| {
+        b.iter(|| run(&mut uncached, &mut rng))
+    });
+}
+
+criterion_group!(benches, decode_cache);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! Run arbitrary bytes through the decoder and the interpreter, on each
+//! machine, to check that they only ever fail with a `Chip8Error` and never
+//! panic. Run it from the top of the repository with:
+//!
+//!     cargo +nightly fuzz run decode_execute
+#![no_main]
+
+use chip8::{analysis::PROFILES, interpreter::State};
+use libfuzzer_sys::fuzz_target;
+use rand::{rngs::StdRng, SeedableRng};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the machine, and the rest are the instructions
+    let (machine, bytes) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let profile = PROFILES[*machine as usize % PROFILES.len()];
+    let mut state = State::with_program_for(profile.variant(), &[])
+        .unwrap()
+        .with_quirks(profile.quirks());
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        // Instructions that fail still move on, so the rest get run too
+        let size = match state.execute_bytes(rest, &mut rng) {
+            Ok(instruction) => instruction.size(),
+            Err(_) => 2,
+        };
+        rest = rest.get(size..).unwrap_or(&[]);
+    }
+});
@@ -0,0 +1,410 @@
+use crate::{
+    disasm::{self, Line},
+    error::Chip8Error,
+    instruction::Instruction,
+    interpreter::{State, Variant},
+    memory,
+    quirks::Profile,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// Every profile, in the order they're compared. The first one is the baseline
+/// that the others are compared against.
+pub const PROFILES: [Profile; 3] = [Profile::Cosmac, Profile::Schip, Profile::XoChip];
+
+/// What happened when a ROM ran for a while under one profile.
+#[derive(Debug)]
+pub struct ProfileRun {
+    pub profile: Profile,
+    /// How many times each kind of instruction ran, by name
+    pub histogram: BTreeMap<String, usize>,
+    /// The address of every instruction that ran, in order
+    pub addresses: Vec<u16>,
+    /// The instructions that ran whose behavior depends on the profile, with
+    /// what they depend on
+    pub profile_specific: BTreeMap<String, &'static str>,
+    /// Why the run stopped before running out of cycles, if it did
+    pub stopped: Option<String>,
+}
+
+/// The name of the kind of instruction, like `LDByte` or `DRW`, without its
+/// operands.
+pub fn kind(instruction: &Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    debug.split('(').next().unwrap_or(&debug).to_string()
+}
+
+/// What about the profile changes this instruction's behavior, if anything.
+fn depends_on(instruction: &Instruction) -> Option<&'static str> {
+    match instruction {
+        Instruction::SHR(..) | Instruction::SHL(..) => Some("shift quirk"),
+        Instruction::LDIntoMemory(_) | Instruction::LDFromMemory(_) => Some("load-store quirk"),
+        Instruction::JPOffset(_) => Some("jump quirk"),
+        Instruction::DRW(..) => Some("draw and display quirks"),
+        instruction if instruction.is_xo_chip() => Some("XO-CHIP only"),
+        _ => None,
+    }
+}
+
+/// Why running this instruction would crash the interpreter, if it would.
+/// We stop before those so one profile's crash doesn't end the comparison.
+fn would_crash(state: &State, instruction: &Instruction) -> Option<String> {
+    if instruction.is_xo_chip() && state.variant() != Variant::XoChip {
+        Some(format!("{} needs XO-CHIP", instruction))
+    } else {
+        None
+    }
+}
+
+/// Run the program for up to `cycles` instructions under the given profile,
+/// without a window. The random number generator is seeded the same way for
+/// every profile, so RND can't make the runs diverge. Fails if the program
+/// doesn't fit in memory.
+pub fn run_profile(
+    program: &[u8],
+    profile: Profile,
+    cycles: usize,
+) -> Result<ProfileRun, Chip8Error> {
+    let mut state =
+        State::with_program_for(profile.variant(), program)?.with_quirks(profile.quirks());
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut run = ProfileRun {
+        profile,
+        histogram: BTreeMap::new(),
+        addresses: vec![],
+        profile_specific: BTreeMap::new(),
+        stopped: None,
+    };
+
+    for _ in 0..cycles {
+        let address = state.pc();
+        let instruction = match state.next_instruction() {
+            None => {
+                run.stopped = Some("ran off the end of memory".to_string());
+                break;
+            }
+            Some(Err(e)) => {
+                run.stopped = Some(e.to_string());
+                break;
+            }
+            Some(Ok(instruction)) => instruction,
+        };
+        if let Some(reason) = would_crash(&state, &instruction) {
+            run.stopped = Some(format!("{} at {:03X}", reason, address));
+            break;
+        }
+        if let Err(e) = state.step(&mut rng) {
+            run.stopped = Some(e.to_string());
+            break;
+        }
+
+        *run.histogram.entry(kind(&instruction)).or_insert(0) += 1;
+        if let Some(reason) = depends_on(&instruction) {
+            run.profile_specific.insert(kind(&instruction), reason);
+        }
+        run.addresses.push(address);
+    }
+    Ok(run)
+}
+
+/// The first point where two runs went to different addresses: how many
+/// instructions in, and the address each one ran. None if one run is the same
+/// as the start of the other.
+pub fn first_divergence(a: &ProfileRun, b: &ProfileRun) -> Option<(usize, u16, u16)> {
+    a.addresses
+        .iter()
+        .zip(&b.addresses)
+        .enumerate()
+        .find(|(_, (x, y))| x != y)
+        .map(|(n, (x, y))| (n, *x, *y))
+}
+
+/// Run the program under every profile and print how they compare.
+pub fn compare_profiles(program: &[u8], cycles: usize) -> Result<(), Chip8Error> {
+    let runs = PROFILES
+        .iter()
+        .map(|profile| run_profile(program, *profile, cycles))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut kinds = runs
+        .iter()
+        .flat_map(|run| run.histogram.keys().cloned())
+        .collect::<Vec<_>>();
+    kinds.sort();
+    kinds.dedup();
+
+    print!("{:<14}", "Instruction");
+    for run in &runs {
+        print!("{:>10}", format!("{:?}", run.profile));
+    }
+    println!();
+    for kind in &kinds {
+        print!("{:<14}", kind);
+        for run in &runs {
+            print!("{:>10}", run.histogram.get(kind).unwrap_or(&0));
+        }
+        println!();
+    }
+    println!();
+
+    let baseline = &runs[0];
+    for run in &runs {
+        print!(
+            "{:?}: ran {} instructions",
+            run.profile,
+            run.addresses.len()
+        );
+        match &run.stopped {
+            Some(reason) => println!(", stopped: {}", reason),
+            None => println!(),
+        }
+        if run.profile != baseline.profile {
+            match first_divergence(baseline, run) {
+                Some((n, x, y)) => println!(
+                    "  Diverges from {:?} after {} instructions ({:03X} vs {:03X})",
+                    baseline.profile, n, x, y
+                ),
+                None => println!("  Same path as {:?}", baseline.profile),
+            }
+        }
+        for (kind, reason) in &run.profile_specific {
+            println!("  Uses {} ({})", kind, reason);
+        }
+    }
+    Ok(())
+}
+
+/// Something that looks wrong in a ROM, found without running it.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// How far into the ROM file it is
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "offset {:04X} (address {:03X}): {}",
+            self.offset,
+            self.offset + memory::PROGRAM_START,
+            self.message
+        )
+    }
+}
+
+/// Look through a ROM for problems without running it:
+/// - an odd number of bytes, since every instruction is 2 (or 4) bytes
+/// - instructions we don't know, that the program can reach
+/// - jumps and calls outside of 200-FFF, where programs go
+/// - sprites that would be read from past the end of memory, when we can tell
+///   where I points because it was loaded just before the DRW
+///
+/// Only the code the program can reach is checked (see `disasm::disassemble`),
+/// so data that happens to look like a bad instruction isn't a problem.
+/// Returns the problems in the order they appear in the ROM.
+pub fn check(program: &[u8]) -> Result<Vec<Problem>, Chip8Error> {
+    let start = memory::PROGRAM_START;
+    let problem = |address: usize, message: String| Problem {
+        offset: address - start,
+        message,
+    };
+    let disassembly = disasm::disassemble(program)?;
+    let mut problems = vec![];
+
+    for address in disassembly.unknown_addresses() {
+        let offset = address as usize - start;
+        let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+        problems.push(problem(
+            address as usize,
+            format!("Unknown instruction {:04X}", opcode),
+        ));
+    }
+
+    // Where I points, if the code just before here set it
+    let mut i = None;
+    for line in &disassembly.lines {
+        let (address, instruction) = match line {
+            Line::Code {
+                address,
+                instruction,
+                ..
+            } => (*address as usize, instruction),
+            // Something else might jump here with I pointing anywhere
+            Line::Label(_) | Line::Data { .. } => {
+                i = None;
+                continue;
+            }
+        };
+        match instruction {
+            Instruction::JP(target) | Instruction::CALL(target) => {
+                let target = target.value();
+                if (target as usize) < start {
+                    problems.push(problem(
+                        address,
+                        format!("{} goes to {:03X}, before the program", instruction, target),
+                    ));
+                }
+            }
+            Instruction::JPOffset(base) => {
+                let base = base.value();
+                if (base as usize) < start {
+                    problems.push(problem(
+                        address,
+                        format!(
+                            "{} goes to {:03X} or later, before the program",
+                            instruction, base
+                        ),
+                    ));
+                } else if base as usize + 0xFF >= memory::MEMORY_SIZE {
+                    problems.push(problem(
+                        address,
+                        format!("{} can go past the end of memory", instruction),
+                    ));
+                }
+            }
+            Instruction::DRW(_, _, rows) => {
+                // A sprite with 0 rows is 16x16, which is 32 bytes
+                let length = match rows {
+                    0 => 32,
+                    rows => *rows as usize,
+                };
+                match i {
+                    Some(i) if i + length > memory::MEMORY_SIZE => problems.push(problem(
+                        address,
+                        format!(
+                            "{} reads {} bytes from {:03X}, past the end of memory",
+                            instruction, length, i
+                        ),
+                    )),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        i = match instruction {
+            Instruction::LDI(target) => Some(Into::<u16>::into(*target) as usize),
+            Instruction::LDILong(target) => Some(*target as usize),
+            // These change I in ways we can't follow
+            Instruction::ADDI(_)
+            | Instruction::LDHF(_)
+            | Instruction::LDIntoMemory(_)
+            | Instruction::LDFromMemory(_) => None,
+            _ => i,
+        };
+    }
+
+    problems.sort_by_key(|problem| problem.offset);
+    if program.len() % 2 == 1 {
+        problems.push(Problem {
+            offset: program.len() - 1,
+            message: format!(
+                "The ROM is {} bytes long, which is odd, so its last byte is only half an instruction",
+                program.len()
+            ),
+        });
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::{program, Register};
+
+    #[test]
+    fn histogram_and_profile_specific_instructions() {
+        let rom = program(&[
+            Instruction::LDByte(Register::from_nibble(0x1), 0x2),
+            Instruction::LDByte(Register::from_nibble(0x1), 0x3),
+            Instruction::SHR(Register::from_nibble(0x1), Register::from_nibble(0x2)),
+        ]);
+        let run = run_profile(&rom, Profile::Cosmac, 3).unwrap();
+
+        assert_eq!(run.histogram["LDByte"], 2);
+        assert_eq!(run.histogram["SHR"], 1);
+        assert_eq!(run.profile_specific["SHR"], "shift quirk");
+        assert!(!run.profile_specific.contains_key("LDByte"));
+        assert_eq!(run.stopped, None);
+    }
+
+    #[test]
+    fn stops_instead_of_crashing() {
+        let rom = program(&[Instruction::PLANE(2)]);
+
+        let cosmac = run_profile(&rom, Profile::Cosmac, 10).unwrap();
+        assert_eq!(cosmac.addresses.len(), 0);
+        assert!(cosmac.stopped.unwrap().contains("needs XO-CHIP"));
+
+        let xo_chip = run_profile(&rom, Profile::XoChip, 10).unwrap();
+        assert_eq!(xo_chip.histogram["PLANE"], 1);
+    }
+
+    #[test]
+    fn finds_first_divergence() {
+        // With the jump quirk, JP V0, 0x300 jumps to 0x300 + V3 instead
+        let rom = program(&[
+            Instruction::LDByte(Register::from_nibble(0x3), 0x10),
+            Instruction::JPOffset(0x300.into()),
+        ]);
+        let cosmac = run_profile(&rom, Profile::Cosmac, 3).unwrap();
+        let schip = run_profile(&rom, Profile::Schip, 3).unwrap();
+
+        assert_eq!(first_divergence(&cosmac, &schip), Some((2, 0x300, 0x310)));
+        assert_eq!(first_divergence(&cosmac, &cosmac), None);
+    }
+
+    #[test]
+    fn a_good_rom_has_no_problems() {
+        let rom = program(&[
+            Instruction::LDI(0x206.into()),
+            Instruction::DRW(Register::from_nibble(0x0), Register::from_nibble(0x0), 1),
+            Instruction::JP(0x204.into()),
+        ]);
+
+        assert_eq!(check(&rom).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn finds_problems_with_offsets() {
+        let mut rom = program(&[
+            Instruction::CALL(0x100.into()),
+            Instruction::LDI(0xFFC.into()),
+            Instruction::DRW(Register::from_nibble(0x0), Register::from_nibble(0x0), 8),
+            Instruction::JPOffset(0xF80.into()),
+        ]);
+        // Never reached, so it's data
+        rom.extend(&[0xFF, 0xFF]);
+        rom.push(0x00);
+
+        let problems = check(&rom)
+            .unwrap()
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                "offset 0000 (address 200): CALL 100 goes to 100, before the program",
+                "offset 0004 (address 204): DRW V0, V0, 8 reads 8 bytes from FFC, past the end of memory",
+                "offset 0006 (address 206): JP V0, F80 can go past the end of memory",
+                "offset 000A (address 20A): The ROM is 11 bytes long, which is odd, so its last byte is only half an instruction",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_reachable_unknown_instructions() {
+        let rom = [0x12, 0x04, 0x00, 0x00, 0xFF, 0xFF];
+        let problems = check(&rom).unwrap();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].offset, 4);
+        assert_eq!(problems[0].message, "Unknown instruction FFFF");
+    }
+}
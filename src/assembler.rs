@@ -0,0 +1,1294 @@
+use crate::{
+    error::Chip8Error,
+    expression,
+    instruction::{Address, Instruction},
+    memory,
+    quirks::Profile,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Formatter},
+    ops::Range,
+};
+
+/// Something that takes up space in the assembled program.
+enum Statement {
+    Instruction(Instruction),
+    Bytes(Vec<u8>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(instruction) => instruction.size(),
+            Statement::Bytes(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Where labels point to. While we're still finding them all (on the first
+/// pass) there are none yet, and any label is fine.
+type Labels = Option<HashMap<String, u16>>;
+
+/// Assemble source code into a program that will be loaded at 0x200.
+///
+/// The syntax is what `Display for Instruction` prints, like `LD V1, 0A` (all
+/// numbers are hex, with or without `0x`), plus:
+/// - labels, like `loop:`, which JP, CALL, and LD I can use instead of an address
+/// - `db 01, 02, 03` to put bytes directly into the program
+/// - `.word 1234, sprite` for 2-byte values, high byte first
+/// - `.fill 10, FF` for 0x10 copies of a byte
+/// - `.table 40, 80 + 7F * sin(i * pi / 20)` for a table of 0x40 bytes, with
+///   `i` counting up from 0 for each one
+///
+///   Values in `.word`, `.fill`, and `.table` can be expressions (see
+///   `expression::evaluate`) that use labels, and are rounded to a whole
+///   number. Negative values are stored as two's complement.
+/// - comments, from `;` to the end of the line
+/// - `.target schip` (or `cosmac`, or `xo-chip`), to only allow the
+///   instructions that machine has (see `assemble_for`)
+/// - `.org 300` to put what comes next at that address, with zeros in between
+/// - `.section sprites align 8` to put what comes next in a named section, and
+///   `.section code` to go back to the code. The assembler puts each section
+///   (at a multiple of its alignment, if it has one) after the code, wherever
+///   there's room, and it's an error for anything to overlap
+///
+/// It also understands the output of `chip8 print`, so disassembling a program
+/// and assembling it again gives back the same bytes.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Chip8Error> {
+    assemble_for(source, None)
+}
+
+/// Assemble source code for a particular machine, like `assemble`. The target
+/// is the source's `.target` directive if it has one, and otherwise `target`
+/// (if they're both there, they have to agree). Every line that uses an
+/// instruction the target doesn't have is listed in the error. With an XO-CHIP
+/// target, `LD I, nnnn` with an address past 0xFFF becomes a long load.
+/// Without any target, every instruction is allowed.
+pub fn assemble_for(source: &str, target: Option<Profile>) -> Result<Vec<u8>, Chip8Error> {
+    Ok(assemble_with_report(source, target, false)?.program)
+}
+
+/// An assembled program, and what we found out while assembling it.
+pub struct Assembly {
+    pub program: Vec<u8>,
+    /// How big each section came out
+    pub report: SizeReport,
+    /// Every line of source with its address and bytes, followed by the
+    /// symbol table (see `listing`)
+    pub listing: String,
+}
+
+/// Assemble source code like `assemble_for`, and also report how big each
+/// section came out and make a listing. If `remove_dead_code` is true, labeled
+/// blocks of code or data that nothing uses are left out first (see
+/// `remove_dead_code`).
+pub fn assemble_with_report(
+    source: &str,
+    target: Option<Profile>,
+    remove_dead_code: bool,
+) -> Result<Assembly, Chip8Error> {
+    let target = find_target(source, target)?;
+    let (source, removed) = if remove_dead_code {
+        self::remove_dead_code(source, target)?
+    } else {
+        (source.to_string(), vec![])
+    };
+    let (program, chunks, listing) = assemble_chunks(&source, target)?;
+    let mut sections: Vec<SectionSize> = vec![];
+    for chunk in chunks.iter().filter(|chunk| chunk.size > 0) {
+        match sections
+            .iter_mut()
+            .find(|section| section.name == chunk.section)
+        {
+            Some(section) => {
+                section.start = section.start.min(chunk.start);
+                section.end = section.end.max(chunk.end());
+                section.size += chunk.size;
+            }
+            None => sections.push(SectionSize {
+                name: chunk.section.clone(),
+                start: chunk.start,
+                end: chunk.end(),
+                size: chunk.size,
+            }),
+        }
+    }
+    Ok(Assembly {
+        program,
+        report: SizeReport { sections, removed },
+        listing,
+    })
+}
+
+/// How big one section came out.
+pub struct SectionSize {
+    pub name: String,
+    pub start: usize,
+    /// Just past its last byte
+    pub end: usize,
+    /// How many bytes are in it, not counting any gaps
+    pub size: usize,
+}
+
+/// How much of the program's memory each section takes up, and which blocks
+/// were removed for being unused.
+pub struct SizeReport {
+    pub sections: Vec<SectionSize>,
+    /// The labels of the blocks that were removed
+    pub removed: Vec<String>,
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:>5} {:>5} {:>6}",
+            "SECTION", "START", "END", "BYTES"
+        )?;
+        for section in &self.sections {
+            writeln!(
+                f,
+                "{:<16} {:>5} {:>5} {:>6}",
+                section.name,
+                format!("{:03X}", section.start),
+                format!("{:03X}", section.end - 1),
+                section.size
+            )?;
+        }
+        let used = self
+            .sections
+            .iter()
+            .map(|section| section.size)
+            .sum::<usize>();
+        let available = memory::MEMORY_SIZE - memory::PROGRAM_START;
+        writeln!(
+            f,
+            "Total: {} of {} bytes ({} free)",
+            used,
+            available,
+            available.saturating_sub(used)
+        )?;
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed as unused: {}", self.removed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Assemble everything, and return the program along with where each chunk
+/// went and the listing.
+fn assemble_chunks(
+    source: &str,
+    target: Option<Profile>,
+) -> Result<(Vec<u8>, Vec<Chunk>, String), Chip8Error> {
+    // First pass: split the program into chunks, and find out where in its
+    // chunk every label and statement is
+    let mut chunks = vec![Chunk::new("code", Some(memory::PROGRAM_START), 1, 0)];
+    // The chunk that each section is adding to
+    let mut sections = HashMap::new();
+    sections.insert("code".to_string(), 0);
+    let mut current = 0;
+    // The chunk every line is in, and how far into it
+    let mut places = vec![];
+    let mut chunk_labels = vec![];
+    for (line_number, line) in source.lines().enumerate() {
+        let error = |message| Chip8Error::Assemble {
+            line: line_number + 1,
+            message,
+        };
+        let text = line.split(';').next().unwrap_or("").trim();
+        match parse_directive(text).map_err(error)? {
+            Some(Directive::Org(address)) => {
+                let section = chunks[current].section.clone();
+                let align = chunks[current].align;
+                chunks.push(Chunk::new(&section, Some(address), align, line_number));
+                current = chunks.len() - 1;
+                sections.insert(section, current);
+            }
+            Some(Directive::Section { name, align }) => match sections.get(&name) {
+                Some(chunk) => match align {
+                    Some(align) if align != chunks[*chunk].align => {
+                        return Err(error(format!(
+                            "Section `{}` is already aligned to {:X}",
+                            name, chunks[*chunk].align
+                        )))
+                    }
+                    _ => current = *chunk,
+                },
+                None => {
+                    chunks.push(Chunk::new(&name, None, align.unwrap_or(1), line_number));
+                    current = chunks.len() - 1;
+                    sections.insert(name, current);
+                }
+            },
+            None => {}
+        }
+        let (label, statement) = parse_line(line, &None, target).map_err(error)?;
+        let offset = chunks[current].size;
+        if let Some(label) = label {
+            chunk_labels.push((label, current, offset));
+        }
+        if let Some(statement) = statement {
+            chunks[current].size += statement.size();
+        }
+        places.push((current, offset));
+    }
+
+    // Now that we know how big every chunk is, find room for them all, and
+    // then where the labels ended up
+    place(&mut chunks)?;
+    let labels: HashMap<String, u16> = chunk_labels
+        .into_iter()
+        .map(|(label, chunk, offset)| (label, (chunks[chunk].start + offset) as u16))
+        .collect();
+    let mut listing = Listing::default();
+
+    // Second pass: encode everything, each in its place
+    let labels = Some(labels);
+    let end = chunks
+        .iter()
+        .map(Chunk::end)
+        .max()
+        .unwrap_or(memory::PROGRAM_START);
+    let mut program = vec![0; end - memory::PROGRAM_START];
+    let mut off_target = vec![];
+    for ((line_number, line), (chunk, offset)) in source.lines().enumerate().zip(places) {
+        let error = |message| Chip8Error::Assemble {
+            line: line_number + 1,
+            message,
+        };
+        let (label, statement) = parse_line(line, &labels, target).map_err(error)?;
+        let address = chunks[chunk].start + offset;
+        listing.add(line, label, &statement, address)?;
+        let bytes = match statement {
+            Some(Statement::Instruction(instruction)) => {
+                match target {
+                    Some(target) if !target.supports(&instruction) => {
+                        off_target.push(format!("  line {}: {}", line_number + 1, line.trim()))
+                    }
+                    _ => {}
+                }
+                instruction.to_bytes()?
+            }
+            Some(Statement::Bytes(bytes)) => bytes,
+            None => continue,
+        };
+        let start = address - memory::PROGRAM_START;
+        program[start..start + bytes.len()].copy_from_slice(&bytes);
+    }
+    let listing = listing.finish(labels.as_ref().unwrap());
+    match target {
+        Some(target) if !off_target.is_empty() => Err(Chip8Error::OffTarget {
+            target,
+            listing: off_target.join("\n"),
+        }),
+        _ => Ok((program, chunks, listing)),
+    }
+}
+
+/// A listing of assembled source code, in the same format as a disassembly
+/// (see `disasm::Disassembly`), so it can be assembled again:
+///
+///     loop:
+///         200: A20A      LD I, sprite   ; comment
+///         20A: FF 81     DATA           ; db FF, 81
+///
+/// Directives and comments on their own lines are copied as they are, and it
+/// ends with a symbol table (as comments) of every label and its address.
+#[derive(Default)]
+struct Listing {
+    lines: Vec<String>,
+}
+
+impl Listing {
+    /// Add a line of source, which assembled to `statement` at `address`.
+    fn add(
+        &mut self,
+        line: &str,
+        label: Option<String>,
+        statement: &Option<Statement>,
+        address: usize,
+    ) -> Result<(), Chip8Error> {
+        let mut parts = line.splitn(2, ';');
+        let mut code = parts.next().unwrap_or("").trim();
+        let comment = parts
+            .next()
+            .map(|comment| format!("   ; {}", comment.trim()))
+            .unwrap_or_default();
+        if let Some(label) = label {
+            self.lines.push(format!("{}:", label));
+            code = code[code.find(':').map_or(0, |colon| colon + 1)..].trim();
+        }
+        match statement {
+            Some(Statement::Instruction(instruction)) => {
+                let words = instruction
+                    .to_bytes()?
+                    .chunks(2)
+                    .map(|word| format!("{:02X}{:02X}", word[0], word[1]))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                self.lines.push(format!(
+                    "    {:03X}: {:<9} {}{}",
+                    address, words, code, comment
+                ));
+            }
+            Some(Statement::Bytes(bytes)) => {
+                // Like a disassembly, 8 bytes a line, with the source as a
+                // comment on the first one
+                for (n, row) in bytes.chunks(8).enumerate() {
+                    let hex = row
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let source = match n {
+                        0 => format!("   ; {}", code),
+                        _ => String::new(),
+                    };
+                    self.lines.push(format!(
+                        "    {:03X}: {:<9} DATA{}",
+                        address + n * 8,
+                        hex,
+                        source
+                    ));
+                }
+            }
+            None if !code.is_empty() => self.lines.push(format!("    {}{}", code, comment)),
+            None if !comment.is_empty() => self.lines.push(format!("    {}", comment.trim())),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The whole listing, with the symbol table at the end.
+    fn finish(mut self, labels: &HashMap<String, u16>) -> String {
+        let mut symbols = labels.iter().collect::<Vec<_>>();
+        symbols.sort_by_key(|(label, address)| (**address, label.to_string()));
+        self.lines.push(String::new());
+        self.lines.push("; Symbols".to_string());
+        for (label, address) in symbols {
+            self.lines.push(format!(";   {:03X}  {}", address, label));
+        }
+        self.lines.join("\n") + "\n"
+    }
+}
+
+/// A label and the lines after it, up to the next label or directive.
+struct Block {
+    label: Option<String>,
+    lines: Range<usize>,
+    /// The labels its statements use
+    uses: Vec<String>,
+    /// Whether running off its end (or reading past its data) carries on into
+    /// the next block
+    falls_through: bool,
+    /// Whether it has to stay, even if nothing uses it
+    keep: bool,
+}
+
+/// Leave out the blocks of code or data that nothing uses, and return what's
+/// left (with the removed lines blank, so line numbers stay the same) and the
+/// labels that were removed.
+/// A block is a label and everything after it, up to the next label or
+/// directive. Blocks are used if they're:
+/// - the start of the program, or anything else without a label
+/// - marked with `.keep` on a line before their label
+/// - named by an instruction in a block that's used
+/// - right after a used block that doesn't end with a JP or RET (that might be
+///   skipped). Data always counts as carrying on, since a program can read
+///   past the end of one table into the next.
+fn remove_dead_code(
+    source: &str,
+    target: Option<Profile>,
+) -> Result<(String, Vec<String>), Chip8Error> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut blocks: Vec<Block> = vec![];
+    let mut keep_next = false;
+    // The last two statements in the current block
+    let mut last = (None, None);
+    for (line_number, line) in lines.iter().enumerate() {
+        let error = |message| Chip8Error::Assemble {
+            line: line_number + 1,
+            message,
+        };
+        let text = line.split(';').next().unwrap_or("").trim();
+        if text == ".keep" {
+            keep_next = true;
+            continue;
+        }
+        if parse_directive(text).map_err(error)?.is_some() {
+            // Something else comes next in memory, so nothing falls through
+            if let Some(block) = blocks.last_mut() {
+                block.falls_through = false;
+            }
+            blocks.push(Block {
+                label: None,
+                lines: line_number..line_number + 1,
+                uses: vec![],
+                falls_through: false,
+                keep: true,
+            });
+            last = (None, None);
+            continue;
+        }
+        let (label, statement) = parse_line(line, &None, target).map_err(error)?;
+        if label.is_some() || blocks.is_empty() {
+            if let Some(block) = blocks.last_mut() {
+                block.falls_through = block.falls_through && falls_through(&last);
+            }
+            blocks.push(Block {
+                keep: label.is_none() || keep_next,
+                label,
+                lines: line_number..line_number,
+                uses: vec![],
+                falls_through: true,
+            });
+            keep_next = false;
+            last = (None, None);
+        }
+        let block = blocks.last_mut().unwrap();
+        block.lines.end = line_number + 1;
+        if let Some(statement) = statement {
+            // Any word that's a label counts as using it
+            block.uses.extend(
+                text.split(|c: char| !c.is_alphanumeric() && c != '_')
+                    .map(String::from),
+            );
+            last = (last.1, Some(statement));
+        }
+    }
+    if let Some(block) = blocks.last_mut() {
+        block.falls_through = block.falls_through && falls_through(&last);
+    }
+
+    // Everything that's kept is used, and so is everything they use
+    let mut used = vec![false; blocks.len()];
+    let mut to_visit = (0..blocks.len())
+        .filter(|index| blocks[*index].keep)
+        .collect::<Vec<_>>();
+    while let Some(index) = to_visit.pop() {
+        if used[index] {
+            continue;
+        }
+        used[index] = true;
+        if blocks[index].falls_through && index + 1 < blocks.len() {
+            to_visit.push(index + 1);
+        }
+        for name in &blocks[index].uses {
+            to_visit.extend(
+                (0..blocks.len()).filter(|other| blocks[*other].label.as_ref() == Some(name)),
+            );
+        }
+    }
+
+    let mut kept = lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    let mut removed = vec![];
+    for (block, used) in blocks.iter().zip(used) {
+        if !used {
+            removed.extend(block.label.clone());
+            for line in block.lines.clone() {
+                kept[line].clear();
+            }
+        }
+    }
+    Ok((kept.join("\n"), removed))
+}
+
+/// Whether a block that ends with these two statements (the last one second)
+/// carries on into the next block.
+fn falls_through(last: &(Option<Statement>, Option<Statement>)) -> bool {
+    use Instruction::*;
+
+    match last {
+        (Some(Statement::Instruction(SEByte(..))), _)
+        | (Some(Statement::Instruction(SNEByte(..))), _)
+        | (Some(Statement::Instruction(SERegister(..))), _)
+        | (Some(Statement::Instruction(SNERegister(..))), _)
+        | (Some(Statement::Instruction(SKP(_))), _)
+        | (Some(Statement::Instruction(SKNP(_))), _) => true,
+        (_, Some(Statement::Instruction(JP(_))))
+        | (_, Some(Statement::Instruction(JPOffset(_))))
+        | (_, Some(Statement::Instruction(RET()))) => false,
+        _ => true,
+    }
+}
+
+/// A directive that controls where things go in memory.
+#[derive(Debug, PartialEq)]
+enum Directive {
+    /// `.org 300`: put what comes next at this address
+    Org(usize),
+    /// `.section sprites` or `.section sprites align 8`: put what comes next
+    /// in this section (along with anything already in it), which starts at a
+    /// multiple of `align`
+    Section { name: String, align: Option<usize> },
+}
+
+/// Parse a layout directive, or return None if the text isn't one.
+fn parse_directive(text: &str) -> Result<Option<Directive>, String> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        [".org", address] => Ok(Some(Directive::Org(parse_number(address)? as usize))),
+        [".org", ..] => Err("Expected `.org` and an address".to_string()),
+        [".section", name] => Ok(Some(Directive::Section {
+            name: name.to_string(),
+            align: None,
+        })),
+        [".section", name, "align", align] => match parse_number(align)? {
+            0 => Err("Sections have to be aligned to at least 1".to_string()),
+            align => Ok(Some(Directive::Section {
+                name: name.to_string(),
+                align: Some(align as usize),
+            })),
+        },
+        [".section", ..] => Err("Expected `.section NAME` or `.section NAME align N`".to_string()),
+        _ => Ok(None),
+    }
+}
+
+/// A run of statements that sit one after another in memory. Every section
+/// starts with one, and each `.org` starts another.
+struct Chunk {
+    section: String,
+    /// Where `.org` put it, or None to let `place` find room for it
+    org: Option<usize>,
+    /// Its section's alignment
+    align: usize,
+    /// The line it starts on, for errors
+    line: usize,
+    size: usize,
+    /// Where it ended up
+    start: usize,
+}
+
+impl Chunk {
+    fn new(section: &str, org: Option<usize>, align: usize, line: usize) -> Self {
+        Self {
+            section: section.to_string(),
+            org,
+            align,
+            line,
+            size: 0,
+            start: org.unwrap_or(0),
+        }
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    /// Whether this chunk would overlap anything from `start` to `end`.
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.size > 0 && start < end && self.start < end && start < self.end()
+    }
+}
+
+/// Decide where every chunk goes. Chunks with an `.org` go there, and the
+/// rest go, in order, in the first place after the one before with room for
+/// them (at their section's alignment). It's an error for chunks to overlap,
+/// or to go outside the program's part of memory.
+fn place(chunks: &mut [Chunk]) -> Result<(), Chip8Error> {
+    let mut next = memory::PROGRAM_START;
+    for index in 0..chunks.len() {
+        if chunks[index].org.is_some() {
+            continue;
+        }
+        let (size, align) = (chunks[index].size, chunks[index].align);
+        let mut start = align_up(next, align);
+        // Move past anything that's already placed and in the way
+        while let Some(end) = chunks
+            .iter()
+            .enumerate()
+            .filter(|(other, chunk)| *other < index || chunk.org.is_some())
+            .find(|(_, chunk)| chunk.overlaps(start, start + size))
+            .map(|(_, chunk)| chunk.end())
+        {
+            start = align_up(end, align);
+        }
+        chunks[index].start = start;
+        next = start + size;
+    }
+
+    for (index, chunk) in chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.size > 0)
+    {
+        let error = |message| Chip8Error::Assemble {
+            line: chunk.line + 1,
+            message,
+        };
+        if chunk.start < memory::PROGRAM_START {
+            return Err(error(format!(
+                "Section `{}` starts at {:03X}, before the program's start at {:03X}",
+                chunk.section,
+                chunk.start,
+                memory::PROGRAM_START
+            )));
+        }
+        if chunk.end() > memory::MEMORY_SIZE {
+            return Err(error(format!(
+                "Section `{}` ends at {:03X}, past the end of memory",
+                chunk.section,
+                chunk.end() - 1
+            )));
+        }
+        if let Some(other) = chunks[..index]
+            .iter()
+            .find(|other| other.overlaps(chunk.start, chunk.end()))
+        {
+            return Err(error(format!(
+                "Section `{}` at {:03X}-{:03X} overlaps section `{}` at {:03X}-{:03X}",
+                chunk.section,
+                chunk.start,
+                chunk.end() - 1,
+                other.section,
+                other.start,
+                other.end() - 1
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Round the address up to a multiple of `align`.
+fn align_up(address: usize, align: usize) -> usize {
+    match address % align {
+        0 => address,
+        rest => address + align - rest,
+    }
+}
+
+/// The machine that the source's `.target` directive names, or `default` if it
+/// doesn't have one.
+fn find_target(source: &str, default: Option<Profile>) -> Result<Option<Profile>, Chip8Error> {
+    let mut target = default;
+    for (line_number, line) in source.lines().enumerate() {
+        let error = |message| Chip8Error::Assemble {
+            line: line_number + 1,
+            message,
+        };
+        let words = line
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        let name = match words.as_slice() {
+            [".target", name] => name,
+            [".target", ..] => return Err(error("Expected `.target` and a machine".to_string())),
+            _ => continue,
+        };
+        let profile = name.parse::<Profile>().map_err(error)?;
+        match target {
+            Some(existing) if existing != profile => {
+                return Err(error(format!(
+                    "The target is already {}, so it can't be {}",
+                    existing, profile
+                )))
+            }
+            _ => target = Some(profile),
+        }
+    }
+    Ok(target)
+}
+
+/// Whether the word is all hex digits.
+fn is_hex(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parse a line into the label it defines (if any) and what it assembles to
+/// (if anything).
+fn parse_line(
+    line: &str,
+    labels: &Labels,
+    target: Option<Profile>,
+) -> Result<(Option<String>, Option<Statement>), String> {
+    let mut text = line.split(';').next().unwrap_or("").trim();
+    let mut label = None;
+
+    if let Some(colon) = text.find(':') {
+        let before = text[..colon].trim();
+        let after = text[colon + 1..].trim();
+        // `chip8 print` puts the address before each line, like `200: 6A02 LD VA, 02`.
+        // That's not a label, and neither is `Unknown: 1234`.
+        if is_hex(before) && !after.is_empty() {
+            text = after;
+            let words = text.split_whitespace().collect::<Vec<_>>();
+            let listed_bytes = words
+                .iter()
+                .take_while(|word| is_hex(word) && (word.len() == 2 || word.len() == 4))
+                .collect::<Vec<_>>();
+            let rest = words[listed_bytes.len()..].join(" ");
+            if rest == "DATA" {
+                let bytes = listed_bytes
+                    .iter()
+                    .map(|word| parse_number(word).map(|n| n as u8))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok((None, Some(Statement::Bytes(bytes))));
+            }
+            // Skip the bytes column and assemble the instruction
+            for word in listed_bytes {
+                text = text.trim_start()[word.len()..].trim_start();
+            }
+        } else if before != "Unknown" && !before.contains(char::is_whitespace) {
+            label = Some(before.to_string());
+            text = after;
+        }
+    }
+
+    if let Some(bytes) = parse_data_directive(text, labels)? {
+        return Ok((label, Some(Statement::Bytes(bytes))));
+    }
+    // Directives like `.org` and `.target` have already been read
+    if text.is_empty() || text.starts_with('.') {
+        return Ok((label, None));
+    }
+    Ok((label, Some(parse_statement(text, labels, target)?)))
+}
+
+/// Parse `.word`, `.fill`, or `.table` into the bytes it makes, or None if
+/// it's something else.
+fn parse_data_directive(text: &str, labels: &Labels) -> Result<Option<Vec<u8>>, String> {
+    let (name, rest) = match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    };
+    let operands = rest
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect::<Vec<_>>();
+    let label = |name: &str| match labels {
+        None => Ok(0.0),
+        Some(labels) => labels
+            .get(name)
+            .map(|address| *address as f64)
+            .ok_or_else(|| format!("Unknown label `{}`", name)),
+    };
+    // Counts decide how big things are, so they have to be known before any
+    // labels are
+    let count = |expression: &str| {
+        let value = expression::evaluate(expression, &|name: &str| {
+            Err(format!("Counts can't use labels, but `{}` does", name))
+        })?;
+        whole_number(expression, value, 0, 0xFFF).map(|n| n as usize)
+    };
+    let byte = |expression: &str, variable: &dyn Fn(&str) -> Result<f64, String>| {
+        let value = expression::evaluate(expression, variable)?;
+        whole_number(expression, value, -0x80, 0xFF).map(|n| n as u8)
+    };
+
+    let bytes = match (name, operands.as_slice()) {
+        (".word", words) if !words.is_empty() => {
+            let mut bytes = vec![];
+            for word in words {
+                let value = expression::evaluate(word, &label)?;
+                let value = whole_number(word, value, -0x8000, 0xFFFF)? as u16;
+                bytes.extend(&value.to_be_bytes());
+            }
+            bytes
+        }
+        (".fill", [n, value]) => vec![byte(value, &label)?; count(n)?],
+        (".table", [n, expression]) => (0..count(n)?)
+            .map(|i| {
+                byte(expression, &|name: &str| match name {
+                    "i" => Ok(i as f64),
+                    _ => label(name),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        (".word", _) => return Err("Expected `.word` and at least one value".to_string()),
+        (".fill", _) => return Err("Expected `.fill COUNT, VALUE`".to_string()),
+        (".table", _) => return Err("Expected `.table COUNT, EXPRESSION`".to_string()),
+        _ => return Ok(None),
+    };
+    Ok(Some(bytes))
+}
+
+/// Round the value of `expression` to a whole number, and make sure it's from
+/// `min` to `max`.
+fn whole_number(expression: &str, value: f64, min: i64, max: i64) -> Result<i64, String> {
+    let n = value.round() as i64;
+    if n < min || n > max {
+        return Err(format!(
+            "`{}` is {}, which isn't from {} to {:X}",
+            expression,
+            n,
+            if min < 0 {
+                format!("-{:X}", -min)
+            } else {
+                format!("{:X}", min)
+            },
+            max
+        ));
+    }
+    Ok(n)
+}
+
+/// Parse a hex number, with or without a leading `0x`.
+fn parse_number(word: &str) -> Result<u16, String> {
+    let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Expected a hex number, got `{}`", word))
+}
+
+fn parse_byte(word: &str) -> Result<u8, String> {
+    let n = parse_number(word)?;
+    if n > 0xFF {
+        return Err(format!("{} doesn't fit in a byte", word));
+    }
+    Ok(n as u8)
+}
+
+/// An address, or a label for one.
+fn parse_address(word: &str, labels: &Labels) -> Result<u16, String> {
+    if let Ok(address) = parse_number(word) {
+        if address > 0xFFF {
+            return Err(format!("Address {} is past the end of memory", word));
+        }
+        return Ok(address);
+    }
+    match labels {
+        None => Ok(0),
+        Some(labels) => labels
+            .get(word)
+            .copied()
+            .ok_or_else(|| format!("Unknown label `{}`", word)),
+    }
+}
+
+/// Parse an instruction (or a `db` directive).
+fn parse_statement(
+    text: &str,
+    labels: &Labels,
+    target: Option<Profile>,
+) -> Result<Statement, String> {
+    use Instruction::*;
+
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    };
+    let mnemonic = mnemonic.to_uppercase();
+    // `SAVE V1 - V3` is just 2 operands, and so is `ADD V1 += V2`, the way
+    // instructions used to print
+    let separator = if mnemonic == "SAVE" || mnemonic == "LOAD" {
+        '-'
+    } else {
+        ','
+    };
+    let operands = rest
+        .replace("+=", ",")
+        .split(separator)
+        .map(|operand| operand.trim().to_string())
+        .filter(|operand| !operand.is_empty())
+        .collect::<Vec<_>>();
+    let operands = operands.iter().map(String::as_str).collect::<Vec<_>>();
+    let address = |word| {
+        parse_address(word, labels)
+            .and_then(|address| Address::new(address).map_err(|e| e.to_string()))
+    };
+
+    let instruction = match (mnemonic.as_str(), operands.as_slice()) {
+        ("DB", bytes) => {
+            let bytes = bytes
+                .iter()
+                .flat_map(|operand| operand.split_whitespace())
+                .map(parse_byte)
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Statement::Bytes(bytes));
+        }
+        ("UNKNOWN:", [word]) => UNKNOWN(parse_number(word)?),
+        ("SYS", [operand]) => {
+            // `SYS 123 (ignored)`, the way it used to print
+            let word = operand.split_whitespace().next().unwrap_or("");
+            SYS(address(word)?)
+        }
+        ("CLS", []) => CLS(),
+        ("RET", []) => RET(),
+        ("SCR", []) => SCR(),
+        ("SCL", []) => SCL(),
+        ("EXIT", []) => EXIT(),
+        ("LOW", []) => LOW(),
+        ("HIGH", []) => HIGH(),
+        ("AUDIO", []) => AUDIO(),
+        ("JP", ["V0", to]) | ("JP", ["v0", to]) => JPOffset(address(to)?),
+        ("JP", [to]) => JP(address(to)?),
+        ("CALL", [to]) => CALL(address(to)?),
+        ("SE", [x, y]) if y.starts_with(&['V', 'v'][..]) => SERegister(x.parse()?, y.parse()?),
+        ("SE", [x, byte]) => SEByte(x.parse()?, parse_byte(byte)?),
+        ("SNE", [x, y]) if y.starts_with(&['V', 'v'][..]) => SNERegister(x.parse()?, y.parse()?),
+        ("SNE", [x, byte]) => SNEByte(x.parse()?, parse_byte(byte)?),
+        ("LD", ["I", to]) | ("LD", ["i", to]) => match to.strip_prefix("long ") {
+            Some(long_address) => LDILong(parse_number(long_address.trim())?),
+            // Only XO-CHIP can point I past 0xFFF, with a long load
+            None => match parse_number(to) {
+                Ok(long_address) if long_address > 0xFFF && target == Some(Profile::XoChip) => {
+                    LDILong(long_address)
+                }
+                _ => LDI(address(to)?),
+            },
+        },
+        ("LD", ["[I]", x]) | ("LD", ["[i]", x]) => LDIntoMemory(x.parse()?),
+        ("LD", [x, "[I]"]) | ("LD", [x, "[i]"]) => LDFromMemory(x.parse()?),
+        ("LD", ["HF", x]) | ("LD", ["hf", x]) => LDHF(x.parse()?),
+        ("LD", ["R", x]) | ("LD", ["r", x]) => LDIntoFlags(x.parse()?),
+        ("LD", [x, "R"]) | ("LD", [x, "r"]) => LDFromFlags(x.parse()?),
+        ("LD", [x, "K"]) | ("LD", [x, "k"]) => LDKey(x.parse()?),
+        ("LD", [x, "DT"]) | ("LD", [x, "dt"]) => LDFromDT(x.parse()?),
+        ("LD", ["DT", x]) | ("LD", ["dt", x]) => LDDT(x.parse()?),
+        ("LD", ["ST", x]) | ("LD", ["st", x]) => LDST(x.parse()?),
+        ("LD", ["F", x]) | ("LD", ["f", x]) => LDF(x.parse()?),
+        ("LD", ["B", x]) | ("LD", ["b", x]) => LDB(x.parse()?),
+        ("LD", [x, y]) if y.starts_with(&['V', 'v'][..]) => LDRegister(x.parse()?, y.parse()?),
+        ("LD", [x, byte]) => LDByte(x.parse()?, parse_byte(byte)?),
+        ("ADD", ["I", x]) | ("ADD", ["i", x]) => ADDI(x.parse()?),
+        ("ADD", [x, y]) if y.starts_with(&['V', 'v'][..]) => ADDRegister(x.parse()?, y.parse()?),
+        ("ADD", [x, byte]) => ADDByte(x.parse()?, parse_byte(byte)?),
+        ("OR", [x, y]) => OR(x.parse()?, y.parse()?),
+        ("AND", [x, y]) => AND(x.parse()?, y.parse()?),
+        ("XOR", [x, y]) => XOR(x.parse()?, y.parse()?),
+        ("SUB", [x, y]) => SUB(x.parse()?, y.parse()?),
+        ("SUBN", [x, y]) => SUBN(x.parse()?, y.parse()?),
+        ("SHR", [x, y]) => SHR(x.parse()?, y.parse()?),
+        ("SHL", [x, y]) => SHL(x.parse()?, y.parse()?),
+        ("RND", [x, byte]) => RND(x.parse()?, parse_byte(byte)?),
+        ("DRW", [x, y, n]) => DRW(x.parse()?, y.parse()?, parse_byte(n)?),
+        ("SKP", [x]) => SKP(x.parse()?),
+        ("SKNP", [x]) => SKNP(x.parse()?),
+        ("SCD", [n]) => SCD(parse_byte(n)?),
+        ("SAVE", [x, y]) => SAVE(x.parse()?, y.parse()?),
+        ("LOAD", [x, y]) => LOAD(x.parse()?, y.parse()?),
+        ("PLANE", [n]) => PLANE(parse_byte(n)?),
+        ("PITCH", [x]) => PITCH(x.parse()?),
+        _ => return Err(format!("Don't know how to assemble `{}`", text)),
+    };
+    Ok(Statement::Instruction(instruction))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm;
+    use crate::instruction::Register;
+
+    #[test]
+    fn assemble_with_labels_data_and_comments() {
+        let source = "
+            ; Draw a sprite forever
+            start:
+                LD I, sprite
+                LD V1, 0x0A   ; x
+                DRW V1, V2, 1
+                JP start
+            sprite:
+                db 0xFF
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xA2, 0x08, 0x61, 0x0A, 0xD1, 0x21, 0x12, 0x00, 0xFF]
+        );
+    }
+
+    #[test]
+    fn assemble_what_display_prints() {
+        let instructions = vec![
+            Instruction::SYS(0x0EF.into()),
+            Instruction::CLS(),
+            Instruction::ADDRegister(Register::from_nibble(0x1), Register::from_nibble(0x2)),
+            Instruction::LDRegister(Register::from_nibble(0x1), Register::from_nibble(0x2)),
+            Instruction::SUBN(Register::from_nibble(0x1), Register::from_nibble(0x2)),
+            Instruction::SKNP(Register::from_nibble(0x3)),
+            Instruction::LDFromDT(Register::from_nibble(0x3)),
+            Instruction::LDDT(Register::from_nibble(0x3)),
+            Instruction::LDF(Register::from_nibble(0x3)),
+            Instruction::LDB(Register::from_nibble(0x3)),
+            Instruction::LDIntoMemory(Register::from_nibble(0x3)),
+            Instruction::LDHF(Register::from_nibble(0x4)),
+            Instruction::LDIntoFlags(Register::from_nibble(0x5)),
+            Instruction::LDFromFlags(Register::from_nibble(0x5)),
+            Instruction::LDKey(Register::from_nibble(0x6)),
+            Instruction::LDST(Register::from_nibble(0x7)),
+            Instruction::SAVE(Register::from_nibble(0x1), Register::from_nibble(0x5)),
+            Instruction::LDILong(0x1234),
+            Instruction::JPOffset(0x300.into()),
+        ];
+        for instruction in instructions {
+            assert_eq!(
+                assemble(&instruction.to_string()).unwrap(),
+                instruction.to_bytes().unwrap(),
+                "{}",
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_through_print() {
+        let rom = vec![
+            0x22, 0x08, // CALL 208
+            0x00, 0xE0, // CLS
+            0x12, 0x02, // JP 202
+            0x0F, 0x1E, // sprite data that looks like code
+            0xF0, 0x00, 0x12, 0x34, // LD I, long 1234
+            0x00, 0xEE, // RET
+        ];
+        let listing = disasm::disassemble(&rom).unwrap().to_string();
+
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        match assemble("RET\nJP nowhere") {
+            Err(Chip8Error::Assemble { line, message }) => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "Unknown label `nowhere`");
+            }
+            other => panic!("Expected an assembler error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn target_lists_every_line_it_doesnt_have() {
+        let source = ".target cosmac\nHIGH\nLD V1, 02\nSCR ; scroll\n";
+        match assemble(source) {
+            Err(Chip8Error::OffTarget { target, listing }) => {
+                assert_eq!(target, Profile::Cosmac);
+                assert_eq!(listing, "  line 2: HIGH\n  line 4: SCR ; scroll");
+            }
+            other => panic!("Expected an off-target error, got {:?}", other),
+        }
+        assert!(assemble_for("HIGH\nSCR", Some(Profile::Schip)).is_ok());
+    }
+
+    #[test]
+    fn targets_have_to_agree() {
+        assert!(assemble_for(".target schip\nHIGH", Some(Profile::Schip)).is_ok());
+        match assemble_for(".target schip", Some(Profile::Cosmac)) {
+            Err(Chip8Error::Assemble { line, message }) => {
+                assert_eq!(line, 1);
+                assert_eq!(
+                    message,
+                    "The target is already cosmac, so it can't be schip"
+                );
+            }
+            other => panic!("Expected an assembler error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn org_leaves_a_gap() {
+        let program = assemble("JP later\n.org 300\nlater: RET").unwrap();
+
+        assert_eq!(program.len(), 0x102);
+        assert_eq!(program[..2], [0x13, 0x00]);
+        assert!(program[2..0x100].iter().all(|byte| *byte == 0));
+        assert_eq!(program[0x100..], [0x00, 0xEE]);
+    }
+
+    #[test]
+    fn sections_go_after_the_code() {
+        let source = "
+            LD I, sprite
+            .section sprites align 10
+            sprite: db FF
+            .section code
+            RET
+        ";
+        let program = assemble(source).unwrap();
+
+        // The code is 200-203, so the sprites go at the next multiple of 0x10
+        assert_eq!(program[..4], [0xA2, 0x10, 0x00, 0xEE]);
+        assert_eq!(program[0x10..], [0xFF]);
+    }
+
+    #[test]
+    fn sections_find_room_around_orgs() {
+        let source = "
+            RET
+            .section data
+            db 01 02 03
+            .section fixed
+            .org 202
+            db FF
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x00, 0xEE, 0xFF, 0x01, 0x02, 0x03]
+        );
+    }
+
+    #[test]
+    fn overlapping_orgs_are_an_error() {
+        match assemble("RET\n.org 202\ndb 01\n.org 201\ndb 02") {
+            Err(Chip8Error::Assemble { line, message }) => {
+                assert_eq!(line, 4);
+                assert_eq!(
+                    message,
+                    "Section `code` at 201-201 overlaps section `code` at 200-201"
+                );
+            }
+            other => panic!("Expected an assembler error, got {:?}", other),
+        }
+        assert!(assemble(".org FFF\ndb 01 02").is_err());
+        assert!(assemble(".org 100\nRET").is_err());
+    }
+
+    #[test]
+    fn words_fills_and_tables() {
+        let source = "
+            LD I, table
+            table:
+                .word 1234, table, -1
+                .fill 3, 2 * 4
+                .table 4, i * i - 1
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0xA2, 0x02, 0x12, 0x34, 0x02, 0x02, 0xFF, 0xFF, 0x08, 0x08, 0x08, 0xFF, 0x00, 0x03,
+                0x08
+            ]
+        );
+    }
+
+    #[test]
+    fn sine_table() {
+        let table = assemble(".table 8, 80 + 7F * sin(i * 2 * pi / 8)").unwrap();
+
+        assert_eq!(table, vec![0x80, 0xDA, 0xFF, 0xDA, 0x80, 0x26, 0x01, 0x26]);
+    }
+
+    #[test]
+    fn data_directive_errors() {
+        let message = |source| match assemble(source) {
+            Err(Chip8Error::Assemble { message, .. }) => message,
+            other => panic!("Expected an assembler error, got {:?}", other),
+        };
+
+        assert_eq!(
+            message(".fill 2, 100"),
+            "`100` is 256, which isn't from -80 to FF"
+        );
+        assert_eq!(
+            message("x: .fill x, 1"),
+            "Counts can't use labels, but `x` does"
+        );
+        assert_eq!(message(".word nowhere"), "Unknown label `nowhere`");
+        assert_eq!(message(".table 4"), "Expected `.table COUNT, EXPRESSION`");
+    }
+
+    #[test]
+    fn listing() {
+        let source = "
+            ; Draw a sprite forever
+            start:
+                LD I, sprite    ; the smiley
+                DRW V1, V2, 1
+                JP start
+            .section sprites
+            sprite: .fill 9, 81
+        ";
+        let assembly = assemble_with_report(source, None, false).unwrap();
+
+        assert_eq!(
+            assembly.listing,
+            "    ; Draw a sprite forever
+start:
+    200: A206      LD I, sprite   ; the smiley
+    202: D121      DRW V1, V2, 1
+    204: 1200      JP start
+    .section sprites
+sprite:
+    206: 81 81 81 81 81 81 81 81 DATA   ; .fill 9, 81
+    20E: 81        DATA
+
+; Symbols
+;   200  start
+;   206  sprite
+"
+        );
+        assert_eq!(assemble(&assembly.listing).unwrap(), assembly.program);
+    }
+
+    #[test]
+    fn removes_unused_subroutines() {
+        let source = "
+            CALL used
+            JP end
+            unused:
+                LD V1, 01
+                RET
+            used:
+                LD V2, 02
+                RET
+            end:
+                JP end
+        ";
+        let assembly = assemble_with_report(source, None, true).unwrap();
+
+        assert_eq!(
+            assembly.program,
+            assemble("CALL used\nJP end\nused: LD V2, 02\nRET\nend: JP end").unwrap()
+        );
+        assert_eq!(assembly.report.removed, vec!["unused"]);
+    }
+
+    #[test]
+    fn keeps_what_might_run_or_be_read() {
+        let source = "
+            LD I, sprites
+            JP skipped
+            skipped:        ; jumped to
+                SE V0, 00
+                JP elsewhere
+            elsewhere:      ; reached when the JP is skipped
+                RET
+            sprites:
+                db FF
+            more_sprites:   ; might be read past the end of `sprites`
+                db 81
+            .keep
+            kept:
+                RET
+            .section data
+            unused:
+                db 01
+        ";
+        let report = assemble_with_report(source, None, true).unwrap().report;
+
+        assert_eq!(report.removed, vec!["unused"]);
+    }
+
+    #[test]
+    fn size_report() {
+        let source = "RET\n.section sprites align 10\ndb 01, 02, 03";
+        let report = assemble_with_report(source, None, false).unwrap().report;
+
+        assert_eq!(
+            report.to_string(),
+            "SECTION          START   END  BYTES\n\
+             code               200   201      2\n\
+             sprites            210   212      3\n\
+             Total: 5 of 3584 bytes (3579 free)\n"
+        );
+    }
+
+    #[test]
+    fn xo_chip_target_uses_long_loads_for_big_addresses() {
+        assert_eq!(
+            assemble(".target xo-chip\nLD I, 1234").unwrap(),
+            vec![0xF0, 0x00, 0x12, 0x34]
+        );
+        assert!(assemble("LD I, 1234").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    assembler, error::Chip8Error, frame::FrameListener, interpreter::State, quirks::Profile,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::fmt::{self, Formatter};
+
+/// A program that starts a beep and shows a block in the top left corner at
+/// the same time, over and over. If the machine (and whatever's listening to
+/// its frames) is in sync, the beep and the block start in the same frame.
+/// Each loop is exactly 110 instructions (11 frames) long, so the beep always
+/// starts at the same point in a frame and never gets split from its flash.
+const SYNC_TEST_SOURCE: &str = "
+    LD I, block
+    LD V1, 00
+    LD V2, 06
+loop:
+    LD ST, V2       ; beep for 6 frames...
+    DRW V1, V1, 8   ; ...while showing the block
+    CALL wait
+    DRW V1, V1, 8   ; hide the block again
+    CALL wait
+    JP loop
+wait:               ; with the CALL, 53 instructions
+    LD V3, 00
+spin:
+    ADD V3, 01
+    SE V3, 11
+    JP spin
+    RET
+block:
+    db FF, FF, FF, FF, FF, FF, FF, FF
+";
+
+/// Watches the end of every frame for the sound timer starting (a beep) and
+/// the top left pixel turning on (a flash), and remembers which frame each
+/// one happened in.
+#[derive(Default)]
+struct SyncProbe {
+    frames: u64,
+    beeping: bool,
+    lit: bool,
+    beeps: Vec<u64>,
+    flashes: Vec<u64>,
+}
+
+impl FrameListener for SyncProbe {
+    fn end_frame(&mut self, state: &State) {
+        self.frames += 1;
+        let beeping = state.sound_timer() > 0;
+        let lit = state.buffer().get_planes(0, 0) != 0;
+        if beeping && !self.beeping {
+            self.beeps.push(self.frames);
+        }
+        if lit && !self.lit {
+            self.flashes.push(self.frames);
+        }
+        self.beeping = beeping;
+        self.lit = lit;
+    }
+}
+
+/// How far apart the beeps and flashes of the sync test were.
+#[derive(Debug, PartialEq)]
+pub struct SyncReport {
+    /// For each beep, how many frames later its flash was (negative if the
+    /// flash came first)
+    pub offsets: Vec<i64>,
+}
+
+impl SyncReport {
+    /// How much the offset changed between the first beep and the last one.
+    /// Anything but 0 means the two are drifting apart.
+    pub fn drift(&self) -> i64 {
+        match (self.offsets.first(), self.offsets.last()) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (n, offset) in self.offsets.iter().enumerate() {
+            writeln!(
+                f,
+                "Beep {}: flash {:+} frames ({:+.1} ms)",
+                n + 1,
+                offset,
+                *offset as f64 * 1000.0 / 60.0
+            )?;
+        }
+        match self.offsets.iter().max_by_key(|offset| offset.abs()) {
+            None => writeln!(f, "No beeps or flashes happened"),
+            Some(0) => writeln!(f, "In sync"),
+            Some(worst) => writeln!(
+                f,
+                "Out of sync by up to {} frames, drifting {:+} frames",
+                worst.abs(),
+                self.drift()
+            ),
+        }
+    }
+}
+
+/// Run the sync test program for `frames` frames with the given profile's
+/// quirks, and compare when each beep started to when its flash did.
+pub fn check(profile: Profile, frames: u64) -> Result<SyncReport, Chip8Error> {
+    let program = assembler::assemble(SYNC_TEST_SOURCE)?;
+    let mut state =
+        State::with_program_for(profile.variant(), &program)?.with_quirks(profile.quirks());
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut probe = SyncProbe::default();
+
+    while probe.frames < frames {
+        state.step(&mut rng)?;
+        if state.between_frames() {
+            probe.end_frame(&state);
+        }
+    }
+
+    let offsets = probe
+        .beeps
+        .iter()
+        .zip(&probe.flashes)
+        .map(|(beep, flash)| *flash as i64 - *beep as i64)
+        .collect();
+    Ok(SyncReport { offsets })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn waiting_for_the_display_delays_the_flash_by_a_frame() {
+        let report = check(Profile::Cosmac, 120).unwrap();
+
+        assert!(report.offsets.len() > 5);
+        assert!(report.offsets.iter().all(|offset| *offset == 1));
+        assert_eq!(report.drift(), 0);
+    }
+
+    #[test]
+    fn drawing_immediately_is_in_sync() {
+        let report = check(Profile::Schip, 120).unwrap();
+
+        assert!(report.offsets.iter().all(|offset| *offset == 0));
+        assert!(report.to_string().ends_with("In sync\n"));
+    }
+}
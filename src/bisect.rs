@@ -0,0 +1,312 @@
+use crate::{
+    error::Chip8Error,
+    instruction::Instruction,
+    interpreter::{State, Variant},
+    quirks::{QuirkSetting, Quirks},
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::fmt::{self, Display, Formatter};
+
+/// What a program should look like after running for a while, to bisect
+/// against.
+pub struct Reference {
+    /// How many instructions to run before comparing
+    pub cycles: u64,
+    /// The screen it should show then: either a PBM image (like F2 or
+    /// `--screenshot-at-cycle` saves) or a dump from `--dump-display`
+    pub screen: String,
+    /// The seed for RND's random numbers
+    pub seed: u64,
+}
+
+impl Reference {
+    /// Whether the machine's screen is the one that's expected, in whichever
+    /// format it was given in.
+    fn matches(&self, state: &State) -> bool {
+        let expected = self.screen.trim();
+        let buffer = state.buffer();
+        let actual = if expected.starts_with("P1") {
+            buffer.to_pbm()
+        } else {
+            buffer.pretty_print_logical()
+        };
+        actual.trim() == expected
+    }
+}
+
+/// The first instruction after which two runs of the same program (with
+/// different quirks) weren't the same anymore.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// How many instructions ran before it
+    pub cycle: u64,
+    pub address: u16,
+    pub instruction: Instruction,
+}
+
+/// What bisecting found: which quirks have to change for the program to
+/// match the reference (or to stop matching it, if it already did).
+#[derive(Debug, PartialEq)]
+pub struct Bisection {
+    /// The quirks that were started from
+    pub base: Quirks,
+    pub cycles: u64,
+    /// Whether the program matched the reference with the base quirks
+    pub base_matches: bool,
+    /// The smallest sets of quirk changes that make it match (or stop
+    /// matching), in order. Empty if no combination of quirks does.
+    pub minimal: Vec<Vec<QuirkSetting>>,
+    /// Where the run with the base quirks and the run with the first of the
+    /// minimal changes first went different ways
+    pub divergence: Option<Divergence>,
+}
+
+impl Display for Bisection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let verdict = if self.base_matches {
+            "matches"
+        } else {
+            "doesn't match"
+        };
+        writeln!(
+            f,
+            "With {}, the screen {} after {} instructions.",
+            self.base, verdict, self.cycles
+        )?;
+        if self.minimal.is_empty() {
+            let what = if self.base_matches {
+                "stops it matching"
+            } else {
+                "makes it match"
+            };
+            return writeln!(f, "No combination of quirks {}.", what);
+        }
+        let what = if self.base_matches {
+            "It stops matching with"
+        } else {
+            "It matches with"
+        };
+        for (n, changes) in self.minimal.iter().enumerate() {
+            let flags = changes
+                .iter()
+                .map(|setting| format!("--quirk {}", setting))
+                .collect::<Vec<_>>();
+            let lead = if n == 0 { what } else { "  or with" };
+            writeln!(f, "{}: {}", lead, flags.join(" "))?;
+        }
+        if let Some(divergence) = &self.divergence {
+            writeln!(
+                f,
+                "The runs first differ at instruction {}: {} at {:03X}",
+                divergence.cycle, divergence.instruction, divergence.address
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the program with every combination of quirks (starting from `base`),
+/// and find the fewest quirks to change so that its screen matches the
+/// reference, or so that it stops matching if it already does. Then find the
+/// first instruction where the run with those changes goes differently, which
+/// is usually the one that depends on the quirk.
+pub fn bisect(
+    program: &[u8],
+    variant: Variant,
+    base: Quirks,
+    reference: &Reference,
+) -> Result<Bisection, Chip8Error> {
+    let settings = QuirkSetting::all_of(&base);
+    let mut results = vec![];
+    for mask in 0..1 << settings.len() {
+        let changes = settings
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|(_, setting)| setting.flipped())
+            .collect::<Vec<_>>();
+        let state = run(program, variant, with_changes(base, &changes), reference)?;
+        results.push((changes, reference.matches(&state)));
+    }
+
+    let base_matches = results[0].1;
+    let wanted = results
+        .iter()
+        .filter(|(_, matches)| *matches != base_matches)
+        .collect::<Vec<_>>();
+    let fewest = wanted.iter().map(|(changes, _)| changes.len()).min();
+    let minimal = wanted
+        .iter()
+        .filter(|(changes, _)| Some(changes.len()) == fewest)
+        .map(|(changes, _)| changes.clone())
+        .collect::<Vec<_>>();
+    let divergence = match minimal.first() {
+        Some(changes) => first_divergence(
+            program,
+            variant,
+            base,
+            with_changes(base, changes),
+            reference,
+        )?,
+        None => None,
+    };
+    Ok(Bisection {
+        base,
+        cycles: reference.cycles,
+        base_matches,
+        minimal,
+        divergence,
+    })
+}
+
+/// `base`, with the changes applied.
+fn with_changes(base: Quirks, changes: &[QuirkSetting]) -> Quirks {
+    let mut quirks = base;
+    for change in changes {
+        change.apply(&mut quirks);
+    }
+    quirks
+}
+
+/// A fresh machine with the program loaded.
+fn machine(program: &[u8], variant: Variant, quirks: Quirks) -> Result<State, Chip8Error> {
+    Ok(State::with_program_for(variant, program)?.with_quirks(quirks))
+}
+
+/// Run the program for the reference's number of instructions, or until it
+/// crashes, and return the machine at the end.
+fn run(
+    program: &[u8],
+    variant: Variant,
+    quirks: Quirks,
+    reference: &Reference,
+) -> Result<State, Chip8Error> {
+    let mut state = machine(program, variant, quirks)?;
+    let mut rng = StdRng::seed_from_u64(reference.seed);
+    for _ in 0..reference.cycles {
+        if state.step(&mut rng).is_err() {
+            break;
+        }
+    }
+    Ok(state)
+}
+
+/// Run the program with both sets of quirks side by side, and find the first
+/// instruction after which the machines are different. Only the registers, I,
+/// the program counter, and (for instructions that draw) the screen are
+/// compared, since that's where quirks show up.
+fn first_divergence(
+    program: &[u8],
+    variant: Variant,
+    a: Quirks,
+    b: Quirks,
+    reference: &Reference,
+) -> Result<Option<Divergence>, Chip8Error> {
+    let mut state_a = machine(program, variant, a)?;
+    let mut state_b = machine(program, variant, b)?;
+    let mut rng_a = StdRng::seed_from_u64(reference.seed);
+    let mut rng_b = StdRng::seed_from_u64(reference.seed);
+    for cycle in 0..reference.cycles {
+        let outcome = match (state_a.step(&mut rng_a), state_b.step(&mut rng_b)) {
+            (Ok(a), Ok(b)) if a == b => a,
+            (Ok(outcome), _) | (_, Ok(outcome)) => {
+                return Ok(Some(Divergence {
+                    cycle,
+                    address: outcome.pc_before,
+                    instruction: outcome.instruction,
+                }))
+            }
+            // They both crashed the same way
+            (Err(_), Err(_)) => return Ok(None),
+        };
+        let same = state_a.pc() == state_b.pc()
+            && state_a.i() == state_b.i()
+            && state_a.registers() == state_b.registers()
+            && (!outcome.instruction.changes_screen() || state_a.buffer() == state_b.buffer());
+        if !same {
+            return Ok(Some(Divergence {
+                cycle,
+                address: outcome.pc_before,
+                instruction: outcome.instruction,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quirks::Profile;
+
+    // LD V1, 08; LD V2, 02; SHR V1, V2; LD I, large font 0; DRW V1, V0, 1;
+    // JP to itself. Whether SHR shifts V1 or V2 decides where the sprite is.
+    const PROGRAM: [u8; 12] = [
+        0x61, 0x08, 0x62, 0x02, 0x81, 0x26, 0xA0, 0xA0, 0xD1, 0x01, 0x12, 0x0A,
+    ];
+
+    fn reference(quirks: Quirks) -> Reference {
+        let mut reference = Reference {
+            cycles: 100,
+            screen: String::new(),
+            seed: 0,
+        };
+        let state = run(&PROGRAM, Variant::Chip8, quirks, &reference).unwrap();
+        reference.screen = state.buffer().to_pbm();
+        reference
+    }
+
+    #[test]
+    fn finds_the_quirk_that_fixes_it() {
+        let mut good = Profile::Cosmac.quirks();
+        good.shift_uses_vy = false;
+        let bisection = bisect(
+            &PROGRAM,
+            Variant::Chip8,
+            Profile::Cosmac.quirks(),
+            &reference(good),
+        )
+        .unwrap();
+
+        assert!(!bisection.base_matches);
+        assert_eq!(
+            bisection.minimal,
+            vec![vec![QuirkSetting::ShiftUsesVy(false)]]
+        );
+        assert_eq!(
+            bisection.divergence,
+            Some(Divergence {
+                cycle: 2,
+                address: 0x204,
+                instruction: Instruction::SHR(
+                    crate::instruction::Register::from_nibble(1),
+                    crate::instruction::Register::from_nibble(2)
+                ),
+            })
+        );
+        assert!(bisection
+            .to_string()
+            .contains("It matches with: --quirk shift=vx\n"));
+    }
+
+    #[test]
+    fn finds_the_quirks_that_break_it() {
+        let cosmac = Profile::Cosmac.quirks();
+        let bisection = bisect(&PROGRAM, Variant::Chip8, cosmac, &reference(cosmac)).unwrap();
+
+        assert!(bisection.base_matches);
+        assert_eq!(
+            bisection.minimal,
+            vec![vec![QuirkSetting::ShiftUsesVy(false)]]
+        );
+    }
+
+    #[test]
+    fn matches_dumps_too() {
+        let quirks = Profile::Cosmac.quirks();
+        let mut reference = reference(quirks);
+        let state = run(&PROGRAM, Variant::Chip8, quirks, &reference).unwrap();
+        reference.screen = state.buffer().pretty_print_logical();
+        assert!(reference.matches(&state));
+    }
+}
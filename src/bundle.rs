@@ -0,0 +1,159 @@
+use crate::{
+    display::Appearance,
+    error::Chip8Error,
+    interpreter::{State, Variant},
+    quirks::Quirks,
+    timing::Timing,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+};
+
+/// Every bundle starts with this, so that other files (like a ROM passed by
+/// mistake) are rejected instead of misread.
+const MAGIC: &[u8] = b"CHIP8BUNDLE1";
+
+/// Everything needed to repeat someone else's session exactly, in one file:
+/// the program, the machine it ran on, the seed for its random numbers, and
+/// optionally a saved state to resume from. Made with `chip8 bundle`, and run
+/// with `chip8 run --bundle`, to make bug reports easy to reproduce.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bundle {
+    /// The program's file name, so it's clear what's in the bundle
+    pub name: String,
+    pub program: Vec<u8>,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    /// The seed for RND's random numbers
+    pub seed: u64,
+    /// A saved state (see `State::serialize`) to resume from, instead of
+    /// starting the program fresh
+    pub state: Option<Vec<u8>>,
+    /// Notes from whoever made the bundle, like what to look for
+    pub notes: Vec<String>,
+}
+
+impl Bundle {
+    /// Write the bundle to a file.
+    pub fn save(&self, path: &Path) -> Result<(), Chip8Error> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serialize(self)?);
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Read a bundle written by `save`.
+    pub fn load(path: &Path) -> Result<Self, Chip8Error> {
+        let bytes = fs::read(path)?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(Chip8Error::NotABundle);
+        }
+        Ok(bincode::deserialize(&bytes[MAGIC.len()..])?)
+    }
+
+    /// The machine to run: the saved state if there is one, and otherwise a
+    /// fresh machine with the program loaded, drawn with `appearance`.
+    pub fn machine(&self, appearance: &Appearance) -> Result<State, Chip8Error> {
+        match &self.state {
+            Some(state) => State::deserialize(state),
+            None => Ok(State::with_program_for(self.variant, &self.program)?
+                .with_quirks(self.quirks)
+                .with_timing(self.timing)
+                .with_appearance(appearance)),
+        }
+    }
+}
+
+/// What's in the bundle, to show before running it.
+impl Display for Bundle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Program: {} ({} bytes)", self.name, self.program.len())?;
+        writeln!(f, "Variant: {}", self.variant)?;
+        writeln!(f, "Quirks: {}", self.quirks)?;
+        writeln!(f, "Timing: {}", self.timing)?;
+        writeln!(f, "Seed: {}", self.seed)?;
+        if self.state.is_some() {
+            writeln!(f, "Resumes from a saved state")?;
+        }
+        for note in &self.notes {
+            writeln!(f, "Note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quirks::Profile;
+
+    fn bundle() -> Bundle {
+        Bundle {
+            name: "add.ch8".to_string(),
+            // ADD V0, 01 over and over
+            program: [0x70, 0x01].repeat(4),
+            variant: Variant::Chip8,
+            quirks: Profile::Schip.quirks(),
+            timing: Timing::Vip,
+            seed: 1234,
+            state: None,
+            notes: vec!["V0 should count up".to_string()],
+        }
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let path = std::env::temp_dir().join(format!("chip8-bundle-{}", std::process::id()));
+        let bundle = bundle();
+        bundle.save(&path).unwrap();
+        assert_eq!(Bundle::load(&path).unwrap(), bundle);
+
+        fs::write(&path, &bundle.program).unwrap();
+        assert!(matches!(Bundle::load(&path), Err(Chip8Error::NotABundle)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resumes_from_the_saved_state() {
+        let mut bundle = bundle();
+        let mut state = bundle.machine(&Appearance::default()).unwrap();
+        assert_eq!(
+            state,
+            State::with_program(&bundle.program)
+                .unwrap()
+                .with_quirks(bundle.quirks)
+                .with_timing(Timing::Vip)
+        );
+
+        crate::interpreter::tick(&mut state, rand::thread_rng()).unwrap();
+        bundle.state = Some(state.serialize().unwrap());
+        let resumed = bundle.machine(&Appearance::default()).unwrap();
+        assert_eq!(resumed.registers()[0], 1);
+        assert_eq!(
+            bundle.to_string(),
+            "Program: add.ch8 (8 bytes)\n\
+             Variant: chip-8\n\
+             Quirks: shift=vx load-store=unchanged jump=vx draw=clip display=immediate\n\
+             Timing: vip\n\
+             Seed: 1234\n\
+             Resumes from a saved state\n\
+             Note: V0 should count up\n"
+        );
+    }
+
+    #[test]
+    fn corrupt_saved_states_are_an_error() {
+        let bundle = Bundle {
+            state: Some(crate::interpreter::corrupt_snapshot()),
+            ..bundle()
+        };
+        assert!(matches!(
+            bundle.machine(&Appearance::default()),
+            Err(Chip8Error::CorruptState(_))
+        ));
+    }
+}
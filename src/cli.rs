@@ -1,5 +1,21 @@
+use crate::{
+    config::Config,
+    disasm,
+    display::Appearance,
+    frontend::{Shader, WindowBackend},
+    interpreter::Variant,
+    keymap::Keymap,
+    memory::{self, parse_address},
+    opcodes,
+    quirks::{Profile, QuirkSetting, Quirks},
+    romdb::RomInfo,
+    timing::Timing,
+};
 use clap_verbosity_flag::Verbosity;
-use std::path::PathBuf;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
@@ -15,25 +31,729 @@ pub struct Arguments {
 
 #[derive(StructOpt)]
 pub enum Subcommand {
-    #[structopt(about = "Print instructions in this file")]
+    #[structopt(about = "Disassemble this file, with labels for jump targets")]
     Print {
-        #[structopt(parse(from_os_str))]
-        input_file_path: PathBuf,
+        #[structopt(parse(from_os_str), required_unless = "all")]
+        input_file_path: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with = "input-file-path",
+            help = "Disassemble every ROM in this directory into its own listing file"
+        )]
+        all: Option<PathBuf>,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            default_value = "listings",
+            help = "With --all, the directory to write the listings and an index.txt to"
+        )]
+        output: PathBuf,
+        #[structopt(
+            long,
+            default_value = "text",
+            conflicts_with = "all",
+            help = "Print a listing (text), or one record per instruction as JSON lines (json) or CSV (csv)"
+        )]
+        format: disasm::Format,
+        #[structopt(
+            long,
+            use_delimiter = true,
+            parse(try_from_str = parse_range),
+            conflicts_with = "all",
+            help = "Show these address ranges, like 0x300-0x340 (not including the end), as data instead of code, separated by commas"
+        )]
+        data_ranges: Vec<Range<u16>>,
+        #[structopt(
+            long,
+            default_value = "0x200",
+            parse(try_from_str = parse_load_address),
+            help = "Load the program at this address like 0x600 for the ETI-660"
+        )]
+        load_address: u16,
     },
     #[structopt(about = "Trace the execution flow")]
     Trace {
         #[structopt(parse(from_os_str))]
         input_file_path: PathBuf,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Also write a line of JSON for every instruction that runs to this file"
+        )]
+        trace_file: Option<PathBuf>,
+        #[structopt(
+            long,
+            value_name = "N",
+            help = "Print the registers, timers, stack, and a hash of the screen as a line of JSON after every N instructions"
+        )]
+        dump_state_every: Option<u64>,
+        #[structopt(
+            long,
+            default_value = "0x200",
+            parse(try_from_str = parse_load_address),
+            help = "Load the program at this address and start running it there, like 0x600 for the ETI-660"
+        )]
+        load_address: u16,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+        #[structopt(flatten)]
+        save_states: SaveStateOptions,
     },
     #[structopt(about = "Run a program")]
     Run {
+        #[structopt(parse(from_os_str), required_unless = "bundle")]
+        input_file_path: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with_all = &["input-file-path", "load-state", "seed"],
+            help = "Run a bundle made by `chip8 bundle` instead, to repeat someone else's session"
+        )]
+        bundle: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Write a line of JSON for every instruction that runs to this file"
+        )]
+        trace_file: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Record the keypad (and the seed) to this file, to replay the run later"
+        )]
+        record: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with_all = &["record", "seed"],
+            help = "Play back the keypad (and the seed) from a file made with --record, instead of reading the keyboard"
+        )]
+        replay: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "Wait for a debugger to connect to this port on localhost, and let it control the program with the same commands as `chip8 debug`"
+        )]
+        debug_port: Option<u16>,
+        #[structopt(
+            long,
+            conflicts_with = "bundle",
+            default_value = "0x200",
+            parse(try_from_str = parse_load_address),
+            help = "Load the program at this address and start running it there, like 0x600 for the ETI-660"
+        )]
+        load_address: u16,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+        #[structopt(flatten)]
+        save_states: SaveStateOptions,
+        #[structopt(flatten)]
+        headless: HeadlessOptions,
+    },
+    #[structopt(
+        about = "Package a program with its machine, seed, and saved state into one file, to share a session"
+    )]
+    Bundle {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "Where to write the bundle [default: the program's path with a .bundle extension]"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(
+            long = "note",
+            number_of_values = 1,
+            help = "A note to include, like what to look for (can be repeated)"
+        )]
+        notes: Vec<String>,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+        #[structopt(flatten)]
+        save_states: SaveStateOptions,
+    },
+    #[structopt(about = "Run a program without a window and show where it spends its time")]
+    Profile {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(long, default_value = "1000000", help = "How many cycles to run")]
+        cycles: u64,
+        #[structopt(
+            long,
+            default_value = "10",
+            help = "How many of the addresses that ran the most to show"
+        )]
+        top: usize,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+    },
+    #[structopt(about = "Compare what a program does under each machine profile")]
+    Compare {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            long,
+            default_value = "1000",
+            help = "How many instructions to run under each profile"
+        )]
+        cycles: usize,
+    },
+    #[structopt(
+        about = "Find which quirks have to change for a program's screen to match (or stop matching) a known-good one"
+    )]
+    Bisect {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "The screen it should show: a PBM screenshot (from F2 or --screenshot-at-cycle) or a --dump-display file"
+        )]
+        reference: PathBuf,
+        #[structopt(
+            long,
+            help = "How many instructions to run before comparing (the same as the reference was taken after)"
+        )]
+        cycles: u64,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+    },
+    #[structopt(
+        about = "Run a program and compare its state after each instruction against another emulator's trace"
+    )]
+    Difftest {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "The states to expect, as JSON lines like `trace --dump-state-every` prints"
+        )]
+        reference: PathBuf,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+    },
+    #[structopt(
+        about = "Check that beeps and what's on screen stay in sync, with a built-in test program"
+    )]
+    AvSync {
+        #[structopt(
+            long,
+            default_value = "cosmac",
+            help = "The machine to test: cosmac, schip, or xo-chip"
+        )]
+        profile: Profile,
+        #[structopt(long, default_value = "600", help = "How many frames to run")]
+        frames: u64,
+    },
+    #[structopt(about = "Assemble a program into a ROM")]
+    Assemble {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "Where to write the ROM [default: the input's path with a .ch8 extension]"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "Only allow instructions this machine has: cosmac, schip, or xo-chip (like `.target` in the source)"
+        )]
+        target: Option<Profile>,
+        #[structopt(
+            long,
+            help = "Leave out labeled code and data that nothing uses (mark a label with `.keep` on the line before it to keep it anyway)"
+        )]
+        strip_dead_code: bool,
+        #[structopt(long, help = "Print how many bytes each section takes up")]
+        size_report: bool,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Also write a listing of every line's address and bytes, and the symbol table, to this file"
+        )]
+        listing: Option<PathBuf>,
+    },
+    #[structopt(about = "Compile a program written in Octo (.8o) into a ROM")]
+    Build {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "Where to write the ROM [default: the input's path with a .ch8 extension]"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(about = "Decompile a ROM into Octo source that builds back into it")]
+    Decompile {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "Where to write the source [default: the input's path with a .8o extension]"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(about = "Show a ROM as it's loaded into memory, in hex and ASCII")]
+    Hexdump {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            long,
+            default_value = "0x200",
+            parse(try_from_str = parse_address),
+            help = "The address to start at, in hex (the program is loaded at 0x200)"
+        )]
+        start: u16,
+        #[structopt(
+            long,
+            default_value = "16",
+            parse(try_from_str = parse_width),
+            help = "How many bytes to show on each line"
+        )]
+        width: usize,
+    },
+    #[structopt(about = "Draw a ROM's sprites as text, to check them without running it")]
+    Sprites {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+        #[structopt(
+            long,
+            parse(try_from_str = parse_address),
+            help = "The address of a sprite to draw, in hex. Without this, draw every sprite that the program draws with LD I, nnn and then DRW"
+        )]
+        at: Option<u16>,
+        #[structopt(
+            long,
+            requires = "at",
+            help = "How many bytes (rows) the sprite at --at has [default: 15]"
+        )]
+        len: Option<usize>,
+    },
+    #[structopt(about = "Pick a ROM to run from a directory of them")]
+    Browse {
+        #[structopt(parse(from_os_str))]
+        directory: PathBuf,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+    },
+    #[structopt(about = "Look for problems in a ROM without running it")]
+    Check {
+        #[structopt(parse(from_os_str))]
+        input_file_path: PathBuf,
+    },
+    #[structopt(
+        about = "Assemble the source and compare it with a ROM, to see how close it is to rebuilding it"
+    )]
+    Verify {
+        #[structopt(parse(from_os_str))]
+        source_file_path: PathBuf,
+        #[structopt(parse(from_os_str))]
+        rom_file_path: PathBuf,
+    },
+    #[structopt(about = "List the exit codes and what they mean")]
+    ExitCodes,
+    #[structopt(about = "List every opcode chip8 knows, with what it does")]
+    Opcodes {
+        #[structopt(
+            long,
+            default_value = "md",
+            help = "How to print them: md (a Markdown table) or json (one object per line)"
+        )]
+        format: opcodes::Format,
+    },
+    #[structopt(about = "Manage the config file of preferences, like scale and colors")]
+    Config {
+        #[structopt(subcommand)]
+        command: ConfigCommand,
+    },
+    #[structopt(
+        about = "Check that chip8 can open a window, play sound, and keep time on this machine"
+    )]
+    Doctor,
+    #[structopt(about = "Run a program one step at a time in a debugger")]
+    Debug {
         #[structopt(parse(from_os_str))]
         input_file_path: PathBuf,
+        #[structopt(flatten)]
+        machine: MachineOptions,
+        #[structopt(flatten)]
+        save_states: SaveStateOptions,
     },
 }
 
-pub fn install_logger(verbose: &mut Verbosity) {
-    verbose.set_default(Some(log::Level::Warn));
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    #[structopt(
+        about = "Write a config file with every setting commented out, to ~/.config/chip8/config.toml"
+    )]
+    Init {
+        #[structopt(long, help = "Replace the config file if there already is one")]
+        force: bool,
+    },
+}
+
+impl Subcommand {
+    /// Whether this runs a program without a window.
+    pub fn is_headless(&self) -> bool {
+        match self {
+            Subcommand::Run { headless, .. } => headless.is_headless(),
+            _ => false,
+        }
+    }
+
+    /// The options for the machine to emulate, for subcommands that run a
+    /// program, so that `main` can fill them in from the config file. `browse`
+    /// does that itself, after each ROM's detected profile.
+    pub fn machine_mut(&mut self) -> Option<&mut MachineOptions> {
+        match self {
+            Subcommand::Trace { machine, .. }
+            | Subcommand::Run { machine, .. }
+            | Subcommand::Bundle { machine, .. }
+            | Subcommand::Profile { machine, .. }
+            | Subcommand::Bisect { machine, .. }
+            | Subcommand::Difftest { machine, .. }
+            | Subcommand::Debug { machine, .. } => Some(machine),
+            _ => None,
+        }
+    }
+}
+
+/// Options for saving the whole machine to disk and resuming it later.
+#[derive(Default, StructOpt)]
+pub struct SaveStateOptions {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Where F5 saves the state and F9 loads it from [default: the program's path with a .state extension]"
+    )]
+    save_state: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Resume from a saved state instead of starting the program fresh"
+    )]
+    pub load_state: Option<PathBuf>,
+}
+
+impl SaveStateOptions {
+    /// Where to save the state when F5 is pressed, and load it from on F9.
+    pub fn save_state_path(&self, input_file_path: &Path) -> PathBuf {
+        match &self.save_state {
+            Some(path) => path.clone(),
+            None => input_file_path.with_extension("state"),
+        }
+    }
+}
+
+/// Options for running without a window, for scripts and tests.
+#[derive(StructOpt)]
+pub struct HeadlessOptions {
+    #[structopt(
+        long,
+        help = "Don't open a window; stop when the program jumps to itself, and print the screen and registers"
+    )]
+    headless: bool,
+    #[structopt(long, help = "Stop after running this many instructions")]
+    pub max_cycles: Option<u64>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "After stopping, write the screen to this file as text (# for on, . for off)"
+    )]
+    pub dump_display: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Save a screenshot after running this many instructions, to the program's path with a .pbm extension"
+    )]
+    pub screenshot_at_cycle: Option<u64>,
+}
+
+impl HeadlessOptions {
+    /// Whether to run without a window. Without the `window` feature, there
+    /// never is one.
+    pub fn is_headless(&self) -> bool {
+        self.headless || !cfg!(feature = "window")
+    }
+}
+
+/// Options that pick which kind of CHIP-8 machine to emulate.
+#[derive(Clone, StructOpt)]
+pub struct MachineOptions {
+    #[structopt(
+        long,
+        help = "Which dialect to interpret: chip-8 or xo-chip [default: chip-8]"
+    )]
+    variant: Option<Variant>,
+    #[structopt(
+        long,
+        help = "Use the quirks (and dialect) of a machine: cosmac, schip, or xo-chip"
+    )]
+    profile: Option<Profile>,
+    #[structopt(
+        long = "quirk",
+        number_of_values = 1,
+        help = "Override a single quirk, like shift=vy or load-store=increment (can be repeated)"
+    )]
+    quirks: Vec<QuirkSetting>,
+    #[structopt(
+        long,
+        default_value = "flat",
+        help = "How long a frame is: flat (the same number of instructions every frame) or vip (as many as the COSMAC VIP could run in a frame, depending on what they are)"
+    )]
+    pub timing: Timing,
+    #[structopt(
+        long,
+        help = "Skip instructions this emulator doesn't know instead of stopping to ask"
+    )]
+    pub ignore_unknown: bool,
+    #[structopt(
+        long,
+        default_value = "600",
+        help = "Log progress (frame count, speed, and a hash of the screen) every this many frames, or never if 0"
+    )]
+    pub progress_every: u64,
+    #[structopt(
+        long,
+        help = "Seed the random numbers for RND, to repeat a run exactly [default: a new random seed, which is printed]"
+    )]
+    pub seed: Option<u64>,
+    #[structopt(
+        long,
+        default_value = "minifb",
+        help = "Which library opens the window: minifb, sdl2 (with sound and vsync, if chip8 was built with the sdl2-frontend feature), terminal (if built with the terminal feature), or wgpu (with shaders, if built with the wgpu-frontend feature)"
+    )]
+    pub frontend: WindowBackend,
+    #[structopt(
+        long,
+        default_value = "none",
+        help = "With --frontend wgpu, draw the screen through a shader: none, or crt (curved, with scanlines and glow). F4 turns it on and off"
+    )]
+    pub shader: Shader,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_scale),
+        help = "Draw every CHIP-8 pixel this many pixels across and down (an even number) [default: 10]"
+    )]
+    scale: Option<usize>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_color),
+        help = "The color of pixels that are on, as hex RRGGBB [default: FFFFFF]"
+    )]
+    fg_color: Option<u32>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_color),
+        help = "The color of pixels that are off, as hex RRGGBB [default: 000000]"
+    )]
+    bg_color: Option<u32>,
+    #[structopt(
+        long,
+        help = "Run about this many instructions a second, with --timing flat [default: 600]"
+    )]
+    pub clock_hz: Option<u32>,
+    #[structopt(
+        long,
+        help = "Let the window be resized, scaling the screen up as many whole times as fit, with black bars around it"
+    )]
+    pub resizable: bool,
+    #[structopt(
+        long,
+        help = "Fill the whole screen, scaling the CHIP-8's screen up as many whole times as fit (with --frontend sdl2 or wgpu; minifb's window is just resizable)"
+    )]
+    pub fullscreen: bool,
+    #[structopt(
+        long,
+        help = "Blend away flashing that's faster than 3 times a second, for people who are sensitive to it"
+    )]
+    pub reduce_flashing: bool,
+    #[structopt(
+        long,
+        value_name = "DECAY",
+        parse(try_from_str = parse_decay),
+        help = "Fade out pixels that turn off instead of switching them off at once, like a CRT's phosphor, losing this much of their glow every frame (more than 0, up to 1), like 0.5"
+    )]
+    pub phosphor: Option<f32>,
+    #[structopt(
+        long,
+        default_value = "300",
+        help = "Pause when the screen hasn't changed and no key has been pressed for this many seconds, until a key is pressed, or never if 0"
+    )]
+    pub idle_pause: u64,
+    #[structopt(
+        long,
+        help = "Bind the keypad's keys (0 to F) to other letters or digits, like 5=I,8=K (the rest stay where they are)"
+    )]
+    pub keymap: Option<Keymap>,
+}
+
+impl MachineOptions {
+    /// Fill in anything that wasn't given on the command line from the config
+    /// file. The config file's profile only applies if neither `--profile`
+    /// nor `--variant` was given, since either one picks the dialect.
+    pub fn apply_config(&mut self, config: &Config) {
+        self.scale = self.scale.or(config.scale);
+        self.fg_color = self.fg_color.or(config.foreground);
+        self.bg_color = self.bg_color.or(config.background);
+        self.clock_hz = self.clock_hz.or(config.clock_hz);
+        if self.profile.is_none() && self.variant.is_none() {
+            self.profile = config.profile;
+        }
+        self.keymap = self.keymap.or(config.keymap);
+    }
+
+    /// Fill in anything that wasn't given on the command line from what the
+    /// ROM database knows about the ROM. Call this before `apply_config`, so
+    /// that the database wins over the config file.
+    pub fn apply_rom_info(&mut self, info: &RomInfo) {
+        if let Some(profile) = info.profile {
+            self.default_profile(profile);
+        }
+        self.clock_hz = self.clock_hz.or(info.clock_hz);
+        self.keymap = self.keymap.or(info.keymap);
+    }
+
+    /// Use `profile` unless `--profile` or `--variant` already picked the
+    /// dialect. Call this before `apply_config`, so it wins over the config
+    /// file's profile.
+    pub fn default_profile(&mut self, profile: Profile) {
+        if self.profile.is_none() && self.variant.is_none() {
+            self.profile = Some(profile);
+        }
+    }
+
+    /// The dialect to interpret. A profile overrides `--variant`.
+    pub fn variant(&self) -> Variant {
+        match self.profile {
+            Some(profile) => profile.variant(),
+            None => self.variant.unwrap_or(Variant::Chip8),
+        }
+    }
+
+    /// The seed for RND's random numbers. Without `--seed`, this picks a new
+    /// one and prints it, so that a run that did something interesting can be
+    /// repeated.
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| {
+            let seed = rand::random();
+            eprintln!(
+                "Random seed: {} (pass --seed {} to repeat this run)",
+                seed, seed
+            );
+            seed
+        })
+    }
+
+    /// How big to draw the screen, and in which colors.
+    pub fn appearance(&self) -> Appearance {
+        let default = Appearance::default();
+        Appearance {
+            scale: self.scale.unwrap_or(default.scale),
+            foreground: self.fg_color.unwrap_or(default.foreground),
+            background: self.bg_color.unwrap_or(default.background),
+        }
+    }
+
+    /// The profile's quirks (or the COSMAC VIP's), with any `--quirk`
+    /// overrides applied in order.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.profile.unwrap_or(Profile::Cosmac).quirks();
+        for setting in &self.quirks {
+            setting.apply(&mut quirks);
+        }
+        quirks
+    }
+}
+
+/// The scale has to be even, so that SCHIP's high-resolution pixels (which are
+/// half as big) are still a whole number of pixels.
+pub fn parse_scale(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(scale) if scale > 0 && scale % 2 == 0 => Ok(scale),
+        _ => Err(format!(
+            "The scale must be an even number, like 10, not {}",
+            s
+        )),
+    }
+}
+
+/// How much of its glow a pixel loses every frame with `--phosphor`: more than
+/// 0, since then it would never fade, and at most 1, which is all at once.
+fn parse_decay(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(decay) if decay > 0.0 && decay <= 1.0 => Ok(decay),
+        _ => Err(format!(
+            "The decay must be more than 0 and at most 1, like 0.5, not {}",
+            s
+        )),
+    }
+}
+
+/// A range of addresses like `0x300-0x340`, which doesn't include the end.
+fn parse_range(s: &str) -> Result<Range<u16>, String> {
+    let dash = s
+        .find('-')
+        .ok_or_else(|| format!("Expected a range like 0x300-0x340, got {}", s))?;
+    let (start, end) = (parse_address(&s[..dash])?, parse_address(&s[dash + 1..])?);
+    if start >= end {
+        return Err(format!("The range {} doesn't have anything in it", s));
+    }
+    Ok(start..end)
+}
+
+/// Programs can't be loaded over the interpreter area, where the fonts are.
+fn parse_load_address(s: &str) -> Result<u16, String> {
+    let address = parse_address(s)?;
+    if (memory::PROGRAM_START..memory::MEMORY_SIZE).contains(&(address as usize)) {
+        Ok(address)
+    } else {
+        Err(format!(
+            "The load address must be from 0x200 to 0xFFF, not {}",
+            s
+        ))
+    }
+}
+
+/// A hexdump needs at least one byte on every line.
+fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(format!("The width must be a number above 0, not {}", s)),
+    }
+}
+
+/// A color as hex RRGGBB, like `FFB000`, with or without a `#`.
+pub fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(u32::from_str_radix(hex, 16).unwrap())
+    } else {
+        Err(format!(
+            "Expected a color as hex RRGGBB, like FFB000, not {}",
+            s
+        ))
+    }
+}
+
+pub fn install_logger(verbose: &mut Verbosity, headless: bool) {
+    // Without a window there's no other sign that a program is still running,
+    // so show the progress logs unless asked to be quiet
+    let default_level = if headless {
+        log::Level::Info
+    } else {
+        log::Level::Warn
+    };
+    verbose.set_default(Some(default_level));
     let level_filter = verbose.log_level().map(|l| l.to_level_filter());
     let mut logger = env_logger::Builder::new();
     logger.filter(None, level_filter.unwrap_or(log::LevelFilter::Warn));
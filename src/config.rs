@@ -0,0 +1,271 @@
+use crate::{cli, error::Chip8Error, keymap::Keymap, quirks::Profile};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A commented config file with every setting, for `chip8 config init`. Each
+/// setting is commented out, so it changes nothing until it's uncommented.
+pub const DEFAULT_CONFIG: &str = r#"# Settings for chip8. Anything given on the command line wins over these.
+
+# How many pixels across and down every CHIP-8 pixel is (an even number), like
+# --scale
+# scale = 10
+
+# The colors of pixels that are on and off, as hex RRGGBB, like --fg-color and
+# --bg-color
+# foreground = "FFFFFF"
+# background = "000000"
+
+# Use the quirks (and dialect) of a machine: cosmac, schip, or xo-chip, like
+# --profile. This applies unless --profile or --variant is given.
+# profile = "cosmac"
+
+# Run about this many instructions a second, like --clock-hz
+# clock_hz = 600
+
+# Bind the keypad's keys (0 to F) to other letters or digits, like --keymap.
+# The ones that aren't listed stay where they are:
+#
+#     1 2 3 C        1 2 3 4
+#     4 5 6 D   ->   Q W E R
+#     7 8 9 E        A S D F
+#     A 0 B F        Z X C V
+#
+# [keys]
+# 5 = "I"
+# 8 = "K"
+"#;
+
+/// Preferences from the config file, each of which the matching command-line
+/// flag overrides (see `MachineOptions::apply_config`). Anything the file
+/// doesn't set is None.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub scale: Option<usize>,
+    pub foreground: Option<u32>,
+    pub background: Option<u32>,
+    pub profile: Option<Profile>,
+    pub clock_hz: Option<u32>,
+    pub keymap: Option<Keymap>,
+}
+
+impl Config {
+    /// Where the config file is: `$XDG_CONFIG_HOME/chip8/config.toml`, or
+    /// `~/.config/chip8/config.toml` without `XDG_CONFIG_HOME`.
+    pub fn path() -> Option<PathBuf> {
+        Some(directory()?.join("config.toml"))
+    }
+
+    /// Read the config file, if there is one. No file is the same as an empty
+    /// one.
+    pub fn load() -> Result<Self, Chip8Error> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> Result<Self, Chip8Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|(line, message)| Chip8Error::Config {
+                path: path.display().to_string(),
+                line,
+                message,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parse the simple kind of TOML that the config file uses: `key = value`
+    /// lines, where a value is a number or a quoted string, `#` comments, and
+    /// a `[keys]` section for key bindings.
+    /// Fails with the line number and what's wrong with it, including for
+    /// settings it doesn't know, so that typos don't go unnoticed.
+    pub fn parse(text: &str) -> Result<Self, (usize, String)> {
+        let mut config = Self::default();
+        // The line that `[keys]` is on, once it's started
+        let mut keys_line = None;
+        let mut keymap = Keymap::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                if trimmed != "[keys]" || keys_line.is_some() {
+                    return Err((line_number, format!("Unexpected section {}", trimmed)));
+                }
+                keys_line = Some(line_number);
+                continue;
+            }
+            let (key, value) = match parse_line(line).map_err(|e| (line_number, e))? {
+                Some(setting) => setting,
+                None => continue,
+            };
+            match keys_line {
+                Some(_) => keymap.bind(key, value),
+                None => config.set(key, value),
+            }
+            .map_err(|message| (line_number, message))?;
+        }
+        // Only check the bindings once they're all in, so that two keys can
+        // be swapped
+        if let Some(line_number) = keys_line {
+            keymap
+                .validate()
+                .map_err(|message| (line_number, message))?;
+            config.keymap = Some(keymap);
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "scale" => self.scale = Some(cli::parse_scale(value)?),
+            "foreground" => self.foreground = Some(cli::parse_color(value)?),
+            "background" => self.background = Some(cli::parse_color(value)?),
+            "profile" => self.profile = Some(value.parse()?),
+            "clock_hz" => {
+                self.clock_hz = Some(value.parse().map_err(|_| {
+                    format!(
+                        "clock_hz must be a number of instructions a second, not {}",
+                        value
+                    )
+                })?)
+            }
+            _ => return Err(format!("Unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// The directory that chip8's files go in: `$XDG_CONFIG_HOME/chip8`, or
+/// `~/.config/chip8` without `XDG_CONFIG_HOME`.
+pub fn directory() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("chip8"))
+}
+
+/// The key and value on a line, without quotes around the value, or None for
+/// a blank or comment line.
+pub fn parse_line(line: &str) -> Result<Option<(&str, &str)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let equals = line
+        .find('=')
+        .ok_or_else(|| format!("Expected `setting = value`, got `{}`", line))?;
+    let key = line[..equals].trim();
+    let rest = line[equals + 1..].trim();
+    let (value, after) = if let Some(quoted) = rest.strip_prefix('"') {
+        let end = quoted
+            .find('"')
+            .ok_or_else(|| format!("The value for `{}` is missing its closing quote", key))?;
+        (&quoted[..end], quoted[end + 1..].trim())
+    } else {
+        match rest.find('#') {
+            Some(comment) => (rest[..comment].trim(), ""),
+            None => (rest, ""),
+        }
+    };
+    if !after.is_empty() && !after.starts_with('#') {
+        return Err(format!(
+            "Unexpected `{}` after the value for `{}`",
+            after, key
+        ));
+    }
+    if key.is_empty() || value.is_empty() {
+        return Err(format!("Expected `setting = value`, got `{}`", line));
+    }
+    Ok(Some((key, value)))
+}
+
+/// Write `DEFAULT_CONFIG` to the config file, unless there already is one
+/// (or `force`). Returns where it went.
+pub fn init(force: bool) -> Result<PathBuf, Chip8Error> {
+    let path = Config::path().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither XDG_CONFIG_HOME nor HOME is set, so there's nowhere to put the config file",
+        )
+    })?;
+    if path.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists (pass --force to replace it)",
+                path.display()
+            ),
+        )
+        .into());
+    }
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    fs::write(&path, DEFAULT_CONFIG)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_settings_and_comments() {
+        let config = Config::parse(
+            "# Big and amber\nscale = 20  # twice as big\nforeground = \"#FFB000\"\n\nprofile = \"schip\"\nclock_hz = 1200\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                scale: Some(20),
+                foreground: Some(0xFFB000),
+                background: None,
+                profile: Some(Profile::Schip),
+                clock_hz: Some(1200),
+                keymap: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_key_bindings() {
+        let config = Config::parse("scale = 20\n\n[keys]\n5 = \"S\"\n8 = \"W\"\n").unwrap();
+        assert_eq!(config.keymap, Some("5=S,8=W".parse().unwrap()));
+
+        assert_eq!(
+            Config::parse("[keys]\n6 = \"W\"\n"),
+            Err((1, "W is bound to both 5 and 6".to_string()))
+        );
+        assert!(matches!(Config::parse("[colors]\n"), Err((1, _))));
+    }
+
+    #[test]
+    fn the_default_config_changes_nothing() {
+        assert_eq!(Config::parse(DEFAULT_CONFIG).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn mistakes_say_which_line() {
+        assert_eq!(
+            Config::parse("scale = 10\nscael = 10\n"),
+            Err((2, "Unknown setting `scael`".to_string()))
+        );
+        assert!(matches!(Config::parse("scale = 11"), Err((1, _))));
+        assert!(matches!(
+            Config::parse("foreground = \"FFFFFF"),
+            Err((1, _))
+        ));
+    }
+
+    #[test]
+    fn a_missing_file_is_empty() {
+        let path = env::temp_dir().join(format!("chip8-no-config-{}.toml", std::process::id()));
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+    }
+}
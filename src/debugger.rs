@@ -0,0 +1,1007 @@
+use crate::{
+    error::Chip8Error,
+    expression,
+    instruction::{Instruction, Register},
+    interpreter::{CallEvent, Microstate, State, StepOutcome},
+    memory::{parse_address, MemoryRegion},
+    opcodes,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Formatter},
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    str::FromStr,
+};
+
+const HELP: &str = "Commands:
+  step (s)             Run one instruction
+  stepback (sb)        Undo the last instruction that ran
+  next (n)             Run one instruction, or a whole subroutine if it's a CALL
+  finish (f)           Run until the current subroutine returns
+  continue (c)         Run until a breakpoint or watched register changes
+  break (b) ADDRESS    Pause when the program counter reaches ADDRESS
+  break (b) ADDRESS if CONDITION
+                       Pause there only if CONDITION holds, like V3 == 1F.
+                       It compares two expressions (with == != < <= > >=)
+                       that can use V0 - VF, I, PC, SP, DT, and ST
+  break-on (bo) NAME   Pause before every instruction named NAME, like DRW
+  regs (r)             Show the registers
+  backtrace (bt)       Show the subroutine calls that led here
+  info (i)             Show a summary of the registers, stack, and screen
+  mem (m) ADDRESS [N]  Show N bytes of memory starting at ADDRESS (default 16),
+                       marking where PC and I point
+  disasm (d) [N]       Show the next N instructions (default 8)
+  watch (w) Vx         Pause when the value in Vx changes, and say which
+                       instruction changed it
+  watch (w) I          Pause when I changes
+  watch (w) mem ADDRESS
+                       Pause when the byte at ADDRESS changes
+  quit (q)             Stop the program
+  help (h)             Show this help";
+
+/// A command typed at the debugger prompt.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Step,
+    StepBack,
+    Next,
+    Finish,
+    Continue,
+    Break(u16, Option<Condition>),
+    BreakOn(String),
+    Registers,
+    Backtrace,
+    Info,
+    Memory(u16, usize),
+    Disassemble(usize),
+    Watch(Watched),
+    Quit,
+    Help,
+}
+
+/// Something the debugger can watch for changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watched {
+    Register(Register),
+    I,
+    /// The byte at this address
+    Memory(u16),
+}
+
+impl Watched {
+    /// The value it has now. Memory that's out of bounds counts as 0.
+    fn value(self, state: &State) -> u16 {
+        match self {
+            Watched::Register(register) => u16::from(state.registers()[register.index()]),
+            Watched::I => state.i(),
+            Watched::Memory(address) => state
+                .memory()
+                .get(address as usize)
+                .copied()
+                .map(u16::from)
+                .unwrap_or(0),
+        }
+    }
+
+    /// A value of this, as hex: I is 16 bits, and the rest are bytes.
+    fn format_value(self, value: u16) -> String {
+        match self {
+            Watched::I => format!("{:04X}", value),
+            Watched::Register(_) | Watched::Memory(_) => format!("{:02X}", value),
+        }
+    }
+}
+
+impl fmt::Display for Watched {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Watched::Register(register) => write!(f, "V{:X}", register.value()),
+            Watched::I => write!(f, "I"),
+            Watched::Memory(address) => write!(f, "[{:03X}]", address),
+        }
+    }
+}
+
+/// A name that a breakpoint's condition can use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variable {
+    Register(Register),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Variable {
+    fn value(self, state: &State) -> u16 {
+        match self {
+            Variable::Register(register) => u16::from(state.registers()[register.index()]),
+            Variable::I => state.i(),
+            Variable::Pc => state.pc(),
+            Variable::Sp => u16::from(state.sp()),
+            Variable::DelayTimer => u16::from(state.delay_timer()),
+            Variable::SoundTimer => u16::from(state.sound_timer()),
+        }
+    }
+}
+
+impl FromStr for Variable {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Ok(Variable::I),
+            "PC" => Ok(Variable::Pc),
+            "SP" => Ok(Variable::Sp),
+            "DT" => Ok(Variable::DelayTimer),
+            "ST" => Ok(Variable::SoundTimer),
+            _ => name.parse().map(Variable::Register).map_err(|_| {
+                format!(
+                    "Unknown name `{}` (try V0 - VF, I, PC, SP, DT, or ST)",
+                    name
+                )
+            }),
+        }
+    }
+}
+
+/// How a condition compares its two sides.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Less,
+    Greater,
+}
+
+impl Comparison {
+    /// Every comparison, with the two-character ones first, so that `<=`
+    /// isn't mistaken for `<`.
+    const ALL: [Comparison; 6] = [
+        Comparison::Equal,
+        Comparison::NotEqual,
+        Comparison::LessOrEqual,
+        Comparison::GreaterOrEqual,
+        Comparison::Less,
+        Comparison::Greater,
+    ];
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::LessOrEqual => "<=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::Greater => ">",
+        }
+    }
+
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Less => left < right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+/// What has to hold for a breakpoint to pause, like `V3 == 1F`. Each side is
+/// an expression, like in the assembler (see `expression::evaluate`), so
+/// numbers are hex, and it can use the registers, I, PC, SP, DT (the delay
+/// timer), and ST (the sound timer).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    left: String,
+    comparison: Comparison,
+    right: String,
+}
+
+impl Condition {
+    /// Whether it holds right now. Fails if one side can't be worked out,
+    /// like when it divides by a register that's 0.
+    fn holds(&self, state: &State) -> Result<bool, String> {
+        let variable = |name: &str| -> Result<f64, String> {
+            Ok(f64::from(name.parse::<Variable>()?.value(state)))
+        };
+        let left = expression::evaluate(&self.left, &variable)?;
+        let right = expression::evaluate(&self.right, &variable)?;
+        Ok(self.comparison.holds(left, right))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (comparison, at) = Comparison::ALL
+            .iter()
+            .find_map(|comparison| Some((*comparison, text.find(comparison.symbol())?)))
+            .ok_or_else(|| format!("`{}` doesn't compare anything (try `V3 == 1F`)", text))?;
+        let condition = Condition {
+            left: text[..at].trim().to_string(),
+            comparison,
+            right: text[at + comparison.symbol().len()..].trim().to_string(),
+        };
+        // Check the names now rather than when the breakpoint is reached
+        let variable = |name: &str| name.parse::<Variable>().map(|_| 1.0);
+        expression::evaluate(&condition.left, &variable)?;
+        expression::evaluate(&condition.right, &variable)?;
+        Ok(condition)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.left,
+            self.comparison.symbol(),
+            self.right
+        )
+    }
+}
+
+/// The name of the instruction, like `DRW`, the way the disassembler writes
+/// it.
+fn mnemonic(instruction: &Instruction) -> String {
+    instruction
+        .to_string()
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Parse an instruction name for `break-on`, which has to be one that
+/// `chip8 opcodes` lists.
+fn parse_mnemonic(name: &str) -> Result<String, String> {
+    let name = name.to_ascii_uppercase();
+    let known = opcodes::docs()
+        .iter()
+        .any(|doc| doc.syntax.split_whitespace().next() == Some(name.as_str()));
+    if known {
+        Ok(name)
+    } else {
+        Err(format!(
+            "There's no instruction named {} (see `chip8 opcodes`)",
+            name
+        ))
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let argument = |n: usize| {
+            words
+                .get(n)
+                .copied()
+                .ok_or_else(|| format!("{} needs an argument (try `help`)", words[0]))
+        };
+        match words.first().copied() {
+            None | Some("step") | Some("s") => Ok(Command::Step),
+            Some("stepback") | Some("sb") => Ok(Command::StepBack),
+            Some("next") | Some("n") => Ok(Command::Next),
+            Some("finish") | Some("f") => Ok(Command::Finish),
+            Some("continue") | Some("c") => Ok(Command::Continue),
+            Some("break") | Some("b") => {
+                let address = parse_address(argument(1)?)?;
+                let condition = match words.get(2).copied() {
+                    None => None,
+                    Some("if") => Some(words[3..].join(" ").parse()?),
+                    Some(other) => {
+                        return Err(format!("Expected `if` after the address, not {}", other))
+                    }
+                };
+                Ok(Command::Break(address, condition))
+            }
+            Some("break-on") | Some("bo") => Ok(Command::BreakOn(parse_mnemonic(argument(1)?)?)),
+            Some("regs") | Some("r") => Ok(Command::Registers),
+            Some("backtrace") | Some("bt") => Ok(Command::Backtrace),
+            Some("info") | Some("i") => Ok(Command::Info),
+            Some("mem") | Some("m") => {
+                let length = match words.get(2) {
+                    Some(n) => n.parse().map_err(|_| format!("Not a number: {}", n))?,
+                    None => 16,
+                };
+                Ok(Command::Memory(parse_address(argument(1)?)?, length))
+            }
+            Some("disasm") | Some("d") => {
+                let count = match words.get(1) {
+                    Some(n) => n.parse().map_err(|_| format!("Not a number: {}", n))?,
+                    None => 8,
+                };
+                Ok(Command::Disassemble(count))
+            }
+            Some("watch") | Some("w") => {
+                let watched = match argument(1)? {
+                    "I" | "i" => Watched::I,
+                    "mem" | "m" => Watched::Memory(parse_address(argument(2)?)?),
+                    register => Watched::Register(register.parse()?),
+                };
+                Ok(Command::Watch(watched))
+            }
+            Some("quit") | Some("q") => Ok(Command::Quit),
+            Some("help") | Some("h") => Ok(Command::Help),
+            Some(other) => Err(format!("Unknown command: {} (try `help`)", other)),
+        }
+    }
+}
+
+/// An interactive debugger. The run loop asks it before every instruction
+/// whether to pause, and while paused it reads commands from stdin, or from
+/// another program connected over TCP (see `Debugger::listen`).
+pub struct Debugger {
+    /// Where to pause, and what has to hold to pause there, if anything
+    breakpoints: HashMap<u16, Option<Condition>>,
+    /// The names of instructions to pause before, like `DRW`
+    break_on: HashSet<String>,
+    /// What's being watched, and the value each one had the last time we
+    /// looked
+    watches: Vec<(Watched, u16)>,
+    /// Whether to pause before the next instruction
+    stepping: bool,
+    /// For `next` and `finish`, how many subroutines have to return before
+    /// pausing, counting the calls and returns that the program makes
+    returns_left: Option<usize>,
+    /// The instruction that was about to run the last time we looked, and its
+    /// address, which is the one that changed anything that's changed since
+    last_instruction: Option<(u16, Instruction)>,
+    /// Where commands come from
+    input: Box<dyn BufRead>,
+    /// Where everything the debugger says goes
+    output: Box<dyn Write>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Create a debugger that pauses before the first instruction, and reads
+    /// commands from stdin.
+    pub fn new() -> Self {
+        Self::with_io(
+            Box::new(BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        )
+    }
+
+    /// Wait for another program, like an editor, to connect to `port` on
+    /// this machine, and then take commands from it instead of stdin. It
+    /// sends the same commands as `chip8 debug` takes, one per line, and gets
+    /// back what the debugger would have printed, with each reply ending in
+    /// the `(chip8) ` prompt.
+    pub fn listen(port: u16) -> Result<Self, Chip8Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        println!("Waiting for a debugger to connect to port {}", port);
+        let (stream, address) = listener.accept()?;
+        println!("{} connected", address);
+        Self::connected_to(stream)
+    }
+
+    /// A debugger that takes commands from a connection.
+    fn connected_to(stream: TcpStream) -> Result<Self, Chip8Error> {
+        let input = BufReader::new(stream.try_clone()?);
+        Ok(Self::with_io(Box::new(input), Box::new(stream)))
+    }
+
+    fn with_io(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            breakpoints: HashMap::new(),
+            break_on: HashSet::new(),
+            watches: vec![],
+            stepping: true,
+            returns_left: None,
+            last_instruction: None,
+            input,
+            output,
+        }
+    }
+
+    /// Say something to whoever's debugging. If they've gone away, reading
+    /// the next command finds out, so a failed write can be ignored.
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    /// Called before every instruction. Pauses for commands if we're stepping,
+    /// hit a breakpoint, or a watched register changed.
+    /// Returns false if the user wants to quit. Otherwise, the state starts
+    /// journaling the step that's about to run, so `stepback` can undo it.
+    pub fn before_instruction(&mut self, state: &mut State) -> Result<bool, Chip8Error> {
+        let go_on = if self.should_pause(state) {
+            self.prompt(state)?
+        } else {
+            true
+        };
+        if go_on {
+            state.begin_step();
+        }
+        Ok(go_on)
+    }
+
+    /// Called after every instruction that ran (or waited), to keep count of
+    /// the calls and returns that `next` and `finish` are waiting on.
+    pub fn after_instruction(&mut self, outcome: &StepOutcome) {
+        if let Some(returns_left) = self.returns_left.as_mut() {
+            match outcome.call {
+                Some(CallEvent::Call { .. }) => *returns_left += 1,
+                Some(CallEvent::Return { .. }) => *returns_left = returns_left.saturating_sub(1),
+                None => {}
+            }
+        }
+    }
+
+    /// Whether to pause before running the instruction at the program counter.
+    /// This also updates the last-seen values of watched registers.
+    /// While the machine is waiting (for a key, say), the last instruction
+    /// hasn't finished yet, so stepping and breakpoints wait for it too.
+    fn should_pause(&mut self, state: &State) -> bool {
+        let waiting = *state.microstate() != Microstate::Running;
+        let mut pause = self.stepping && !waiting;
+        if self.returns_left == Some(0) && !waiting {
+            self.returns_left = None;
+            pause = true;
+        }
+        let mut reasons = vec![];
+        if !waiting {
+            match self.breakpoints.get(&state.pc()) {
+                Some(None) => reasons.push(format!("Breakpoint at {:03X}", state.pc())),
+                Some(Some(condition)) => match condition.holds(state) {
+                    Ok(true) => {
+                        reasons.push(format!("Breakpoint at {:03X} ({})", state.pc(), condition))
+                    }
+                    Ok(false) => {}
+                    Err(message) => {
+                        reasons.push(format!("Breakpoint at {:03X}: {}", state.pc(), message))
+                    }
+                },
+                None => {}
+            }
+        }
+        reasons.extend(self.changed_watches(state));
+        if !waiting {
+            self.last_instruction = match state.next_instruction() {
+                Some(Ok(instruction)) => Some((state.pc(), instruction)),
+                _ => None,
+            };
+            if let Some((address, instruction)) = &self.last_instruction {
+                if self.break_on.contains(&mnemonic(instruction)) {
+                    reasons.push(format!("Breaking on {} at {:03X}", instruction, address));
+                }
+            }
+        }
+        for reason in &reasons {
+            self.say(reason);
+        }
+        pause || !reasons.is_empty()
+    }
+
+    /// Describe everything watched that's changed since we last looked, and
+    /// which instruction changed it, and remember the new values.
+    fn changed_watches(&mut self, state: &State) -> Vec<String> {
+        let mut changes = vec![];
+        for (watched, last_value) in self.watches.iter_mut() {
+            let value = watched.value(state);
+            if value == *last_value {
+                continue;
+            }
+            let mut change = format!(
+                "{} changed from {} -> {}",
+                watched,
+                watched.format_value(*last_value),
+                watched.format_value(value)
+            );
+            if let Some((address, instruction)) = &self.last_instruction {
+                change.push_str(&format!(" (by {:03X}: {})", address, instruction));
+            }
+            changes.push(change);
+            *last_value = value;
+        }
+        changes
+    }
+
+    /// Read and run commands until one of them resumes the program.
+    fn prompt(&mut self, state: &mut State) -> Result<bool, Chip8Error> {
+        let here = disassembly(state, 1);
+        self.say(&here);
+        loop {
+            write!(self.output, "(chip8) ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                // EOF, like Ctrl-D
+                return Ok(false);
+            }
+            match line.parse::<Command>() {
+                Ok(Command::Step) => {
+                    self.stepping = true;
+                    return Ok(true);
+                }
+                Ok(Command::Continue) => {
+                    self.stepping = false;
+                    return Ok(true);
+                }
+                // If the next instruction is a CALL, run until it returns
+                Ok(Command::Next) => {
+                    self.stepping = false;
+                    self.returns_left = Some(0);
+                    return Ok(true);
+                }
+                Ok(Command::Finish) if state.sp() == 0 => {
+                    self.say("Not in a subroutine, so there's nothing to finish");
+                }
+                Ok(Command::Finish) => {
+                    self.stepping = false;
+                    self.returns_left = Some(1);
+                    return Ok(true);
+                }
+                Ok(Command::StepBack) => self.step_back(state),
+                Ok(Command::Quit) => return Ok(false),
+                Ok(command) => self.run_command(state, command),
+                Err(message) => self.say(&message),
+            }
+        }
+    }
+
+    /// Undo the last instruction that ran, including any waiting it did before
+    /// it finished, and show where that leaves the program.
+    fn step_back(&mut self, state: &mut State) {
+        if !state.step_back() {
+            self.say("There's nothing to step back to");
+            return;
+        }
+        while *state.microstate() != Microstate::Running && state.step_back() {}
+        // What's watched is back to what it was, which isn't a change to
+        // pause for
+        for (watched, last_value) in self.watches.iter_mut() {
+            *last_value = watched.value(state);
+        }
+        self.last_instruction = None;
+        let here = disassembly(state, 1);
+        self.say(&here);
+    }
+
+    /// Run a command that doesn't resume the program.
+    fn run_command(&mut self, state: &State, command: Command) {
+        match command {
+            Command::Break(address, condition) => {
+                match &condition {
+                    Some(condition) => self.say(&format!(
+                        "Breakpoint set at {:03X} if {}",
+                        address, condition
+                    )),
+                    None => self.say(&format!("Breakpoint set at {:03X}", address)),
+                }
+                self.breakpoints.insert(address, condition);
+            }
+            Command::BreakOn(name) => {
+                self.say(&format!("Pausing before every {}", name));
+                self.break_on.insert(name);
+            }
+            Command::Registers => self.say(&registers(state)),
+            Command::Backtrace => self.say(backtrace(state).trim_end()),
+            Command::Info => self.say(&state.to_string()),
+            Command::Memory(address, length) => {
+                self.say(memory_dump(state, address, length).trim_end())
+            }
+            Command::Disassemble(count) => self.say(&disassembly(state, count)),
+            Command::Watch(watched) => {
+                let value = watched.value(state);
+                self.watches.push((watched, value));
+                self.say(&format!(
+                    "Watching {} (currently {})",
+                    watched,
+                    watched.format_value(value)
+                ));
+            }
+            Command::Help => self.say(HELP),
+            Command::Step
+            | Command::StepBack
+            | Command::Next
+            | Command::Finish
+            | Command::Continue
+            | Command::Quit => {}
+        }
+    }
+}
+
+/// The registers, 8 to a line, and then I, the program counter, and the
+/// stack.
+fn registers(state: &State) -> String {
+    let mut text = String::new();
+    for (n, value) in state.registers().iter().enumerate() {
+        text.push_str(&format!("V{:X}={:02X} ", n, value));
+        if n % 8 == 7 {
+            text.push('\n');
+        }
+    }
+    text.push_str(&format!(
+        "I={:04X} PC={:03X} SP={:X} Stack={:03X?}",
+        state.i(),
+        state.pc(),
+        state.sp(),
+        &state.stack()[..state.sp() as usize]
+    ));
+    text
+}
+
+/// Memory, the same way `chip8 hexdump` shows it, with where the program
+/// counter and I point.
+fn memory_dump(state: &State, address: u16, length: usize) -> String {
+    let start = (address as usize).min(state.memory().len());
+    let end = start.saturating_add(length).min(state.memory().len());
+    let region = MemoryRegion::new(state.memory(), start..end);
+    region.hexdump(16, &pointers(state))
+}
+
+/// The registers that point into memory, to mark in a hexdump.
+pub fn pointers(state: &State) -> [(&'static str, usize); 2] {
+    [("PC", state.pc() as usize), ("I", state.i() as usize)]
+}
+
+/// Where the program is, and the CALL that led to each subroutine it's in,
+/// innermost first:
+///
+///     #0 306
+///     #1 20A: CALL 300
+///
+/// Every return address on the stack is just past the CALL that pushed it.
+fn backtrace(state: &State) -> String {
+    let mut lines = vec![format!("#0 {:03X}", state.pc())];
+    let calls = state.stack()[..state.sp() as usize].iter().rev();
+    for (frame, return_address) in calls.enumerate() {
+        let address = return_address.wrapping_sub(2);
+        let call = match state.memory().get(address as usize..) {
+            Some(bytes) => Instruction::from_bytes(bytes)
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_else(|e| e.to_string()),
+            None => String::from("?"),
+        };
+        lines.push(format!("#{} {:03X}: {}", frame + 1, address, call));
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// The next `count` instructions, one per line, marking the one at the
+/// program counter.
+fn disassembly(state: &State, count: usize) -> String {
+    let mut lines = vec![];
+    let mut address = state.pc() as usize;
+    for _ in 0..count {
+        if address + 2 > state.memory().len() {
+            break;
+        }
+        match Instruction::from_bytes(&state.memory()[address..]) {
+            Ok(instruction) => {
+                let marker = if address == state.pc() as usize {
+                    "=>"
+                } else {
+                    "  "
+                };
+                lines.push(format!("{} {:03X}: {}", marker, address, instruction));
+                address += instruction.size();
+            }
+            Err(e) => {
+                lines.push(format!("{:03X}: {}", address, e));
+                break;
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::tick;
+    use std::{io::Read, thread};
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!("".parse(), Ok(Command::Step));
+        assert_eq!("step".parse(), Ok(Command::Step));
+        assert_eq!("sb".parse(), Ok(Command::StepBack));
+        assert_eq!("c".parse(), Ok(Command::Continue));
+        assert_eq!("next".parse(), Ok(Command::Next));
+        assert_eq!("f".parse(), Ok(Command::Finish));
+        assert_eq!("bt".parse(), Ok(Command::Backtrace));
+        assert_eq!("info".parse(), Ok(Command::Info));
+        assert_eq!("break 0x230".parse(), Ok(Command::Break(0x230, None)));
+        assert_eq!("b 230".parse(), Ok(Command::Break(0x230, None)));
+        assert_eq!(
+            "break 0x230 if V3 == 0x1F".parse(),
+            Ok(Command::Break(
+                0x230,
+                Some(Condition {
+                    left: "V3".to_string(),
+                    comparison: Comparison::Equal,
+                    right: "0x1F".to_string(),
+                })
+            ))
+        );
+        assert_eq!("bo drw".parse(), Ok(Command::BreakOn("DRW".to_string())));
+        assert_eq!("mem 0x200 32".parse(), Ok(Command::Memory(0x200, 32)));
+        assert_eq!("mem 0x200".parse(), Ok(Command::Memory(0x200, 16)));
+        assert_eq!("disasm".parse(), Ok(Command::Disassemble(8)));
+        assert_eq!(
+            "watch V3".parse(),
+            Ok(Command::Watch(Watched::Register(Register::from_nibble(
+                0x3
+            ))))
+        );
+        assert_eq!(
+            "w va".parse(),
+            Ok(Command::Watch(Watched::Register(Register::from_nibble(
+                0xA
+            ))))
+        );
+        assert_eq!("watch I".parse(), Ok(Command::Watch(Watched::I)));
+        assert_eq!(
+            "watch mem 0x2F0".parse(),
+            Ok(Command::Watch(Watched::Memory(0x2F0)))
+        );
+    }
+
+    #[test]
+    fn parse_bad_commands() {
+        assert!("break".parse::<Command>().is_err());
+        assert!("break xyz".parse::<Command>().is_err());
+        assert!("break 230 V3 == 1".parse::<Command>().is_err());
+        assert!("break 230 if V3".parse::<Command>().is_err());
+        assert!("break 230 if V3 == X".parse::<Command>().is_err());
+        assert!("break-on DANCE".parse::<Command>().is_err());
+        assert!("watch V10".parse::<Command>().is_err());
+        assert!("watch mem".parse::<Command>().is_err());
+        assert!("dance".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn pauses_at_breakpoints() {
+        let state = State::with_program(&[0x00, 0xE0]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        assert!(!debugger.should_pause(&state));
+
+        debugger.run_command(&state, Command::Break(0x200, None));
+        assert!(debugger.should_pause(&state));
+    }
+
+    #[test]
+    fn conditions_compare_expressions() {
+        // LD V3, 0x1F; ADD V3, 0x01
+        let mut state = State::with_program(&[0x63, 0x1F, 0x73, 0x01]).unwrap();
+        let condition = |text: &str| text.parse::<Condition>().unwrap();
+        assert!(!condition("V3 == 1F").holds(&state).unwrap());
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(condition("V3 == 0x1F").holds(&state).unwrap());
+        assert!(condition("v3 + 1 >= 20").holds(&state).unwrap());
+        assert!(condition("PC != 200").holds(&state).unwrap());
+        assert!(condition("I < V3 * 2").holds(&state).unwrap());
+        assert!(condition("1 / V0 == 1").holds(&state).is_err());
+    }
+
+    #[test]
+    fn pauses_at_breakpoints_only_if_the_condition_holds() {
+        // LD V3, 0x1F; JP 200
+        let mut state = State::with_program(&[0x63, 0x1F, 0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        let condition = "V3 == 1F".parse().unwrap();
+        debugger.run_command(&state, Command::Break(0x200, Some(condition)));
+        assert!(!debugger.should_pause(&state));
+
+        tick(&mut state, rand::thread_rng()).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(state.pc(), 0x200);
+        assert!(debugger.should_pause(&state));
+    }
+
+    #[test]
+    fn pauses_before_instructions_with_the_name() {
+        // LD V3, 0x1F; DRW V0, V0, 1
+        let mut state = State::with_program(&[0x63, 0x1F, 0xD0, 0x01]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        debugger.run_command(&state, Command::BreakOn("DRW".to_string()));
+        assert!(!debugger.should_pause(&state));
+
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(debugger.should_pause(&state));
+    }
+
+    #[test]
+    fn pauses_when_watched_register_changes() {
+        // LD V3, 0x12
+        let mut state = State::with_program(&[0x63, 0x12]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        debugger.run_command(
+            &state,
+            Command::Watch(Watched::Register(Register::from_nibble(0x3))),
+        );
+        assert!(!debugger.should_pause(&state));
+
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(debugger.should_pause(&state));
+        // It only pauses once per change
+        assert!(!debugger.should_pause(&state));
+    }
+
+    #[test]
+    fn says_which_instruction_changed_memory() {
+        // LD V0, 0x12; LD I, 0x2F0; LD [I], V0
+        let mut state = State::with_program(&[0x60, 0x12, 0xA2, 0xF0, 0xF0, 0x55]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        debugger.run_command(&state, Command::Watch(Watched::Memory(0x2F0)));
+        debugger.run_command(&state, Command::Watch(Watched::I));
+
+        assert!(!debugger.should_pause(&state));
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(!debugger.should_pause(&state));
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(
+            debugger.changed_watches(&state),
+            vec!["I changed from 0000 -> 02F0 (by 202: LD I, 2F0)"]
+        );
+        debugger.should_pause(&state);
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(
+            debugger.changed_watches(&state),
+            vec![
+                "[2F0] changed from 00 -> 12 (by 204: LD [I], V0)",
+                // The COSMAC VIP's LD [I], Vx leaves I past what it stored
+                "I changed from 02F0 -> 02F1 (by 204: LD [I], V0)"
+            ]
+        );
+    }
+
+    /// CALL 204; CLS; (at 204) CALL 208; RET; (at 208) CLS; RET
+    const NESTED_CALLS: [u8; 12] = [
+        0x22, 0x04, 0x00, 0xE0, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xE0, 0x00, 0xEE,
+    ];
+
+    /// Run until the debugger wants to pause, and return the program counter.
+    fn run_until_pause(debugger: &mut Debugger, state: &mut State) -> u16 {
+        loop {
+            let outcome = state.step(rand::thread_rng()).unwrap();
+            debugger.after_instruction(&outcome);
+            if debugger.should_pause(state) {
+                return state.pc();
+            }
+        }
+    }
+
+    #[test]
+    fn memory_dump_stops_at_the_end_of_memory() {
+        let state = State::with_program(&NESTED_CALLS).unwrap();
+        let dump = memory_dump(&state, 0xFF0, usize::MAX);
+        assert_eq!(dump, memory_dump(&state, 0xFF0, 0x10));
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let mut state = State::with_program(&NESTED_CALLS).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        debugger.returns_left = Some(0);
+
+        assert_eq!(run_until_pause(&mut debugger, &mut state), 0x202);
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let mut state = State::with_program(&NESTED_CALLS).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(state.pc(), 0x208);
+        assert_eq!(
+            backtrace(&state),
+            "#0 208\n#1 204: CALL 208\n#2 200: CALL 204\n"
+        );
+
+        let mut debugger = Debugger::new();
+        debugger.stepping = false;
+        debugger.returns_left = Some(1);
+        assert_eq!(run_until_pause(&mut debugger, &mut state), 0x206);
+    }
+
+    #[test]
+    fn steps_back_through_what_each_instruction_changed() {
+        // LD V0, 0x12; LD I, 0x1FF; LD [I], V0; CALL 208;
+        // (at 208) DRW V0, V0, 1, which draws the first byte of the program
+        let mut state =
+            State::with_program(&[0x60, 0x12, 0xA1, 0xFF, 0xF0, 0x55, 0x22, 0x08, 0xD0, 0x01])
+                .unwrap();
+        let mut snapshots = vec![];
+        for _ in 0..5 {
+            snapshots.push(state.clone());
+            state.begin_step();
+            tick(&mut state, rand::thread_rng()).unwrap();
+        }
+        assert_eq!(state.sp(), 1);
+        assert_eq!(state.memory()[0x1FF], 0x12);
+        assert_ne!(state.buffer(), snapshots[4].buffer());
+
+        while let Some(snapshot) = snapshots.pop() {
+            assert!(state.step_back());
+            assert_eq!(state, snapshot);
+        }
+        assert!(!state.step_back());
+    }
+
+    #[test]
+    fn stepping_back_undoes_the_waiting_too() {
+        // LD V1, K; LD V2, 0x34
+        let mut state = State::with_program(&[0xF1, 0x0A, 0x62, 0x34]).unwrap();
+        let mut debugger = Debugger::new();
+        let start = state.clone();
+        state.begin_step();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        state.begin_step();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        state.set_keypad([true; 16]);
+        state.begin_step();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(*state.microstate(), Microstate::Running);
+        assert_eq!(state.pc(), 0x202);
+
+        debugger.step_back(&mut state);
+        assert_eq!(state.pc(), start.pc());
+        assert_eq!(state.microstate(), start.microstate());
+        assert!(!state.step_back());
+    }
+
+    #[test]
+    fn takes_commands_over_tcp() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            stream.write_all(b"break 0x204\nregs\nquit\n").unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut debugger = Debugger::connected_to(stream).unwrap();
+        // LD V3, 0x1F
+        let mut state = State::with_program(&[0x63, 0x1F]).unwrap();
+        assert!(!debugger.before_instruction(&mut state).unwrap());
+        // Hang up, so the client stops reading
+        drop(debugger);
+
+        let reply = client.join().unwrap();
+        assert!(reply
+            .starts_with("=> 200: LD V3, 1F\n(chip8) Breakpoint set at 204\n(chip8) V0=00 V1=00"));
+        assert!(reply.ends_with("I=0000 PC=200 SP=0 Stack=[]\n(chip8) "));
+    }
+
+    #[test]
+    fn waits_for_the_instruction_to_finish() {
+        // LD V1, K
+        let mut state = State::with_program(&[0xF1, 0x0A]).unwrap();
+        let mut debugger = Debugger::new();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(!debugger.should_pause(&state));
+
+        state.set_keypad([true; 16]);
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert!(debugger.should_pause(&state));
+    }
+}
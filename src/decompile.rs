@@ -0,0 +1,305 @@
+use crate::{
+    disasm::{self, Line},
+    error::Chip8Error,
+    instruction::Instruction,
+    memory,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Decompile a program that will be loaded at 0x200 into Octo source, which
+/// `chip8 build` (or Octo) compiles back into the same bytes. It starts from
+/// the disassembler's split into code and data, and then:
+/// - labels everything that's jumped to, called, or pointed at with `i :=`,
+///   and uses the labels instead of addresses
+/// - writes the data that `i :=` points at one byte per line in binary, since
+///   it's usually a sprite, and other data as rows of hex bytes
+/// - names registers with `:alias` when they only ever do one job, like being
+///   the x coordinate of every sprite that's drawn with them
+pub fn decompile(program: &[u8]) -> Result<String, Chip8Error> {
+    let disassembly = disasm::disassemble(program)?;
+    let start = memory::PROGRAM_START as u16;
+
+    // Labels can go at the start of an instruction or on any byte of data
+    let mut places = BTreeSet::new();
+    let mut labels = BTreeSet::new();
+    labels.insert(start);
+    for line in &disassembly.lines {
+        match line {
+            Line::Label(address) => {
+                labels.insert(*address);
+            }
+            Line::Code { address, .. } => {
+                places.insert(*address);
+            }
+            Line::Data { address, bytes } => {
+                places.extend((0..bytes.len()).map(|i| address + i as u16));
+            }
+        }
+    }
+    let mut sprites = BTreeSet::new();
+    for line in &disassembly.lines {
+        if let Line::Code { instruction, .. } = line {
+            let target = match instruction {
+                Instruction::LDI(address) => address.value(),
+                Instruction::LDILong(address) => *address,
+                _ => continue,
+            };
+            if places.contains(&target) {
+                labels.insert(target);
+                sprites.insert(target);
+            }
+        }
+    }
+    let aliases = infer_aliases(&disassembly.lines);
+
+    let mut source = String::new();
+    for (register, alias) in &aliases {
+        source.push_str(&format!(":alias {} v{:x}\n", alias, register));
+    }
+    if !aliases.is_empty() {
+        source.push('\n');
+    }
+    let mut row = vec![];
+    let mut in_sprite = false;
+    for line in &disassembly.lines {
+        match line {
+            Line::Label(_) => {}
+            Line::Code {
+                address,
+                instruction,
+                ..
+            } => {
+                flush(&mut source, &mut row);
+                in_sprite = false;
+                if labels.contains(address) {
+                    source.push_str(&format!(": {}\n", name(*address)));
+                }
+                let note = if disassembly.is_dynamic(*address) {
+                    " # may be modified at runtime"
+                } else {
+                    ""
+                };
+                source.push_str(&format!(
+                    "    {}{}\n",
+                    statement(instruction, &labels, &aliases),
+                    note
+                ));
+            }
+            Line::Data { address, bytes } => {
+                for (i, byte) in bytes.iter().enumerate() {
+                    let address = address + i as u16;
+                    if labels.contains(&address) {
+                        flush(&mut source, &mut row);
+                        source.push_str(&format!(": {}\n", name(address)));
+                        in_sprite = sprites.contains(&address);
+                    }
+                    if in_sprite {
+                        source.push_str(&format!("    0b{:08b}\n", byte));
+                    } else {
+                        row.push(format!("0x{:02X}", byte));
+                        if row.len() == 8 {
+                            flush(&mut source, &mut row);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    flush(&mut source, &mut row);
+    Ok(source)
+}
+
+/// The name of the label at this address. The program starts at `main`.
+fn name(address: u16) -> String {
+    if address == memory::PROGRAM_START as u16 {
+        "main".to_string()
+    } else {
+        disasm::label(address)
+    }
+}
+
+/// Write out a row of data bytes, if there are any.
+fn flush(source: &mut String, row: &mut Vec<String>) {
+    if !row.is_empty() {
+        source.push_str(&format!("    {}\n", row.join(" ")));
+        row.clear();
+    }
+}
+
+/// The instruction as an Octo statement, with labels instead of addresses
+/// and aliases instead of registers.
+fn statement(
+    instruction: &Instruction,
+    labels: &BTreeSet<u16>,
+    aliases: &BTreeMap<u8, &str>,
+) -> String {
+    use Instruction::*;
+
+    let target = |address: u16| {
+        if labels.contains(&address) {
+            Some(name(address))
+        } else {
+            None
+        }
+    };
+    let labelled = match instruction {
+        JP(address) => target(address.value()).map(|name| format!("jump {}", name)),
+        JPOffset(address) => target(address.value()).map(|name| format!("jump0 {}", name)),
+        CALL(address) => target(address.value()).map(|name| format!(":call {}", name)),
+        LDI(address) => target(address.value()).map(|name| format!("i := {}", name)),
+        LDILong(address) => target(*address).map(|name| format!("i := long {}", name)),
+        _ => None,
+    };
+    if let Some(statement) = labelled {
+        return statement;
+    }
+    match instruction {
+        // The register here is how many to save or load, not a variable
+        LDIntoMemory(_) | LDFromMemory(_) | LDIntoFlags(_) | LDFromFlags(_) | SAVE(..)
+        | LOAD(..) => format!("{:#}", instruction),
+        _ => format!("{:#}", instruction)
+            .split(' ')
+            .map(|word| {
+                let register = word
+                    .strip_prefix('v')
+                    .filter(|digit| digit.len() == 1)
+                    .and_then(|digit| u8::from_str_radix(digit, 16).ok());
+                match register.and_then(|register| aliases.get(&register)) {
+                    Some(alias) => alias.to_string(),
+                    None => word.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// The jobs a register does in this instruction that are worth naming it
+/// for.
+fn roles(instruction: &Instruction) -> Vec<(u8, &'static str)> {
+    match instruction {
+        Instruction::DRW(x, y, _) => vec![(x.value(), "sprite_x"), (y.value(), "sprite_y")],
+        Instruction::LDKey(x) => vec![(x.value(), "pressed_key")],
+        Instruction::RND(x, _) => vec![(x.value(), "random_value")],
+        Instruction::LDF(x) | Instruction::LDHF(x) => vec![(x.value(), "digit")],
+        _ => vec![],
+    }
+}
+
+/// Name each register that does only one job (see `roles`) that no other
+/// register does. VF is left alone, since instructions change it as a flag.
+fn infer_aliases(lines: &[Line]) -> BTreeMap<u8, &'static str> {
+    let mut jobs: BTreeMap<u8, BTreeSet<&'static str>> = BTreeMap::new();
+    for line in lines {
+        if let Line::Code { instruction, .. } = line {
+            for (register, role) in roles(instruction) {
+                jobs.entry(register).or_default().insert(role);
+            }
+        }
+    }
+    let mut aliases = BTreeMap::new();
+    for (register, roles) in &jobs {
+        if *register == 0xF || roles.len() != 1 {
+            continue;
+        }
+        let role = *roles.iter().next().unwrap();
+        let shared = jobs
+            .iter()
+            .any(|(other, other_roles)| other != register && other_roles.contains(&role));
+        if !shared {
+            aliases.insert(*register, role);
+        }
+    }
+    aliases
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        instruction::{program, Register},
+        octo,
+    };
+
+    #[test]
+    fn labels_sprites_and_aliases() {
+        let mut rom = program(&[
+            Instruction::CLS(),
+            Instruction::LDI(0x212.into()),
+            Instruction::LDByte(Register::from_nibble(0), 0),
+            Instruction::LDByte(Register::from_nibble(1), 5),
+            Instruction::DRW(Register::from_nibble(0), Register::from_nibble(1), 2),
+            Instruction::ADDByte(Register::from_nibble(0), 8),
+            Instruction::SEByte(Register::from_nibble(0), 0x40),
+            Instruction::JP(0x208.into()),
+            Instruction::EXIT(),
+        ]);
+        rom.extend(&[0xFF, 0x81]);
+
+        let source = decompile(&rom).unwrap();
+        assert_eq!(
+            source,
+            ":alias sprite_x v0
+:alias sprite_y v1
+
+: main
+    clear
+    i := L_0212
+    sprite_x := 0x00
+    sprite_y := 0x05
+: L_0208
+    sprite sprite_x sprite_y 2
+    sprite_x += 0x08
+    if sprite_x != 0x40 then
+    jump L_0208
+    exit
+: L_0212
+    0b11111111
+    0b10000001
+"
+        );
+        assert_eq!(octo::compile(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn compiles_back_into_the_same_bytes() {
+        let mut rom = program(&[Instruction::JP(0x206.into())]);
+        // Bytes that are jumped over
+        rom.extend(&[0xAB, 0xCD, 0xEF, 0x01]);
+        rom.extend(program(&[
+            Instruction::CALL(0x20C.into()),
+            Instruction::RND(Register::from_nibble(2), 0x0F),
+            Instruction::RND(Register::from_nibble(3), 0x0F),
+            Instruction::RET(),
+        ]));
+        // An instruction we don't know, and an odd byte at the end
+        rom.extend(&[0xFF, 0xFF, 0x42]);
+
+        let source = decompile(&rom).unwrap();
+        assert!(!source.contains(":alias"), "{}", source);
+        assert_eq!(octo::compile(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn compiles_registers_timers_keys_and_digits_back_into_the_same_bytes() {
+        let (x, y) = (Register::from_nibble(1), Register::from_nibble(2));
+        let rom = program(&[
+            Instruction::LDRegister(x, y),
+            Instruction::OR(x, y),
+            Instruction::AND(x, y),
+            Instruction::XOR(x, y),
+            Instruction::SUB(x, y),
+            Instruction::SUBN(x, y),
+            Instruction::LDFromDT(x),
+            Instruction::LDDT(x),
+            Instruction::SKP(y),
+            Instruction::SKNP(y),
+            Instruction::LDF(x),
+            Instruction::LDB(x),
+            Instruction::EXIT(),
+        ]);
+
+        let source = decompile(&rom).unwrap();
+        assert_eq!(octo::compile(&source).unwrap(), rom, "{}", source);
+    }
+}
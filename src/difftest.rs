@@ -0,0 +1,288 @@
+use crate::{
+    error::Chip8Error,
+    instruction::Instruction,
+    interpreter::{State, Variant},
+    quirks::Quirks,
+    tracefile::Snapshot,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+};
+
+/// How many of the instructions before a divergence to show.
+const CONTEXT: usize = 8;
+/// A program that waits this many cycles in a row is waiting for a key that
+/// isn't coming, since nothing presses any.
+const MAX_WAITS: u64 = 10_000;
+
+/// Read a reference trace: the `Snapshot` lines from `trace
+/// --dump-state-every` (or another emulator that writes the same format), in
+/// order. Lines that don't start with `{`, like the rest of what `trace`
+/// prints, are skipped.
+pub fn read_reference(trace: &str) -> Result<Vec<Snapshot>, Chip8Error> {
+    let mut snapshots: Vec<Snapshot> = vec![];
+    for (n, line) in trace.lines().enumerate() {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            continue;
+        }
+        let bad = |message: String| Chip8Error::BadReference {
+            line: n + 1,
+            message,
+        };
+        let snapshot = line.parse::<Snapshot>().map_err(bad)?;
+        if let Some(last) = snapshots.last() {
+            if snapshot.cycle <= last.cycle {
+                return Err(bad(format!(
+                    "Cycle {} comes after cycle {}",
+                    snapshot.cycle, last.cycle
+                )));
+            }
+        }
+        snapshots.push(snapshot);
+    }
+    Ok(snapshots)
+}
+
+/// Where our run first went differently from the reference.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// How many instructions had run
+    pub cycle: u64,
+    /// The last cycle where the states still matched, if any did
+    pub last_match: Option<u64>,
+    /// What was different, like `V3: ours 04, reference 05`, or why we never
+    /// got that far
+    pub differences: Vec<String>,
+    /// The instructions that ran just before it, oldest first, with their
+    /// addresses
+    pub recent: Vec<(u16, Instruction)>,
+    /// Our whole machine then, as `State` prints it
+    pub state: String,
+}
+
+/// What comparing a run against a reference trace found.
+#[derive(Debug, PartialEq)]
+pub struct Comparison {
+    /// How many of the reference's states matched ours
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let divergence = match &self.divergence {
+            Some(divergence) => divergence,
+            None => return writeln!(f, "All {} states match the reference.", self.matched),
+        };
+        write!(
+            f,
+            "The run differs from the reference after {} instructions",
+            divergence.cycle
+        )?;
+        match divergence.last_match {
+            Some(cycle) => writeln!(f, " (it last matched after {}):", cycle)?,
+            None => writeln!(f, ":")?,
+        }
+        for difference in &divergence.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        if !divergence.recent.is_empty() {
+            writeln!(f, "The instructions that ran before it:")?;
+            for (address, instruction) in &divergence.recent {
+                writeln!(f, "  {:03X}  {}", address, instruction)?;
+            }
+        }
+        writeln!(f, "Our machine then:")?;
+        write!(f, "{}", divergence.state)
+    }
+}
+
+/// What's different between our snapshot and the reference's.
+fn differences(ours: &Snapshot, reference: &Snapshot) -> Vec<String> {
+    let mut differences = vec![];
+    let mut check = |name: String, a: String, b: String| {
+        if a != b {
+            differences.push(format!("{}: ours {}, reference {}", name, a, b));
+        }
+    };
+    let hex = |address: &u16| format!("{:03X}", address);
+    check("PC".to_string(), hex(&ours.pc), hex(&reference.pc));
+    check("I".to_string(), hex(&ours.i), hex(&reference.i));
+    for (n, (a, b)) in ours.registers.iter().zip(&reference.registers).enumerate() {
+        check(
+            format!("V{:X}", n),
+            format!("{:02X}", a),
+            format!("{:02X}", b),
+        );
+    }
+    let bytes = [
+        ("DT", ours.delay_timer, reference.delay_timer),
+        ("ST", ours.sound_timer, reference.sound_timer),
+        ("SP", ours.sp, reference.sp),
+    ];
+    for (name, a, b) in &bytes {
+        check(name.to_string(), a.to_string(), b.to_string());
+    }
+    let stack =
+        |stack: &[u16]| format!("[{}]", stack.iter().map(hex).collect::<Vec<_>>().join(", "));
+    check(
+        "Stack".to_string(),
+        stack(&ours.stack),
+        stack(&reference.stack),
+    );
+    check(
+        "Screen".to_string(),
+        ours.screen.clone(),
+        reference.screen.clone(),
+    );
+    differences
+}
+
+/// Run the program and compare its state against each of the reference's,
+/// after the same number of instructions, stopping at the first one that's
+/// different. Like `trace`, only instructions that finish count: a DRW that
+/// waits for the next frame is one instruction, however long it waits. Fails
+/// if the program doesn't fit in memory.
+pub fn compare(
+    program: &[u8],
+    variant: Variant,
+    quirks: Quirks,
+    seed: u64,
+    reference: &[Snapshot],
+) -> Result<Comparison, Chip8Error> {
+    let mut state = State::with_program_for(variant, program)?.with_quirks(quirks);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut recent = VecDeque::with_capacity(CONTEXT);
+    let mut cycle = 0;
+    let mut last_match = None;
+    for (matched, expected) in reference.iter().enumerate() {
+        let mut waits = 0;
+        let mut failure = None;
+        while cycle < expected.cycle && failure.is_none() {
+            match state.step(&mut rng) {
+                Ok(outcome) if outcome.waited => {
+                    waits += 1;
+                    if waits >= MAX_WAITS {
+                        failure = Some(format!(
+                            "Ours is stuck on {} at {:03X}",
+                            outcome.instruction, outcome.pc_before
+                        ));
+                    }
+                }
+                Ok(outcome) => {
+                    waits = 0;
+                    cycle += 1;
+                    if recent.len() == CONTEXT {
+                        recent.pop_front();
+                    }
+                    recent.push_back((outcome.pc_before, outcome.instruction));
+                }
+                Err(e) => failure = Some(format!("Ours crashed: {}", e)),
+            }
+        }
+        let differences = match failure {
+            Some(failure) => vec![failure],
+            None => differences(&Snapshot::of(cycle, &state), expected),
+        };
+        if !differences.is_empty() {
+            return Ok(Comparison {
+                matched,
+                divergence: Some(Divergence {
+                    cycle: expected.cycle,
+                    last_match,
+                    differences,
+                    recent: recent.into_iter().collect(),
+                    state: state.to_string(),
+                }),
+            });
+        }
+        last_match = Some(cycle);
+    }
+    Ok(Comparison {
+        matched: reference.len(),
+        divergence: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quirks::Profile;
+
+    // LD V1, 08; LD V2, 02; SHR V1, V2; LD I, large font 0; DRW V1, V0, 1;
+    // JP to itself. Whether SHR shifts V1 or V2 decides where the sprite is.
+    const PROGRAM: [u8; 12] = [
+        0x61, 0x08, 0x62, 0x02, 0x81, 0x26, 0xA0, 0xA0, 0xD1, 0x01, 0x12, 0x0A,
+    ];
+
+    /// The trace `trace --dump-state-every 1` would write with these quirks.
+    fn reference(quirks: Quirks) -> String {
+        let mut state = State::with_program(&PROGRAM)
+            .unwrap()
+            .with_variant(Variant::Chip8)
+            .with_quirks(quirks);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut lines = vec!["Some other output".to_string()];
+        let mut cycle = 0;
+        while cycle < 6 {
+            if !state.step(&mut rng).unwrap().waited {
+                cycle += 1;
+                lines.push(Snapshot::of(cycle, &state).to_string());
+            }
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn the_same_interpreter_matches_itself() {
+        let quirks = Profile::Cosmac.quirks();
+        let reference = read_reference(&reference(quirks)).unwrap();
+        let comparison = compare(&PROGRAM, Variant::Chip8, quirks, 0, &reference).unwrap();
+        assert_eq!(comparison.matched, 6);
+        assert_eq!(comparison.divergence, None);
+    }
+
+    #[test]
+    fn finds_the_first_divergence() {
+        let mut other = Profile::Cosmac.quirks();
+        other.shift_uses_vy = false;
+        let reference = read_reference(&reference(other)).unwrap();
+        let comparison = compare(
+            &PROGRAM,
+            Variant::Chip8,
+            Profile::Cosmac.quirks(),
+            0,
+            &reference,
+        )
+        .unwrap();
+        assert_eq!(comparison.matched, 2);
+        let divergence = comparison.divergence.unwrap();
+        assert_eq!(divergence.cycle, 3);
+        assert_eq!(divergence.last_match, Some(2));
+        assert_eq!(
+            divergence.differences,
+            vec!["V1: ours 01, reference 04".to_string()]
+        );
+        assert_eq!(divergence.recent.last().unwrap().0, 0x204);
+    }
+
+    #[test]
+    fn only_reads_snapshots_in_order() {
+        let line = |cycle| {
+            let mut snapshot = Snapshot::of(0, &State::with_program(&PROGRAM).unwrap());
+            snapshot.cycle = cycle;
+            snapshot.to_string()
+        };
+        let trace = format!("{}\n{}", line(2), line(1));
+        match read_reference(&trace) {
+            Err(Chip8Error::BadReference { line, message }) => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "Cycle 1 comes after cycle 2");
+            }
+            _ => panic!("Expected the reference to be out of order"),
+        }
+    }
+}
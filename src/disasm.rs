@@ -0,0 +1,747 @@
+use crate::{error::Chip8Error, instruction::Instruction, memory, tracefile::json_string};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+    fs,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+
+/// The file extensions that ROMs usually have, for `disassemble_all`.
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+/// One line of a disassembled program.
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    /// The start of code that something jumps to or calls
+    Label(u16),
+    /// An instruction that the program can reach, and its bytes in the ROM
+    Code {
+        address: u16,
+        instruction: Instruction,
+        bytes: Vec<u8>,
+    },
+    /// Bytes that no reachable instruction runs, like sprites
+    Data { address: u16, bytes: Vec<u8> },
+}
+
+/// A program, split into code and data, with labels for every jump target.
+#[derive(Debug)]
+pub struct Disassembly {
+    /// Where the program is loaded
+    start: u16,
+    labels: BTreeSet<u16>,
+    /// Reachable addresses with instructions we don't know
+    unknown: BTreeSet<u16>,
+    /// Reachable instructions that the program might write over while it
+    /// runs, so what's there when it gets to them could be something else
+    dynamic: BTreeSet<u16>,
+    /// Where reachable code runs into the end of the ROM partway through an
+    /// instruction
+    cut_off: Option<u16>,
+    /// The last byte, if the ROM has an odd number of them, since it can't be
+    /// a whole instruction
+    trailing_byte: Option<u16>,
+    pub lines: Vec<Line>,
+}
+
+/// How to print a disassembly: as an assembly listing, or as one record per
+/// instruction (or run of data) for other tools to read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    /// One JSON object per line, like trace files
+    Json,
+    /// A header row, then one row per record
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "Unknown format {} (expected text, json, or csv)",
+                s
+            )),
+        }
+    }
+}
+
+/// One instruction or run of data, split into fields (see `Format`).
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// How many bytes into the ROM it starts
+    pub offset: usize,
+    /// Where it's loaded in memory
+    pub address: u16,
+    /// The label at this address, if anything jumps or calls here
+    pub label: Option<String>,
+    /// The bytes, in hex
+    pub opcode: String,
+    /// DATA for data
+    pub mnemonic: String,
+    pub operands: Vec<String>,
+}
+
+/// The name of the label at this address.
+pub fn label(address: u16) -> String {
+    format!("L_{:04X}", address)
+}
+
+/// Where this instruction can send the program counter: the addresses it jumps
+/// or calls to, and whether it can carry on to the next instruction (or skip
+/// it).
+fn successors(instruction: &Instruction) -> (Option<u16>, bool, bool) {
+    match instruction {
+        Instruction::JP(address) => (Some(address.value()), false, false),
+        Instruction::CALL(address) => (Some(address.value()), true, false),
+        // We can't know where these go without running the program
+        Instruction::RET() | Instruction::JPOffset(_) | Instruction::UNKNOWN(_) => {
+            (None, false, false)
+        }
+        // The program stops here
+        Instruction::EXIT() => (None, false, false),
+        Instruction::SEByte(..)
+        | Instruction::SNEByte(..)
+        | Instruction::SERegister(..)
+        | Instruction::SNERegister(..)
+        | Instruction::SKP(_)
+        | Instruction::SKNP(_) => (None, true, true),
+        _ => (None, true, false),
+    }
+}
+
+/// Decode the instruction at `offset` into the program, if there's one there.
+fn decode(program: &[u8], offset: usize) -> Result<Option<Instruction>, Chip8Error> {
+    if offset + 2 > program.len() {
+        return Ok(None);
+    }
+    Ok(Some(Instruction::from_bytes(&program[offset..])?))
+}
+
+/// Disassemble a program that will be loaded at 0x200. Starting from the
+/// first instruction, follow every jump, call, and skip to find out which bytes
+/// are code; everything else is data.
+pub fn disassemble(program: &[u8]) -> Result<Disassembly, Chip8Error> {
+    disassemble_with_data(program, memory::PROGRAM_START as u16, &[])
+}
+
+/// Disassemble a program like `disassemble`, but loaded at `load_address`
+/// (where it starts running), and treat the bytes in the `data` address
+/// ranges as data even if something jumps into them, like a table of sprites
+/// that looks like code.
+pub fn disassemble_with_data(
+    program: &[u8],
+    load_address: u16,
+    data: &[Range<u16>],
+) -> Result<Disassembly, Chip8Error> {
+    let start = load_address;
+    let end = start as usize + program.len();
+    let in_program = |address: u16| (start as usize..end).contains(&(address as usize));
+    let in_data = |address: u16| data.iter().any(|range| range.contains(&address));
+
+    // Which bytes are the start of an instruction, and which are part of one
+    let mut instruction_starts = BTreeSet::new();
+    let mut code_bytes = vec![false; program.len()];
+    let mut labels = BTreeSet::new();
+    let mut unknown = BTreeSet::new();
+    let mut cut_off = None;
+    let mut to_visit = vec![start];
+
+    while let Some(address) = to_visit.pop() {
+        if !in_program(address) || in_data(address) || instruction_starts.contains(&address) {
+            continue;
+        }
+        let offset = (address - start) as usize;
+        let instruction = match decode(program, offset)? {
+            Some(instruction) => instruction,
+            None => {
+                cut_off = Some(address);
+                continue;
+            }
+        };
+        if let Instruction::UNKNOWN(_) = instruction {
+            unknown.insert(address);
+            continue;
+        }
+        instruction_starts.insert(address);
+        for byte in code_bytes.iter_mut().skip(offset).take(instruction.size()) {
+            *byte = true;
+        }
+
+        let (target, continues, skips) = successors(&instruction);
+        if let Some(target) = target {
+            labels.insert(target);
+            to_visit.push(target);
+        }
+        // There's nothing after an instruction at the very end of 64KB
+        let next = match address.checked_add(instruction.size() as u16) {
+            Some(next) => next,
+            None => continue,
+        };
+        if continues {
+            to_visit.push(next);
+        }
+        if skips {
+            // Skip over the whole next instruction, which might be 4 bytes
+            let next_size = decode(program, (next - start) as usize)?
+                .map(|next_instruction| next_instruction.size())
+                .unwrap_or(2);
+            if let Some(after_next) = next.checked_add(next_size as u16) {
+                to_visit.push(after_next);
+            }
+        }
+    }
+    // JP V0, nnn usually jumps into a table at nnn, so label it even though we
+    // can't follow it
+    for address in &instruction_starts {
+        if let Some(Instruction::JPOffset(base)) = decode(program, (address - start) as usize)? {
+            labels.insert(base.value());
+        }
+    }
+    // Only label addresses we'll actually print
+    labels.retain(|address| in_program(*address));
+
+    // Some programs write their own instructions at runtime. If the program
+    // can store bytes, assume it can store them wherever it points I with
+    // LD I, nnn, and mark the code there as only what it starts out as.
+    let mut targets = vec![];
+    let mut store_size = 0;
+    for address in &instruction_starts {
+        match decode(program, (address - start) as usize)? {
+            Some(Instruction::LDI(target)) => targets.push(target.value() as usize),
+            Some(Instruction::LDIntoMemory(x)) => store_size = store_size.max(x.index() + 1),
+            Some(Instruction::SAVE(x, y)) => {
+                store_size = store_size.max(x.index().max(y.index()) - x.index().min(y.index()) + 1)
+            }
+            _ => {}
+        }
+    }
+    let mut dynamic = BTreeSet::new();
+    for address in &instruction_starts {
+        let offset = (address - start) as usize;
+        let size = decode(program, offset)?.unwrap().size();
+        let first = *address as usize;
+        if store_size > 0
+            && targets
+                .iter()
+                .any(|target| *target < first + size && first < target + store_size)
+        {
+            dynamic.insert(*address);
+        }
+    }
+
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < program.len() {
+        let address = start + offset as u16;
+        if labels.contains(&address) {
+            lines.push(Line::Label(address));
+        }
+        if instruction_starts.contains(&address) {
+            let instruction = decode(program, offset)?.unwrap();
+            let end = (offset + instruction.size()).min(program.len());
+            let bytes = program[offset..end].to_vec();
+            offset += instruction.size();
+            lines.push(Line::Code {
+                address,
+                instruction,
+                bytes,
+            });
+        } else {
+            // Up to 8 bytes of data at a time, stopping at code or a label
+            let mut bytes = vec![program[offset]];
+            offset += 1;
+            while offset < program.len()
+                && bytes.len() < 8
+                && !code_bytes[offset]
+                && !labels.contains(&(start + offset as u16))
+            {
+                bytes.push(program[offset]);
+                offset += 1;
+            }
+            lines.push(Line::Data { address, bytes });
+        }
+    }
+
+    Ok(Disassembly {
+        start,
+        labels,
+        unknown,
+        dynamic,
+        cut_off,
+        trailing_byte: if program.len() % 2 == 1 {
+            Some(start + program.len() as u16 - 1)
+        } else {
+            None
+        },
+        lines,
+    })
+}
+
+/// Disassemble every ROM in `directory` into its own listing file in
+/// `output_directory` (so `pong.ch8` becomes `pong.lst`), and write an
+/// `index.txt` there with each ROM's size and how many unknown instructions
+/// it can reach. A ROM that can't be disassembled gets its error in the index
+/// instead of a listing.
+/// Returns how many ROMs there were.
+pub fn disassemble_all(directory: &Path, output_directory: &Path) -> Result<usize, Chip8Error> {
+    let mut roms = vec![];
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        if ROM_EXTENSIONS.contains(&extension.as_str()) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+
+    fs::create_dir_all(output_directory)?;
+    let mut index = vec![format!("{:<32} {:>6} {:>7}", "ROM", "BYTES", "UNKNOWN")];
+    for path in &roms {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let program = fs::read(path)?;
+        match disassemble(&program) {
+            Ok(disassembly) => {
+                let listing = output_directory.join(Path::new(name.as_ref()).with_extension("lst"));
+                fs::write(listing, disassembly.to_string())?;
+                index.push(format!(
+                    "{:<32} {:>6} {:>7}",
+                    name,
+                    program.len(),
+                    disassembly.unknown_count()
+                ));
+            }
+            Err(e) => index.push(format!("{:<32} {:>6} Error: {}", name, program.len(), e)),
+        }
+    }
+    fs::write(output_directory.join("index.txt"), index.join("\n") + "\n")?;
+    Ok(roms.len())
+}
+
+impl Disassembly {
+    /// How many reachable instructions we don't know.
+    pub fn unknown_count(&self) -> usize {
+        self.unknown.len()
+    }
+
+    /// The addresses of the reachable instructions we don't know, in order.
+    pub fn unknown_addresses(&self) -> impl Iterator<Item = u16> + '_ {
+        self.unknown.iter().copied()
+    }
+
+    /// Whether the program might write over the instruction at `address`
+    /// while it runs.
+    pub fn is_dynamic(&self, address: u16) -> bool {
+        self.dynamic.contains(&address)
+    }
+
+    /// The code and data, one record each, with the labels folded into the
+    /// record they're on.
+    pub fn records(&self) -> Vec<Record> {
+        let start = self.start as usize;
+        let mut records = vec![];
+        let mut label_here = None;
+        for line in &self.lines {
+            let (address, opcode, mnemonic, operands) = match line {
+                Line::Label(address) => {
+                    label_here = Some(label(*address));
+                    continue;
+                }
+                Line::Code {
+                    address,
+                    instruction,
+                    bytes,
+                } => {
+                    let (mnemonic, operands) = instruction.parts();
+                    let opcode = bytes
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect::<String>();
+                    (*address, opcode, mnemonic.to_string(), operands)
+                }
+                Line::Data { address, bytes } => {
+                    let opcode = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    (*address, opcode, "DATA".to_string(), vec![])
+                }
+            };
+            records.push(Record {
+                offset: address as usize - start,
+                address,
+                label: label_here.take(),
+                opcode,
+                mnemonic,
+                operands,
+            });
+        }
+        records
+    }
+
+    /// The whole disassembly in the given format.
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_string(),
+            Format::Json => self
+                .records()
+                .iter()
+                .map(|record| {
+                    let operands = record
+                        .operands
+                        .iter()
+                        .map(|operand| json_string(operand))
+                        .collect::<Vec<_>>();
+                    format!(
+                        "{{\"offset\":{},\"address\":\"{:03X}\",\"label\":{},\"opcode\":\"{}\",\"mnemonic\":\"{}\",\"operands\":[{}]}}\n",
+                        record.offset,
+                        record.address,
+                        record.label.as_deref().map_or("null".to_string(), json_string),
+                        record.opcode,
+                        record.mnemonic,
+                        operands.join(",")
+                    )
+                })
+                .collect(),
+            // None of the fields can have commas or quotes in them, so there's
+            // nothing to escape
+            Format::Csv => {
+                let mut csv =
+                    String::from("offset,address,label,opcode,mnemonic,operand1,operand2,operand3\n");
+                for record in self.records() {
+                    let mut operands = record.operands.clone();
+                    operands.resize(3, String::new());
+                    csv.push_str(&format!(
+                        "{},{:03X},{},{},{},{}\n",
+                        record.offset,
+                        record.address,
+                        record.label.unwrap_or_default(),
+                        record.opcode,
+                        record.mnemonic,
+                        operands.join(",")
+                    ));
+                }
+                csv
+            }
+        }
+    }
+
+    /// The instruction as assembly, with jump and call targets replaced by
+    /// their labels.
+    fn format_instruction(&self, instruction: &Instruction) -> String {
+        let with_label = |mnemonic: &str, address: u16| {
+            if self.labels.contains(&address) {
+                format!("{} {}", mnemonic, label(address))
+            } else {
+                instruction.to_string()
+            }
+        };
+        match instruction {
+            Instruction::JP(address) => with_label("JP", address.value()),
+            Instruction::CALL(address) => with_label("CALL", address.value()),
+            Instruction::JPOffset(address) => with_label("JP V0,", address.value()),
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Label(address) => writeln!(f, "{}:", label(*address))?,
+                Line::Code {
+                    address,
+                    instruction,
+                    bytes,
+                } => {
+                    let bytes = bytes
+                        .chunks(2)
+                        .map(|word| word.iter().map(|b| format!("{:02X}", b)).collect())
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    let note = if self.dynamic.contains(address) {
+                        " ; may be modified at runtime"
+                    } else {
+                        ""
+                    };
+                    writeln!(
+                        f,
+                        "    {:03X}: {:<9} {}{}",
+                        address,
+                        bytes,
+                        self.format_instruction(instruction),
+                        note
+                    )?
+                }
+                Line::Data { address, bytes } => {
+                    let hex = bytes
+                        .iter()
+                        .map(|b| format!("{:02X}", b))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    writeln!(f, "    {:03X}: {:<9} DATA", address, hex)?
+                }
+            }
+        }
+        if let Some(address) = self.cut_off {
+            writeln!(
+                f,
+                "; The code at {:03X} is cut off by the end of the ROM",
+                address
+            )?;
+        }
+        if let Some(address) = self.trailing_byte {
+            writeln!(
+                f,
+                "; The ROM has an odd number of bytes, so the last one (at {:03X}) is left over",
+                address
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::{program, Register};
+
+    #[test]
+    fn labels_jump_and_call_targets() {
+        let rom = program(&[
+            Instruction::CALL(0x206.into()),
+            Instruction::JP(0x200.into()),
+            Instruction::UNKNOWN(0xFFFF),
+            Instruction::RET(),
+        ]);
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert_eq!(
+            disassembly.lines,
+            vec![
+                Line::Label(0x200),
+                Line::Code {
+                    address: 0x200,
+                    instruction: Instruction::CALL(0x206.into()),
+                    bytes: vec![0x22, 0x06]
+                },
+                Line::Code {
+                    address: 0x202,
+                    instruction: Instruction::JP(0x200.into()),
+                    bytes: vec![0x12, 0x00]
+                },
+                Line::Data {
+                    address: 0x204,
+                    bytes: vec![0xFF, 0xFF]
+                },
+                Line::Label(0x206),
+                Line::Code {
+                    address: 0x206,
+                    instruction: Instruction::RET(),
+                    bytes: vec![0x00, 0xEE]
+                },
+            ]
+        );
+        let text = disassembly.to_string();
+        assert!(text.contains("CALL L_0206"));
+        assert!(text.contains("JP L_0200"));
+        assert!(text.contains("204: FF FF"));
+    }
+
+    #[test]
+    fn unreachable_bytes_are_data() {
+        // The sprite after the jump looks like LD V0, 0xFF but nothing runs it
+        let rom = program(&[
+            Instruction::JP(0x200.into()),
+            Instruction::LDByte(Register::from_nibble(0x0), 0xFF),
+        ]);
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert_eq!(
+            disassembly.lines[2],
+            Line::Data {
+                address: 0x202,
+                bytes: vec![0x60, 0xFF]
+            }
+        );
+    }
+
+    #[test]
+    fn skips_can_go_either_way() {
+        let rom = program(&[
+            Instruction::SEByte(Register::from_nibble(0x1), 0x2),
+            Instruction::JP(0x200.into()),
+            Instruction::RET(),
+        ]);
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert!(disassembly
+            .lines
+            .iter()
+            .all(|line| !matches!(line, Line::Data { .. })));
+    }
+
+    #[test]
+    fn data_ranges_are_never_code() {
+        // A jump over a sprite, and a jump into it that never runs
+        let rom = program(&[
+            Instruction::JP(0x204.into()),
+            Instruction::LDByte(Register::from_nibble(0x0), 0xFF),
+            Instruction::SEByte(Register::from_nibble(0x0), 0x1),
+            Instruction::JP(0x202.into()),
+        ]);
+        #[allow(clippy::single_range_in_vec_init)] // One range of data, not a list of addresses
+        let disassembly = disassemble_with_data(&rom, 0x200, &[0x202..0x204]).unwrap();
+
+        assert!(disassembly.lines.contains(&Line::Data {
+            address: 0x202,
+            bytes: vec![0x60, 0xFF]
+        }));
+        assert!(!disassembly
+            .lines
+            .iter()
+            .any(|line| matches!(line, Line::Code { address: 0x202, .. })));
+    }
+
+    #[test]
+    fn code_the_program_can_store_over_is_dynamic() {
+        // LD I, 208; LD V0, 12; LD [I], V0; LD V1, 00; then the JP at 208,
+        // which the store overwrites
+        let rom = program(&[
+            Instruction::LDI(0x208.into()),
+            Instruction::LDByte(Register::from_nibble(0x0), 0x12),
+            Instruction::LDIntoMemory(Register::from_nibble(0x0)),
+            Instruction::LDByte(Register::from_nibble(0x1), 0x00),
+            Instruction::JP(0x200.into()),
+        ]);
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert_eq!(disassembly.dynamic, vec![0x208].into_iter().collect());
+        assert!(disassembly
+            .to_string()
+            .contains("208: 1200      JP L_0200 ; may be modified at runtime"));
+    }
+
+    #[test]
+    fn programs_can_be_loaded_elsewhere() {
+        let rom = program(&[
+            Instruction::CALL(0x604.into()),
+            Instruction::JP(0x600.into()),
+            Instruction::RET(),
+        ]);
+        let disassembly = disassemble_with_data(&rom, 0x600, &[]).unwrap();
+
+        assert_eq!(disassembly.unknown_count(), 0);
+        assert!(disassembly.to_string().contains("604: 00EE      RET"));
+        assert_eq!(disassembly.records()[2].offset, 4);
+    }
+
+    #[test]
+    fn odd_and_cut_off_roms_say_so() {
+        // LD V0, 01 and then half of the next instruction
+        let disassembly = disassemble(&[0x60, 0x01, 0x12]).unwrap();
+
+        assert_eq!(
+            disassembly.lines[1],
+            Line::Data {
+                address: 0x202,
+                bytes: vec![0x12]
+            }
+        );
+        let text = disassembly.to_string();
+        assert!(text.contains("200: 6001      LD V0, 01"));
+        assert!(text.contains("202: 12        DATA"));
+        assert!(text.contains("The code at 202 is cut off by the end of the ROM"));
+        assert!(text.contains("the last one (at 202) is left over"));
+    }
+
+    #[test]
+    fn records_for_other_tools() {
+        let rom = program(&[
+            Instruction::DRW(Register::from_nibble(0x1), Register::from_nibble(0x2), 5),
+            Instruction::JP(0x200.into()),
+        ]);
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert_eq!(
+            disassembly.records()[0],
+            Record {
+                offset: 0,
+                address: 0x200,
+                label: Some("L_0200".to_string()),
+                opcode: "D125".to_string(),
+                mnemonic: "DRW".to_string(),
+                operands: vec!["V1".to_string(), "V2".to_string(), "5".to_string()],
+            }
+        );
+        assert_eq!(
+            disassembly.render(Format::Json).lines().nth(1),
+            Some("{\"offset\":2,\"address\":\"202\",\"label\":null,\"opcode\":\"1200\",\"mnemonic\":\"JP\",\"operands\":[\"200\"]}")
+        );
+        assert_eq!(
+            disassembly.render(Format::Csv).lines().collect::<Vec<_>>(),
+            vec![
+                "offset,address,label,opcode,mnemonic,operand1,operand2,operand3",
+                "0,200,L_0200,D125,DRW,V1,V2,5",
+                "2,202,,1200,JP,200,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembles_a_full_size_xo_chip_program() {
+        // SYS 000 all the way to the end of 64KB, with a skip just before the
+        // last instruction
+        let mut rom = vec![0; memory::XO_CHIP_MEMORY_SIZE - memory::PROGRAM_START];
+        let skip = rom.len() - 4;
+        rom[skip..skip + 2].copy_from_slice(&program(&[Instruction::SEByte(
+            Register::from_nibble(0x0),
+            0,
+        )]));
+
+        let disassembly = disassemble(&rom).unwrap();
+
+        assert_eq!(
+            disassembly.lines.last(),
+            Some(&Line::Code {
+                address: 0xFFFE,
+                instruction: Instruction::SYS(0x000.into()),
+                bytes: vec![0x00, 0x00],
+            })
+        );
+    }
+
+    #[test]
+    fn disassemble_a_directory() {
+        let directory = std::env::temp_dir().join(format!("chip8-roms-{}", std::process::id()));
+        let output = directory.join("listings");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("a.ch8"), program(&[Instruction::RET()])).unwrap();
+        fs::write(directory.join("b.ch8"), [0x12, 0x02, 0xFF, 0xFF]).unwrap();
+        fs::write(directory.join("notes.txt"), "not a ROM").unwrap();
+
+        let count = disassemble_all(&directory, &output).unwrap();
+        let index = fs::read_to_string(output.join("index.txt")).unwrap();
+        let listing = fs::read_to_string(output.join("a.lst")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(count, 2);
+        let rows = index
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows[1], vec!["a.ch8", "2", "0"]);
+        // JP 202 reaches FFFF
+        assert_eq!(rows[2], vec!["b.ch8", "4", "1"]);
+        assert!(listing.contains("RET"));
+    }
+}
@@ -1,65 +1,543 @@
-use minifb::{Key, Window, WindowOptions};
-use std::time::Duration;
+use crate::error::Chip8Error;
+#[cfg(feature = "window")]
+use crate::{
+    flash::FlashGuard,
+    frontend::{Frontend, Shader, WindowBackend},
+    idle::IdleDetector,
+    instruction::Instruction,
+    interpreter::State,
+    keymap::Keymap,
+    phosphor::Phosphor,
+    timing::FramePacer,
+};
+#[cfg(feature = "gui")]
+use minifb::{KeyRepeat, ScaleMode, Window, WindowOptions};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "window")]
+use std::path::PathBuf;
+use std::{
+    hash::{Hash, Hasher},
+    iter,
+};
 
-const CHIP8_WIDTH: usize = 64;
-const CHIP8_HEIGHT: usize = 32;
-// Our display is 10x bigger than CHIP-8 in every direction
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+// The SCHIP's high-resolution mode doubles the screen in every direction
+pub const SCHIP_WIDTH: usize = 128;
+pub const SCHIP_HEIGHT: usize = 64;
+// By default, our display is 10x bigger than CHIP-8 in every direction
 const SCALE: usize = 10;
+// The default colors
 pub const ON: u32 = 0xFF_FF_FF; // white
 pub const OFF: u32 = 0; // black
-const SIXTY_FPS: Duration = Duration::from_micros(16600);
-
-/// A framebuffer that pretends to be 10x smaller than it is. This lets it
-/// display a 64x32 screen at 640x320. It scales pixels proportionately, too:
-/// flipping a logical pixel at (0, 0) flips all 100 physical pixels from (0, 0)
-/// to (9, 9).
-#[derive(Debug, Clone, PartialEq)]
-pub struct ScaledFramebuffer {
-    buffer: Vec<u32>,
-    pub true_width: usize,
-    pub true_height: usize,
+/// XO-CHIP has 2 drawing planes, so every pixel is one of 4 colors. The index
+/// into this array is the pixel's plane bits: 0b01 is plane 1, 0b10 is plane 2.
+pub const PALETTE: [u32; 4] = [OFF, ON, 0xAA_AA_AA, 0x55_55_55];
+// The debug overlay's text is drawn in this color, on a dimmed background
+const OVERLAY_TEXT: u32 = 0x00_FF_00; // green
+                                      // Every pixel of the overlay's font is this many physical pixels across and down
+const OVERLAY_SCALE: usize = 2;
+// The timeline of the rewind history is a bar this many physical pixels tall,
+// along the bottom of the screen. The part before the frame that's shown is
+// green, and the part after it is gray.
+const TIMELINE_HEIGHT: usize = 4;
+const TIMELINE_AFTER: u32 = 0x40_40_40; // gray
+                                        // Each character is 3x5 pixels, with a gap of 1 pixel to its right and below it
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+// `Framebuffer::pretty_print_thumbnail` is this many characters across and down
+const THUMBNAIL_WIDTH: usize = 32;
+const THUMBNAIL_HEIGHT: usize = 8;
+/// The keys for the CHIP-8's 16-key keypad, in order from 0 to F. The keypad
+/// was laid out like this, so it maps onto the left side of a QWERTY keyboard:
+///
+///     1 2 3 C        1 2 3 4
+///     4 5 6 D   ->   Q W E R
+///     7 8 9 E        A S D F
+///     A 0 B F        Z X C V
+///
+/// `Keymap` can bind them to other keys.
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub const KEYPAD: [Key; 16] = [
+    Key::X,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Q,
+    Key::W,
+    Key::E,
+    Key::A,
+    Key::S,
+    Key::D,
+    Key::Z,
+    Key::C,
+    Key::Key4,
+    Key::R,
+    Key::F,
+    Key::V,
+];
+/// The keys that control the emulator while a program runs, which mustn't
+/// also be keypad keys. The questions asked while paused (like about unknown
+/// instructions) can use keypad keys, since the program isn't reading them.
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub const HOTKEYS: [Key; 13] = [
+    Key::Escape,
+    Key::P,
+    Key::Period,
+    Key::Tab,
+    Key::Space,
+    Key::Backspace,
+    Key::Left,
+    Key::Right,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F5,
+    Key::F9,
+];
+#[cfg(feature = "window")]
+pub const TITLE: &str = "CHIP-8 - ESC to exit";
+
+/// How big the screen is drawn, and in which colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Appearance {
+    /// How many physical pixels across (and down) one CHIP-8 pixel is. It's
+    /// even, so that SCHIP's high-resolution pixels are half as big.
+    pub scale: usize,
+    /// The color of pixels that are on
+    pub foreground: u32,
+    /// The color of pixels that are off
+    pub background: u32,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            scale: SCALE,
+            foreground: ON,
+            background: OFF,
+        }
+    }
+}
+
+impl Appearance {
+    /// `PALETTE`, with these colors for plane 1 and for blank pixels.
+    fn palette(&self) -> [u32; 4] {
+        [self.background, self.foreground, PALETTE[2], PALETTE[3]]
+    }
+}
+
+/// The part of the screen that's changed since it was last drawn, in logical
+/// pixels. `right` and `bottom` are just past the last column and row that
+/// changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirtyRegion {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+
+impl DirtyRegion {
+    /// Just the pixel at (x, y).
+    fn pixel(x: usize, y: usize) -> Self {
+        Self {
+            left: x,
+            top: y,
+            right: x + 1,
+            bottom: y + 1,
+        }
+    }
+
+    /// The whole of a screen that's `width` by `height`.
+    fn whole(width: usize, height: usize) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        }
+    }
+
+    /// The smallest region that covers both of them.
+    fn union(self, other: Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+/// A change to the screen, with what it was before, so it can be undone (see
+/// `Framebuffer::record_changes`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenChange {
+    /// The planes that were set at logical location (x, y)
+    Pixel(usize, usize, u8),
+    /// The resolution, and every pixel, from before switching resolution
+    /// cleared the screen
+    Resolution {
+        width: usize,
+        height: usize,
+        scale: usize,
+        pixels: Vec<u8>,
+    },
+}
+
+/// The CHIP-8's screen, at its logical resolution: 64x32, or 128x64 in SCHIP
+/// high-resolution mode (see `set_logical_size`). Each pixel is stored once,
+/// as which XO-CHIP planes are set there.
+/// Frontends scale it up when they draw it (see `scaled_pixels`), so that
+/// every logical pixel is 10x10 physical pixels (or however many
+/// `Appearance::scale` says), and a 64x32 screen is drawn at 640x320. The
+/// physical size stays the same when the logical resolution changes, so in
+/// high-resolution mode every logical pixel is 5x5 physical pixels instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Framebuffer {
+    /// Which planes are set at each logical pixel, row by row
+    pixels: Vec<u8>,
+    /// The logical width, in CHIP-8 pixels
+    pub width: usize,
+    /// The logical height, in CHIP-8 pixels
+    pub height: usize,
+    /// How many physical pixels across (and down) one logical pixel is drawn
+    /// as
+    scale: usize,
+    /// The color of each combination of XO-CHIP planes, like `PALETTE`
+    palette: [u32; 4],
+    /// What's changed since `mark_drawn`. A framebuffer that was just made
+    /// (or loaded from a saved state) hasn't been drawn at all, so all of it
+    /// has.
+    #[serde(skip, default = "Framebuffer::never_drawn")]
+    dirty: Option<DirtyRegion>,
+    /// Every change since `record_changes`, oldest first, or None if they
+    /// aren't being recorded
+    #[serde(skip)]
+    changes: Option<Vec<ScreenChange>>,
 }
 
-impl ScaledFramebuffer {
+/// Two framebuffers are the same if they look the same, whether or not
+/// they've been drawn yet.
+impl PartialEq for Framebuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.pixels == other.pixels
+            && (self.width, self.height, self.scale) == (other.width, other.height, other.scale)
+            && self.palette == other.palette
+    }
+}
+
+impl Hash for Framebuffer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pixels.hash(state);
+        (self.width, self.height, self.scale).hash(state);
+        self.palette.hash(state);
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
     /// Initialize with the CHIP-8's width and height.
     pub fn new() -> Self {
-        Self::with_size(CHIP8_WIDTH, CHIP8_HEIGHT)
+        Self::with_appearance(&Appearance::default())
+    }
+
+    /// Initialize with the CHIP-8's width and height, drawn at the given scale
+    /// and in the given colors.
+    pub fn with_appearance(appearance: &Appearance) -> Self {
+        Self::with_size(CHIP8_WIDTH, CHIP8_HEIGHT, appearance)
     }
 
     /// Create a framebuffer from logical pixels. So for the CHIP-8, which has a
-    /// 64x32 screen, pass in 64 and 32, and it will draw it on a 640x320
-    /// display.
-    fn with_size(logical_width: usize, logical_height: usize) -> Self {
-        let scaled_width = logical_width * SCALE;
-        let scaled_height = logical_height * SCALE;
+    /// 64x32 screen, pass in 64 and 32, and at the default scale it will be
+    /// drawn on a 640x320 display.
+    fn with_size(logical_width: usize, logical_height: usize, appearance: &Appearance) -> Self {
         Self {
             // Start with a blank screen
-            buffer: vec![OFF; scaled_width * scaled_height],
-            true_width: scaled_width,
-            true_height: scaled_height,
+            pixels: vec![0; logical_width * logical_height],
+            width: logical_width,
+            height: logical_height,
+            scale: appearance.scale,
+            palette: appearance.palette(),
+            dirty: Some(DirtyRegion::whole(logical_width, logical_height)),
+            changes: None,
+        }
+    }
+
+    /// How big the screen is drawn, and in which colors, to draw another one
+    /// the same way. The scale is for the CHIP-8's 64x32 screen, like
+    /// `with_appearance` takes, even in SCHIP's high resolution.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn appearance(&self) -> Appearance {
+        Appearance {
+            scale: self.true_width() / CHIP8_WIDTH,
+            foreground: self.palette[1],
+            background: self.palette[0],
+        }
+    }
+
+    /// Everything's dirty, however big the screen turns out to be (see
+    /// `dirty_region`).
+    fn never_drawn() -> Option<DirtyRegion> {
+        Some(DirtyRegion::whole(usize::MAX, usize::MAX))
+    }
+
+    /// The part of the screen that's changed since `mark_drawn`, or nothing
+    /// if it's all the same, so frontends don't have to draw it again.
+    pub fn dirty_region(&self) -> Option<DirtyRegion> {
+        self.dirty.map(|dirty| DirtyRegion {
+            right: dirty.right.min(self.width),
+            bottom: dirty.bottom.min(self.height),
+            ..dirty
+        })
+    }
+
+    /// Start tracking changes again, because everything that changed has been
+    /// drawn.
+    pub fn mark_drawn(&mut self) {
+        self.dirty = None;
+    }
+
+    /// Remember that the region changed.
+    fn mark_dirty(&mut self, region: DirtyRegion) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    /// Start recording every change to the screen, forgetting any that were
+    /// recorded before, so they can be undone.
+    pub fn record_changes(&mut self) {
+        self.changes = Some(vec![]);
+    }
+
+    /// Stop recording changes, and return the ones that were recorded, oldest
+    /// first.
+    pub fn take_changes(&mut self) -> Vec<ScreenChange> {
+        self.changes.take().unwrap_or_default()
+    }
+
+    /// Put the screen back the way it was before the changes, which are oldest
+    /// first, like `take_changes` returns them.
+    pub fn undo(&mut self, changes: Vec<ScreenChange>) {
+        for change in changes.into_iter().rev() {
+            match change {
+                ScreenChange::Pixel(x, y, planes) => self.set_planes(x, y, planes),
+                ScreenChange::Resolution {
+                    width,
+                    height,
+                    scale,
+                    pixels,
+                } => {
+                    self.width = width;
+                    self.height = height;
+                    self.scale = scale;
+                    self.pixels = pixels;
+                    self.mark_dirty(DirtyRegion::whole(width, height));
+                }
+            }
+        }
+    }
+
+    /// How many physical pixels across the screen is drawn as.
+    pub fn true_width(&self) -> usize {
+        self.width * self.scale
+    }
+
+    /// How many physical pixels down the screen is drawn as.
+    pub fn true_height(&self) -> usize {
+        self.height * self.scale
+    }
+
+    /// Change the logical resolution without changing the physical size, for
+    /// switching between the CHIP-8's 64x32 screen and the SCHIP's 128x64
+    /// screen. The physical size must be an exact multiple of the new logical
+    /// size, or this fails with `CannotScale` and leaves the screen alone.
+    /// Otherwise, this clears the screen.
+    pub fn set_logical_size(
+        &mut self,
+        logical_width: usize,
+        logical_height: usize,
+    ) -> Result<(), Chip8Error> {
+        let (true_width, true_height) = (self.true_width(), self.true_height());
+        let scale = true_width.checked_div(logical_width).unwrap_or(0);
+        if scale == 0
+            || scale * logical_width != true_width
+            || scale * logical_height != true_height
+        {
+            return Err(Chip8Error::CannotScale {
+                logical_width,
+                logical_height,
+                true_width,
+                true_height,
+            });
+        }
+        let pixels = vec![0; logical_width * logical_height];
+        let old_pixels = std::mem::replace(&mut self.pixels, pixels);
+        if let Some(changes) = self.changes.as_mut() {
+            changes.push(ScreenChange::Resolution {
+                width: self.width,
+                height: self.height,
+                scale: self.scale,
+                pixels: old_pixels,
+            });
+        }
+        self.scale = scale;
+        self.width = logical_width;
+        self.height = logical_height;
+        self.mark_dirty(DirtyRegion::whole(logical_width, logical_height));
+        Ok(())
+    }
+
+    /// Each row of logical pixels, from the top, as which planes are set at
+    /// each pixel (see `get_planes`). This is what frontends that do their own
+    /// scaling should draw from.
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks_exact(self.width)
+    }
+
+    /// The physical pixels to draw, as 0RGB: `true_width` by `true_height`,
+    /// with every logical pixel scaled up to `scale` by `scale` pixels.
+    pub fn scaled_pixels(&self) -> Vec<u32> {
+        let logical = self
+            .pixels
+            .iter()
+            .map(|planes| self.palette[*planes as usize])
+            .collect::<Vec<_>>();
+        self.scale_up(&logical)
+    }
+
+    /// Scale up colors for each logical pixel (as 0RGB, row by row) to the
+    /// physical pixels to draw, like `scaled_pixels` does with the screen's
+    /// own colors.
+    pub fn scale_up(&self, logical: &[u32]) -> Vec<u32> {
+        let true_width = self.true_width();
+        let mut pixels = Vec::with_capacity(true_width * self.true_height());
+        for row in logical.chunks_exact(self.width) {
+            let scaled_row = row
+                .iter()
+                .flat_map(|color| iter::repeat_n(*color, self.scale))
+                .collect::<Vec<_>>();
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&scaled_row);
+            }
         }
+        pixels
     }
 
-    pub fn as_bytes(&self) -> &Vec<u32> {
-        &self.buffer
+    /// Fit physical pixels drawn from this framebuffer (like `scaled_pixels`,
+    /// maybe with the overlay over them) into a window of a different size:
+    /// every logical pixel is scaled up the same whole number of times, as
+    /// many as fit, and centered, with black bars around the rest.
+    /// A window too small for even one physical pixel per logical pixel just
+    /// shows the top left.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn fit(&self, pixels: &[u32], window_width: usize, window_height: usize) -> Vec<u32> {
+        let scale = (window_width / self.width)
+            .min(window_height / self.height)
+            .max(1);
+        let (width, height) = (self.width * scale, self.height * scale);
+        let left = window_width.saturating_sub(width) / 2;
+        let top = window_height.saturating_sub(height) / 2;
+        let true_width = self.true_width();
+        let mut fitted = vec![OFF; window_width * window_height];
+        for y in 0..height.min(window_height) {
+            // Each logical pixel is `scale` pixels across in the window, and
+            // `self.scale` in `pixels`
+            let source = y * self.scale / scale * true_width;
+            let row = &mut fitted[(top + y) * window_width..(top + y + 1) * window_width];
+            for (x, pixel) in row[left..].iter_mut().take(width).enumerate() {
+                *pixel = pixels[source + x * self.scale / scale];
+            }
+        }
+        fitted
     }
 
-    /// Get the value of a pixel at logical location (x, y).
-    /// It only checks one physical pixel, and assumes all of the other pixels
-    /// that make up this one logical pixel have the same value.
+    /// Get the color of the pixel at logical location (x, y).
     pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
-        self.buffer[(SCALE * x) + (SCALE * y * self.true_width)]
+        self.palette[self.get_planes(x, y) as usize]
     }
 
-    /// Set the value of a pixel at logical location (x, y).
-    /// Behind the scenes, this actually sets `SCALE * SCALE` physical pixels because
-    /// it sets `SCALE` pixels across times `SCALE` pixels down.
+    /// Set the pixel at logical location (x, y) to the given color. Colors
+    /// that aren't in the palette turn the pixel off.
     pub fn set_pixel(&mut self, x: usize, y: usize, new_value: u32) {
-        for x_offset in 0..SCALE {
-            let scaled_x = SCALE * x + x_offset;
-            for y_offset in 0..SCALE {
-                let scaled_y = (SCALE * y + y_offset) * self.true_width;
-                self.buffer[scaled_x + scaled_y] = new_value;
+        let planes = self
+            .palette
+            .iter()
+            .position(|c| *c == new_value)
+            .unwrap_or(0);
+        self.set_planes(x, y, planes as u8);
+    }
+
+    /// Set which XO-CHIP planes are set at logical location (x, y).
+    fn set_planes(&mut self, x: usize, y: usize, planes: u8) {
+        let index = y * self.width + x;
+        if self.pixels[index] != planes {
+            if let Some(changes) = self.changes.as_mut() {
+                changes.push(ScreenChange::Pixel(x, y, self.pixels[index]));
+            }
+            self.pixels[index] = planes;
+            self.mark_dirty(DirtyRegion::pixel(x, y));
+        }
+    }
+
+    /// Move every logical pixel down by `rows`, filling the top with blank rows.
+    pub fn scroll_down(&mut self, rows: usize) {
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let planes = if y >= rows {
+                    self.get_planes(x, y - rows)
+                } else {
+                    0
+                };
+                self.set_planes(x, y, planes);
+            }
+        }
+    }
+
+    /// Move every logical pixel left by `columns`, filling the right side with
+    /// blank columns.
+    pub fn scroll_left(&mut self, columns: usize) {
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let planes = if x + columns < self.width {
+                    self.get_planes(x + columns, y)
+                } else {
+                    0
+                };
+                self.set_planes(x, y, planes);
+            }
+        }
+    }
+
+    /// Move every logical pixel right by `columns`, filling the left side with
+    /// blank columns.
+    pub fn scroll_right(&mut self, columns: usize) {
+        for x in (0..self.width).rev() {
+            for y in 0..self.height {
+                let planes = if x >= columns {
+                    self.get_planes(x - columns, y)
+                } else {
+                    0
+                };
+                self.set_planes(x, y, planes);
+            }
+        }
+    }
+
+    /// Turn off the given XO-CHIP planes (a bitmask from 0 to 3) of every
+    /// logical pixel. Regular CHIP-8 only ever has plane 0b01 selected.
+    pub fn clear(&mut self, planes: u8) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.set_planes(x, y, self.get_planes(x, y) & !planes);
             }
         }
     }
@@ -68,141 +546,1185 @@ impl ScaledFramebuffer {
     /// (true = 1, false = 0).
     /// If the input bit is 0, does nothing.
     /// If the input bit is 1, flips the value at (x, y).
+    /// Only the given plane (0b01, or 0b10 for XO-CHIP's second plane) of the
+    /// pixel is flipped; regular CHIP-8 drawing always uses plane 0b01.
     /// Returns true if a set pixel was changed to unset, and false otherwise.
-    pub fn xor(&mut self, input_bit: bool, x: usize, y: usize) -> bool {
+    pub fn xor(&mut self, input_bit: bool, x: usize, y: usize, plane: u8) -> bool {
         if !input_bit {
             debug!("xor ({}, {}): input is 0, not doing anything", x, y);
             return false;
         }
 
-        if self.get_pixel(x, y) == ON {
-            debug!("xor ({}, {}): Flipping from ON to OFF", x, y);
-            self.set_pixel(x, y, OFF);
+        let planes = self.get_planes(x, y);
+        if planes & plane != 0 {
+            debug!(
+                "xor ({}, {}): Flipping plane {} from ON to OFF",
+                x, y, plane
+            );
+            self.set_planes(x, y, planes & !plane);
             true
         } else {
-            debug!("xor ({}, {}): Flipping from OFF to ON", x, y);
-            self.set_pixel(x, y, ON);
+            debug!(
+                "xor ({}, {}): Flipping plane {} from OFF to ON",
+                x, y, plane
+            );
+            self.set_planes(x, y, planes | plane);
             false
         }
     }
 
+    /// Get which XO-CHIP planes are set at logical location (x, y), as a
+    /// bitmask from 0 to 3.
+    pub fn get_planes(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
     /// Pretty-print a grid of 1 (on) and 0 (off) that represents the screen.
     /// Prints physical pixels, for debugging.
     pub fn pretty_print_physical(&self) -> String {
         let mut result = vec![];
-        for (index, row) in self.buffer.chunks_exact(self.true_width).enumerate() {
+        let pixels = self.scaled_pixels();
+        for (index, row) in pixels.chunks_exact(self.true_width()).enumerate() {
             let column = row
                 .iter()
-                .map(|b| format!("{}", if b == &ON { 1 } else { 0 }))
+                .map(|b| format!("{}", if b == &self.palette[1] { 1 } else { 0 }))
                 .collect::<Vec<_>>();
             result.push(format!("{} {}", index, column.join("")));
         }
         result.join("\n")
     }
 
+    /// The whole screen as text, one character per logical pixel: `#` if it's
+    /// on and `.` otherwise.
+    pub fn pretty_print_logical(&self) -> String {
+        let mut result = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                result.push(if self.get_planes(x, y) != 0 { '#' } else { '.' });
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    /// The screen as a plain PBM image, at its logical resolution. PBM is
+    /// about the simplest image format there is: a header, then a 1 for every
+    /// pixel that's on (black) and a 0 for every pixel that's off (white).
+    /// Most image viewers can open it, and it's easy to diff.
+    pub fn to_pbm(&self) -> String {
+        let mut result = format!("P1\n{} {}\n", self.width, self.height);
+        for y in 0..self.height {
+            let row = (0..self.width)
+                .map(|x| if self.get_planes(x, y) != 0 { "1" } else { "0" })
+                .collect::<Vec<_>>();
+            result.push_str(&row.join(" "));
+            result.push('\n');
+        }
+        result
+    }
+
+    /// Draw `lines` of text over the top left of `pixels` (which are this
+    /// framebuffer's physical size, like the ones from `scaled_pixels`), for
+    /// the debug overlay. Each line gets a dimmed background so it's readable
+    /// over whatever the program drew. Text that doesn't fit is cut off.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn draw_overlay(&self, pixels: &mut [u32], lines: &[String]) {
+        let (true_width, true_height) = (self.true_width(), self.true_height());
+        let cell_width = (GLYPH_WIDTH + 1) * OVERLAY_SCALE;
+        let cell_height = (GLYPH_HEIGHT + 1) * OVERLAY_SCALE;
+        for (row, line) in lines.iter().enumerate() {
+            let top = row * cell_height;
+            if top + cell_height > true_height {
+                break;
+            }
+            let width = (line.chars().count() * cell_width + OVERLAY_SCALE).min(true_width);
+            for y in top..top + cell_height {
+                for pixel in &mut pixels[y * true_width..y * true_width + width] {
+                    // Halve every channel
+                    *pixel = (*pixel >> 1) & 0x7F_7F_7F;
+                }
+            }
+            for (column, c) in line.chars().enumerate() {
+                let left = OVERLAY_SCALE + column * cell_width;
+                if left + cell_width > true_width {
+                    break;
+                }
+                for (dy, bits) in glyph(c).iter().enumerate() {
+                    for dx in 0..GLYPH_WIDTH {
+                        if bits & (0b100 >> dx) == 0 {
+                            continue;
+                        }
+                        for sy in 0..OVERLAY_SCALE {
+                            for sx in 0..OVERLAY_SCALE {
+                                let x = left + dx * OVERLAY_SCALE + sx;
+                                let y = top + OVERLAY_SCALE + dy * OVERLAY_SCALE + sy;
+                                if y < true_height {
+                                    pixels[y * true_width + x] = OVERLAY_TEXT;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw a timeline along the bottom of `pixels` (which are this
+    /// framebuffer's physical size, like the ones from `scaled_pixels`),
+    /// showing how far through `length` frames `position` is.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn draw_timeline(&self, pixels: &mut [u32], position: usize, length: usize) {
+        let true_width = self.true_width();
+        let done = match length {
+            0 => true_width,
+            length => position * true_width / length,
+        };
+        let top = self.true_height().saturating_sub(TIMELINE_HEIGHT);
+        for row in pixels[top * true_width..].chunks_mut(true_width) {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = if x < done {
+                    OVERLAY_TEXT
+                } else {
+                    TIMELINE_AFTER
+                };
+            }
+        }
+    }
+
+    /// A small ASCII picture of the screen, `THUMBNAIL_WIDTH` by
+    /// `THUMBNAIL_HEIGHT` characters. Each character stands for a block of
+    /// logical pixels, and is `#` if any of them are on and `.` otherwise.
+    pub fn pretty_print_thumbnail(&self) -> String {
+        let block_width = self.width / THUMBNAIL_WIDTH;
+        let block_height = self.height / THUMBNAIL_HEIGHT;
+        let mut result = vec![];
+        for row in 0..THUMBNAIL_HEIGHT {
+            let line = (0..THUMBNAIL_WIDTH)
+                .map(|column| {
+                    let any_on = (0..block_height).any(|dy| {
+                        (0..block_width).any(|dx| {
+                            let x = column * block_width + dx;
+                            let y = row * block_height + dy;
+                            self.get_planes(x, y) != 0
+                        })
+                    });
+                    if any_on {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            result.push(line);
+        }
+        result.join("\n")
+    }
+
     /// Draw the given sprite at logical location (x, y).
     /// The sprite is interpreted as a bit pattern with 0 = off and 1 = on.
     /// For example, these 3 bytes would draw a "0":
     /// 00111100
     /// 00100100
     /// 00111100
-    /// Returns true if a set pixel was changed to unset, and false otherwise.
-    pub fn draw_sprite_at(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
-        let mut changed_from_on_to_off = false;
-        let bit_is_set = |byte: &u8, position: u8| ((byte & (1 << position)) >> position) == 1;
-        for (y_offset, row) in sprite.iter().enumerate() {
-            // Move left across the bits of the byte:
+    /// It's drawn on the given plane, as with `xor`.
+    /// If `clip` is true, any part of the sprite that goes past the edge of the
+    /// screen is cut off.
+    /// Returns the logical (x, y) of every set pixel that was changed to
+    /// unset, so it collided if that isn't empty.
+    pub fn draw_sprite_at(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        plane: u8,
+        clip: bool,
+    ) -> Vec<(usize, usize)> {
+        let rows = sprite.iter().map(|byte| u16::from(*byte) << 8);
+        self.draw_rows_at(x, y, rows, 8, plane, clip)
+    }
+
+    /// Draw the given SCHIP 16x16 sprite at logical location (x, y).
+    /// Every row is 2 bytes wide, so the sprite is 32 bytes in total.
+    /// Returns where it collided, as with `draw_sprite_at`.
+    pub fn draw_wide_sprite_at(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        plane: u8,
+        clip: bool,
+    ) -> Vec<(usize, usize)> {
+        let rows = sprite
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        self.draw_rows_at(x, y, rows, 16, plane, clip)
+    }
+
+    /// Draw rows of a sprite, reading the leftmost `width` bits of each row.
+    /// The starting location always wraps around the screen, so drawing at
+    /// (70, 0) on a 64x32 screen draws at (6, 0). Pixels past the edge of the
+    /// screen are clipped if `clip` is true and wrap around otherwise.
+    fn draw_rows_at(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: impl Iterator<Item = u16>,
+        width: usize,
+        plane: u8,
+        clip: bool,
+    ) -> Vec<(usize, usize)> {
+        let mut changed_from_on_to_off = vec![];
+        let bit_is_set = |row: u16, position: usize| ((row >> position) & 1) == 1;
+        let x = x % self.width;
+        let y = y % self.height;
+        for (y_offset, row) in rows.enumerate() {
+            if clip && y + y_offset >= self.height {
+                break;
+            }
+            let pixel_y = (y + y_offset) % self.height;
+            // Move left across the bits of the row:
             // 11010001
             // ^-------
             // 11010001
             //  ^------
-            for x_offset in 0..=7 {
-                let input_bit = bit_is_set(row, (7 - x_offset) as u8);
-                let result = self.xor(input_bit, x + x_offset, y + y_offset);
-                changed_from_on_to_off = result || changed_from_on_to_off;
+            for x_offset in 0..width {
+                if clip && x + x_offset >= self.width {
+                    break;
+                }
+                let pixel_x = (x + x_offset) % self.width;
+                let input_bit = bit_is_set(row, 15 - x_offset);
+                if self.xor(input_bit, pixel_x, pixel_y, plane) {
+                    changed_from_on_to_off.push((pixel_x, pixel_y));
+                }
             }
         }
         changed_from_on_to_off
     }
 }
 
-/// It knows how to draw a `ScaledFramebuffer` to the screen.
-pub struct Display {
+/// The screen at its logical resolution, in the shapes that graphics
+/// libraries take, for frontends that do their own scaling, like a GPU
+/// texture that's stretched to fit the window.
+impl Framebuffer {
+    /// The logical width, in CHIP-8 pixels: 64, or 128 in high-resolution
+    /// mode.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The logical height, in CHIP-8 pixels: 32, or 64 in high-resolution
+    /// mode.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Every logical pixel's color as 4 bytes (red, green, blue, and an alpha
+    /// of 0xFF), row by row from the top: `width() * height() * 4` bytes in
+    /// all, which is what OpenGL, wgpu, and the image crate take as RGBA8.
+    pub fn as_rgba_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|planes| {
+                let [_, r, g, b] = self.palette[*planes as usize].to_be_bytes();
+                vec![r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    /// One bit per logical pixel, set if any plane is on there, 8 pixels to a
+    /// byte with the leftmost one in the highest bit, like a sprite. Each row
+    /// is `width() / 8` bytes.
+    pub fn as_packed_bits(&self) -> Vec<u8> {
+        self.pixels
+            .chunks(8)
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, planes)| **planes != 0)
+                    .fold(0, |byte, (x, _)| byte | (0x80 >> x))
+            })
+            .collect()
+    }
+}
+
+/// Whole-screen comparisons, so tests can check what's drawn against a grid
+/// written out in the test (or recorded in a file, like `tests/rom_suite.rs`
+/// does) instead of one `get_pixel` at a time.
+impl Framebuffer {
+    /// The screen as a grid of digits, one per logical pixel, saying which
+    /// XO-CHIP planes are set there (see `get_planes`): `0` for off and `1`
+    /// for on in plain CHIP-8. This is what `from_logical_bitstring` reads.
+    pub fn to_logical_bitstring(&self) -> String {
+        let mut result = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                result.push_str(&self.get_planes(x, y).to_string());
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    /// A framebuffer with the logical size and pixels of a grid like
+    /// `to_logical_bitstring` makes. Whitespace around each line, and blank
+    /// lines, are ignored, so the grid can be an indented multi-line literal.
+    pub fn from_logical_bitstring(s: &str) -> Result<Self, String> {
+        let rows = s
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        let width = rows.first().map_or(0, |row| row.len());
+        if width == 0 {
+            return Err("The screen is empty".to_string());
+        }
+        let mut fb = Self::with_size(width, rows.len(), &Appearance::default());
+        for (y, row) in rows.iter().enumerate() {
+            if row.len() != width {
+                return Err(format!(
+                    "Row {} is {} pixels wide instead of {}",
+                    y,
+                    row.len(),
+                    width
+                ));
+            }
+            for (x, c) in row.chars().enumerate() {
+                let planes = match c.to_digit(4) {
+                    Some(planes) => planes as usize,
+                    None => return Err(format!("Unexpected {:?} at ({}, {})", c, x, y)),
+                };
+                fb.set_pixel(x, y, fb.palette[planes]);
+            }
+        }
+        Ok(fb)
+    }
+
+    /// Nothing if this screen has the same logical pixels as `expected`.
+    /// Otherwise, a grid like `to_logical_bitstring` makes, but with every
+    /// pixel that's different marked as `X`, and how many there are.
+    pub fn diff(&self, expected: &Self) -> Option<String> {
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return Some(format!(
+                "The screen is {}x{} instead of {}x{}",
+                self.width, self.height, expected.width, expected.height
+            ));
+        }
+        let mut grid = String::new();
+        let mut mismatches = 0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let planes = self.get_planes(x, y);
+                if planes == expected.get_planes(x, y) {
+                    grid.push_str(&planes.to_string());
+                } else {
+                    grid.push('X');
+                    mismatches += 1;
+                }
+            }
+            grid.push('\n');
+        }
+        if mismatches == 0 {
+            None
+        } else {
+            Some(format!("{} pixels are different:\n{}", mismatches, grid))
+        }
+    }
+}
+
+/// The debug overlay's 3x5 font. Each row is 3 bits, with the leftmost pixel
+/// in the highest bit. Lowercase letters are drawn as uppercase, and anything
+/// else without a glyph is drawn as a space.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// The keys that the window reads: the keys that can be keypad keys (every
+/// letter and digit, see `KEYPAD` and `Keymap`) and the hotkeys. Every
+/// `Backend` knows which of its own keys each one is.
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Escape,
+    Period,
+    Tab,
+    Space,
+    Backspace,
+    Left,
+    Right,
+    F1,
+    F2,
+    F3,
+    F5,
+    F9,
+}
+
+/// A library that can open a window, draw pixels in it, and read the
+/// keyboard. `Display` does everything else, so it works the same with any of
+/// them.
+#[cfg(feature = "window")]
+pub trait Backend {
+    /// Whether the window is still open.
+    fn is_open(&self) -> bool;
+
+    fn is_key_down(&self, key: Key) -> bool;
+
+    /// Whether the key was pressed since the last `update`. Holding it down
+    /// doesn't count as pressing it again.
+    fn is_key_pressed(&self, key: Key) -> bool;
+
+    fn set_title(&mut self, title: &str);
+
+    /// Draw the pixels (as 0RGB) and read the keyboard again.
+    fn update(&mut self, pixels: &[u32], width: usize, height: usize);
+
+    /// Read the keyboard again, keeping what was drawn last, because nothing
+    /// on the screen has changed.
+    fn refresh(&mut self);
+
+    /// Frames are coming as fast as possible, or back to 60 a second. The
+    /// backend doesn't have to keep time itself (`Display` does, with a
+    /// `FramePacer`), but backends that wait for vsync have to skip frames to
+    /// keep up.
+    fn set_turbo(&mut self, _turbo: bool) {}
+
+    /// Start or stop beeping. Backends that can't play sound don't.
+    fn beep(&mut self, _on: bool) {}
+
+    /// The file that was dropped onto the window since last time, if any.
+    /// Backends that can't tell never have one.
+    fn take_dropped_file(&mut self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Draw the screen through a shader. Returns whether the backend can;
+    /// only the wgpu one can.
+    fn set_shader(&mut self, _shader: Shader) -> bool {
+        false
+    }
+
+    /// How big the window is now, if it's been resized and the backend needs
+    /// `update` to be passed pixels that fill it (see `Framebuffer::fit`).
+    /// Backends that scale the screen to fit by themselves don't say.
+    fn window_size(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+/// The default backend, which has no sound.
+#[cfg(feature = "gui")]
+struct Minifb {
     window: Window,
+    resizable: bool,
 }
 
-impl Display {
-    pub fn new(width: usize, height: usize) -> Self {
-        let mut window = Window::new(
-            "CHIP-8 - ESC to exit",
-            width,
-            height,
-            WindowOptions::default(),
-        )
-        .unwrap_or_else(|e| panic!("{}", e));
-        window.limit_update_rate(Some(SIXTY_FPS));
+#[cfg(feature = "gui")]
+impl Minifb {
+    /// minifb can't go fullscreen, so with `fullscreen` the window is just
+    /// resizable, to be maximized.
+    fn open(
+        width: usize,
+        height: usize,
+        resizable: bool,
+        fullscreen: bool,
+    ) -> Result<Self, Chip8Error> {
+        if fullscreen {
+            warn!("minifb can't go fullscreen, so the window is resizable instead. --frontend sdl2 and --frontend wgpu can.");
+        }
+        let resizable = resizable || fullscreen;
+        let options = WindowOptions {
+            resize: resizable,
+            // When it's resized, `Display` fills the window itself
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new(TITLE, width, height, options)
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        // `Display` keeps time instead
+        window.limit_update_rate(None);
+        Ok(Self { window, resizable })
+    }
 
-        Self { window }
+    fn key(key: Key) -> minifb::Key {
+        match key {
+            Key::Key0 => minifb::Key::Key0,
+            Key::Key1 => minifb::Key::Key1,
+            Key::Key2 => minifb::Key::Key2,
+            Key::Key3 => minifb::Key::Key3,
+            Key::Key4 => minifb::Key::Key4,
+            Key::Key5 => minifb::Key::Key5,
+            Key::Key6 => minifb::Key::Key6,
+            Key::Key7 => minifb::Key::Key7,
+            Key::Key8 => minifb::Key::Key8,
+            Key::Key9 => minifb::Key::Key9,
+            Key::A => minifb::Key::A,
+            Key::B => minifb::Key::B,
+            Key::C => minifb::Key::C,
+            Key::D => minifb::Key::D,
+            Key::E => minifb::Key::E,
+            Key::F => minifb::Key::F,
+            Key::G => minifb::Key::G,
+            Key::H => minifb::Key::H,
+            Key::I => minifb::Key::I,
+            Key::J => minifb::Key::J,
+            Key::K => minifb::Key::K,
+            Key::L => minifb::Key::L,
+            Key::M => minifb::Key::M,
+            Key::N => minifb::Key::N,
+            Key::O => minifb::Key::O,
+            Key::P => minifb::Key::P,
+            Key::Q => minifb::Key::Q,
+            Key::R => minifb::Key::R,
+            Key::S => minifb::Key::S,
+            Key::T => minifb::Key::T,
+            Key::U => minifb::Key::U,
+            Key::V => minifb::Key::V,
+            Key::W => minifb::Key::W,
+            Key::X => minifb::Key::X,
+            Key::Y => minifb::Key::Y,
+            Key::Z => minifb::Key::Z,
+            Key::Escape => minifb::Key::Escape,
+            Key::Period => minifb::Key::Period,
+            Key::Tab => minifb::Key::Tab,
+            Key::Space => minifb::Key::Space,
+            Key::Backspace => minifb::Key::Backspace,
+            Key::Left => minifb::Key::Left,
+            Key::Right => minifb::Key::Right,
+            Key::F1 => minifb::Key::F1,
+            Key::F2 => minifb::Key::F2,
+            Key::F3 => minifb::Key::F3,
+            Key::F5 => minifb::Key::F5,
+            Key::F9 => minifb::Key::F9,
+        }
     }
+}
 
-    /// Usage: `while display.is_running { ... }
-    pub fn is_running(&self) -> bool {
-        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+#[cfg(feature = "gui")]
+impl Backend for Minifb {
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.window.is_key_down(Self::key(key))
     }
 
-    /// Update the screen with the new buffer data.
-    pub fn draw(&mut self, buffer: &ScaledFramebuffer) {
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.window.is_key_pressed(Self::key(key), KeyRepeat::No)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn update(&mut self, pixels: &[u32], width: usize, height: usize) {
         self.window
-            .update_with_buffer(buffer.as_bytes(), buffer.true_width, buffer.true_height)
+            .update_with_buffer(pixels, width, height)
             .unwrap();
     }
+
+    fn refresh(&mut self) {
+        self.window.update();
+    }
+
+    fn window_size(&self) -> Option<(usize, usize)> {
+        match self.window.get_size() {
+            // Minimized
+            (0, _) | (_, 0) => None,
+            size if self.resizable => Some(size),
+            _ => None,
+        }
+    }
+}
+
+/// It knows how to draw a `Framebuffer` to the screen, in a window
+/// opened by one of the backends.
+/// Only available with the `window` feature, which every backend's feature
+/// turns on.
+#[cfg(feature = "window")]
+pub struct Display {
+    window: Box<dyn Backend>,
+    /// Whether the user paused the program with P
+    paused: bool,
+    /// Whether the user turned on the debug overlay with F1
+    overlay: bool,
+    /// While paused, how many frames back through the rewind history the user
+    /// has gone with Left
+    scrub: usize,
+    /// The instruction the run loop most recently decoded, for the overlay
+    last_instruction: Option<Instruction>,
+    /// Tracks how much the screen flashes
+    flash: FlashGuard,
+    /// Whether to blend away flashing that's too fast (see `FlashGuard`)
+    reduce_flashing: bool,
+    /// Whether the user was already asked about reducing flashing
+    warned_about_flashing: bool,
+    /// Fades out pixels that turn off, if the user asked for it
+    phosphor: Option<Phosphor>,
+    /// Notices when nothing is happening
+    idle: IdleDetector,
+    /// Whether the program was paused because nothing was happening
+    idle_paused: bool,
+    /// Whether the window shows the framebuffer exactly as it was last drawn,
+    /// without an overlay, a message, or anything else, so it only has to be
+    /// drawn again if the framebuffer changes
+    shows_buffer: bool,
+    /// Which keys are the keypad's
+    keymap: Keymap,
+    /// The size of the window the screen was last drawn to fit, if the
+    /// backend needed it to be (see `Backend::window_size`)
+    window_size: Option<(usize, usize)>,
+    /// Starts frames 60 times a second
+    pacer: FramePacer,
+    /// Whether Tab or Space is held down to fast-forward
+    turbo: bool,
+    /// Whether the current frame started late enough that it only needs to
+    /// run, not be drawn, to catch up
+    catching_up: bool,
+}
+
+#[cfg(feature = "window")]
+impl Display {
+    /// Open a window with the given backend. If it's `resizable` or
+    /// `fullscreen`, the screen is scaled up as many whole times as fit in the
+    /// window, with black bars around it.
+    // The terminal picks its own size
+    #[cfg_attr(
+        not(any(feature = "gui", feature = "sdl2-frontend", feature = "wgpu-frontend")),
+        allow(unused_variables)
+    )]
+    pub fn new(
+        backend: WindowBackend,
+        width: usize,
+        height: usize,
+        resizable: bool,
+        fullscreen: bool,
+    ) -> Result<Self, Chip8Error> {
+        let window: Box<dyn Backend> = match backend {
+            #[cfg(feature = "gui")]
+            WindowBackend::Minifb => Box::new(Minifb::open(width, height, resizable, fullscreen)?),
+            #[cfg(feature = "sdl2-frontend")]
+            WindowBackend::Sdl2 => Box::new(crate::sdl::Sdl2::open(
+                width, height, resizable, fullscreen,
+            )?),
+            #[cfg(feature = "terminal")]
+            WindowBackend::Terminal => Box::new(crate::terminal::Terminal::open()?),
+            #[cfg(feature = "wgpu-frontend")]
+            WindowBackend::Wgpu => Box::new(crate::gpu::Wgpu::open(
+                width, height, resizable, fullscreen,
+            )?),
+            // The ones whose features are turned off
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(Chip8Error::Window(format!(
+                    "This chip8 was built without the {} frontend. Build it with `--features {}` to use it.",
+                    backend,
+                    backend.feature()
+                )))
+            }
+        };
+
+        Ok(Self {
+            window,
+            paused: false,
+            overlay: false,
+            scrub: 0,
+            last_instruction: None,
+            flash: FlashGuard::default(),
+            reduce_flashing: false,
+            warned_about_flashing: false,
+            phosphor: None,
+            idle: IdleDetector::new(0),
+            idle_paused: false,
+            shows_buffer: false,
+            keymap: Keymap::default(),
+            window_size: None,
+            pacer: FramePacer::new(),
+            turbo: false,
+            catching_up: false,
+        })
+    }
+
+    /// Handle the hotkeys that change how fast the program runs: P pauses and
+    /// resumes it, and holding Tab or Space fast-forwards by drawing as fast
+    /// as possible instead of at 60 frames a second. F1 also turns the debug
+    /// overlay on and off here.
+    pub fn update_speed(&mut self) {
+        if self.was_pressed(Key::F1) {
+            self.overlay = !self.overlay;
+        }
+        if self.idle_paused {
+            // Carry on as soon as anything happens, without P also pausing
+            if self.was_pressed(Key::P)
+                || self
+                    .keymap
+                    .keys()
+                    .iter()
+                    .any(|key| self.window.is_key_down(*key))
+            {
+                self.idle_paused = false;
+                self.idle.reset();
+                self.set_message(None);
+            }
+        } else if self.was_pressed(Key::P) {
+            self.paused = !self.paused;
+            self.set_message(if self.paused {
+                Some("Paused - P to resume, . to step, Left/Right to scrub")
+            } else {
+                None
+            });
+        }
+        self.turbo = self.window.is_key_down(Key::Tab) || self.window.is_key_down(Key::Space);
+        self.window.set_turbo(self.turbo);
+    }
+
+    /// Wait until it's time for the next frame, 60 times a second by the wall
+    /// clock however fast the monitor refreshes, or not at all while
+    /// fast-forwarding. Frames that start a whole frame late aren't drawn, so
+    /// a slow computer can catch up.
+    pub fn wait_for_frame(&mut self) {
+        if self.turbo {
+            self.pacer.restart();
+            self.catching_up = false;
+        } else {
+            self.catching_up = self.pacer.wait() > 0;
+        }
+    }
+
+    /// Blend away flashing that's too fast, for people who are sensitive to
+    /// it.
+    pub fn set_reduce_flashing(&mut self, reduce_flashing: bool) {
+        self.reduce_flashing = reduce_flashing;
+    }
+
+    /// Fade out pixels that turn off, losing `decay` of their glow every
+    /// frame, or switch them off right away if it's None.
+    pub fn set_phosphor(&mut self, decay: Option<f32>) {
+        self.phosphor = decay.map(Phosphor::new);
+    }
+
+    /// The file that was dropped onto the window since last time, if any
+    /// (only with SDL2, since minifb can't tell).
+    pub fn dropped_file(&mut self) -> Option<PathBuf> {
+        self.window.take_dropped_file()
+    }
+
+    /// Draw the screen through a shader, if the backend can (see
+    /// `Backend::set_shader`).
+    pub fn set_shader(&mut self, shader: Shader) {
+        if !self.window.set_shader(shader) && shader != Shader::None {
+            warn!(
+                "Only --frontend wgpu can draw through shaders, so --shader {} does nothing",
+                shader
+            );
+        }
+    }
+
+    /// Read the keypad from these keys instead of `KEYPAD`.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Pause after `seconds` of the screen not changing and no keys being
+    /// pressed, until a key is pressed, or never if it's 0.
+    pub fn set_idle_pause(&mut self, seconds: u64) {
+        self.idle = IdleDetector::new(seconds * 60);
+    }
+
+    /// Pause because nothing is happening, and stop beeping.
+    fn pause_while_idle(&mut self) {
+        self.idle_paused = true;
+        self.window.beep(false);
+        let message = "Paused because nothing's happening - press a key to resume";
+        info!("{}", message);
+        self.set_message(Some(message));
+    }
+
+    /// The first time the screen flashes too much, pause and ask whether to
+    /// reduce the flashing.
+    fn warn_about_flashing(&mut self, buffer: &Framebuffer) {
+        self.warned_about_flashing = true;
+        let message = "This program flashes rapidly - R: reduce flashing, C: carry on";
+        println!("{}", message);
+        self.set_message(Some(message));
+        if self.wait_for_key(buffer, &[Key::R, Key::C]) == Some(Key::R) {
+            self.reduce_flashing = true;
+        }
+        self.set_message(None);
+    }
+
+    /// Whether the program is paused, by the user or because nothing was
+    /// happening. Call `draw` while it's paused, so that the window keeps
+    /// reading the keyboard.
+    pub fn is_paused(&self) -> bool {
+        self.paused || self.idle_paused
+    }
+
+    /// Whether Backspace is held down to rewind.
+    pub fn is_rewinding(&self) -> bool {
+        self.window.is_key_down(Key::Backspace)
+    }
+
+    /// While paused, Left and Right move back and forward through the rewind
+    /// history (of `history` frames), a frame at a time for as long as they're
+    /// held. Returns how many frames back from the newest one to show.
+    pub fn scrub(&mut self, history: usize) -> usize {
+        if !self.paused {
+            self.scrub = 0;
+        } else if self.window.is_key_down(Key::Left) {
+            self.scrub = (self.scrub + 1).min(history);
+        } else if self.window.is_key_down(Key::Right) {
+            self.scrub = self.scrub.saturating_sub(1);
+        }
+        self.scrub
+    }
+
+    /// Go back to showing the newest frame, because the program is carrying on
+    /// from the one that was scrubbed to.
+    pub fn stop_scrubbing(&mut self) {
+        self.scrub = 0;
+    }
+
+    /// Show a frame from the rewind history, `frames_back` frames before the
+    /// newest of `history` frames, with the registers from then and a timeline
+    /// of the history.
+    pub fn show_history(&mut self, state: &State, frames_back: usize, history: usize) {
+        let mut lines = state.summary_lines();
+        lines.push(format!("Frame -{} of {}", frames_back, history));
+        let buffer = state.buffer();
+        let mut pixels = buffer.scaled_pixels();
+        buffer.draw_overlay(&mut pixels, &lines);
+        buffer.draw_timeline(&mut pixels, history - frames_back, history);
+        self.present(buffer, &pixels);
+        self.shows_buffer = false;
+    }
+
+    /// Whether `.` was pressed to run one instruction while paused.
+    pub fn step_requested(&self) -> bool {
+        self.paused && self.was_pressed(Key::Period)
+    }
+
+    /// Whether the key was pressed since the last time we looked. Holding it
+    /// down doesn't count as pressing it again.
+    pub fn was_pressed(&self, key: Key) -> bool {
+        self.window.is_key_pressed(key)
+    }
+
+    /// Show a message in the title bar, or go back to the usual title if
+    /// there's no message.
+    pub fn set_message(&mut self, message: Option<&str>) {
+        // The terminal shows the title under the screen, so draw both again
+        self.shows_buffer = false;
+        match message {
+            Some(message) => self.window.set_title(message),
+            None => self.window.set_title(TITLE),
+        }
+    }
+
+    /// Pause until one of the given keys is pressed, and return it, while
+    /// still showing `buffer`. Returns None if the window is closed first.
+    pub fn wait_for_key(&mut self, buffer: &Framebuffer, keys: &[Key]) -> Option<Key> {
+        while self.is_running() {
+            self.wait_for_frame();
+            self.draw(buffer);
+            if let Some(key) = keys.iter().find(|key| self.was_pressed(**key)) {
+                return Some(*key);
+            }
+        }
+        None
+    }
+
+    /// Remember the instruction that's about to run, so the overlay can show it.
+    pub fn decoded(&mut self, instruction: &Instruction) {
+        if self.overlay {
+            self.last_instruction = Some(instruction.clone());
+        }
+    }
+
+    /// Update the screen with the new buffer data, scaled up to the window's
+    /// size. If nothing's changed since it was last drawn, this only reads the
+    /// keyboard.
+    pub fn draw(&mut self, buffer: &Framebuffer) {
+        if self.shows_buffer && buffer.dirty_region().is_none() && !self.was_resized() {
+            return self.window.refresh();
+        }
+        self.present(buffer, &buffer.scaled_pixels());
+        self.shows_buffer = true;
+    }
+
+    /// Whether the window changed size since the screen was last drawn, so
+    /// it has to be drawn again to fit.
+    fn was_resized(&self) -> bool {
+        self.window.window_size() != self.window_size
+    }
+
+    /// Draw the physical pixels from the buffer (see `scaled_pixels`), fitted
+    /// to the window if the backend doesn't do that itself.
+    fn present(&mut self, buffer: &Framebuffer, pixels: &[u32]) {
+        self.window_size = self.window.window_size();
+        match self.window_size {
+            Some((width, height))
+                if (width, height) != (buffer.true_width(), buffer.true_height()) =>
+            {
+                self.window
+                    .update(&buffer.fit(pixels, width, height), width, height)
+            }
+            _ => self
+                .window
+                .update(pixels, buffer.true_width(), buffer.true_height()),
+        }
+    }
+}
+
+#[cfg(feature = "window")]
+impl Frontend for Display {
+    /// Which of the CHIP-8's keys (see `set_keymap`) are held down.
+    fn keypad(&self) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (down, key) in keypad.iter_mut().zip(self.keymap.keys()) {
+            *down = self.window.is_key_down(*key);
+        }
+        keypad
+    }
+
+    /// Draw the machine's screen, with the registers and the last instruction
+    /// over it if the debug overlay is on, with pixels that turned off fading
+    /// out if there's phosphor, and with any flashing reduced.
+    fn show(&mut self, state: &State) {
+        // While paused, this is called all the time instead of once a frame
+        if !self.is_paused() && self.idle.track(state, &self.keypad()) {
+            self.pause_while_idle();
+        }
+        let buffer = state.buffer();
+        if self.shows_buffer && buffer.dirty_region().is_none() && !self.was_resized() {
+            // Nothing's changed, so there's nothing to upload, and nothing
+            // that could have flashed
+            self.flash.track_unchanged();
+            return self.window.refresh();
+        }
+        let (width, height) = (buffer.true_width(), buffer.true_height());
+        let mut pixels = match &mut self.phosphor {
+            Some(phosphor) => buffer.scale_up(&phosphor.next_frame(buffer)),
+            None => buffer.scaled_pixels(),
+        };
+        self.flash.track(&pixels, width, height);
+        if self.flash.is_dangerous() && !self.reduce_flashing && !self.warned_about_flashing {
+            self.warn_about_flashing(buffer);
+        }
+        if self.overlay {
+            let mut lines = state.summary_lines();
+            if let Some(instruction) = &self.last_instruction {
+                lines.push(format!("Last: {}", instruction));
+            }
+            buffer.draw_overlay(&mut pixels, &lines);
+        }
+        if self.reduce_flashing {
+            self.flash.reduce(&mut pixels, width, height);
+        }
+        if self.catching_up {
+            // Draw it next time instead
+            self.shows_buffer = false;
+            return self.window.refresh();
+        }
+        self.present(buffer, &pixels);
+        // The overlay, the blending, and the fading change from frame to frame
+        // by themselves
+        self.shows_buffer = !self.overlay && !self.reduce_flashing && self.phosphor.is_none();
+    }
+
+    fn beep(&mut self, on: bool) {
+        self.window.beep(on);
+    }
+
+    /// Usage: `while display.is_running { ... }
+    fn is_running(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    // Assert on all 100 pixels (10 in x direction, 10 in y direction) that a single logical pixel corresponds to.
-    fn assert_pixel(fb: &ScaledFramebuffer, x: usize, y: usize, color: u32) {
+    // Assert on all 100 pixels (10 in x direction, 10 in y direction) that a single logical pixel is drawn as.
+    fn assert_pixel(fb: &Framebuffer, x: usize, y: usize, color: u32) {
+        let pixels = fb.scaled_pixels();
         for x_offset in 0..SCALE {
             for y_offset in 0..SCALE {
-                let scaled_y = (SCALE * y + y_offset) * fb.true_width;
+                let scaled_y = (SCALE * y + y_offset) * fb.true_width();
                 let scaled_x = (SCALE * x) + x_offset;
-                assert_eq!(fb.buffer[scaled_y + scaled_x], color);
+                assert_eq!(pixels[scaled_y + scaled_x], color);
             }
         }
     }
 
+    #[test]
+    fn fits_the_screen_into_a_bigger_window_in_whole_steps() {
+        let appearance = Appearance {
+            scale: 2,
+            ..Appearance::default()
+        };
+        let mut fb = Framebuffer::with_size(4, 2, &appearance);
+        fb.xor(true, 0, 0, 0b01);
+        fb.xor(true, 3, 1, 0b01);
+        // 3 times fits down but not across, so it's scaled up twice, in the
+        // middle of the window
+        let fitted = fb.fit(&fb.scaled_pixels(), 11, 6);
+        let rows = fitted
+            .chunks(11)
+            .map(|row| {
+                row.iter()
+                    .map(|pixel| if *pixel == ON { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                "...........",
+                ".##........",
+                ".##........",
+                ".......##..",
+                ".......##..",
+                "...........",
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_logical_pixels_for_other_frontends() {
+        let appearance = Appearance {
+            foreground: 0xFFB000,
+            ..Appearance::default()
+        };
+        let mut fb = Framebuffer::with_size(16, 2, &appearance);
+        fb.xor(true, 0, 0, 0b01);
+        fb.xor(true, 9, 1, 0b01);
+
+        assert_eq!((fb.width(), fb.height()), (16, 2));
+        let rgba = fb.as_rgba_bytes();
+        assert_eq!(rgba.len(), 16 * 2 * 4);
+        assert_eq!(
+            &rgba[..8],
+            &[0xFF, 0xB0, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(fb.as_packed_bits(), vec![0x80, 0x00, 0x00, 0x40]);
+    }
+
     #[test]
     fn turn_pixel_on() {
-        let mut fb = ScaledFramebuffer::with_size(5, 5);
+        let mut fb = Framebuffer::with_size(5, 5, &Appearance::default());
         let x = 2;
         let y = 2;
-        let flipped_to_off = fb.xor(true, x, y);
+        let flipped_to_off = fb.xor(true, x, y, 0b01);
 
-        assert_eq!(flipped_to_off, false);
+        assert!(!flipped_to_off);
         assert_pixel(&fb, x, y, ON);
     }
 
     #[test]
     fn turn_pixel_off() {
-        let mut fb = ScaledFramebuffer::with_size(5, 5);
+        let mut fb = Framebuffer::with_size(5, 5, &Appearance::default());
         let x = 2;
         let y = 2;
-        fb.xor(true, x, y);
-        fb.xor(true, x, y);
+        fb.xor(true, x, y, 0b01);
+        fb.xor(true, x, y, 0b01);
 
         assert_pixel(&fb, x, y, OFF);
     }
 
     #[test]
     fn xor_detect_when_pixel_flips_from_on_to_off() {
-        let mut fb = ScaledFramebuffer::with_size(5, 5);
+        let mut fb = Framebuffer::with_size(5, 5, &Appearance::default());
         let x = 2;
         let y = 2;
 
-        assert_eq!(fb.xor(true, x, y), false);
-        assert_eq!(fb.xor(true, x, y), true);
+        assert!(!fb.xor(true, x, y, 0b01));
+        assert!(fb.xor(true, x, y, 0b01));
     }
 
     #[test]
@@ -215,8 +1737,8 @@ mod test {
             0b10010000,
             0b11110000,
         ];
-        let mut fb = ScaledFramebuffer::with_size(8, 5);
-        fb.draw_sprite_at(0, 0, sprite);
+        let mut fb = Framebuffer::with_size(8, 5, &Appearance::default());
+        fb.draw_sprite_at(0, 0, sprite, 0b01, false);
 
         // First row
         for x in 0..4 {
@@ -255,11 +1777,11 @@ mod test {
             0b10000000,
             0b11110000,
         ];
-        let mut fb = ScaledFramebuffer::with_size(8, 5);
-        fb.draw_sprite_at(0, 0, first_sprite);
-        fb.draw_sprite_at(0, 0, second_sprite);
+        let mut fb = Framebuffer::with_size(8, 5, &Appearance::default());
+        fb.draw_sprite_at(0, 0, first_sprite, 0b01, false);
+        fb.draw_sprite_at(0, 0, second_sprite, 0b01, false);
 
-        let expected = vec![
+        let expected = [
             vec![OFF; 8],
             vec![ON, ON, OFF, OFF, OFF, OFF, OFF, OFF],
             vec![OFF, OFF, OFF, ON, OFF, OFF, OFF, OFF],
@@ -278,9 +1800,379 @@ mod test {
     fn draw_sprite_detect_when_pixel_flips_from_on_to_off() {
         let sprite1 = &[0b11110000];
         let sprite2 = &[0b00010000];
-        let mut fb = ScaledFramebuffer::with_size(8, 1);
+        let mut fb = Framebuffer::with_size(8, 1, &Appearance::default());
+
+        assert_eq!(fb.draw_sprite_at(0, 0, sprite1, 0b01, false), vec![]);
+        assert_eq!(fb.draw_sprite_at(0, 0, sprite2, 0b01, false), vec![(3, 0)]);
+    }
+
+    #[test]
+    fn draw_sprite_reports_where_it_collided_after_wrapping() {
+        let sprite = &[0b11000000, 0b11000000];
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.set_pixel(0, 0, ON);
+        fb.set_pixel(7, 3, ON);
+
+        assert_eq!(
+            fb.draw_sprite_at(7, 3, sprite, 0b01, false),
+            vec![(7, 3), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn draw_sprite_clipped_at_edges() {
+        let sprite = &[0b11000000, 0b11000000];
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.draw_sprite_at(7, 3, sprite, 0b01, true);
+
+        // Only the top-left pixel of the sprite fits on the screen
+        assert_pixel(&fb, 7, 3, ON);
+        assert_pixel(&fb, 0, 3, OFF);
+        assert_pixel(&fb, 7, 0, OFF);
+    }
+
+    #[test]
+    fn draw_sprite_wrapped_at_edges() {
+        let sprite = &[0b11000000, 0b11000000];
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.draw_sprite_at(7, 3, sprite, 0b01, false);
+
+        // The sprite wraps around to every corner
+        assert_pixel(&fb, 7, 3, ON);
+        assert_pixel(&fb, 0, 3, ON);
+        assert_pixel(&fb, 7, 0, ON);
+        assert_pixel(&fb, 0, 0, ON);
+        assert_pixel(&fb, 1, 1, OFF);
+    }
+
+    #[test]
+    fn draw_sprite_starting_location_wraps() {
+        let sprite = &[0b10000000];
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        // Even when clipping, the starting location wraps around
+        fb.draw_sprite_at(8 + 2, 4 + 1, sprite, 0b01, true);
+
+        assert_pixel(&fb, 2, 1, ON);
+    }
+
+    #[test]
+    fn draw_wide_sprite_clipped_in_high_resolution() {
+        let sprite = [0xFF; 32];
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.set_logical_size(16, 8).unwrap();
+        fb.draw_wide_sprite_at(15, 7, &sprite, 0b01, true);
+
+        assert_eq!(fb.get_pixel(15, 7), ON);
+        assert_eq!(fb.get_pixel(0, 7), OFF);
+        assert_eq!(fb.get_pixel(15, 0), OFF);
+    }
+
+    #[test]
+    fn xor_planes_independently() {
+        let mut fb = Framebuffer::with_size(5, 5, &Appearance::default());
+        assert!(!fb.xor(true, 1, 1, 0b10));
+        assert_pixel(&fb, 1, 1, PALETTE[0b10]);
+
+        assert!(!fb.xor(true, 1, 1, 0b01));
+        assert_pixel(&fb, 1, 1, PALETTE[0b11]);
+        assert_eq!(fb.get_planes(1, 1), 0b11);
+
+        // Turning off plane 2 leaves plane 1 on
+        assert!(fb.xor(true, 1, 1, 0b10));
+        assert_pixel(&fb, 1, 1, ON);
+    }
+
+    #[test]
+    fn draw_wide_sprite() {
+        let mut sprite = [0; 32];
+        // Top row: leftmost and rightmost pixels only
+        sprite[0] = 0b10000000;
+        sprite[1] = 0b00000001;
+        let mut fb = Framebuffer::with_size(16, 16, &Appearance::default());
+        fb.draw_wide_sprite_at(0, 0, &sprite, 0b01, false);
+
+        assert_pixel(&fb, 0, 0, ON);
+        assert_pixel(&fb, 15, 0, ON);
+        for x in 1..15 {
+            assert_pixel(&fb, x, 0, OFF);
+        }
+    }
+
+    #[test]
+    fn set_logical_size_rescales_and_clears() {
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.set_pixel(0, 0, ON);
+        fb.set_logical_size(16, 8).unwrap();
+
+        assert_eq!((fb.width, fb.height), (16, 8));
+        assert_eq!(fb.scale, SCALE / 2);
+        assert_eq!(fb.get_pixel(0, 0), OFF);
+
+        // The bottom-right logical pixel now covers the bottom-right 5x5
+        // physical pixels
+        fb.set_pixel(15, 7, ON);
+        let pixels = fb.scaled_pixels();
+        assert_eq!(pixels.len(), 80 * 40);
+        assert_eq!(pixels[pixels.len() - 1], ON);
+        assert_eq!(pixels[pixels.len() - 1 - fb.scale], OFF);
+    }
+
+    #[test]
+    fn undoes_recorded_changes() {
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        fb.set_pixel(1, 1, ON);
+        let before = fb.clone();
+        fb.record_changes();
+        fb.draw_sprite_at(0, 0, &[0xFF, 0xFF], 0b01, false);
+        fb.set_logical_size(16, 8).unwrap();
+        fb.set_pixel(15, 7, ON);
+
+        let changes = fb.take_changes();
+        assert_eq!(changes.len(), 8 * 2 + 2);
+        fb.undo(changes);
+        assert_eq!(fb, before);
+        // Changes after `take_changes` aren't recorded
+        fb.set_pixel(0, 0, ON);
+        assert_eq!(fb.take_changes(), vec![]);
+    }
+
+    #[test]
+    fn set_logical_size_fails_if_it_cannot_scale_evenly() {
+        let appearance = Appearance {
+            scale: 3,
+            ..Appearance::default()
+        };
+        let mut fb = Framebuffer::with_size(8, 4, &appearance);
+        fb.set_pixel(0, 0, ON);
+        assert!(matches!(
+            fb.set_logical_size(16, 8),
+            Err(Chip8Error::CannotScale { true_width: 24, .. })
+        ));
+        assert!(matches!(
+            fb.set_logical_size(0, 0),
+            Err(Chip8Error::CannotScale { .. })
+        ));
+        // Nothing changed
+        assert_eq!((fb.width, fb.height, fb.scale), (8, 4, 3));
+        assert_eq!(fb.get_pixel(0, 0), ON);
+    }
+
+    #[test]
+    fn draws_at_any_scale_in_any_colors() {
+        let appearance = Appearance {
+            scale: 4,
+            foreground: 0xFF_B0_00,
+            background: 0x10_10_10,
+        };
+        let mut fb = Framebuffer::with_size(8, 4, &appearance);
+        assert_eq!((fb.true_width(), fb.true_height()), (32, 16));
+        assert_eq!(fb.get_pixel(0, 0), 0x10_10_10);
+
+        assert!(!fb.xor(true, 1, 1, 0b01));
+        assert_eq!(fb.scaled_pixels()[4 * 32 + 4..4 * 32 + 8], [0xFF_B0_00; 4]);
+        assert_eq!(fb.get_planes(1, 1), 0b01);
+        assert!(fb.xor(true, 1, 1, 0b01));
+        assert_eq!(fb.get_pixel(1, 1), 0x10_10_10);
+
+        fb.set_logical_size(16, 8).unwrap();
+        assert_eq!(fb.scale, 2);
+        assert_eq!(fb.get_pixel(15, 7), 0x10_10_10);
+    }
+
+    #[test]
+    fn timeline() {
+        let fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        let mut pixels = fb.scaled_pixels();
+        fb.draw_timeline(&mut pixels, 1, 4);
+
+        let bottom = &pixels[(fb.true_height() - 1) * fb.true_width()..];
+        assert_eq!(bottom[..20], [OVERLAY_TEXT; 20]);
+        assert_eq!(bottom[20..], [TIMELINE_AFTER; 60]);
+        let above = (fb.true_height() - TIMELINE_HEIGHT - 1) * fb.true_width();
+        assert_eq!(pixels[above], OFF);
+    }
+
+    #[test]
+    fn stores_each_logical_pixel_once() {
+        let mut fb = Framebuffer::with_size(4, 2, &Appearance::default());
+        fb.xor(true, 1, 0, 0b01);
+        fb.xor(true, 3, 1, 0b10);
+
+        let rows = fb.iter_rows().collect::<Vec<_>>();
+        assert_eq!(rows, vec![&[0, 1, 0, 0][..], &[0, 0, 0, 2][..]]);
+        assert_eq!(fb.pixels.len(), 8);
+    }
+
+    #[test]
+    fn tracks_the_region_that_changed() {
+        let mut fb = Framebuffer::with_size(8, 4, &Appearance::default());
+        assert_eq!(fb.dirty_region(), Some(DirtyRegion::whole(8, 4)));
+
+        fb.mark_drawn();
+        assert_eq!(fb.dirty_region(), None);
+        // Drawing nothing, or a pixel the way it already is, changes nothing
+        fb.xor(false, 0, 0, 0b01);
+        fb.set_pixel(0, 0, OFF);
+        assert_eq!(fb.dirty_region(), None);
+
+        fb.xor(true, 2, 1, 0b01);
+        fb.xor(true, 5, 3, 0b01);
+        assert_eq!(
+            fb.dirty_region(),
+            Some(DirtyRegion {
+                left: 2,
+                top: 1,
+                right: 6,
+                bottom: 4
+            })
+        );
+    }
+
+    #[test]
+    fn loaded_framebuffers_are_all_dirty() {
+        let mut fb = Framebuffer::new();
+        fb.mark_drawn();
+        let loaded: Framebuffer = bincode::deserialize(&bincode::serialize(&fb).unwrap()).unwrap();
+
+        assert_eq!(loaded, fb);
+        assert_eq!(
+            loaded.dirty_region(),
+            Some(DirtyRegion::whole(CHIP8_WIDTH, CHIP8_HEIGHT))
+        );
+    }
+
+    #[test]
+    fn scroll_down() {
+        let mut fb = Framebuffer::with_size(4, 4, &Appearance::default());
+        fb.set_pixel(1, 0, ON);
+        fb.scroll_down(2);
+
+        assert_pixel(&fb, 1, 0, OFF);
+        assert_pixel(&fb, 1, 2, ON);
+    }
+
+    #[test]
+    fn scroll_left_and_right() {
+        let mut fb = Framebuffer::with_size(8, 2, &Appearance::default());
+        fb.set_pixel(4, 1, ON);
+        fb.scroll_left(4);
+        assert_pixel(&fb, 0, 1, ON);
+        assert_pixel(&fb, 4, 1, OFF);
+
+        fb.scroll_right(4);
+        assert_pixel(&fb, 0, 1, OFF);
+        assert_pixel(&fb, 4, 1, ON);
+    }
+
+    #[test]
+    fn pretty_print_logical() {
+        let mut fb = Framebuffer::new();
+        fb.set_pixel(1, 0, ON);
+        let text = fb.pretty_print_logical();
+        let rows = text.lines().collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 32);
+        assert_eq!(rows[0], format!(".#{}", ".".repeat(62)));
+        assert_eq!(rows[1], ".".repeat(64));
+    }
+
+    #[test]
+    fn to_pbm() {
+        let mut fb = Framebuffer::new();
+        fb.set_pixel(2, 1, ON);
+        let pbm = fb.to_pbm();
+        let lines = pbm.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "P1");
+        assert_eq!(lines[1], "64 32");
+        assert_eq!(lines.len(), 2 + 32);
+        assert!(lines[3].starts_with("0 0 1 0"));
+    }
+
+    #[test]
+    fn logical_bitstring_round_trip() {
+        let fb = Framebuffer::from_logical_bitstring(
+            "
+            0110
+            1001
+            0230
+            ",
+        )
+        .unwrap();
+
+        assert_eq!((fb.width, fb.height), (4, 3));
+        assert_eq!(fb.get_pixel(1, 0), ON);
+        assert_eq!(fb.get_planes(2, 2), 0b11);
+        assert_eq!(fb.to_logical_bitstring(), "0110\n1001\n0230\n");
+        assert!(Framebuffer::from_logical_bitstring("01\n0").is_err());
+        assert!(Framebuffer::from_logical_bitstring("0#").is_err());
+    }
+
+    #[test]
+    fn diff_marks_the_pixels_that_are_different() {
+        let expected = Framebuffer::from_logical_bitstring("0110\n1001").unwrap();
+        let mut fb = expected.clone();
+        assert_eq!(fb.diff(&expected), None);
+
+        fb.xor(true, 0, 0, 0b01);
+        fb.xor(true, 3, 1, 0b01);
+        assert_eq!(
+            fb.diff(&expected),
+            Some("2 pixels are different:\nX110\n100X\n".to_string())
+        );
+        assert_eq!(
+            Framebuffer::new().diff(&expected),
+            Some("The screen is 64x32 instead of 4x2".to_string())
+        );
+    }
+
+    #[test]
+    fn overlay_draws_text_over_a_dimmed_background() {
+        let mut fb = Framebuffer::new();
+        fb.set_pixel(63, 0, ON);
+        fb.set_pixel(0, 31, ON);
+        let mut pixels = fb.scaled_pixels();
+        fb.draw_overlay(&mut pixels, &["1".to_string()]);
+        let at = |x: usize, y: usize| pixels[y * fb.true_width() + x];
+
+        // The top row of "1" is its middle pixel, after a margin of 1 pixel
+        assert_eq!(at(OVERLAY_SCALE, OVERLAY_SCALE), OFF);
+        assert_eq!(at(OVERLAY_SCALE * 2, OVERLAY_SCALE), OVERLAY_TEXT);
+        // The line's background is dimmed, but the rest of the screen isn't
+        assert_eq!(at(0, 0), OFF);
+        assert_eq!(at(fb.true_width() - 1, 0), ON);
+        assert_eq!(at(0, fb.true_height() - 1), ON);
+        // The framebuffer itself is unchanged
+        assert_eq!(fb.get_pixel(0, 0), OFF);
+    }
+
+    #[test]
+    fn overlay_dims_under_the_text() {
+        let mut fb = Framebuffer::new();
+        fb.set_pixel(0, 0, ON);
+        let mut pixels = fb.scaled_pixels();
+        fb.draw_overlay(&mut pixels, &[" ".to_string()]);
+
+        assert_eq!(pixels[0], 0x7F_7F_7F);
+    }
+
+    #[test]
+    fn glyphs_for_lowercase_and_unknown_characters() {
+        assert_eq!(glyph('v'), glyph('V'));
+        assert_eq!(glyph('~'), glyph(' '));
+    }
+
+    #[test]
+    fn pretty_print_thumbnail() {
+        let mut fb = Framebuffer::new();
+        // Each character is 2x4 logical pixels at 64x32
+        fb.set_pixel(0, 0, ON);
+        fb.set_pixel(63, 31, ON);
+        let thumbnail = fb.pretty_print_thumbnail();
+        let rows = thumbnail.lines().collect::<Vec<_>>();
 
-        assert_eq!(fb.draw_sprite_at(0, 0, sprite1), false);
-        assert_eq!(fb.draw_sprite_at(0, 0, sprite2), true);
+        assert_eq!(rows.len(), THUMBNAIL_HEIGHT);
+        assert_eq!(rows[0], format!("#{}", ".".repeat(THUMBNAIL_WIDTH - 1)));
+        assert_eq!(rows[7], format!("{}#", ".".repeat(THUMBNAIL_WIDTH - 1)));
     }
 }
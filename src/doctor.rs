@@ -0,0 +1,320 @@
+#[cfg(feature = "window")]
+use crate::{
+    display::{Display, Key, HOTKEYS, KEYPAD},
+    frontend::WindowBackend,
+};
+use std::{
+    fmt::{self, Formatter},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the timer check asks to sleep for, each time.
+const SLEEP: Duration = Duration::from_millis(1);
+/// Sleeping this much longer than asked makes frames noticeably uneven, since
+/// a frame is only about 16ms.
+const SLEEP_TOLERANCE: Duration = Duration::from_millis(2);
+/// The terminal frontend needs at least this many columns and rows: 64x32
+/// pixels at two to a character, plus the title.
+#[cfg(feature = "terminal")]
+const TERMINAL_SIZE: (u16, u16) = (64, 17);
+
+/// How a check went.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    /// It works, but not as well as it could
+    Warning,
+    Failed,
+    /// There was nothing to check, like for a frontend that isn't built in
+    Skipped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Failed => "FAILED",
+            Status::Skipped => "skipped",
+        };
+        // Let `{:>7}` and the like pad it
+        f.pad(status)
+    }
+}
+
+/// One thing `chip8 doctor` checked about this machine, and what to do if it
+/// didn't pass.
+#[derive(Debug, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    /// What it found
+    pub detail: String,
+    /// How to fix it
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.to_string(),
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: &str) -> Self {
+        self.fix = Some(fix.to_string());
+        self
+    }
+}
+
+/// The status, what was checked, what was found, and how to fix it, like:
+///
+///     FAILED  Window (minifb): Could not open a window: ...
+///             Fix: ...
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>7}  {}: {}", self.status, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n         Fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check everything that commonly stops chip8 from working on a machine: that
+/// each frontend can open a window, that there's a sound device, that sleeping
+/// is accurate enough to keep 60 frames a second, and that the keys don't
+/// conflict. Opens (and closes) a window for each frontend.
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = window_checks();
+    checks.push(check_audio());
+    checks.push(check_timer());
+    checks.push(Check::new(
+        "Config file",
+        Status::Skipped,
+        "chip8 doesn't read a config file",
+    ));
+    #[cfg(feature = "window")]
+    checks.push(check_keys(&KEYPAD, &HOTKEYS));
+    checks
+}
+
+/// Check every frontend.
+#[cfg(feature = "window")]
+fn window_checks() -> Vec<Check> {
+    [
+        WindowBackend::Minifb,
+        WindowBackend::Sdl2,
+        WindowBackend::Terminal,
+        WindowBackend::Wgpu,
+    ]
+    .iter()
+    .map(|backend| check_window(*backend))
+    .collect()
+}
+
+#[cfg(not(feature = "window"))]
+fn window_checks() -> Vec<Check> {
+    vec![Check::new(
+        "Window",
+        Status::Skipped,
+        "This chip8 was built without any frontends, so it only runs with --headless",
+    )
+    .with_fix("Build it with `--features gui` to open a window")]
+}
+
+/// Try to open a window with the backend, if it's built in.
+#[cfg(feature = "window")]
+fn check_window(backend: WindowBackend) -> Check {
+    let name = format!("Window ({})", backend);
+    if !backend.is_built_in() {
+        return Check::new(
+            &name,
+            Status::Skipped,
+            &format!("This chip8 was built without the {} frontend", backend),
+        )
+        .with_fix(&format!(
+            "Build it with `--features {}` to use it",
+            backend.feature()
+        ));
+    }
+    if backend == WindowBackend::Terminal {
+        return check_terminal(&name);
+    }
+    match Display::new(backend, 640, 320, false, false) {
+        Ok(_) => Check::new(&name, Status::Ok, "Opened a window"),
+        Err(e) => {
+            let fix = match backend {
+                WindowBackend::Sdl2 => {
+                    "Check that the SDL2 library is installed and that there's a desktop to open a window on, or try `--frontend minifb`"
+                }
+                WindowBackend::Wgpu => {
+                    "Check that the graphics driver supports Vulkan, Metal, or DirectX 12 and that there's a desktop to open a window on, or try `--frontend minifb`"
+                }
+                _ => {
+                    "Check that there's a desktop to open a window on (on Linux, that DISPLAY or WAYLAND_DISPLAY is set). Over SSH, try `--frontend terminal`, or run without a window with `--headless`"
+                }
+            };
+            Check::new(&name, Status::Failed, &e.to_string()).with_fix(fix)
+        }
+    }
+}
+
+/// Check that there's a terminal, and that it's big enough to draw in. This
+/// doesn't open the frontend, since that would take over the terminal.
+#[cfg(feature = "terminal")]
+fn check_terminal(name: &str) -> Check {
+    let (columns, rows) = TERMINAL_SIZE;
+    match crate::terminal::Terminal::size() {
+        Ok((width, height)) if width >= columns && height >= rows => Check::new(
+            name,
+            Status::Ok,
+            &format!("The terminal is {}x{}", width, height),
+        ),
+        Ok((width, height)) => Check::new(
+            name,
+            Status::Warning,
+            &format!("The terminal is only {}x{}", width, height),
+        )
+        .with_fix(&format!(
+            "Make the terminal at least {} columns by {} rows",
+            columns, rows
+        )),
+        Err(e) => Check::new(name, Status::Failed, &e.to_string())
+            .with_fix("Run chip8 in a terminal, without piping or redirecting its output"),
+    }
+}
+
+// `check_window` only gets here for backends that are built in
+#[cfg(all(feature = "window", not(feature = "terminal")))]
+fn check_terminal(_name: &str) -> Check {
+    unreachable!()
+}
+
+/// Only the SDL2 frontend can beep, so check that it can open a sound device.
+#[cfg(feature = "sdl2-frontend")]
+fn check_audio() -> Check {
+    match crate::sdl::Sdl2::check_audio() {
+        Ok(()) => Check::new("Audio", Status::Ok, "Opened the sound device"),
+        Err(e) => Check::new(
+            "Audio",
+            Status::Warning,
+            &format!("Couldn't open the sound device: {}", e),
+        )
+        .with_fix(
+            "Check that a sound device is connected and not in use. Programs still run without the beep",
+        ),
+    }
+}
+
+#[cfg(not(feature = "sdl2-frontend"))]
+fn check_audio() -> Check {
+    Check::new(
+        "Audio",
+        Status::Skipped,
+        "Only the sdl2 frontend can beep, and this chip8 was built without it",
+    )
+    .with_fix(
+        "Build it with `--features sdl2-frontend` and pass `--frontend sdl2` to hear the beep",
+    )
+}
+
+/// Sleep for a short time over and over, and see how much longer than asked
+/// it takes. The window sleeps until each frame is due, so this has to be
+/// accurate to keep 60 frames a second smooth.
+fn check_timer() -> Check {
+    let mut worst = Duration::from_secs(0);
+    for _ in 0..20 {
+        let start = Instant::now();
+        thread::sleep(SLEEP);
+        worst = worst.max(start.elapsed().saturating_sub(SLEEP));
+    }
+    timer_check(worst)
+}
+
+/// The check for a timer that oversleeps by up to `worst`.
+fn timer_check(worst: Duration) -> Check {
+    let detail = format!(
+        "Sleeping for {}ms took up to {:.1}ms longer",
+        SLEEP.as_millis(),
+        worst.as_secs_f64() * 1000.0
+    );
+    if worst <= SLEEP_TOLERANCE {
+        Check::new("Timer", Status::Ok, &detail)
+    } else {
+        Check::new("Timer", Status::Warning, &detail)
+            .with_fix("Frames may be uneven. Close busy programs")
+    }
+}
+
+/// Check that every keypad key and hotkey is different, since a key that's
+/// both would do two things at once.
+#[cfg(feature = "window")]
+fn check_keys(keypad: &[Key], hotkeys: &[Key]) -> Check {
+    let mut seen = vec![];
+    let mut conflicts = vec![];
+    for key in keypad.iter().chain(hotkeys) {
+        if seen.contains(key) {
+            conflicts.push(format!("{:?}", key));
+        }
+        seen.push(*key);
+    }
+    if conflicts.is_empty() {
+        Check::new(
+            "Keys",
+            Status::Ok,
+            &format!(
+                "The {} keypad keys and {} hotkeys are all different",
+                keypad.len(),
+                hotkeys.len()
+            ),
+        )
+    } else {
+        Check::new(
+            "Keys",
+            Status::Failed,
+            &format!("Used more than once: {}", conflicts.join(", ")),
+        )
+        .with_fix("Give each of those keys only one job")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shows_the_fix() {
+        let check = Check::new("Audio", Status::Warning, "No sound device").with_fix("Plug one in");
+        assert_eq!(
+            check.to_string(),
+            "warning  Audio: No sound device\n         Fix: Plug one in"
+        );
+        assert_eq!(
+            Check::new("Timer", Status::Ok, "Fine").to_string(),
+            "     ok  Timer: Fine"
+        );
+    }
+
+    #[test]
+    fn slow_timers_are_a_warning() {
+        assert_eq!(timer_check(Duration::from_micros(500)).status, Status::Ok);
+        let slow = timer_check(Duration::from_millis(15));
+        assert_eq!(slow.status, Status::Warning);
+        assert!(slow.detail.contains("15.0ms"));
+    }
+
+    #[cfg(feature = "window")]
+    #[test]
+    fn finds_conflicting_keys() {
+        assert_eq!(check_keys(&KEYPAD, &HOTKEYS).status, Status::Ok);
+
+        let check = check_keys(&[Key::Q, Key::W], &[Key::P, Key::W]);
+        assert_eq!(check.status, Status::Failed);
+        assert_eq!(check.detail, "Used more than once: W");
+    }
+}
@@ -0,0 +1,240 @@
+// Only the web page runs programs this way so far, but it's tested either way
+#![cfg_attr(not(feature = "wasm"), allow(dead_code))]
+
+use crate::{
+    error::Chip8Error,
+    frame::FrameListener,
+    frontend::Frontend,
+    interpreter::{State, Variant},
+    memory::SharedMemoryHook,
+    persistence::Persistence,
+    quirks::{Profile, Quirks},
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+/// A CHIP-8 machine with everything it needs to run a program: how to
+/// interpret it, random numbers for RND, and a frontend to draw on and read
+/// the keypad from. This is the one thing to set up to run programs somewhere
+/// other than the `chip8` command's window, like in another program:
+///
+///     let mut emulator = Emulator::builder()
+///         .rom(&rom)
+///         .profile(Profile::Schip)
+///         .clock_hz(700)
+///         .seed(42)
+///         .frontend(MyDisplay::new())
+///         .build()?;
+///     while emulator.is_running() {
+///         emulator.run_frame()?;
+///     }
+pub struct Emulator<F: Frontend> {
+    state: State,
+    rng: StdRng,
+    frontend: F,
+}
+
+impl<F: Frontend> Emulator<F> {
+    /// Start setting up an emulator. It needs at least a ROM and a frontend.
+    pub fn builder() -> EmulatorBuilder<F> {
+        EmulatorBuilder {
+            rom: None,
+            variant: Variant::Chip8,
+            quirks: Quirks::default(),
+            clock_hz: None,
+            seed: None,
+            frontend: None,
+            memory_hooks: vec![],
+            persistence: None,
+        }
+    }
+
+    /// Run the instructions in one frame, and then draw the screen. Call this
+    /// 60 times a second.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.frontend.begin_frame(&mut self.state);
+        loop {
+            self.state.step(&mut self.rng)?;
+            if self.state.between_frames() {
+                break;
+            }
+        }
+        self.frontend.timers_ticked(&self.state);
+        self.frontend.end_frame(&self.state);
+        Ok(())
+    }
+
+    /// Whether the frontend wants to keep running the program, and the
+    /// program hasn't stopped itself with EXIT.
+    pub fn is_running(&self) -> bool {
+        self.frontend.is_running() && !self.state.has_exited()
+    }
+
+    /// The machine, as of the end of the last frame.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn frontend(&self) -> &F {
+        &self.frontend
+    }
+
+    pub fn frontend_mut(&mut self) -> &mut F {
+        &mut self.frontend
+    }
+}
+
+/// Sets up an `Emulator` (see `Emulator::builder`). Anything that isn't set
+/// is the same as the `chip8` command's default: the COSMAC VIP's quirks,
+/// `INSTRUCTIONS_PER_FRAME` instructions every frame, and a random seed.
+pub struct EmulatorBuilder<F: Frontend> {
+    rom: Option<Vec<u8>>,
+    variant: Variant,
+    quirks: Quirks,
+    clock_hz: Option<u32>,
+    seed: Option<u64>,
+    frontend: Option<F>,
+    memory_hooks: Vec<(Range<usize>, SharedMemoryHook)>,
+    persistence: Option<Rc<RefCell<dyn Persistence>>>,
+}
+
+impl<F: Frontend> EmulatorBuilder<F> {
+    /// The program to run.
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.rom = Some(rom.to_vec());
+        self
+    }
+
+    /// Which dialect of CHIP-8 the program is written in.
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Use the dialect and quirks of a machine.
+    pub fn profile(self, profile: Profile) -> Self {
+        self.variant(profile.variant()).quirks(profile.quirks())
+    }
+
+    /// Run about this many instructions a second (see `State::with_clock_hz`).
+    pub fn clock_hz(mut self, hz: u32) -> Self {
+        self.clock_hz = Some(hz);
+        self
+    }
+
+    /// Seed RND's random numbers, to repeat a run exactly.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Where to draw the screen and read the keypad from.
+    pub fn frontend(mut self, frontend: F) -> Self {
+        self.frontend = Some(frontend);
+        self
+    }
+
+    /// Let `hook` see what the program reads from and writes to `addresses`
+    /// (see `State::with_memory_hook`). Nothing in chip8 itself needs this,
+    /// only programs that embed it.
+    pub fn memory_hook(mut self, addresses: Range<usize>, hook: SharedMemoryHook) -> Self {
+        self.memory_hooks.push((addresses, hook));
+        self
+    }
+
+    /// Where to keep the program's RPL user flags between runs (see
+    /// `State::with_persistence`). Without it, they're lost when the
+    /// emulator is.
+    pub fn persistence(mut self, persistence: Rc<RefCell<dyn Persistence>>) -> Self {
+        self.persistence = Some(persistence);
+        self
+    }
+
+    /// Load the ROM into a fresh machine. Fails if there's no ROM or no
+    /// frontend.
+    pub fn build(self) -> Result<Emulator<F>, Chip8Error> {
+        let rom = self.rom.ok_or(Chip8Error::Incomplete("a ROM to run"))?;
+        let frontend = self
+            .frontend
+            .ok_or(Chip8Error::Incomplete("a frontend to draw on"))?;
+        let mut state = State::with_program_for(self.variant, &rom)?.with_quirks(self.quirks);
+        if let Some(hz) = self.clock_hz {
+            state = state.with_clock_hz(hz);
+        }
+        for (addresses, hook) in self.memory_hooks {
+            state = state.with_memory_hook(addresses, hook);
+        }
+        if let Some(persistence) = self.persistence {
+            state = state.with_persistence(persistence)?;
+        }
+        let seed = self.seed.unwrap_or_else(rand::random);
+        Ok(Emulator {
+            state,
+            rng: StdRng::seed_from_u64(seed),
+            frontend,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Counts how many times it's drawn, and always holds down key 5.
+    #[derive(Default)]
+    struct Counter {
+        frames: usize,
+    }
+
+    impl Frontend for Counter {
+        fn keypad(&self) -> [bool; 16] {
+            let mut keypad = [false; 16];
+            keypad[5] = true;
+            keypad
+        }
+
+        fn show(&mut self, _state: &State) {
+            self.frames += 1;
+        }
+
+        fn is_running(&self) -> bool {
+            self.frames < 3
+        }
+    }
+
+    #[test]
+    fn runs_a_frame_at_a_time() {
+        // ADD V0, 1; JP 200
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut emulator = Emulator::builder()
+            .rom(&rom)
+            .profile(Profile::Schip)
+            .clock_hz(1200)
+            .seed(42)
+            .frontend(Counter::default())
+            .build()
+            .unwrap();
+        while emulator.is_running() {
+            emulator.run_frame().unwrap();
+        }
+
+        assert_eq!(emulator.frontend().frames, 3);
+        // 3 frames of 20 instructions, half of which are ADDs
+        assert_eq!(emulator.state().registers()[0], 30);
+        assert!(emulator.state().keypad()[5]);
+    }
+
+    #[test]
+    fn needs_a_rom_and_a_frontend() {
+        let no_rom = Emulator::builder().frontend(Counter::default()).build();
+        assert!(matches!(no_rom, Err(Chip8Error::Incomplete(_))));
+
+        let no_frontend = Emulator::<Counter>::builder().rom(&[]).build();
+        assert!(matches!(no_frontend, Err(Chip8Error::Incomplete(_))));
+    }
+}
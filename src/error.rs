@@ -1,7 +1,189 @@
+use crate::quirks::Profile;
+use std::io;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Chip8Error {
     #[error("IO Error: {0:?}")]
     Io(#[from] std::io::Error),
+    #[error("Could not encode instruction: {0}")]
+    Encode(#[from] EncodeError),
+    #[error("Register V{0:X} is out of range (must be from 0x0 - 0xF)")]
+    InvalidRegister(u8),
+    #[error("Address {0:03X} is out of range (must be from 0x000 - 0xFFF)")]
+    InvalidAddress(u16),
+    #[error("Unknown instruction {opcode:04X} at {pc:03X}")]
+    UnknownInstruction { pc: u16, opcode: u16 },
+    #[error("Stack overflow: CALL at {pc:03X} with 16 subroutines already running")]
+    StackOverflow { pc: u16 },
+    #[error("Stack underflow: RET at {pc:03X} without a subroutine to return from")]
+    StackUnderflow { pc: u16 },
+    #[error("Memory out of bounds: {address:04X} is past the end of memory")]
+    MemoryOutOfBounds { address: usize },
+    #[error(
+        "The program is {size} bytes, but only {max} bytes fit in memory after where it's loaded"
+    )]
+    ProgramTooLarge { size: usize, max: usize },
+    #[error("{instruction} at {pc:03X} is an XO-CHIP instruction, run with --variant xo-chip")]
+    NeedsXoChip { pc: u16, instruction: String },
+    #[error("There are no bytes to decode an instruction from")]
+    NothingToDecode,
+    #[error("Cannot scale {logical_width}x{logical_height} to fit {true_width}x{true_height}")]
+    CannotScale {
+        logical_width: usize,
+        logical_height: usize,
+        true_width: usize,
+        true_height: usize,
+    },
+    #[error("Line {line}: {message}")]
+    Assemble { line: usize, message: String },
+    #[error("These lines use instructions that {target} doesn't have:\n{listing}")]
+    OffTarget { target: Profile, listing: String },
+    #[error("Found {0} problems in the ROM")]
+    RomProblems(usize),
+    #[error("The source doesn't match the ROM in {0} places")]
+    Mismatch(usize),
+    #[error("The run went differently from the reference after {0} instructions")]
+    Diverged(u64),
+    #[error("Line {line} of the reference trace: {message}")]
+    BadReference { line: usize, message: String },
+    #[error("Could not open a window: {0}")]
+    Window(String),
+    #[error("Could not save or load state: {0}")]
+    SaveState(#[from] bincode::Error),
+    #[error("The saved state is corrupt: {0}")]
+    CorruptState(String),
+    #[error("This isn't a bundle made by `chip8 bundle`")]
+    NotABundle,
+    #[error("This isn't a replay made with `--record`")]
+    NotAReplay,
+    #[error("{0} checks failed")]
+    ChecksFailed(usize),
+    #[error("The emulator needs {0}")]
+    Incomplete(&'static str),
+    #[error("{path}, line {line}: {message}")]
+    Config {
+        path: String,
+        line: usize,
+        message: String,
+    },
+}
+
+/// Why an `Instruction` couldn't be turned back into bytes.
+#[derive(Error, Debug, PartialEq)]
+pub enum EncodeError {
+    #[error("{instruction} operand {value:X} is out of range (must be from 0x0 - 0xF)")]
+    OperandOutOfRange {
+        instruction: &'static str,
+        value: u8,
+    },
+    #[error("{instruction} would encode as {opcode:04X}, which decodes as {decoded}")]
+    Ambiguous {
+        instruction: String,
+        opcode: u16,
+        decoded: String,
+    },
+}
+
+/// The process's exit code for each kind of failure, so scripts can tell them
+/// apart without reading stderr. 2 is left for bad command-line arguments,
+/// which structopt exits with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Anything that doesn't fit in the other categories, like an IO error
+    OtherError = 1,
+    RomNotFound = 3,
+    /// The ROM has bytes we couldn't decode (or encode), or the assembly
+    /// source has a mistake
+    DecodeError = 4,
+    /// The program did something the interpreter can't do, like returning
+    /// from a subroutine it never called
+    InterpreterFault = 5,
+    /// A test ROM reported that it failed
+    TestFailure = 6,
+    /// What a program did didn't match what we expected it to do
+    VerificationMismatch = 7,
+}
+
+impl ExitCode {
+    /// Every exit code, in order.
+    pub const ALL: [ExitCode; 7] = [
+        ExitCode::Success,
+        ExitCode::OtherError,
+        ExitCode::RomNotFound,
+        ExitCode::DecodeError,
+        ExitCode::InterpreterFault,
+        ExitCode::TestFailure,
+        ExitCode::VerificationMismatch,
+    ];
+
+    /// What this exit code means, for `chip8 exit-codes`.
+    pub fn description(self) -> &'static str {
+        match self {
+            ExitCode::Success => "Success",
+            ExitCode::OtherError => "Any other error, like being unable to read a file",
+            ExitCode::RomNotFound => "The ROM (or another file it needed) doesn't exist",
+            ExitCode::DecodeError => {
+                "The ROM has bytes that couldn't be decoded, or the assembly has a mistake"
+            }
+            ExitCode::InterpreterFault => {
+                "The program crashed the interpreter (unknown instruction, stack overflow or underflow, memory out of bounds, or an XO-CHIP instruction without --variant xo-chip)"
+            }
+            ExitCode::TestFailure => "A test ROM failed",
+            ExitCode::VerificationMismatch => "The program didn't do what was expected",
+        }
+    }
+}
+
+impl From<&Chip8Error> for ExitCode {
+    fn from(error: &Chip8Error) -> Self {
+        match error {
+            Chip8Error::Io(e) if e.kind() == io::ErrorKind::NotFound => ExitCode::RomNotFound,
+            Chip8Error::Io(_)
+            | Chip8Error::SaveState(_)
+            | Chip8Error::CorruptState(_)
+            | Chip8Error::NotABundle
+            | Chip8Error::NotAReplay
+            | Chip8Error::ChecksFailed(_)
+            | Chip8Error::Incomplete(_)
+            | Chip8Error::Config { .. }
+            | Chip8Error::BadReference { .. }
+            | Chip8Error::ProgramTooLarge { .. }
+            | Chip8Error::CannotScale { .. }
+            | Chip8Error::Window(_) => ExitCode::OtherError,
+            Chip8Error::Encode(_)
+            | Chip8Error::InvalidRegister(_)
+            | Chip8Error::InvalidAddress(_)
+            | Chip8Error::NothingToDecode
+            | Chip8Error::Assemble { .. }
+            | Chip8Error::OffTarget { .. }
+            | Chip8Error::RomProblems(_) => ExitCode::DecodeError,
+            Chip8Error::UnknownInstruction { .. }
+            | Chip8Error::StackOverflow { .. }
+            | Chip8Error::StackUnderflow { .. }
+            | Chip8Error::MemoryOutOfBounds { .. }
+            | Chip8Error::NeedsXoChip { .. } => ExitCode::InterpreterFault,
+            Chip8Error::Mismatch(_) | Chip8Error::Diverged(_) => ExitCode::VerificationMismatch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        let not_found = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        assert_eq!(
+            ExitCode::from(&Chip8Error::Io(not_found)),
+            ExitCode::RomNotFound
+        );
+        assert_eq!(
+            ExitCode::from(&Chip8Error::StackUnderflow { pc: 0x200 }),
+            ExitCode::InterpreterFault
+        );
+        assert_eq!(ExitCode::InterpreterFault as i32, 5);
+    }
 }
@@ -0,0 +1,184 @@
+use std::{f64::consts::PI, iter::Peekable, str::Chars};
+
+/// Evaluate an arithmetic expression from the assembler, like
+/// `80 + 7F * sin(i * pi / 20)`.
+///
+/// Numbers are hex, like everywhere else in the assembler. There's `+`, `-`,
+/// `*`, `/`, `%`, and parentheses, with the usual precedence, plus `sin(x)`
+/// and `cos(x)` (in radians) and the constant `pi`. Any other name is looked
+/// up with `variable`, which returns an error for names it doesn't know.
+/// The math is done with fractions, so round the result to get a whole number.
+pub fn evaluate(text: &str, variable: &dyn Fn(&str) -> Result<f64, String>) -> Result<f64, String> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        variable,
+    };
+    let value = parser.sum()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("Unexpected `{}` in `{}`", c, text)),
+    }
+}
+
+/// A recursive descent parser that works out the value as it goes.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    variable: &'a dyn Fn(&str) -> Result<f64, String>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    /// The next character that isn't whitespace, without using it up.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    /// Terms added or subtracted together.
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.chars.next();
+                    value += self.product()?;
+                }
+                Some('-') => {
+                    self.chars.next();
+                    value -= self.product()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// Factors multiplied or divided together.
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        loop {
+            let operator = match self.peek() {
+                Some(c) if c == '*' || c == '/' || c == '%' => c,
+                _ => return Ok(value),
+            };
+            self.chars.next();
+            let right = self.factor()?;
+            if right == 0.0 && operator != '*' {
+                return Err("Division by zero".to_string());
+            }
+            match operator {
+                '*' => value *= right,
+                '/' => value /= right,
+                _ => value %= right,
+            }
+        }
+    }
+
+    /// A number, a name, a function call, a negation, or a parenthesized
+    /// expression.
+    fn factor(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(-self.factor()?)
+            }
+            Some('(') => {
+                self.chars.next();
+                let value = self.sum()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.peek() {
+                    if !c.is_alphanumeric() && *c != '_' {
+                        break;
+                    }
+                    word.push(*c);
+                    self.chars.next();
+                }
+                self.word(&word)
+            }
+            Some(c) => Err(format!("Unexpected `{}`", c)),
+            None => Err("Expected a number, but the expression ended".to_string()),
+        }
+    }
+
+    /// A number, a name, or a function call that starts with `word`.
+    fn word(&mut self, word: &str) -> Result<f64, String> {
+        match word {
+            "sin" | "cos" => {
+                self.expect('(')?;
+                let argument = self.sum()?;
+                self.expect(')')?;
+                Ok(if word == "sin" {
+                    argument.sin()
+                } else {
+                    argument.cos()
+                })
+            }
+            "pi" => Ok(PI),
+            _ => {
+                let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+                match u32::from_str_radix(digits, 16) {
+                    Ok(n) => Ok(n as f64),
+                    Err(_) => (self.variable)(word),
+                }
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(format!("Expected `{}`", expected)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn no_variables(name: &str) -> Result<f64, String> {
+        Err(format!("Unknown name `{}`", name))
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(evaluate("2 + 3 * 4", &no_variables), Ok(14.0));
+        assert_eq!(evaluate("(2 + 3) * 4", &no_variables), Ok(20.0));
+        assert_eq!(evaluate("10 - 2 - 1", &no_variables), Ok(13.0));
+        assert_eq!(evaluate("-0x10 % 3", &no_variables), Ok(-1.0));
+    }
+
+    #[test]
+    fn functions_and_variables() {
+        let x = |name: &str| match name {
+            "x" => Ok(0x10 as f64),
+            _ => no_variables(name),
+        };
+        assert_eq!(evaluate("x * 2", &x), Ok(32.0));
+        assert!((evaluate("sin(pi / 2) + cos(0)", &x).unwrap() - 2.0).abs() < 1e-9);
+        assert_eq!(evaluate("y", &x), Err("Unknown name `y`".to_string()));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(evaluate("1 +", &no_variables).is_err());
+        assert!(evaluate("(1", &no_variables).is_err());
+        assert!(evaluate("1 2", &no_variables).is_err());
+        assert!(evaluate("1 / 0", &no_variables).is_err());
+    }
+}
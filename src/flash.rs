@@ -0,0 +1,241 @@
+// Only the window reduces flashing, but it's tested either way
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use std::collections::VecDeque;
+
+/// The screen is split into a grid of regions this many across and down, and
+/// each region's flashing is tracked separately.
+const REGIONS_ACROSS: usize = 8;
+const REGIONS_DOWN: usize = 4;
+/// A region's brightness (from 0 to 1) has to change by at least this much
+/// from one frame to the next to count as half of a flash.
+const FLASH_CONTRAST: f32 = 0.2;
+/// How many frames the flash limit is counted over: one second.
+const WINDOW_FRAMES: u64 = 60;
+/// The most a region can flash in a second before it's too much. The usual
+/// guideline (like WCAG's) is no more than 3 flashes a second, and each flash
+/// is two changes: to bright and back.
+const MAX_CHANGES_PER_SECOND: usize = 3 * 2;
+/// Flashing is only a danger when it covers at least this much of the screen.
+const LARGE_AREA: f32 = 0.25;
+/// When reducing flashing, a flashing region only moves this far from what was
+/// shown last towards the new frame, so back-and-forth flashes blend into a
+/// steady color.
+const BLEND: u32 = 4;
+
+/// Tracks how often each region of the screen flashes between bright and
+/// dark, and can blend the regions that flash too often with the frame before,
+/// for people who are sensitive to flashing lights. CHIP-8 programs flicker a
+/// lot, since they erase sprites by drawing them again.
+#[derive(Default)]
+pub struct FlashGuard {
+    /// Each region's brightness in the last frame
+    brightness: Vec<f32>,
+    /// For each region, the frames when its brightness changed a lot, in the
+    /// last second
+    changes: Vec<VecDeque<u64>>,
+    /// What was shown last, after blending
+    shown: Vec<u32>,
+    /// How many frames have been tracked
+    frame: u64,
+}
+
+impl FlashGuard {
+    /// Look at the next frame, which is `width` by `height` physical pixels,
+    /// and remember which regions changed a lot since the last one.
+    pub fn track(&mut self, pixels: &[u32], width: usize, height: usize) {
+        let brightness = region_brightness(pixels, width, height);
+        if self.brightness.len() == brightness.len() {
+            for (region, (before, after)) in self.brightness.iter().zip(&brightness).enumerate() {
+                if (after - before).abs() >= FLASH_CONTRAST {
+                    self.changes[region].push_back(self.frame);
+                }
+            }
+        } else {
+            self.changes = vec![VecDeque::new(); brightness.len()];
+        }
+        self.brightness = brightness;
+        self.next_frame();
+    }
+
+    /// Count a frame that's exactly the same as the last one, which can't
+    /// have flashed, without looking at its pixels.
+    pub fn track_unchanged(&mut self) {
+        self.next_frame();
+    }
+
+    /// Move on to the next frame, forgetting changes from over a second ago.
+    fn next_frame(&mut self) {
+        let now = self.frame;
+        for changes in &mut self.changes {
+            while changes.front().map(|frame| frame + WINDOW_FRAMES <= now) == Some(true) {
+                changes.pop_front();
+            }
+        }
+        self.frame += 1;
+    }
+
+    /// Whether the region at this index in the grid is flashing too often.
+    fn is_flashing(&self, region: usize) -> bool {
+        self.changes[region].len() > MAX_CHANGES_PER_SECOND
+    }
+
+    /// Whether enough of the screen is flashing too often that it could be
+    /// dangerous for someone who's sensitive to it.
+    pub fn is_dangerous(&self) -> bool {
+        let flashing = (0..self.changes.len())
+            .filter(|region| self.is_flashing(*region))
+            .count();
+        flashing as f32 >= LARGE_AREA * (REGIONS_ACROSS * REGIONS_DOWN) as f32
+    }
+
+    /// Blend the regions that are flashing too often with what was shown
+    /// last, so they fade instead of flashing. Call `track` with the frame
+    /// first.
+    pub fn reduce(&mut self, pixels: &mut [u32], width: usize, height: usize) {
+        if self.shown.len() == pixels.len() && !self.changes.is_empty() {
+            for y in 0..height {
+                for x in 0..width {
+                    if !self.is_flashing(region_at(x, y, width, height)) {
+                        continue;
+                    }
+                    let index = y * width + x;
+                    pixels[index] = blend(self.shown[index], pixels[index]);
+                }
+            }
+        }
+        self.shown = pixels.to_vec();
+    }
+}
+
+/// Which region of the grid the physical pixel at (x, y) is in.
+fn region_at(x: usize, y: usize, width: usize, height: usize) -> usize {
+    let column = x * REGIONS_ACROSS / width;
+    let row = y * REGIONS_DOWN / height;
+    row * REGIONS_ACROSS + column
+}
+
+/// How bright each region of the grid is on average, from 0 (black) to 1
+/// (white).
+fn region_brightness(pixels: &[u32], width: usize, height: usize) -> Vec<f32> {
+    let mut totals = [0.0; REGIONS_ACROSS * REGIONS_DOWN];
+    let mut counts = vec![0; REGIONS_ACROSS * REGIONS_DOWN];
+    for y in 0..height {
+        for x in 0..width {
+            let region = region_at(x, y, width, height);
+            totals[region] += luminance(pixels[y * width + x]);
+            counts[region] += 1;
+        }
+    }
+    totals
+        .iter()
+        .zip(counts)
+        .map(|(total, count)| {
+            if count == 0 {
+                0.0
+            } else {
+                total / count as f32
+            }
+        })
+        .collect()
+}
+
+/// How bright a 0RGB color looks, from 0 to 1.
+fn luminance(color: u32) -> f32 {
+    let [_, r, g, b] = color.to_be_bytes();
+    (0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b)) / 255.0
+}
+
+/// Move `from` a `1 / BLEND` of the way towards `to`, one channel at a time.
+fn blend(from: u32, to: u32) -> u32 {
+    let from = from.to_be_bytes();
+    let to = to.to_be_bytes();
+    let mut result = [0; 4];
+    for channel in 1..4 {
+        let (from, to) = (u32::from(from[channel]), u32::from(to[channel]));
+        result[channel] = ((from * (BLEND - 1) + to) / BLEND) as u8;
+    }
+    u32::from_be_bytes(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 8;
+
+    fn filled(color: u32) -> Vec<u32> {
+        vec![color; WIDTH * HEIGHT]
+    }
+
+    #[test]
+    fn flashing_the_whole_screen_is_dangerous() {
+        let mut guard = FlashGuard::default();
+        for frame in 0..20 {
+            assert!(!guard.is_dangerous(), "too soon, at frame {}", frame);
+            let color = if frame % 2 == 0 { 0 } else { 0xFF_FF_FF };
+            guard.track(&filled(color), WIDTH, HEIGHT);
+            if guard.is_dangerous() {
+                return;
+            }
+        }
+        panic!("Flashing every frame should be dangerous");
+    }
+
+    #[test]
+    fn small_or_slow_changes_arent() {
+        let mut guard = FlashGuard::default();
+        for frame in 0..120 {
+            // One small corner flashes every frame
+            let mut pixels = filled(0);
+            if frame % 2 == 0 {
+                pixels[0] = 0xFF_FF_FF;
+                pixels[1] = 0xFF_FF_FF;
+            }
+            guard.track(&pixels, WIDTH, HEIGHT);
+            assert!(!guard.is_dangerous());
+        }
+
+        // The whole screen flashes, but only twice a second
+        let mut guard = FlashGuard::default();
+        for frame in 0..120 {
+            let color = if frame / 30 % 2 == 0 { 0 } else { 0xFF_FF_FF };
+            guard.track(&filled(color), WIDTH, HEIGHT);
+            assert!(!guard.is_dangerous());
+        }
+    }
+
+    #[test]
+    fn unchanged_frames_let_flashing_wear_off() {
+        let mut guard = FlashGuard::default();
+        for frame in 0..20 {
+            let color = if frame % 2 == 0 { 0 } else { 0xFF_FF_FF };
+            guard.track(&filled(color), WIDTH, HEIGHT);
+        }
+        assert!(guard.is_dangerous());
+
+        for _ in 0..60 {
+            guard.track_unchanged();
+        }
+        assert!(!guard.is_dangerous());
+    }
+
+    #[test]
+    fn reducing_blends_flashing_regions() {
+        let mut guard = FlashGuard::default();
+        let mut shown = vec![];
+        for frame in 0..60 {
+            let mut pixels = filled(if frame % 2 == 0 { 0 } else { 0xFF_FF_FF });
+            guard.track(&pixels, WIDTH, HEIGHT);
+            guard.reduce(&mut pixels, WIDTH, HEIGHT);
+            shown.push(pixels[0]);
+        }
+
+        // Once it's flashing too often, each frame only changes a little
+        let [_, before, _, _] = shown[58].to_be_bytes();
+        let [_, after, _, _] = shown[59].to_be_bytes();
+        assert!((i32::from(after) - i32::from(before)).abs() < 0x50);
+        assert_eq!(blend(0, 0xFF_FF_FF), 0x3F_3F_3F);
+    }
+}
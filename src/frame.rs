@@ -0,0 +1,79 @@
+use crate::interpreter::State;
+
+/// The points in every frame that a frontend can hook into. A frame is
+/// `INSTRUCTIONS_PER_FRAME` cycles long (by default), and its events always happen in this
+/// order: `BeginFrame`, `TimersTicked`, then (after the frame's cycles have
+/// run) `EndFrame`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameEvent {
+    /// A new frame is about to start running instructions
+    BeginFrame,
+    /// The delay and sound timers just counted down (the vertical blank)
+    TimersTicked,
+    /// Every instruction in the frame has run
+    EndFrame,
+}
+
+impl FrameEvent {
+    /// Call the listener's method for this event.
+    pub fn send_to(self, listener: &mut impl FrameListener, state: &mut State) {
+        match self {
+            FrameEvent::BeginFrame => listener.begin_frame(state),
+            FrameEvent::TimersTicked => listener.timers_ticked(state),
+            FrameEvent::EndFrame => listener.end_frame(state),
+        }
+    }
+}
+
+/// Something that wants to know when frames begin and end, like the window
+/// (which reads the keyboard at the start of a frame and draws at the end) or
+/// the watchdog (which counts frames).
+/// Every method does nothing unless it's overridden.
+pub trait FrameListener {
+    /// A new frame is starting. This is the time to read input, since the
+    /// listener can change the state.
+    fn begin_frame(&mut self, _state: &mut State) {}
+
+    /// The timers just counted down. This is the time to start or stop a beep.
+    fn timers_ticked(&mut self, _state: &State) {}
+
+    /// The frame is over. This is the time to draw.
+    fn end_frame(&mut self, _state: &State) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Vec<FrameEvent>);
+
+    impl FrameListener for Recorder {
+        fn begin_frame(&mut self, _state: &mut State) {
+            self.0.push(FrameEvent::BeginFrame);
+        }
+
+        fn end_frame(&mut self, _state: &State) {
+            self.0.push(FrameEvent::EndFrame);
+        }
+    }
+
+    #[test]
+    fn send_to_calls_the_matching_method() {
+        let mut state = State::with_program(&[]).unwrap();
+        let mut recorder = Recorder::default();
+        for event in &[
+            FrameEvent::BeginFrame,
+            FrameEvent::TimersTicked,
+            FrameEvent::EndFrame,
+        ] {
+            event.send_to(&mut recorder, &mut state);
+        }
+
+        // TimersTicked isn't overridden, so it does nothing
+        assert_eq!(
+            recorder.0,
+            vec![FrameEvent::BeginFrame, FrameEvent::EndFrame]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// Only windows and the web page have frontends, but they're tested either way
+#![cfg_attr(not(any(feature = "window", feature = "wasm")), allow(dead_code))]
+
+use crate::{frame::FrameListener, interpreter::State};
+use std::{fmt, str::FromStr};
+
+/// Somewhere to run a program: a screen to draw the machine's display on, and
+/// a keyboard to read its keypad from. The window (see `display::Display`) is
+/// one, and so is the web page's canvas (see `web::Canvas`).
+pub trait Frontend {
+    /// Which of the CHIP-8's 16 keys are held down, in order from 0 to F.
+    fn keypad(&self) -> [bool; 16];
+
+    /// Draw the machine's display.
+    fn show(&mut self, state: &State);
+
+    /// Whether to keep running the program.
+    fn is_running(&self) -> bool;
+
+    /// Start or stop beeping. Frontends that can't play sound don't.
+    fn beep(&mut self, _on: bool) {}
+}
+
+/// Every frontend reads the keypad at the start of every frame, beeps for as
+/// long as the sound timer is counting down, and draws the screen at the end
+/// of the frame.
+impl<F: Frontend> FrameListener for F {
+    fn begin_frame(&mut self, state: &mut State) {
+        state.set_keypad(self.keypad());
+    }
+
+    fn timers_ticked(&mut self, state: &State) {
+        self.beep(state.sound_timer() > 0);
+    }
+
+    fn end_frame(&mut self, state: &State) {
+        self.show(state);
+    }
+}
+
+/// Which library opens the window (see `display::Backend`). Each one needs
+/// its own feature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowBackend {
+    /// minifb, the default (with the `gui` feature), which has no sound
+    Minifb,
+    /// SDL2, with sound and vsync (with the `sdl2-frontend` feature)
+    Sdl2,
+    /// The terminal, drawn with text, so it works over SSH (with the
+    /// `terminal` feature)
+    Terminal,
+    /// The GPU, with wgpu, which can draw through shaders (with the
+    /// `wgpu-frontend` feature)
+    Wgpu,
+}
+
+impl WindowBackend {
+    /// The Cargo feature that builds this backend in.
+    pub fn feature(&self) -> &'static str {
+        match self {
+            WindowBackend::Minifb => "gui",
+            WindowBackend::Sdl2 => "sdl2-frontend",
+            WindowBackend::Terminal => "terminal",
+            WindowBackend::Wgpu => "wgpu-frontend",
+        }
+    }
+
+    /// Whether this chip8 was built with the backend's feature.
+    pub fn is_built_in(&self) -> bool {
+        match self {
+            WindowBackend::Minifb => cfg!(feature = "gui"),
+            WindowBackend::Sdl2 => cfg!(feature = "sdl2-frontend"),
+            WindowBackend::Terminal => cfg!(feature = "terminal"),
+            WindowBackend::Wgpu => cfg!(feature = "wgpu-frontend"),
+        }
+    }
+}
+
+impl FromStr for WindowBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minifb" => Ok(WindowBackend::Minifb),
+            "sdl2" => Ok(WindowBackend::Sdl2),
+            "terminal" => Ok(WindowBackend::Terminal),
+            "wgpu" => Ok(WindowBackend::Wgpu),
+            _ => Err(format!(
+                "Unknown frontend {} (expected minifb, sdl2, terminal, or wgpu)",
+                s
+            )),
+        }
+    }
+}
+
+/// The same names that `from_str` accepts.
+impl fmt::Display for WindowBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowBackend::Minifb => write!(f, "minifb"),
+            WindowBackend::Sdl2 => write!(f, "sdl2"),
+            WindowBackend::Terminal => write!(f, "terminal"),
+            WindowBackend::Wgpu => write!(f, "wgpu"),
+        }
+    }
+}
+
+/// How the wgpu frontend draws the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shader {
+    /// Just the pixels, scaled up
+    None,
+    /// Like an old TV: curved, with scanlines, and glowing a little
+    Crt,
+}
+
+impl FromStr for Shader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Shader::None),
+            "crt" => Ok(Shader::Crt),
+            _ => Err(format!("Unknown shader {} (expected none or crt)", s)),
+        }
+    }
+}
+
+/// The same names that `from_str` accepts.
+impl fmt::Display for Shader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shader::None => write!(f, "none"),
+            Shader::Crt => write!(f, "crt"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frame::FrameEvent, interpreter::tick};
+
+    #[derive(Default)]
+    struct Headless {
+        keypad: [bool; 16],
+        shown: usize,
+        beeping: bool,
+    }
+
+    impl Frontend for Headless {
+        fn keypad(&self) -> [bool; 16] {
+            self.keypad
+        }
+
+        fn show(&mut self, _state: &State) {
+            self.shown += 1;
+        }
+
+        fn is_running(&self) -> bool {
+            true
+        }
+
+        fn beep(&mut self, on: bool) {
+            self.beeping = on;
+        }
+    }
+
+    #[test]
+    fn reads_the_keypad_and_draws_every_frame() {
+        // LD V0, K
+        let mut state = State::with_program(&[0xF0, 0x0A]).unwrap();
+        let mut frontend = Headless::default();
+        frontend.keypad[0xB] = true;
+
+        FrameEvent::BeginFrame.send_to(&mut frontend, &mut state);
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_eq!(state.registers()[0x0], 0xB);
+        assert_eq!(frontend.shown, 0);
+        FrameEvent::EndFrame.send_to(&mut frontend, &mut state);
+        assert_eq!(frontend.shown, 1);
+    }
+
+    #[test]
+    fn beeps_while_the_sound_timer_counts_down() {
+        // LD V0, 02; LD ST, V0
+        let mut state = State::with_program(&[0x60, 0x02, 0xF0, 0x18]).unwrap();
+        let mut frontend = Headless::default();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+
+        FrameEvent::TimersTicked.send_to(&mut frontend, &mut state);
+        assert!(frontend.beeping);
+        state = State::with_program(&[]).unwrap();
+        FrameEvent::TimersTicked.send_to(&mut frontend, &mut state);
+        assert!(!frontend.beeping);
+    }
+
+    #[test]
+    fn window_backend_names() {
+        for backend in &[
+            WindowBackend::Minifb,
+            WindowBackend::Sdl2,
+            WindowBackend::Terminal,
+        ] {
+            assert_eq!(backend.to_string().parse(), Ok(*backend));
+        }
+        assert!("gtk".parse::<WindowBackend>().is_err());
+    }
+}
@@ -0,0 +1,554 @@
+use crate::{
+    display::{Backend, Key, TITLE},
+    error::Chip8Error,
+    frontend::Shader,
+};
+use pixels::{
+    wgpu::{self, util::DeviceExt},
+    Pixels, SurfaceTexture,
+};
+use std::collections::HashSet;
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    platform::run_return::EventLoopExtRunReturn,
+    window::{Fullscreen, Window, WindowBuilder},
+};
+
+/// While fast-forwarding, only every this many frames is actually shown, since
+/// vsync would otherwise hold it to the monitor's refresh rate.
+const TURBO_FRAMES_PER_PRESENT: u32 = 10;
+
+/// The CRT effect: the screen bulges out like a tube's glass, every pixel
+/// glows a little into its neighbors like phosphor does, and there are dark
+/// gaps between the lines. The COSMAC VIP drew each of its 32 rows as 4 of
+/// the TV's lines, so there are 128 of them.
+const CRT_SHADER: &str = r#"
+struct VertexOutput {
+    [[location(0)]] tex_coord: vec2<f32>;
+    [[builtin(position)]] position: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] position: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coord = fma(position, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+[[group(0), binding(0)]] var r_screen: texture_2d<f32>;
+[[group(0), binding(1)]] var r_sampler: sampler;
+struct Locals {
+    // Where the screen is in the window, as x, y, width, and height from 0
+    // to 1, since it has black bars around it
+    clip: vec4<f32>;
+};
+[[group(0), binding(2)]] var<uniform> r_locals: Locals;
+
+let CURVATURE: f32 = 0.04;
+let GLOW: f32 = 0.35;
+let SCANLINES: f32 = 128.0;
+let SCANLINE_DEPTH: f32 = 0.3;
+
+[[stage(fragment)]]
+fn fs_main([[location(0)]] tex_coord: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    // Bend the screen's own coordinates, from -1 to 1 across it
+    let clip = r_locals.clip;
+    var screen = (tex_coord - clip.xy) / clip.zw * 2.0 - 1.0;
+    screen = screen * (1.0 + screen.yx * screen.yx * CURVATURE);
+    let local = screen * 0.5 + 0.5;
+    if (local.x < 0.0 || local.x > 1.0 || local.y < 0.0 || local.y > 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let uv = clip.xy + local * clip.zw;
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(r_screen));
+    let color = textureSample(r_screen, r_sampler, uv).rgb;
+    let glow = (textureSample(r_screen, r_sampler, uv + vec2<f32>(texel.x * 2.0, 0.0)).rgb
+        + textureSample(r_screen, r_sampler, uv - vec2<f32>(texel.x * 2.0, 0.0)).rgb
+        + textureSample(r_screen, r_sampler, uv + vec2<f32>(0.0, texel.y * 2.0)).rgb
+        + textureSample(r_screen, r_sampler, uv - vec2<f32>(0.0, texel.y * 2.0)).rgb) * 0.25;
+    let scanline = 1.0 - SCANLINE_DEPTH * (0.5 + 0.5 * cos(local.y * SCANLINES * 6.2831853));
+    return vec4<f32>(min((color + glow * GLOW) * scanline, vec3<f32>(1.0)), 1.0);
+}
+"#;
+
+/// A window drawn on the GPU with `pixels` (and so wgpu), which can draw the
+/// screen through a shader, like the CRT effect. F4 turns the shader on and
+/// off. Like SDL2, it waits for vsync before showing a frame.
+pub struct Wgpu {
+    event_loop: EventLoop<()>,
+    window: Window,
+    pixels: Pixels,
+    crt: CrtRenderer,
+    shader: Shader,
+    open: bool,
+    /// The keys held down right now
+    held: HashSet<VirtualKeyCode>,
+    /// The keys pressed since the last update, not counting key repeats
+    pressed: HashSet<VirtualKeyCode>,
+    /// The window's new size, if it changed since the last update
+    resized: Option<PhysicalSize<u32>>,
+    turbo: bool,
+    /// Frames since the last one that was shown, while fast-forwarding
+    skipped_frames: u32,
+}
+
+impl Wgpu {
+    pub fn open(
+        width: usize,
+        height: usize,
+        resizable: bool,
+        fullscreen: bool,
+    ) -> Result<Self, Chip8Error> {
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title(TITLE)
+            .with_inner_size(PhysicalSize::new(width as u32, height as u32))
+            .with_resizable(resizable)
+            .with_fullscreen(if fullscreen {
+                Some(Fullscreen::Borderless(None))
+            } else {
+                None
+            })
+            .build(&event_loop)
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        let size = window.inner_size();
+        let surface = SurfaceTexture::new(size.width, size.height, &window);
+        // The screen is scaled up a whole number of times, with black bars
+        // around it if need be
+        let pixels = Pixels::new(width as u32, height as u32, surface)
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        let crt = CrtRenderer::new(&pixels, size.width, size.height);
+        Ok(Self {
+            event_loop,
+            window,
+            pixels,
+            crt,
+            shader: Shader::None,
+            open: true,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            resized: None,
+            turbo: false,
+            skipped_frames: 0,
+        })
+    }
+
+    fn key_code(key: Key) -> VirtualKeyCode {
+        match key {
+            Key::Key0 => VirtualKeyCode::Key0,
+            Key::Key1 => VirtualKeyCode::Key1,
+            Key::Key2 => VirtualKeyCode::Key2,
+            Key::Key3 => VirtualKeyCode::Key3,
+            Key::Key4 => VirtualKeyCode::Key4,
+            Key::Key5 => VirtualKeyCode::Key5,
+            Key::Key6 => VirtualKeyCode::Key6,
+            Key::Key7 => VirtualKeyCode::Key7,
+            Key::Key8 => VirtualKeyCode::Key8,
+            Key::Key9 => VirtualKeyCode::Key9,
+            Key::A => VirtualKeyCode::A,
+            Key::B => VirtualKeyCode::B,
+            Key::C => VirtualKeyCode::C,
+            Key::D => VirtualKeyCode::D,
+            Key::E => VirtualKeyCode::E,
+            Key::F => VirtualKeyCode::F,
+            Key::G => VirtualKeyCode::G,
+            Key::H => VirtualKeyCode::H,
+            Key::I => VirtualKeyCode::I,
+            Key::J => VirtualKeyCode::J,
+            Key::K => VirtualKeyCode::K,
+            Key::L => VirtualKeyCode::L,
+            Key::M => VirtualKeyCode::M,
+            Key::N => VirtualKeyCode::N,
+            Key::O => VirtualKeyCode::O,
+            Key::P => VirtualKeyCode::P,
+            Key::Q => VirtualKeyCode::Q,
+            Key::R => VirtualKeyCode::R,
+            Key::S => VirtualKeyCode::S,
+            Key::T => VirtualKeyCode::T,
+            Key::U => VirtualKeyCode::U,
+            Key::V => VirtualKeyCode::V,
+            Key::W => VirtualKeyCode::W,
+            Key::X => VirtualKeyCode::X,
+            Key::Y => VirtualKeyCode::Y,
+            Key::Z => VirtualKeyCode::Z,
+            Key::Escape => VirtualKeyCode::Escape,
+            Key::Period => VirtualKeyCode::Period,
+            Key::Tab => VirtualKeyCode::Tab,
+            Key::Space => VirtualKeyCode::Space,
+            Key::Backspace => VirtualKeyCode::Back,
+            Key::Left => VirtualKeyCode::Left,
+            Key::Right => VirtualKeyCode::Right,
+            Key::F1 => VirtualKeyCode::F1,
+            Key::F2 => VirtualKeyCode::F2,
+            Key::F3 => VirtualKeyCode::F3,
+            Key::F5 => VirtualKeyCode::F5,
+            Key::F9 => VirtualKeyCode::F9,
+        }
+    }
+
+    /// Handle the events since last time: which keys were pressed and let
+    /// go, and whether the window was resized or closed. F4 toggles the
+    /// shader here, since it's only this backend's.
+    fn read_events(&mut self) {
+        self.pressed.clear();
+        let Self {
+            event_loop,
+            open,
+            held,
+            pressed,
+            resized,
+            ..
+        } = self;
+        // Run the event loop until it's out of events, instead of forever
+        event_loop.run_return(|event, _, control_flow| match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *open = false,
+                WindowEvent::Resized(size) => *resized = Some(size),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(code),
+                            state,
+                            ..
+                        },
+                    ..
+                } => match state {
+                    ElementState::Pressed => {
+                        if held.insert(code) {
+                            pressed.insert(code);
+                        }
+                    }
+                    ElementState::Released => {
+                        held.remove(&code);
+                    }
+                },
+                _ => {}
+            },
+            Event::MainEventsCleared => *control_flow = ControlFlow::Exit,
+            _ => {}
+        });
+
+        if let Some(size) = self.resized.take() {
+            self.pixels.resize_surface(size.width, size.height);
+            self.crt.resize(&self.pixels, size.width, size.height);
+        }
+        if self.pressed.contains(&VirtualKeyCode::F4) {
+            self.shader = match self.shader {
+                Shader::None => Shader::Crt,
+                Shader::Crt => Shader::None,
+            };
+            info!("Drawing with the {} shader", self.shader);
+        }
+    }
+
+    /// Draw the last frame that was copied in, through the shader if there
+    /// is one. This waits for vsync.
+    fn present(&mut self) -> Result<(), pixels::Error> {
+        let crt = &self.crt;
+        match self.shader {
+            Shader::None => self.pixels.render(),
+            Shader::Crt => self.pixels.render_with(|encoder, render_target, context| {
+                // Scale the screen up into the CRT's texture, then draw that
+                // through the shader
+                context.scaling_renderer.render(encoder, &crt.texture_view);
+                let clip_rect = context.scaling_renderer.clip_rect();
+                crt.update(&context.queue, clip_rect);
+                crt.render(encoder, render_target, clip_rect);
+            }),
+        }
+    }
+}
+
+impl Backend for Wgpu {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.held.contains(&Self::key_code(key))
+    }
+
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&Self::key_code(key))
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn update(&mut self, pixels: &[u32], width: usize, height: usize) {
+        self.read_events();
+        if self.turbo && self.skipped_frames < TURBO_FRAMES_PER_PRESENT {
+            self.skipped_frames += 1;
+            return;
+        }
+        self.skipped_frames = 0;
+
+        // The texture wants RGBA bytes, and the pixels are 0RGB words
+        self.pixels.resize_buffer(width as u32, height as u32);
+        for (rgba, pixel) in self.pixels.get_frame().chunks_exact_mut(4).zip(pixels) {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            rgba.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+        if let Err(e) = self.present() {
+            warn!("Couldn't draw to the window: {}", e);
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.read_events();
+    }
+
+    fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    fn set_shader(&mut self, shader: Shader) -> bool {
+        self.shader = shader;
+        true
+    }
+}
+
+/// Draws the scaled-up screen through `CRT_SHADER`. `pixels` draws the screen
+/// into `texture_view`, the size of the window, and this draws that to the
+/// window.
+struct CrtRenderer {
+    texture_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    /// The `Locals` uniform
+    locals: wgpu::Buffer,
+    /// One triangle that covers the whole window
+    vertices: wgpu::Buffer,
+    /// The window's size, which the texture is too
+    size: (u32, u32),
+}
+
+impl CrtRenderer {
+    fn new(pixels: &Pixels, width: u32, height: u32) -> Self {
+        let device = pixels.device();
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("CRT shader"),
+            source: wgpu::ShaderSource::Wgsl(CRT_SHADER.into()),
+        });
+        let texture_view = Self::create_texture_view(pixels, width, height);
+        // Linear, so the curved screen doesn't shimmer
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("CRT sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..wgpu::SamplerDescriptor::default()
+        });
+
+        // A triangle twice as big as the window covers all of it, with fewer
+        // vertices than a rectangle
+        let corners: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+        let vertex_bytes = corners
+            .iter()
+            .flatten()
+            .flat_map(|coordinate| coordinate.to_ne_bytes().to_vec())
+            .collect::<Vec<u8>>();
+        let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("CRT vertices"),
+            contents: &vertex_bytes,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let locals = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("CRT locals"),
+            contents: &[0; 16],
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("CRT bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(16),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &texture_view, &sampler, &locals);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("CRT pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("CRT pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: 8,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &[wgpu::VertexAttribute {
+                        format: wgpu::VertexFormat::Float32x2,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: pixels.render_texture_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            texture_view,
+            sampler,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+            locals,
+            vertices,
+            size: (width, height),
+        }
+    }
+
+    fn create_texture_view(pixels: &Pixels, width: u32, height: u32) -> wgpu::TextureView {
+        let texture = pixels.device().create_texture(&wgpu::TextureDescriptor {
+            label: Some("CRT texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: pixels.render_texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        locals: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("CRT bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: locals.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Make the texture the window's new size.
+    fn resize(&mut self, pixels: &Pixels, width: u32, height: u32) {
+        self.size = (width, height);
+        self.texture_view = Self::create_texture_view(pixels, width, height);
+        self.bind_group = Self::create_bind_group(
+            pixels.device(),
+            &self.bind_group_layout,
+            &self.texture_view,
+            &self.sampler,
+            &self.locals,
+        );
+    }
+
+    /// Tell the shader where the screen is in the window: `clip_rect` is its
+    /// x, y, width, and height in pixels.
+    fn update(&self, queue: &wgpu::Queue, clip_rect: (u32, u32, u32, u32)) {
+        let (x, y, width, height) = clip_rect;
+        let (window_width, window_height) = (self.size.0 as f32, self.size.1 as f32);
+        let clip = [
+            x as f32 / window_width,
+            y as f32 / window_height,
+            width as f32 / window_width,
+            height as f32 / window_height,
+        ];
+        let bytes = clip
+            .iter()
+            .flat_map(|value| value.to_ne_bytes().to_vec())
+            .collect::<Vec<u8>>();
+        queue.write_buffer(&self.locals, 0, &bytes);
+    }
+
+    fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        clip_rect: (u32, u32, u32, u32),
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("CRT render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        let (x, y, width, height) = clip_rect;
+        pass.set_scissor_rect(x, y, width, height);
+        pass.draw(0..3, 0..1);
+    }
+}
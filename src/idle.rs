@@ -0,0 +1,94 @@
+// Only the window pauses when idle, but it's tested either way
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use crate::{interpreter::State, watchdog};
+
+/// Notices when nothing has happened for a while: the screen hasn't changed
+/// and no key has been pressed, like when a program sits on a title screen
+/// waiting for a player. The window pauses then, to save battery, and carries
+/// on as soon as a key is pressed.
+/// This is different from a program that's stopped for good by jumping to
+/// itself, which `run --headless` looks for: an idle program is still
+/// running, and would respond to a key.
+pub struct IdleDetector {
+    /// How many frames in a row nothing has to happen for, or 0 to never
+    /// count as idle
+    limit: u64,
+    /// How many frames in a row nothing has happened
+    quiet_frames: u64,
+    /// What was on the screen last frame (see `watchdog::screen_hash`)
+    last_screen: Option<u64>,
+}
+
+impl IdleDetector {
+    /// Count as idle after `limit` frames of nothing happening, or never if
+    /// it's 0.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            quiet_frames: 0,
+            last_screen: None,
+        }
+    }
+
+    /// Look at the frame that just ended, with the keypad's keys as they were
+    /// during it, and return whether the program is idle.
+    pub fn track(&mut self, state: &State, keypad: &[bool; 16]) -> bool {
+        let screen = watchdog::screen_hash(state);
+        if self.last_screen == Some(screen) && !keypad.contains(&true) {
+            self.quiet_frames += 1;
+        } else {
+            self.quiet_frames = 0;
+        }
+        self.last_screen = Some(screen);
+        self.is_idle()
+    }
+
+    /// Whether nothing has happened for long enough.
+    pub fn is_idle(&self) -> bool {
+        self.limit > 0 && self.quiet_frames >= self.limit
+    }
+
+    /// Start counting again, like after the program carries on.
+    pub fn reset(&mut self) {
+        self.quiet_frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::tick;
+
+    #[test]
+    fn idle_when_nothing_changes() {
+        let state = State::with_program(&[]).unwrap();
+        let mut idle = IdleDetector::new(3);
+        let results = (0..5)
+            .map(|_| idle.track(&state, &[false; 16]))
+            .collect::<Vec<_>>();
+        assert_eq!(results, vec![false, false, false, true, true]);
+
+        idle.reset();
+        assert!(!idle.is_idle());
+    }
+
+    #[test]
+    fn keys_and_drawing_arent_idle() {
+        // Draw the top of the large 0 in the font, over and over
+        let mut state = State::with_program(&[0xA0, 0xA0, 0xD0, 0x15, 0x12, 0x02]).unwrap();
+        let mut idle = IdleDetector::new(2);
+        let mut keypad = [false; 16];
+        keypad[5] = true;
+        for _ in 0..4 {
+            assert!(!idle.track(&state, &keypad));
+        }
+
+        for _ in 0..4 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+            assert!(!idle.track(&state, &[false; 16]));
+        }
+
+        assert!(!IdleDetector::new(0).track(&state, &[false; 16]));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{error::Chip8Error, frame::FrameListener, interpreter::State};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fs, path::Path};
+
+/// Every replay starts with this, so that other files are rejected instead of
+/// misread.
+const MAGIC: &[u8] = b"CHIP8REPLAY1";
+
+/// The input from a run: every time the keypad changed, and the seed for
+/// RND's random numbers. Running the same program again with the same input
+/// and seed does exactly the same thing.
+/// Made with `--record`, and played back with `--replay`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    /// Every time the keypad changed, in order
+    pub changes: Vec<KeypadChange>,
+}
+
+/// Which keys were held down from a frame on.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeypadChange {
+    /// Which frame it changed at, counting from 0
+    pub frame: u64,
+    pub keypad: [bool; 16],
+}
+
+impl Replay {
+    /// Write the replay to a file.
+    pub fn save(&self, path: &Path) -> Result<(), Chip8Error> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serialize(self)?);
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Read a replay written by `save`.
+    pub fn load(path: &Path) -> Result<Self, Chip8Error> {
+        let bytes = fs::read(path)?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(Chip8Error::NotAReplay);
+        }
+        Ok(bincode::deserialize(&bytes[MAGIC.len()..])?)
+    }
+}
+
+/// Records the keypad at the start of every frame, after the frontend has set
+/// it. It has to hear about frames after the frontend does.
+pub struct Recorder {
+    replay: Replay,
+    frame: u64,
+    keypad: [bool; 16],
+}
+
+impl Recorder {
+    /// Record a run whose random numbers come from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            replay: Replay {
+                seed,
+                changes: vec![],
+            },
+            frame: 0,
+            keypad: [false; 16],
+        }
+    }
+
+    /// Everything recorded so far.
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+}
+
+impl FrameListener for Recorder {
+    fn begin_frame(&mut self, state: &mut State) {
+        let keypad = state.keypad();
+        if keypad != self.keypad {
+            self.replay.changes.push(KeypadChange {
+                frame: self.frame,
+                keypad,
+            });
+            self.keypad = keypad;
+        }
+        self.frame += 1;
+    }
+}
+
+/// Plays back a replay, setting the keypad at the start of every frame
+/// instead of the frontend. It has to hear about frames after the frontend
+/// does, so it gets the last word.
+pub struct Player {
+    changes: VecDeque<KeypadChange>,
+    frame: u64,
+    keypad: [bool; 16],
+}
+
+impl Player {
+    pub fn new(replay: &Replay) -> Self {
+        Self {
+            changes: replay.changes.iter().cloned().collect(),
+            frame: 0,
+            keypad: [false; 16],
+        }
+    }
+
+    /// Whether every change has been played back.
+    pub fn is_finished(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl FrameListener for Player {
+    fn begin_frame(&mut self, state: &mut State) {
+        while let Some(change) = self.changes.front() {
+            if change.frame > self.frame {
+                break;
+            }
+            self.keypad = change.keypad;
+            self.changes.pop_front();
+        }
+        state.set_keypad(self.keypad);
+        self.frame += 1;
+    }
+}
+
+/// The input for a run besides the frontend's: a replay to play back instead
+/// of it, and a recording to make. It has to hear about frames after the
+/// frontend does.
+pub struct Input {
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+}
+
+impl Input {
+    /// Record the keypad if `record` is set, for a run whose random numbers
+    /// come from `seed`, and play back `replay` if there is one.
+    pub fn new(seed: u64, record: bool, replay: Option<&Replay>) -> Self {
+        Self {
+            recorder: if record {
+                Some(Recorder::new(seed))
+            } else {
+                None
+            },
+            player: replay.map(Player::new),
+        }
+    }
+
+    /// Whether there's more of a replay to play back.
+    pub fn is_replaying(&self) -> bool {
+        self.player.as_ref().map(Player::is_finished) == Some(false)
+    }
+
+    /// Write what was recorded (if anything) to a file.
+    pub fn save_recording(&self, path: &Path) -> Result<(), Chip8Error> {
+        match &self.recorder {
+            Some(recorder) => recorder.replay().save(path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FrameListener for Input {
+    fn begin_frame(&mut self, state: &mut State) {
+        if let Some(player) = self.player.as_mut() {
+            player.begin_frame(state);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.begin_frame(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keypad(key: usize) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        keypad[key] = true;
+        keypad
+    }
+
+    fn change(frame: u64, keypad: [bool; 16]) -> KeypadChange {
+        KeypadChange { frame, keypad }
+    }
+
+    #[test]
+    fn records_changes() {
+        let mut state = State::with_program(&[]).unwrap();
+        let mut recorder = Recorder::new(1234);
+        for held in &[None, Some(5), Some(5), None, Some(0xA)] {
+            state.set_keypad(held.map(keypad).unwrap_or([false; 16]));
+            recorder.begin_frame(&mut state);
+        }
+
+        assert_eq!(
+            recorder.replay(),
+            &Replay {
+                seed: 1234,
+                changes: vec![
+                    change(1, keypad(5)),
+                    change(3, [false; 16]),
+                    change(4, keypad(0xA))
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn plays_back_changes() {
+        let replay = Replay {
+            seed: 0,
+            changes: vec![change(1, keypad(5)), change(3, [false; 16])],
+        };
+        let mut state = State::with_program(&[]).unwrap();
+        let mut player = Player::new(&replay);
+        let mut played = vec![];
+        for _ in 0..4 {
+            // Whatever the frontend says is overridden
+            state.set_keypad(keypad(0xF));
+            player.begin_frame(&mut state);
+            played.push(state.keypad());
+        }
+
+        assert_eq!(played, vec![[false; 16], keypad(5), keypad(5), [false; 16]]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}", std::process::id()));
+        let replay = Replay {
+            seed: 99,
+            changes: vec![change(10, keypad(2))],
+        };
+        replay.save(&path).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay);
+
+        fs::write(&path, b"not a replay").unwrap();
+        assert!(matches!(Replay::load(&path), Err(Chip8Error::NotAReplay)));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,47 +1,138 @@
-use crate::error::Chip8Error;
+use crate::error::{Chip8Error, EncodeError};
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 /// An Address is a 12-bit value stored in a u16.
 pub struct Address(u16);
 
-impl From<u16> for Address {
-    /// The address is a 12-bit value, meaning that its maximum value is 0x0FFF,
-    /// not 0xFFFF as the u16 type implies.
-    /// If passed a value larger than 0x0FFF, it will use only the last 3 bits.
-    fn from(chunk: u16) -> Self {
-        Self(chunk & 0x0FFF)
+impl Address {
+    /// The highest address, at the end of 4KB of memory.
+    pub const MAX: u16 = 0xFFF;
+
+    /// Create an Address, or return an error if `address` is past 0xFFF.
+    pub fn new(address: u16) -> Result<Self, Chip8Error> {
+        if address > Self::MAX {
+            Err(Chip8Error::InvalidAddress(address))
+        } else {
+            Ok(Address(address))
+        }
+    }
+
+    /// The address in the lowest 12 bits of an opcode, like the 278 in
+    /// `A278`. Only the decoder makes addresses this way: everything else
+    /// uses `Address::new`, which rejects addresses past 0xFFF instead of
+    /// cutting them down to fit.
+    fn from_opcode(opcode: u16) -> Self {
+        Address(opcode & 0x0FFF)
+    }
+
+    pub fn value(self) -> u16 {
+        self.0
+    }
+
+    /// The address `n` bytes later, or None if that's past 0xFFF.
+    pub fn checked_add(self, n: u16) -> Option<Self> {
+        self.0
+            .checked_add(n)
+            .filter(|sum| *sum <= Self::MAX)
+            .map(Address)
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:03X}", self.0)
+    }
+}
+
+impl TryFrom<u16> for Address {
+    type Error = Chip8Error;
+
+    fn try_from(address: u16) -> Result<Self, Self::Error> {
+        Address::new(address)
     }
 }
 
 // This makes it much easier to write tests, by allowing writing `0x32.into()`
 // (since `0x32` is detected as an i32 by default) rather than having to write
-// this clunky phrase: `(0x32 as u16).into()`.
+// this clunky phrase: `Address::new(0x32).unwrap()`.
 #[cfg(test)]
 impl From<i32> for Address {
-    fn from(chunk: i32) -> Self {
-        use std::convert::TryInto;
-        u16::try_from(chunk).unwrap().try_into().unwrap()
+    fn from(address: i32) -> Self {
+        Address::new(u16::try_from(address).unwrap()).unwrap()
     }
 }
 
-impl Into<u16> for Address {
-    fn into(self) -> u16 {
-        self.0
+impl From<Address> for u16 {
+    fn from(address: Address) -> u16 {
+        address.0
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 /// A Register is a 4-bit value that addresses a register numbered from 0x0 to 0xF.
-pub struct Register(pub u8);
+/// Saved states and traces are checked when they're read back in, so they
+/// can't hold a register past 0xF.
+#[serde(try_from = "u8")]
+pub struct Register(u8);
 
-impl From<u8> for Register {
-    fn from(n: u8) -> Self {
+impl Register {
+    /// VF doubles as a flag for carries, borrows, and sprite collisions.
+    pub const VF: Register = Register(0xF);
+
+    /// Create a Register, or return an error if `n` is bigger than 0xF.
+    pub fn new(n: u8) -> Result<Self, Chip8Error> {
         if n > 0xF {
-            panic!("Register value must be from 0x0 - 0xF (got {:X})", n)
+            Err(Chip8Error::InvalidRegister(n))
+        } else {
+            Ok(Register(n))
         }
-        Register(n)
+    }
+
+    /// Create a Register from the lowest 4 bits of `n`, ignoring the rest.
+    /// This can't fail, so it's what the decoder uses for nibbles it has
+    /// already pulled out of an opcode.
+    pub fn from_nibble(n: u8) -> Self {
+        Register(n & 0x0F)
+    }
+
+    /// Which of the 16 registers this is, from 0x0 to 0xF.
+    pub fn value(self) -> u8 {
+        self.0
+    }
+
+    /// Which of the 16 registers this is, as an index. Every way of making a
+    /// Register checks it, so it can't index past VF.
+    pub fn index(self) -> usize {
+        usize::from(self.0)
+    }
+}
+
+impl TryFrom<u8> for Register {
+    type Error = Chip8Error;
+
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        Register::new(n)
+    }
+}
+
+/// Read a register the way it's written, like `V3` or `vA`, for the
+/// assembler, the Octo compiler, and the debugger.
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix('V')
+            .or_else(|| s.strip_prefix('v'))
+            .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("Expected a register like V3, got `{}`", s))?;
+        let n = u8::from_str_radix(digits, 16)
+            .map_err(|_| format!("Register {} is out of range (must be from 0x0 - 0xF)", s))?;
+        Register::new(n).map_err(|e| e.to_string())
     }
 }
 
@@ -51,78 +142,262 @@ impl PartialEq<u16> for Address {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// One instruction, decoded.
+/// Each variant's doc comment starts with how it's written (after `SCHIP:` or
+/// `XO-CHIP:` if it's not in the original CHIP-8), followed by what it does,
+/// and then which quirks (see `Quirks`) change what it does. `chip8 opcodes`
+/// prints them, so keep them in that format.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Instruction {
-    /// Ignored
-    SYS(),
+    /// SYS nnn
+    /// Jump to a machine code routine at nnn. Ignored.
+    SYS(Address),
 
+    /// CLS
+    /// Clear the display. On XO-CHIP, only the selected planes are cleared.
+    CLS(),
+
+    /// RET
     /// Return from a subroutine.
     /// The interpreter sets the program counter to the address at the top of the
     /// stack, then subtracts 1 from the stack pointer.
     RET(),
 
-    // Jump to location nnn. The interpreter sets the program counter to nnn.
+    /// JP nnn
+    /// Jump to location nnn. The interpreter sets the program counter to nnn.
     JP(Address),
 
+    /// CALL nnn
     /// Call subroutine at nnn.
     /// The interpreter increments the stack pointer, then puts the current PC on
     /// the top of the stack. The PC is then set to nnn.
     CALL(Address),
 
+    /// SE Vx, kk
     /// Skip next instruction if Vx == kk.
     SEByte(Register, u8),
 
+    /// SNE Vx, kk
     /// Skip next instruction if Vx != kk.
     SNEByte(Register, u8),
 
+    /// SE Vx, Vy
     /// Skip next instruction if Vx == Vy.
     SERegister(Register, Register),
 
-    /// Skip next instruction if Vx == Vy.
+    /// SNE Vx, Vy
+    /// Skip next instruction if Vx != Vy.
     SNERegister(Register, Register),
 
+    /// LD Vx, kk
     /// Set Vx = kk. The interpreter puts the value kk into register Vx.
     LDByte(Register, u8),
 
-    /// Vx += kk
+    /// LD Vx, Vy
+    /// Set Vx = Vy.
+    LDRegister(Register, Register),
+
+    /// OR Vx, Vy
+    /// Set Vx = Vx OR Vy.
+    OR(Register, Register),
+
+    /// AND Vx, Vy
+    /// Set Vx = Vx AND Vy.
+    AND(Register, Register),
+
+    /// XOR Vx, Vy
+    /// Set Vx = Vx XOR Vy.
+    XOR(Register, Register),
+
+    /// ADD Vx, kk
     /// Adds the value kk to the value of register Vx, then stores the result in Vx.
     ADDByte(Register, u8),
 
-    /// Vx += Vy
+    /// ADD Vx, Vy
     /// Set Vx = Vx + Vy, set VF = carry.
     /// The values of Vx and Vy are added together. If the result is greater than
     /// 8 bits (i.e., > 255,) VF is set to 1, otherwise 0.
     /// Only the lowest 8 bits of the result are kept, and stored in Vx.
     ADDRegister(Register, Register),
 
+    /// SUB Vx, Vy
+    /// Set Vx = Vx - Vy, set VF = NOT borrow.
+    /// VF is 1 if Vx >= Vy, otherwise 0. Only the lowest 8 bits are kept.
+    SUB(Register, Register),
+
+    /// SUBN Vx, Vy
+    /// Set Vx = Vy - Vx, set VF = NOT borrow.
+    /// VF is 1 if Vy >= Vx, otherwise 0. Only the lowest 8 bits are kept.
+    SUBN(Register, Register),
+
+    /// SHR Vx, Vy
+    /// Shift right by 1 and store the result in Vx. VF is set to the bit that
+    /// was shifted out. Whether Vx or Vy is shifted depends on the quirks.
+    /// Quirks: shift
+    SHR(Register, Register),
+
+    /// SHL Vx, Vy
+    /// Shift left by 1 and store the result in Vx. VF is set to the bit that
+    /// was shifted out. Whether Vx or Vy is shifted depends on the quirks.
+    /// Quirks: shift
+    SHL(Register, Register),
+
+    /// LD I, nnn
     /// Set register I to nnn.
     LDI(Address),
 
+    /// JP V0, nnn
+    /// Jump to location nnn plus the value in V0. With the CHIP-48 and SCHIP's
+    /// jump quirk (see `Quirks::jump_uses_vx`), it's Bxnn: jump to xnn plus
+    /// the value in Vx instead.
+    /// Quirks: jump
+    JPOffset(Address),
+
+    /// RND Vx, kk
     /// Set Vx = random byte & kk.
     RND(Register, u8),
 
     /// DRW Vx, Vy, n
-    /// Display n-byte sprite starting at memory location I at (Vx, Vy).
+    /// Display n-byte sprite starting at memory location I at (Vx, Vy). On
+    /// the SCHIP, a sprite with n = 0 is 16x16.
+    /// Quirks: draw, display
     DRW(Register, Register, u8),
 
-    // ADD I, Vx
-    // Set I = I + Vx.
+    /// SKP Vx
+    /// Skip next instruction if the key with the value of Vx is pressed.
+    SKP(Register),
+
+    /// SKNP Vx
+    /// Skip next instruction if the key with the value of Vx is not pressed.
+    SKNP(Register),
+
+    /// LD Vx, DT
+    /// Set Vx = the delay timer's value.
+    LDFromDT(Register),
+
+    /// LD Vx, K
+    /// Wait for a key press, then store the value of the key in Vx.
+    LDKey(Register),
+
+    /// LD DT, Vx
+    /// Set the delay timer to Vx. It counts down to 0 at 60Hz.
+    LDDT(Register),
+
+    /// LD ST, Vx
+    /// Set the sound timer to Vx. The machine beeps until it counts down to 0.
+    LDST(Register),
+
+    /// ADD I, Vx
+    /// Set I = I + Vx.
     ADDI(Register),
 
+    /// LD F, Vx
+    /// Set I to the location of the small (4x5) font sprite for digit Vx.
+    LDF(Register),
+
+    /// LD B, Vx
+    /// Store the hundreds, tens, and ones digits of Vx in memory at I, I+1,
+    /// and I+2.
+    LDB(Register),
+
+    /// LD [I], Vx
+    /// Store registers V0 through Vx in memory starting at I.
+    /// Quirks: load-store
+    LDIntoMemory(Register),
+
+    /// LD Vx, [I]
+    /// Load registers V0 through Vx from memory starting at I.
+    /// Quirks: load-store
+    LDFromMemory(Register),
+
+    /// SCHIP: SCD n
+    /// Scroll the display down by n pixels.
+    SCD(u8),
+
+    /// SCHIP: SCR
+    /// Scroll the display right by 4 pixels.
+    SCR(),
+
+    /// SCHIP: SCL
+    /// Scroll the display left by 4 pixels.
+    SCL(),
+
+    /// SCHIP: EXIT
+    /// Stop the program and close the interpreter.
+    EXIT(),
+
+    /// SCHIP: LOW
+    /// Switch to the regular 64x32 screen.
+    LOW(),
+
+    /// SCHIP: HIGH
+    /// Switch to the 128x64 high-resolution screen.
+    HIGH(),
+
+    /// SCHIP: LD HF, Vx
+    /// Set I to the location of the large (8x10) font sprite for digit Vx.
+    LDHF(Register),
+
+    /// SCHIP: LD R, Vx
+    /// Store registers V0 through Vx in the RPL user flags, which the HP48
+    /// kept between programs. SCHIP only allows V0 through V7.
+    LDIntoFlags(Register),
+
+    /// SCHIP: LD Vx, R
+    /// Read registers V0 through Vx from the RPL user flags.
+    LDFromFlags(Register),
+
+    /// XO-CHIP: SAVE Vx - Vy
+    /// Store registers Vx through Vy (inclusive, in either order) in memory
+    /// starting at I. I is not changed. Octo writes it `save vx - vy`.
+    SAVE(Register, Register),
+
+    /// XO-CHIP: LOAD Vx - Vy
+    /// Load registers Vx through Vy (inclusive, in either order) from memory
+    /// starting at I. I is not changed. Octo writes it `load vx - vy`.
+    LOAD(Register, Register),
+
+    /// XO-CHIP: LD I, long nnnn
+    /// Set register I to the full 16-bit address nnnn. This is the only
+    /// instruction that takes up 4 bytes: F000 followed by nnnn.
+    LDILong(u16),
+
+    /// XO-CHIP: PLANE n
+    /// Select which drawing planes (a bitmask from 0 to 3) later instructions
+    /// draw to and clear.
+    PLANE(u8),
+
+    /// XO-CHIP: AUDIO
+    /// Load the 16-byte audio pattern buffer from memory starting at I.
+    AUDIO(),
+
+    /// XO-CHIP: PITCH Vx
+    /// Set the audio pattern playback rate from the value in Vx.
+    PITCH(Register),
+
     /// Until this program knows how to parse every CHIP-8 instruction, this
     /// makes it possible to print out "unknown" (so far) instructions.
     UNKNOWN(u16),
 }
 
+/// Instructions print in the mnemonics from Cowgod's Chip-8 Technical
+/// Reference, which is what `assembler` reads: addresses are always 3 hex
+/// digits, bytes 2, and nibbles 1, like `LD I, 278` or `DRW V0, V1, 5`.
+/// The alternate form (`{:#}`) prints Octo's syntax instead, like
+/// `i := 0x278` or `sprite v0 v1 5`, to paste into Octo.
 impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use Instruction::*;
 
+        if f.alternate() {
+            return self.fmt_octo(f);
+        }
         match self {
-            SYS() => write!(f, "SYS (ignored)"),
+            SYS(address) => write!(f, "SYS {}", address),
+            CLS() => write!(f, "CLS"),
             RET() => write!(f, "RET"),
-            JP(address) => write!(f, "JP {:02X}", address.0),
-            CALL(address) => write!(f, "CALL {:02X}", address.0),
+            JP(address) => write!(f, "JP {}", address),
+            CALL(address) => write!(f, "CALL {}", address),
             SEByte(register, byte) => write!(f, "SE V{:X}, {:02X}", register.0, byte),
             SNEByte(register, byte) => write!(f, "SNE V{:X}, {:02X}", register.0, byte),
             SERegister(register_x, register_y) => {
@@ -132,19 +407,217 @@ impl Display for Instruction {
                 write!(f, "SNE V{:X}, V{:X}", register_x.0, register_y.0)
             }
             LDByte(register, byte) => write!(f, "LD V{:X}, {:02X}", register.0, byte),
+            LDRegister(register_x, register_y) => {
+                write!(f, "LD V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            OR(register_x, register_y) => write!(f, "OR V{:X}, V{:X}", register_x.0, register_y.0),
+            AND(register_x, register_y) => {
+                write!(f, "AND V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            XOR(register_x, register_y) => {
+                write!(f, "XOR V{:X}, V{:X}", register_x.0, register_y.0)
+            }
             ADDByte(register, byte) => write!(f, "ADD V{:X}, {:02X}", register.0, byte),
             ADDRegister(register_x, register_y) => {
-                write!(f, "ADD V{:X} += V{:X}", register_x.0, register_y.0)
+                write!(f, "ADD V{:X}, V{:X}", register_x.0, register_y.0)
             }
-            LDI(address) => write!(f, "LD I, {:02X}", address.0),
+            SUB(register_x, register_y) => {
+                write!(f, "SUB V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            SUBN(register_x, register_y) => {
+                write!(f, "SUBN V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            SHR(register_x, register_y) => {
+                write!(f, "SHR V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            SHL(register_x, register_y) => {
+                write!(f, "SHL V{:X}, V{:X}", register_x.0, register_y.0)
+            }
+            LDI(address) => write!(f, "LD I, {}", address),
+            JPOffset(address) => write!(f, "JP V0, {}", address),
             RND(register, byte) => write!(f, "RND V{:X}, {:02X}", register.0, byte),
-            DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:02X}", x.0, y.0, n),
+            DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:X}", x.0, y.0, n),
+            SKP(register) => write!(f, "SKP V{:X}", register.0),
+            SKNP(register) => write!(f, "SKNP V{:X}", register.0),
+            LDFromDT(register) => write!(f, "LD V{:X}, DT", register.0),
+            LDKey(register) => write!(f, "LD V{:X}, K", register.0),
+            LDDT(register) => write!(f, "LD DT, V{:X}", register.0),
+            LDST(register) => write!(f, "LD ST, V{:X}", register.0),
             ADDI(register) => write!(f, "ADD I, V{:X}", register.0),
-            UNKNOWN(bytes) => write!(f, "Unknown: {:02X}", bytes),
+            LDF(register) => write!(f, "LD F, V{:X}", register.0),
+            LDB(register) => write!(f, "LD B, V{:X}", register.0),
+            LDIntoMemory(register) => write!(f, "LD [I], V{:X}", register.0),
+            LDFromMemory(register) => write!(f, "LD V{:X}, [I]", register.0),
+            SCD(n) => write!(f, "SCD {:X}", n),
+            SCR() => write!(f, "SCR"),
+            SCL() => write!(f, "SCL"),
+            EXIT() => write!(f, "EXIT"),
+            LOW() => write!(f, "LOW"),
+            HIGH() => write!(f, "HIGH"),
+            LDHF(register) => write!(f, "LD HF, V{:X}", register.0),
+            LDIntoFlags(register) => write!(f, "LD R, V{:X}", register.0),
+            LDFromFlags(register) => write!(f, "LD V{:X}, R", register.0),
+            SAVE(register_x, register_y) => {
+                write!(f, "SAVE V{:X} - V{:X}", register_x.0, register_y.0)
+            }
+            LOAD(register_x, register_y) => {
+                write!(f, "LOAD V{:X} - V{:X}", register_x.0, register_y.0)
+            }
+            LDILong(address) => write!(f, "LD I, long {:04X}", address),
+            PLANE(n) => write!(f, "PLANE {:X}", n),
+            AUDIO() => write!(f, "AUDIO"),
+            PITCH(register) => write!(f, "PITCH V{:X}", register.0),
+            UNKNOWN(bytes) => write!(f, "Unknown: {:04X}", bytes),
         }
     }
 }
 
+impl Instruction {
+    /// Write this instruction the way Octo does. Octo's conditionals say when
+    /// to run the next instruction, not when to skip it, so `SE V1, 02` is
+    /// `if v1 != 0x02 then`. Octo has no SYS, so SYS (and anything unknown)
+    /// is written as its two bytes.
+    fn fmt_octo(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use Instruction::*;
+
+        match self {
+            SYS(address) => write!(
+                f,
+                "0x{:02X} 0x{:02X}",
+                address.value() >> 8,
+                address.value() & 0xFF
+            ),
+            CLS() => write!(f, "clear"),
+            RET() => write!(f, "return"),
+            JP(address) => write!(f, "jump 0x{}", address),
+            CALL(address) => write!(f, ":call 0x{}", address),
+            SEByte(register, byte) => write!(f, "if v{:x} != 0x{:02X} then", register.0, byte),
+            SNEByte(register, byte) => write!(f, "if v{:x} == 0x{:02X} then", register.0, byte),
+            SERegister(register_x, register_y) => {
+                write!(f, "if v{:x} != v{:x} then", register_x.0, register_y.0)
+            }
+            SNERegister(register_x, register_y) => {
+                write!(f, "if v{:x} == v{:x} then", register_x.0, register_y.0)
+            }
+            LDByte(register, byte) => write!(f, "v{:x} := 0x{:02X}", register.0, byte),
+            LDRegister(register_x, register_y) => {
+                write!(f, "v{:x} := v{:x}", register_x.0, register_y.0)
+            }
+            OR(register_x, register_y) => write!(f, "v{:x} |= v{:x}", register_x.0, register_y.0),
+            AND(register_x, register_y) => write!(f, "v{:x} &= v{:x}", register_x.0, register_y.0),
+            XOR(register_x, register_y) => write!(f, "v{:x} ^= v{:x}", register_x.0, register_y.0),
+            ADDByte(register, byte) => write!(f, "v{:x} += 0x{:02X}", register.0, byte),
+            ADDRegister(register_x, register_y) => {
+                write!(f, "v{:x} += v{:x}", register_x.0, register_y.0)
+            }
+            SUB(register_x, register_y) => write!(f, "v{:x} -= v{:x}", register_x.0, register_y.0),
+            SUBN(register_x, register_y) => {
+                write!(f, "v{:x} =- v{:x}", register_x.0, register_y.0)
+            }
+            SHR(register_x, register_y) => {
+                write!(f, "v{:x} >>= v{:x}", register_x.0, register_y.0)
+            }
+            SHL(register_x, register_y) => {
+                write!(f, "v{:x} <<= v{:x}", register_x.0, register_y.0)
+            }
+            LDI(address) => write!(f, "i := 0x{}", address),
+            JPOffset(address) => write!(f, "jump0 0x{}", address),
+            RND(register, byte) => write!(f, "v{:x} := random 0x{:02X}", register.0, byte),
+            DRW(x, y, n) => write!(f, "sprite v{:x} v{:x} {}", x.0, y.0, n),
+            SKP(register) => write!(f, "if v{:x} -key then", register.0),
+            SKNP(register) => write!(f, "if v{:x} key then", register.0),
+            LDFromDT(register) => write!(f, "v{:x} := delay", register.0),
+            LDKey(register) => write!(f, "v{:x} := key", register.0),
+            LDDT(register) => write!(f, "delay := v{:x}", register.0),
+            LDST(register) => write!(f, "buzzer := v{:x}", register.0),
+            ADDI(register) => write!(f, "i += v{:x}", register.0),
+            LDF(register) => write!(f, "i := hex v{:x}", register.0),
+            LDB(register) => write!(f, "bcd v{:x}", register.0),
+            LDIntoMemory(register) => write!(f, "save v{:x}", register.0),
+            LDFromMemory(register) => write!(f, "load v{:x}", register.0),
+            SCD(n) => write!(f, "scroll-down {}", n),
+            SCR() => write!(f, "scroll-right"),
+            SCL() => write!(f, "scroll-left"),
+            EXIT() => write!(f, "exit"),
+            LOW() => write!(f, "lores"),
+            HIGH() => write!(f, "hires"),
+            LDHF(register) => write!(f, "i := bighex v{:x}", register.0),
+            LDIntoFlags(register) => write!(f, "saveflags v{:x}", register.0),
+            LDFromFlags(register) => write!(f, "loadflags v{:x}", register.0),
+            SAVE(register_x, register_y) => {
+                write!(f, "save v{:x} - v{:x}", register_x.0, register_y.0)
+            }
+            LOAD(register_x, register_y) => {
+                write!(f, "load v{:x} - v{:x}", register_x.0, register_y.0)
+            }
+            LDILong(address) => write!(f, "i := long 0x{:04X}", address),
+            PLANE(n) => write!(f, "plane {}", n),
+            AUDIO() => write!(f, "audio"),
+            PITCH(register) => write!(f, "pitch := v{:x}", register.0),
+            UNKNOWN(bytes) => write!(f, "0x{:02X} 0x{:02X}", bytes >> 8, bytes & 0xFF),
+        }
+    }
+}
+
+/// The pattern of every opcode the decoder knows, and the name of the
+/// `Instruction` that it decodes to, for `chip8 opcodes`. In the patterns, x
+/// and y are registers, and n, kk, nnn, and nnnn are numbers. Where patterns
+/// overlap (like 00EE and 0nnn), the first one wins, like in the decoder.
+/// Its tests check every opcode against this, so add to it when the decoder
+/// learns a new one.
+pub const OPCODES: &[(&str, &str)] = &[
+    ("00Cn", "SCD"),
+    ("00E0", "CLS"),
+    ("00EE", "RET"),
+    ("00FB", "SCR"),
+    ("00FC", "SCL"),
+    ("00FD", "EXIT"),
+    ("00FE", "LOW"),
+    ("00FF", "HIGH"),
+    ("0nnn", "SYS"),
+    ("1nnn", "JP"),
+    ("2nnn", "CALL"),
+    ("3xkk", "SEByte"),
+    ("4xkk", "SNEByte"),
+    ("5xy0", "SERegister"),
+    ("5xy2", "SAVE"),
+    ("5xy3", "LOAD"),
+    ("6xkk", "LDByte"),
+    ("7xkk", "ADDByte"),
+    ("8xy0", "LDRegister"),
+    ("8xy1", "OR"),
+    ("8xy2", "AND"),
+    ("8xy3", "XOR"),
+    ("8xy4", "ADDRegister"),
+    ("8xy5", "SUB"),
+    ("8xy6", "SHR"),
+    ("8xy7", "SUBN"),
+    ("8xyE", "SHL"),
+    ("9xy0", "SNERegister"),
+    ("Annn", "LDI"),
+    ("Bnnn", "JPOffset"),
+    ("Cxkk", "RND"),
+    ("Dxyn", "DRW"),
+    ("Ex9E", "SKP"),
+    ("ExA1", "SKNP"),
+    ("F000 nnnn", "LDILong"),
+    ("Fn01", "PLANE"),
+    ("F002", "AUDIO"),
+    ("Fx07", "LDFromDT"),
+    ("Fx0A", "LDKey"),
+    ("Fx15", "LDDT"),
+    ("Fx18", "LDST"),
+    ("Fx1E", "ADDI"),
+    ("Fx29", "LDF"),
+    ("Fx30", "LDHF"),
+    ("Fx33", "LDB"),
+    ("Fx3A", "PITCH"),
+    ("Fx55", "LDIntoMemory"),
+    ("Fx65", "LDFromMemory"),
+    ("Fx75", "LDIntoFlags"),
+    ("Fx85", "LDFromFlags"),
+];
+
 /// Break a u8 like 0xAB into 0xA and 0xB
 fn nibbles(byte: u8) -> [u8; 2] {
     let a: u8 = byte >> 4;
@@ -164,44 +637,270 @@ impl TryFrom<u16> for Instruction {
 
         let instruction = match a {
             0x0 => match chunk {
+                0x00E0 => CLS(),
                 0x00EE => RET(),
-                _ => SYS(),
+                0x00C0..=0x00CF => SCD(d),
+                0x00FB => SCR(),
+                0x00FC => SCL(),
+                0x00FD => EXIT(),
+                0x00FE => LOW(),
+                0x00FF => HIGH(),
+                _ => SYS(Address::from_opcode(chunk)),
+            },
+            0x1 => JP(Address::from_opcode(chunk)),
+            0x2 => CALL(Address::from_opcode(chunk)),
+            0x3 => SEByte(Register::from_nibble(b), byte2),
+            0x4 => SNEByte(Register::from_nibble(b), byte2),
+            0x5 => match d {
+                // Chunk is 5bc0
+                0x0 => SERegister(Register::from_nibble(b), Register::from_nibble(c)),
+                0x2 => SAVE(Register::from_nibble(b), Register::from_nibble(c)),
+                0x3 => LOAD(Register::from_nibble(b), Register::from_nibble(c)),
+                _ => UNKNOWN(chunk),
+            },
+            0x6 => LDByte(Register::from_nibble(b), byte2),
+            0x7 => ADDByte(Register::from_nibble(b), byte2),
+            0x8 => match d {
+                0x0 => LDRegister(Register::from_nibble(b), Register::from_nibble(c)),
+                0x1 => OR(Register::from_nibble(b), Register::from_nibble(c)),
+                0x2 => AND(Register::from_nibble(b), Register::from_nibble(c)),
+                0x3 => XOR(Register::from_nibble(b), Register::from_nibble(c)),
+                0x4 => ADDRegister(Register::from_nibble(b), Register::from_nibble(c)),
+                0x5 => SUB(Register::from_nibble(b), Register::from_nibble(c)),
+                0x6 => SHR(Register::from_nibble(b), Register::from_nibble(c)),
+                0x7 => SUBN(Register::from_nibble(b), Register::from_nibble(c)),
+                0xE => SHL(Register::from_nibble(b), Register::from_nibble(c)),
+                _ => UNKNOWN(chunk),
             },
-            0x1 => JP(chunk.into()),
-            0x2 => CALL(chunk.into()),
-            0x3 => SEByte(Register(b), byte2),
-            0x4 => SNEByte(Register(b), byte2),
-            0x5 => {
-                if d == 0 {
-                    // Chunk is 5bc0
-                    SERegister(Register(b), Register(c))
-                } else {
-                    UNKNOWN(chunk)
-                }
-            }
-            0x6 => LDByte(Register(b), byte2),
-            0x7 => ADDByte(Register(b), byte2),
-            0x8 => ADDRegister(Register(b), Register(c)),
             0x9 => {
                 if d == 0 {
                     // Chunk is 9bc0
-                    SNERegister(Register(b), Register(c))
+                    SNERegister(Register::from_nibble(b), Register::from_nibble(c))
                 } else {
                     UNKNOWN(chunk)
                 }
             }
-            0xA => LDI(chunk.into()),
-            0xC => RND(Register(b), byte2),
-            0xD => DRW(Register(b), Register(c), d),
-            0xF => ADDI(Register(b)),
+            0xA => LDI(Address::from_opcode(chunk)),
+            0xB => JPOffset(Address::from_opcode(chunk)),
+            0xC => RND(Register::from_nibble(b), byte2),
+            0xD => DRW(Register::from_nibble(b), Register::from_nibble(c), d),
+            0xE => match byte2 {
+                0x9E => SKP(Register::from_nibble(b)),
+                0xA1 => SKNP(Register::from_nibble(b)),
+                _ => UNKNOWN(chunk),
+            },
+            0xF => match byte2 {
+                // The address for a long load is in the next 2 bytes, so
+                // `Instruction::from_bytes` fills it in.
+                0x00 if b == 0 => LDILong(0),
+                0x01 => PLANE(b),
+                0x02 if b == 0 => AUDIO(),
+                0x07 => LDFromDT(Register::from_nibble(b)),
+                0x0A => LDKey(Register::from_nibble(b)),
+                0x15 => LDDT(Register::from_nibble(b)),
+                0x18 => LDST(Register::from_nibble(b)),
+                0x1E => ADDI(Register::from_nibble(b)),
+                0x29 => LDF(Register::from_nibble(b)),
+                0x30 => LDHF(Register::from_nibble(b)),
+                0x33 => LDB(Register::from_nibble(b)),
+                0x55 => LDIntoMemory(Register::from_nibble(b)),
+                0x65 => LDFromMemory(Register::from_nibble(b)),
+                0x75 => LDIntoFlags(Register::from_nibble(b)),
+                0x85 => LDFromFlags(Register::from_nibble(b)),
+                0x3A => PITCH(Register::from_nibble(b)),
+                _ => UNKNOWN(chunk),
+            },
             _ => UNKNOWN(chunk),
         };
         Ok(instruction)
     }
 }
 
-impl Into<u16> for Instruction {
-    fn into(self) -> u16 {
+impl Instruction {
+    /// How many bytes this instruction takes up in memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LDILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether this instruction first appeared in the SCHIP. XO-CHIP has these
+    /// too.
+    pub fn is_schip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SCD(_)
+                | Instruction::SCR()
+                | Instruction::SCL()
+                | Instruction::EXIT()
+                | Instruction::LOW()
+                | Instruction::HIGH()
+                | Instruction::LDHF(_)
+                | Instruction::LDIntoFlags(_)
+                | Instruction::LDFromFlags(_)
+                // A 16x16 sprite
+                | Instruction::DRW(_, _, 0)
+        )
+    }
+
+    /// Whether this instruction can change what's on the screen.
+    pub fn changes_screen(&self) -> bool {
+        matches!(
+            self,
+            Instruction::DRW(..)
+                | Instruction::CLS()
+                | Instruction::SCD(_)
+                | Instruction::SCR()
+                | Instruction::SCL()
+                | Instruction::LOW()
+                | Instruction::HIGH()
+        )
+    }
+
+    /// The mnemonic and each operand on its own, written the same way as in
+    /// `Display`, for tools that want separate fields instead of one string.
+    pub fn parts(&self) -> (&'static str, Vec<String>) {
+        use Instruction::*;
+
+        let register = |register: &Register| format!("V{:X}", register.0);
+        let byte = |byte: &u8| format!("{:02X}", byte);
+        let address = |address: &Address| address.to_string();
+        let word = |word: &str| word.to_string();
+
+        match self {
+            SYS(a) => ("SYS", vec![address(a)]),
+            CLS() => ("CLS", vec![]),
+            RET() => ("RET", vec![]),
+            JP(a) => ("JP", vec![address(a)]),
+            CALL(a) => ("CALL", vec![address(a)]),
+            SEByte(x, b) => ("SE", vec![register(x), byte(b)]),
+            SNEByte(x, b) => ("SNE", vec![register(x), byte(b)]),
+            SERegister(x, y) => ("SE", vec![register(x), register(y)]),
+            SNERegister(x, y) => ("SNE", vec![register(x), register(y)]),
+            LDByte(x, b) => ("LD", vec![register(x), byte(b)]),
+            LDRegister(x, y) => ("LD", vec![register(x), register(y)]),
+            OR(x, y) => ("OR", vec![register(x), register(y)]),
+            AND(x, y) => ("AND", vec![register(x), register(y)]),
+            XOR(x, y) => ("XOR", vec![register(x), register(y)]),
+            ADDByte(x, b) => ("ADD", vec![register(x), byte(b)]),
+            ADDRegister(x, y) => ("ADD", vec![register(x), register(y)]),
+            SUB(x, y) => ("SUB", vec![register(x), register(y)]),
+            SUBN(x, y) => ("SUBN", vec![register(x), register(y)]),
+            SHR(x, y) => ("SHR", vec![register(x), register(y)]),
+            SHL(x, y) => ("SHL", vec![register(x), register(y)]),
+            LDI(a) => ("LD", vec![word("I"), address(a)]),
+            JPOffset(a) => ("JP", vec![word("V0"), address(a)]),
+            RND(x, b) => ("RND", vec![register(x), byte(b)]),
+            DRW(x, y, n) => ("DRW", vec![register(x), register(y), format!("{:X}", n)]),
+            SKP(x) => ("SKP", vec![register(x)]),
+            SKNP(x) => ("SKNP", vec![register(x)]),
+            LDFromDT(x) => ("LD", vec![register(x), word("DT")]),
+            LDKey(x) => ("LD", vec![register(x), word("K")]),
+            LDDT(x) => ("LD", vec![word("DT"), register(x)]),
+            LDST(x) => ("LD", vec![word("ST"), register(x)]),
+            ADDI(x) => ("ADD", vec![word("I"), register(x)]),
+            LDF(x) => ("LD", vec![word("F"), register(x)]),
+            LDB(x) => ("LD", vec![word("B"), register(x)]),
+            LDIntoMemory(x) => ("LD", vec![word("[I]"), register(x)]),
+            LDFromMemory(x) => ("LD", vec![register(x), word("[I]")]),
+            SCD(n) => ("SCD", vec![format!("{:X}", n)]),
+            SCR() => ("SCR", vec![]),
+            SCL() => ("SCL", vec![]),
+            EXIT() => ("EXIT", vec![]),
+            LOW() => ("LOW", vec![]),
+            HIGH() => ("HIGH", vec![]),
+            LDHF(x) => ("LD", vec![word("HF"), register(x)]),
+            LDIntoFlags(x) => ("LD", vec![word("R"), register(x)]),
+            LDFromFlags(x) => ("LD", vec![register(x), word("R")]),
+            SAVE(x, y) => ("SAVE", vec![register(x), register(y)]),
+            LOAD(x, y) => ("LOAD", vec![register(x), register(y)]),
+            LDILong(a) => ("LD", vec![word("I"), format!("{:04X}", a)]),
+            PLANE(n) => ("PLANE", vec![format!("{:X}", n)]),
+            AUDIO() => ("AUDIO", vec![]),
+            PITCH(x) => ("PITCH", vec![register(x)]),
+            UNKNOWN(opcode) => ("UNKNOWN", vec![format!("{:04X}", opcode)]),
+        }
+    }
+
+    /// Whether this instruction only exists in XO-CHIP.
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::LDILong(_)
+                | Instruction::PLANE(_)
+                | Instruction::AUDIO()
+                | Instruction::PITCH(_)
+                | Instruction::SAVE(..)
+                | Instruction::LOAD(..)
+        )
+    }
+
+    /// Decode the instruction at the start of `bytes`, which may be 2 or 4
+    /// bytes long. Use `size()` on the result to find out how far to advance.
+    /// A long load whose address word is cut off decodes as UNKNOWN.
+    /// Fails with `NothingToDecode` if `bytes` is empty; any other bytes at
+    /// all decode to something.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        let word = |offset: usize| {
+            bytes
+                .get(offset..offset + 2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        };
+        let first = bytes.first().ok_or(Chip8Error::NothingToDecode)?;
+        let chunk = word(0).unwrap_or_else(|| u16::from(*first) << 8);
+        match Instruction::try_from(chunk)? {
+            Instruction::LDILong(_) => match word(2) {
+                Some(address) => Ok(Instruction::LDILong(address)),
+                None => Ok(Instruction::UNKNOWN(chunk)),
+            },
+            instruction => Ok(instruction),
+        }
+    }
+
+    /// Encode this instruction as the bytes that would appear in a ROM.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            Instruction::LDILong(address) => {
+                let [high, low] = address.to_be_bytes();
+                Ok(vec![0xF0, 0x00, high, low])
+            }
+            instruction => {
+                let chunk = u16::try_from(instruction.clone())?;
+                Ok(chunk.to_be_bytes().to_vec())
+            }
+        }
+    }
+}
+
+/// Encode each instruction and put them one after the other, to build a ROM
+/// in tests.
+#[cfg(test)]
+pub fn program(instructions: &[Instruction]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(|instruction| instruction.to_bytes().unwrap())
+        .collect()
+}
+
+/// Check that a non-register operand fits in the 4 bits it gets in an opcode.
+fn operand_nibble(instruction: &'static str, value: u8) -> Result<u16, EncodeError> {
+    if value > 0xF {
+        Err(EncodeError::OperandOutOfRange { instruction, value })
+    } else {
+        Ok(u16::from(value))
+    }
+}
+
+impl TryFrom<Instruction> for u16 {
+    type Error = EncodeError;
+
+    /// Encode an instruction as a 2-byte opcode. This fails if an operand is
+    /// too big for the bits it gets, for example a `DRW` with a sprite height
+    /// above 0xF. It also fails for the few
+    /// instructions whose opcode the decoder reads as something else, like
+    /// `SYS 0EE` (which is `RET`) or `UNKNOWN(6000)` (which is `LD V0, 00`).
+    fn try_from(instruction: Instruction) -> Result<Self, Self::Error> {
         use Instruction::*;
 
         // Yes, it's not actually tens/hundreds/thousands places since we're in
@@ -209,27 +908,77 @@ impl Into<u16> for Instruction {
         let tens = |n: Register| u16::from(n.0) * 0x10;
         let hundreds = |n: Register| u16::from(n.0) * 0x100;
 
-        match self {
-            // Since SYS is technically any 0nnn opcode that's not 00E0 or 00EE,
-            // just pick something that's not used by anything else.
-            SYS() => 0x0123,
+        let chunk = match instruction {
+            SYS(address) => address.value(),
+            CLS() => 0x00E0,
             RET() => 0x00EE,
-            JP(address) => 0x1000 + address.0,
-            CALL(address) => 0x2000 + address.0,
+            JP(address) => 0x1000 + address.value(),
+            CALL(address) => 0x2000 + address.value(),
             SEByte(register, byte) => 0x3000 + hundreds(register) + u16::from(byte),
             SNEByte(register, byte) => 0x4000 + hundreds(register) + u16::from(byte),
             SERegister(register_x, register_y) => 0x5000 + hundreds(register_x) + tens(register_y),
             SNERegister(register_x, register_y) => 0x9000 + hundreds(register_x) + tens(register_y),
             LDByte(register, byte) => 0x6000 + hundreds(register) + u16::from(byte),
             ADDByte(register, byte) => 0x7000 + hundreds(register) + u16::from(byte),
+            LDRegister(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y),
+            OR(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x1,
+            AND(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x2,
+            XOR(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x3,
             ADDRegister(register_x, register_y) => {
                 0x8000 + hundreds(register_x) + tens(register_y) + 0x4
             }
-            LDI(address) => 0xA000 + address.0,
+            SUB(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x5,
+            SUBN(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x7,
+            SHR(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0x6,
+            SHL(register_x, register_y) => 0x8000 + hundreds(register_x) + tens(register_y) + 0xE,
+            LDI(address) => 0xA000 + address.value(),
+            JPOffset(address) => 0xB000 + address.value(),
             RND(register, byte) => 0xC000 + hundreds(register) + u16::from(byte),
-            DRW(x, y, n) => 0xD000 + hundreds(x) + tens(y) + u16::from(n),
+            DRW(x, y, n) => 0xD000 + hundreds(x) + tens(y) + operand_nibble("DRW", n)?,
+            SKP(register) => 0xE000 + hundreds(register) + 0x9E,
+            SKNP(register) => 0xE000 + hundreds(register) + 0xA1,
+            LDFromDT(register) => 0xF000 + hundreds(register) + 0x07,
+            LDKey(register) => 0xF000 + hundreds(register) + 0x0A,
+            LDDT(register) => 0xF000 + hundreds(register) + 0x15,
+            LDST(register) => 0xF000 + hundreds(register) + 0x18,
             ADDI(register) => 0xF000 + hundreds(register) + 0x1E,
+            LDF(register) => 0xF000 + hundreds(register) + 0x29,
+            LDB(register) => 0xF000 + hundreds(register) + 0x33,
+            LDIntoMemory(register) => 0xF000 + hundreds(register) + 0x55,
+            LDFromMemory(register) => 0xF000 + hundreds(register) + 0x65,
+            SCD(n) => 0x00C0 + operand_nibble("SCD", n)?,
+            SCR() => 0x00FB,
+            SCL() => 0x00FC,
+            EXIT() => 0x00FD,
+            LOW() => 0x00FE,
+            HIGH() => 0x00FF,
+            LDHF(register) => 0xF000 + hundreds(register) + 0x30,
+            LDIntoFlags(register) => 0xF000 + hundreds(register) + 0x75,
+            LDFromFlags(register) => 0xF000 + hundreds(register) + 0x85,
+            SAVE(register_x, register_y) => 0x5000 + hundreds(register_x) + tens(register_y) + 0x2,
+            LOAD(register_x, register_y) => 0x5000 + hundreds(register_x) + tens(register_y) + 0x3,
+            // Only the first word: use `to_bytes` to get the address too.
+            LDILong(_) => 0xF000,
+            PLANE(n) => 0xF000 + operand_nibble("PLANE", n)? * 0x100 + 0x01,
+            AUDIO() => 0xF002,
+            PITCH(register) => 0xF000 + hundreds(register) + 0x3A,
             UNKNOWN(bytes) => bytes,
+        };
+        // A long load is only its first word here, so it can't decode back
+        // to the same address
+        if let LDILong(_) = instruction {
+            return Ok(chunk);
+        }
+        match Instruction::try_from(chunk) {
+            Ok(decoded) if decoded == instruction => Ok(chunk),
+            // What `from_bytes` decodes a long load to when its address is
+            // cut off
+            Ok(LDILong(_)) if instruction == UNKNOWN(0xF000) => Ok(chunk),
+            decoded => Err(EncodeError::Ambiguous {
+                instruction: instruction.to_string(),
+                opcode: chunk,
+                decoded: decoded.map_or_else(|e| e.to_string(), |decoded| decoded.to_string()),
+            }),
         }
     }
 }
@@ -237,12 +986,84 @@ impl Into<u16> for Instruction {
 #[cfg(test)]
 mod test {
     use super::{Instruction::*, *};
+    use proptest::prelude::*;
 
     // This helper function exists so that we don't have to inline an ugly
-    // `Into::<u16>::into(instruction)` into all the other tests. The return
-    // type on this function gives `into()` the information it needs.
+    // `u16::try_from(instruction).unwrap()` into all the other tests.
     fn into_u16(i: Instruction) -> u16 {
-        i.into()
+        u16::try_from(i).unwrap()
+    }
+
+    #[test]
+    fn address_new_rejects_addresses_past_the_end_of_memory() {
+        assert_eq!(Address::new(0xFFF).unwrap().value(), 0xFFF);
+        assert!(matches!(
+            Address::new(0x1000),
+            Err(Chip8Error::InvalidAddress(0x1000))
+        ));
+        assert!(Address::try_from(0x1234_u16).is_err());
+        // Only the decoder keeps the lowest 12 bits, since that's all an
+        // opcode has room for
+        assert_eq!(Address::from_opcode(0x1234).value(), 0x234);
+    }
+
+    #[test]
+    fn address_checked_add_stops_at_the_end_of_memory() {
+        let address = Address::from(0xFF0);
+        assert_eq!(address.checked_add(0xF), Some(Address::from(0xFFF)));
+        assert_eq!(address.checked_add(0x10), None);
+    }
+
+    #[test]
+    fn address_displays_as_3_hex_digits() {
+        assert_eq!(Address::from(0x2A).to_string(), "02A");
+    }
+
+    #[test]
+    fn instructions_display_in_cowgod_syntax() {
+        let displayed = [
+            JP(0x0A2.into()),
+            LDI(0x278.into()),
+            ADDRegister(r(0x1), r(0x2)),
+            DRW(r(0x0), r(0x1), 0x5),
+            SYS(0x0E0.into()),
+            CLS(),
+            LDFromDT(r(0x3)),
+            LDB(r(0x3)),
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            displayed,
+            vec![
+                "JP 0A2",
+                "LD I, 278",
+                "ADD V1, V2",
+                "DRW V0, V1, 5",
+                "SYS 0E0",
+                "CLS",
+                "LD V3, DT",
+                "LD B, V3"
+            ]
+        );
+    }
+
+    #[test]
+    fn alternate_display_is_octo_syntax() {
+        let octo = |instruction: Instruction| format!("{:#}", instruction);
+        assert_eq!(octo(LDI(0x278.into())), "i := 0x278");
+        assert_eq!(octo(CALL(0x0A2.into())), ":call 0x0A2");
+        assert_eq!(octo(SEByte(r(0x1), 0x2)), "if v1 != 0x02 then");
+        assert_eq!(octo(ADDRegister(r(0xA), r(0x2))), "va += v2");
+        assert_eq!(octo(DRW(r(0x0), r(0x1), 0xF)), "sprite v0 v1 15");
+        assert_eq!(octo(SAVE(r(0x1), r(0x3))), "save v1 - v3");
+        assert_eq!(octo(CLS()), "clear");
+        assert_eq!(octo(SUBN(r(0x1), r(0x2))), "v1 =- v2");
+        // Skips if the key is pressed, so runs the next one if it isn't
+        assert_eq!(octo(SKP(r(0x4))), "if v4 -key then");
+        assert_eq!(octo(LDF(r(0x4))), "i := hex v4");
+        assert_eq!(octo(UNKNOWN(0x5121)), "0x51 0x21");
     }
 
     #[test]
@@ -252,7 +1073,7 @@ mod test {
 
     #[test]
     fn as_u16_sys() {
-        assert_eq!(into_u16(SYS()), 0x0123)
+        assert_eq!(into_u16(SYS(0x123.into())), 0x0123)
     }
 
     #[test]
@@ -266,7 +1087,7 @@ mod test {
     }
 
     fn r(n: u8) -> Register {
-        Register(n)
+        Register::new(n).unwrap()
     }
 
     #[test]
@@ -309,11 +1130,31 @@ mod test {
         assert_eq!(into_u16(DRW(r(0xA), r(0xB), 0xC)), 0xDABC)
     }
 
+    #[test]
+    fn as_u16_ldkey() {
+        assert_eq!(into_u16(LDKey(r(0xB))), 0xFB0A)
+    }
+
+    #[test]
+    fn as_u16_ldst() {
+        assert_eq!(into_u16(LDST(r(0xB))), 0xFB18)
+    }
+
     #[test]
     fn as_u16_addi() {
         assert_eq!(into_u16(ADDI(r(0xB))), 0xFB1E)
     }
 
+    #[test]
+    fn as_u16_scd() {
+        assert_eq!(into_u16(SCD(0xA)), 0x00CA)
+    }
+
+    #[test]
+    fn as_u16_ldhf() {
+        assert_eq!(into_u16(LDHF(r(0x3))), 0xF330)
+    }
+
     #[test]
     fn as_u16_rnd() {
         assert_eq!(into_u16(RND(r(0xA), 0xBC)), 0xCABC)
@@ -324,6 +1165,114 @@ mod test {
         assert_eq!(into_u16(ADDRegister(r(0xA), r(0xB))), 0x8AB4)
     }
 
+    #[test]
+    fn as_u16_cls() {
+        assert_eq!(into_u16(CLS()), 0x00E0)
+    }
+
+    #[test]
+    fn as_u16_register_arithmetic() {
+        assert_eq!(into_u16(LDRegister(r(0xA), r(0xB))), 0x8AB0);
+        assert_eq!(into_u16(OR(r(0xA), r(0xB))), 0x8AB1);
+        assert_eq!(into_u16(AND(r(0xA), r(0xB))), 0x8AB2);
+        assert_eq!(into_u16(XOR(r(0xA), r(0xB))), 0x8AB3);
+        assert_eq!(into_u16(SUB(r(0xA), r(0xB))), 0x8AB5);
+        assert_eq!(into_u16(SUBN(r(0xA), r(0xB))), 0x8AB7);
+    }
+
+    #[test]
+    fn as_u16_keys() {
+        assert_eq!(into_u16(SKP(r(0xB))), 0xEB9E);
+        assert_eq!(into_u16(SKNP(r(0xB))), 0xEBA1);
+    }
+
+    #[test]
+    fn as_u16_delay_timer_font_and_bcd() {
+        assert_eq!(into_u16(LDFromDT(r(0xB))), 0xFB07);
+        assert_eq!(into_u16(LDDT(r(0xB))), 0xFB15);
+        assert_eq!(into_u16(LDF(r(0xB))), 0xFB29);
+        assert_eq!(into_u16(LDB(r(0xB))), 0xFB33);
+    }
+
+    #[test]
+    fn as_u16_rejects_out_of_range_operand() {
+        assert_eq!(
+            u16::try_from(DRW(r(0x1), r(0x2), 0x10)),
+            Err(EncodeError::OperandOutOfRange {
+                instruction: "DRW",
+                value: 0x10
+            })
+        );
+    }
+
+    #[test]
+    fn as_u16_rejects_opcodes_that_decode_as_something_else() {
+        assert_eq!(
+            u16::try_from(SYS(0x0EE.into())),
+            Err(EncodeError::Ambiguous {
+                instruction: "SYS 0EE".to_string(),
+                opcode: 0x00EE,
+                decoded: "RET".to_string(),
+            })
+        );
+        assert!(u16::try_from(SYS(0x0E0.into())).is_err());
+        assert!(u16::try_from(UNKNOWN(0x6000)).is_err());
+        assert_eq!(into_u16(UNKNOWN(0x5001)), 0x5001);
+        // A long load whose address was cut off
+        assert_eq!(into_u16(UNKNOWN(0xF000)), 0xF000);
+    }
+
+    #[test]
+    fn register_new() {
+        assert_eq!(Register::new(0xF).unwrap(), r(0xF));
+        assert!(matches!(
+            Register::new(0x10),
+            Err(Chip8Error::InvalidRegister(0x10))
+        ));
+    }
+
+    #[test]
+    fn register_from_nibble() {
+        assert_eq!(Register::from_nibble(0x3), r(0x3));
+        assert_eq!(Register::from_nibble(0xA3), r(0x3));
+    }
+
+    #[test]
+    fn register_index() {
+        assert_eq!(r(0xA).index(), 0xA);
+        assert_eq!(Register::VF.index(), 0xF);
+    }
+
+    #[test]
+    fn register_from_str() {
+        assert_eq!("V3".parse(), Ok(r(0x3)));
+        assert_eq!("vA".parse(), Ok(r(0xA)));
+        assert_eq!("vf".parse(), Ok(r(0xF)));
+        assert_eq!(
+            "V10".parse::<Register>(),
+            Err("Register V10 is out of range (must be from 0x0 - 0xF)".to_string())
+        );
+        for bad in &["V", "3", "V+3", "I", "VG"] {
+            assert_eq!(
+                bad.parse::<Register>(),
+                Err(format!("Expected a register like V3, got `{}`", bad))
+            );
+        }
+    }
+
+    #[test]
+    fn registers_past_vf_are_not_deserialized() {
+        let bytes = bincode::serialize(&LDByte(r(0x3), 0x12)).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Instruction>(&bytes).unwrap(),
+            LDByte(r(0x3), 0x12)
+        );
+        // The register comes right after the variant's 4-byte index
+        let mut bad = bytes;
+        bad[4] = 0x13;
+        assert!(bincode::deserialize::<Instruction>(&bad).is_err());
+    }
+
     #[test]
     fn from_u16() {
         use std::collections::HashMap;
@@ -331,7 +1280,7 @@ mod test {
         #[rustfmt::skip]
         let instructions: HashMap<u16, Instruction> = [
             (0x00EE, RET()),
-            (0x0ABC, SYS()),
+            (0x0ABC, SYS(0xABC.into())),
             (0x1A12, JP(0xA12.into())),
             (0x221A, CALL(0x21A.into())),
             (0x3934, SEByte(r(0x9), 0x34)),
@@ -340,11 +1289,32 @@ mod test {
             (0x6003, LDByte(r(0x0), 0x03)),
             (0x7123, ADDByte(r(0x1), 0x23)),
             (0x8124, ADDRegister(r(0x1), r(0x2))),
+            (0x8126, SHR(r(0x1), r(0x2))),
+            (0x812E, SHL(r(0x1), r(0x2))),
             (0x9AB0, SNERegister(r(0xA), r(0xB))),
             (0xA278, LDI(0x278.into())),
+            (0xB278, JPOffset(0x278.into())),
             (0xC123, RND(r(0x1), 0x23)),
             (0xD123, DRW(r(0x1), r(0x2), 0x3)),
-            (0xF51E, ADDI(r(0x5)))
+            (0xF30A, LDKey(r(0x3))),
+            (0xF318, LDST(r(0x3))),
+            (0xF51E, ADDI(r(0x5))),
+            (0x00C5, SCD(0x5)),
+            (0x00FB, SCR()),
+            (0x00FC, SCL()),
+            (0x00FD, EXIT()),
+            (0x00FE, LOW()),
+            (0x00FF, HIGH()),
+            (0xF730, LDHF(r(0x7))),
+            (0xF355, LDIntoMemory(r(0x3))),
+            (0xF365, LDFromMemory(r(0x3))),
+            (0xF375, LDIntoFlags(r(0x3))),
+            (0xF785, LDFromFlags(r(0x7))),
+            (0x5122, SAVE(r(0x1), r(0x2))),
+            (0x5A33, LOAD(r(0xA), r(0x3))),
+            (0xF201, PLANE(0x2)),
+            (0xF002, AUDIO()),
+            (0xF43A, PITCH(r(0x4)))
         ].iter().cloned().collect();
 
         for (chunk, instruction) in instructions.into_iter() {
@@ -352,4 +1322,142 @@ mod test {
             assert_eq!(actual, instruction);
         }
     }
+
+    #[test]
+    fn from_bytes_long_ldi() {
+        let instruction = Instruction::from_bytes(&[0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]).unwrap();
+        assert_eq!(instruction, LDILong(0x1234));
+        assert_eq!(instruction.size(), 4);
+    }
+
+    #[test]
+    fn from_bytes_truncated_long_ldi() {
+        let instruction = Instruction::from_bytes(&[0xF0, 0x00, 0x12]).unwrap();
+        assert_eq!(instruction, UNKNOWN(0xF000));
+        assert_eq!(instruction.size(), 2);
+    }
+
+    #[test]
+    fn from_bytes_needs_at_least_one_byte() {
+        assert_eq!(Instruction::from_bytes(&[0xA2]).unwrap(), LDI(0x200.into()));
+        assert!(matches!(
+            Instruction::from_bytes(&[]),
+            Err(Chip8Error::NothingToDecode)
+        ));
+    }
+
+    #[test]
+    fn to_bytes_round_trip() {
+        for instruction in &[LDILong(0xABCD), PLANE(0x3), AUDIO(), LDI(0x123.into())] {
+            let bytes = instruction.to_bytes().unwrap();
+            assert_eq!(bytes.len(), instruction.size());
+            assert_eq!(&Instruction::from_bytes(&bytes).unwrap(), instruction);
+        }
+    }
+
+    fn register() -> impl Strategy<Value = Register> {
+        (0..=0xFu8).prop_map(Register)
+    }
+
+    fn address() -> impl Strategy<Value = Address> {
+        (0..=Address::MAX).prop_map(|address| Address::new(address).unwrap())
+    }
+
+    fn nibble() -> impl Strategy<Value = u8> {
+        0..=0xFu8
+    }
+
+    /// Every kind of instruction, with operands that fit in their opcodes.
+    fn any_instruction() -> impl Strategy<Value = Instruction> {
+        let byte = any::<u8>;
+        prop_oneof![
+            address().prop_map(SYS),
+            Just(CLS()),
+            Just(RET()),
+            address().prop_map(JP),
+            address().prop_map(CALL),
+            (register(), byte()).prop_map(|(x, b)| SEByte(x, b)),
+            (register(), byte()).prop_map(|(x, b)| SNEByte(x, b)),
+            (register(), register()).prop_map(|(x, y)| SERegister(x, y)),
+            (register(), register()).prop_map(|(x, y)| SNERegister(x, y)),
+            (register(), byte()).prop_map(|(x, b)| LDByte(x, b)),
+            (register(), register()).prop_map(|(x, y)| LDRegister(x, y)),
+            (register(), register()).prop_map(|(x, y)| OR(x, y)),
+            (register(), register()).prop_map(|(x, y)| AND(x, y)),
+            (register(), register()).prop_map(|(x, y)| XOR(x, y)),
+            (register(), byte()).prop_map(|(x, b)| ADDByte(x, b)),
+            (register(), register()).prop_map(|(x, y)| ADDRegister(x, y)),
+            (register(), register()).prop_map(|(x, y)| SUB(x, y)),
+            (register(), register()).prop_map(|(x, y)| SUBN(x, y)),
+            (register(), register()).prop_map(|(x, y)| SHR(x, y)),
+            (register(), register()).prop_map(|(x, y)| SHL(x, y)),
+            address().prop_map(LDI),
+            address().prop_map(JPOffset),
+            (register(), byte()).prop_map(|(x, b)| RND(x, b)),
+            (register(), register(), nibble()).prop_map(|(x, y, n)| DRW(x, y, n)),
+            register().prop_map(SKP),
+            register().prop_map(SKNP),
+            register().prop_map(LDFromDT),
+            register().prop_map(LDKey),
+            register().prop_map(LDDT),
+            register().prop_map(LDST),
+            register().prop_map(ADDI),
+            register().prop_map(LDF),
+            register().prop_map(LDB),
+            register().prop_map(LDIntoMemory),
+            register().prop_map(LDFromMemory),
+            nibble().prop_map(SCD),
+            Just(SCR()),
+            Just(SCL()),
+            Just(EXIT()),
+            Just(LOW()),
+            Just(HIGH()),
+            register().prop_map(LDHF),
+            register().prop_map(LDIntoFlags),
+            register().prop_map(LDFromFlags),
+            (register(), register()).prop_map(|(x, y)| SAVE(x, y)),
+            (register(), register()).prop_map(|(x, y)| LOAD(x, y)),
+            any::<u16>().prop_map(LDILong),
+            nibble().prop_map(PLANE),
+            Just(AUDIO()),
+            register().prop_map(PITCH),
+            any::<u16>().prop_map(UNKNOWN),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn decoding_undoes_encoding(instruction in any_instruction()) {
+            match instruction.to_bytes() {
+                Ok(bytes) => prop_assert_eq!(Instruction::from_bytes(&bytes).unwrap(), instruction),
+                // Only these can be given an opcode that means something else
+                Err(EncodeError::Ambiguous { .. }) => {
+                    prop_assert!(matches!(instruction, SYS(_) | UNKNOWN(_)))
+                }
+                Err(e) => prop_assert!(false, "{} didn't encode: {}", instruction, e),
+            }
+        }
+
+        #[test]
+        fn encoding_undoes_decoding(opcode in any::<u16>()) {
+            let instruction = Instruction::try_from(opcode).unwrap();
+            prop_assert_eq!(u16::try_from(instruction), Ok(opcode));
+        }
+    }
+
+    #[test]
+    fn parts_split_the_mnemonic_from_the_operands() {
+        assert_eq!(
+            DRW(Register(0x1), Register(0x2), 5).parts(),
+            (
+                "DRW",
+                vec!["V1".to_string(), "V2".to_string(), "5".to_string()]
+            )
+        );
+        assert_eq!(
+            LDIntoMemory(Register(0xA)).parts(),
+            ("LD", vec!["[I]".to_string(), "VA".to_string()])
+        );
+        assert_eq!(RET().parts(), ("RET", vec![]));
+    }
 }
@@ -1,19 +1,290 @@
 use crate::{
-    display::{Display, ScaledFramebuffer},
+    debugger::Debugger,
+    display::{self, Appearance, Framebuffer, ScreenChange},
+    font,
+    frame::FrameEvent,
+    frontend::{Shader, WindowBackend},
+    input::{Input, Replay},
     instruction::{Instruction, Instruction::*},
+    keymap::Keymap,
+    library,
+    memory::{self, MemoryHooks, MemoryRegion, SharedMemoryHook},
+    persistence::{Persistence, SharedPersistence},
+    quirks::Quirks,
+    statediff::StateDiff,
+    timing::{self, FrameCost, Timing},
+    tracefile::{StateDump, TraceFile},
+    watchdog::Watchdog,
+};
+#[cfg(feature = "window")]
+use crate::{
+    display::{Display, Key},
+    frontend::Frontend,
+    rewind::{self, Rewind},
 };
 use crate::{error::Chip8Error, instruction::Register};
 use log::Level::Debug;
-use rand::{Rng, RngCore};
-use std::convert::TryFrom;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "window")]
+use std::collections::HashSet;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::{self, Formatter},
+    fs::{self, File},
+    io::{self, BufWriter, Stdout},
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
+
+/// We don't draw in time with a real screen, so count every this many
+/// instructions as a frame (unless `State::with_clock_hz` says otherwise). At
+/// 60 frames per second, that's about as fast as a COSMAC VIP.
+pub const INSTRUCTIONS_PER_FRAME: u64 = 10;
+
+/// How many RPL user flags there are. The HP48 only had 8, for V0 to V7, but
+/// XO-CHIP lets `LD R, Vx` and `LD Vx, R` use all 16 registers.
+const FLAG_COUNT: usize = 16;
+/// What the RPL user flags are called in `Persistence`.
+const FLAGS_KEY: &str = "flags";
+
+/// What the machine is doing between instructions. Some instructions can't
+/// finish right away, so rather than blocking until they can, the machine
+/// remembers what it's waiting for and checks again every cycle. That way a
+/// save state (or the debugger) can see the wait, and resuming picks it up.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Microstate {
+    Running,
+    /// LD Vx, K: waiting for a key to be pressed, to store it in `dest`
+    WaitingForKey {
+        dest: Register,
+    },
+    /// DRW with the display-wait quirk: waiting for the next frame to start
+    /// before drawing
+    WaitingForVBlank {
+        pending_drw: Instruction,
+    },
+    /// SCHIP's EXIT: the program asked to stop, so nothing else runs. The
+    /// timers still count down.
+    Exited,
+}
+
+/// Which dialect of CHIP-8 to interpret.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Variant {
+    /// The original CHIP-8, plus the SCHIP instructions that don't conflict with it.
+    Chip8,
+    /// XO-CHIP: adds a second drawing plane, long loads, register range
+    /// save/load, and an audio pattern buffer.
+    XoChip,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip-8" | "chip8" => Ok(Variant::Chip8),
+            "xo-chip" | "xochip" => Ok(Variant::XoChip),
+            _ => Err(format!(
+                "Unknown variant {} (expected chip-8 or xo-chip)",
+                s
+            )),
+        }
+    }
+}
+
+impl Variant {
+    /// How many bytes of memory the machine has.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Variant::Chip8 => memory::MEMORY_SIZE,
+            Variant::XoChip => memory::XO_CHIP_MEMORY_SIZE,
+        }
+    }
+}
+
+/// The same names that `from_str` accepts.
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Chip8 => write!(f, "chip-8"),
+            Variant::XoChip => write!(f, "xo-chip"),
+        }
+    }
+}
 
+/// What one call to `State::step` did.
 #[derive(Clone, Debug, PartialEq)]
+pub struct StepOutcome {
+    /// The instruction that ran, or the one the machine is waiting on
+    pub instruction: Instruction,
+    /// Where the instruction is in memory. That's `pc_before`, unless the
+    /// machine was already waiting on it, and so had already moved past it.
+    pub address: u16,
+    /// The program counter before the step
+    pub pc_before: u16,
+    /// The program counter after the step
+    pub pc_after: u16,
+    /// Whether the instruction has to wait (see `Microstate`) instead of
+    /// finishing this cycle, like DRW with the display-wait quirk
+    pub waited: bool,
+    /// How many logical pixels it turned on or off. Switching resolution
+    /// clears the screen, so that counts every pixel that was on.
+    pub pixels_changed: usize,
+    /// For DRW, the logical (x, y) of every pixel it turned off, which is
+    /// what sets VF
+    pub collisions: Vec<(usize, usize)>,
+    /// Whether the sound timer went from 0 to above 0, so a beep started
+    pub sound_started: bool,
+    /// Whether it called a subroutine or returned from one
+    pub call: Option<CallEvent>,
+    /// Where the instructions start that it wrote over, after they had
+    /// already run (see `State::overwritten_code`)
+    pub overwritten: Vec<u16>,
+}
+
+/// The instructions that have been decoded so far, by address, so that a
+/// loop doesn't decode the same bytes every time around. Writing to memory
+/// forgets the instructions that overlap it, so that a program that modifies
+/// itself runs its new instructions.
+/// It isn't part of the machine: snapshots leave it out, and any two caches
+/// are equal.
+#[derive(Clone, Debug, Default)]
+struct DecodeCache(Vec<Option<Instruction>>);
+
+impl DecodeCache {
+    fn get(&self, address: usize) -> Option<&Instruction> {
+        self.0.get(address)?.as_ref()
+    }
+
+    fn insert(&mut self, address: usize, instruction: Instruction) {
+        // Only XO-CHIP programs run past the first 4KB, so only grow for them
+        if self.0.len() <= address {
+            self.0.resize(memory::MEMORY_SIZE.max(address + 1), None);
+        }
+        if let Some(slot) = self.0.get_mut(address) {
+            *slot = Some(instruction);
+        }
+    }
+
+    /// Forget the instructions that include any of the `length` bytes at
+    /// `address`. The longest instruction is 4 bytes, so that's the ones that
+    /// start up to 3 bytes before it. Returns where the ones that did
+    /// include them start, since those are instructions that already ran
+    /// and will be different next time.
+    fn invalidate(&mut self, address: usize, length: usize) -> Vec<u16> {
+        let start = address.saturating_sub(3).min(self.0.len());
+        let end = (address + length).min(self.0.len());
+        let mut overwritten = vec![];
+        for (slot_address, slot) in (start..end).zip(&mut self.0[start..end]) {
+            if let Some(instruction) = slot.take() {
+                if slot_address + instruction.size() > address {
+                    overwritten.push(slot_address as u16);
+                }
+            }
+        }
+        overwritten
+    }
+}
+
+impl PartialEq for DecodeCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// How many steps the debugger can go back.
+const JOURNAL_LENGTH: usize = 10_000;
+
+/// Something a step wrote, with what was there before.
+#[derive(Clone, Debug)]
+enum Change {
+    Register(Register, u8),
+    /// The bytes of memory starting at this address
+    Memory(usize, Vec<u8>),
+    /// The return address at this level of the stack
+    Stack(usize, u16),
+    Screen(Vec<ScreenChange>),
+}
+
+/// Everything one step changed, to undo it. The small parts of the machine,
+/// like the program counter, I, and the timers, are saved whole, since that's
+/// as cheap as noting whether they changed. The registers, memory, the stack,
+/// and the screen only note what was written (see `Change`).
+#[derive(Clone, Debug)]
+struct JournalEntry {
+    pc: u16,
+    i: u16,
+    sp: u8,
+    delay_timer: u8,
+    sound_timer: u8,
+    cycles_this_frame: u64,
+    vip_cycles_this_frame: u32,
+    microstate: Microstate,
+    collisions: Vec<(usize, usize)>,
+    high_resolution: bool,
+    planes: u8,
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    flags: [u8; FLAG_COUNT],
+    changes: Vec<Change>,
+}
+
+/// What the last `JOURNAL_LENGTH` steps changed, oldest first, so the
+/// debugger can step back through them (see `State::step_back`). Nothing is
+/// kept until `State::begin_step` is first called.
+/// Like `DecodeCache`, it isn't part of the machine: snapshots leave it out,
+/// and any two journals are equal.
+#[derive(Clone, Debug, Default)]
+struct Journal(VecDeque<JournalEntry>);
+
+impl Journal {
+    /// Note a change in the step that's running, if there is one.
+    fn record(&mut self, change: Change) {
+        if let Some(entry) = self.0.back_mut() {
+            entry.changes.push(change);
+        }
+    }
+
+    /// Whether there's a step to record changes in.
+    fn is_recording(&self) -> bool {
+        !self.0.is_empty()
+    }
+}
+
+impl PartialEq for Journal {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// A change to the call stack, from CALL or RET (see `StepOutcome`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallEvent {
+    /// A CALL at `from` went to the subroutine at `to`
+    Call { from: u16, to: u16 },
+    /// A RET went back to `to`, just after the CALL
+    Return { to: u16 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct State {
-    /// 4KB = 4096 bytes of RAM.
+    /// 4KB = 4096 bytes of RAM, or 64KB with XO-CHIP (see
+    /// `Variant::memory_size`).
     /// The first 512 bytes (0x000 to 0x1FF) are for the interpreter and not to be used.
     /// Most CHIP-8 programs start at 0x200 = 512.
-    /// So, the main memory is from 0x200 to 0xFFF.
+    /// So, the main memory is from 0x200 to 0xFFF (or 0xFFFF).
+    /// See the `memory` module for these bounds, and `font_area`,
+    /// `program_area`, and `free_ram` to look at each part.
     memory: Vec<u8>,
+    /// Where the program was loaded, which is where it starts running
+    load_address: usize,
+    /// How many bytes long the loaded program is
+    program_size: usize,
     /// Chip-8 has 16 general purpose 8-bit registers, usually referred to as Vx, where x is a hexadecimal digit (0 through F).
     registers: Vec<u8>,
     /// A 16-bit register called I. This register is generally used to
@@ -28,47 +299,477 @@ pub struct State {
     /// the interpreter should return to when finished with a subroutine.
     /// Chip-8 allows for up to 16 levels of nested subroutines.
     stack: Vec<u16>,
+    /// Delay timer, which counts down to 0 at 60Hz
+    delay_timer: u8,
+    /// Sound timer, which counts down to 0 at 60Hz and beeps while it's not 0
+    sound_timer: u8,
+    /// How many cycles of the current frame have started, from 1 to
+    /// `instructions_per_frame` (or 0 before the very first one)
+    cycles_this_frame: u64,
+    /// How the length of a frame is measured
+    timing: Timing,
+    /// With `Timing::Flat`, how many instructions every frame runs
+    instructions_per_frame: u64,
+    /// With `Timing::Vip`, how many of the VIP's machine cycles the current
+    /// frame's instructions have taken
+    vip_cycles_this_frame: u32,
+    /// Whether an instruction is waiting to finish
+    microstate: Microstate,
+    /// Which of the 16 keys (0 to F) are held down
+    keypad: [bool; 16],
+    /// The logical pixels that the last instruction turned off by drawing
+    /// over them (see `collisions`)
+    collisions: Vec<(usize, usize)>,
+    /// Where the instructions start that the last instruction wrote over,
+    /// after they had already run (see `overwritten_code`)
+    #[serde(skip)]
+    overwritten: Vec<u16>,
 
     /// The framebuffer
-    buffer: ScaledFramebuffer,
+    buffer: Framebuffer,
+    /// Whether the SCHIP 128x64 high-resolution mode is on
+    high_resolution: bool,
+
+    /// Which dialect of CHIP-8 this is running
+    variant: Variant,
+    /// How to resolve the instructions that CHIP-8 dialects disagree about
+    quirks: Quirks,
+    /// XO-CHIP: which drawing planes (a bitmask from 0 to 3) DRW draws to
+    planes: u8,
+    /// XO-CHIP: a 16-byte (128-bit) audio pattern, played 1 bit at a time
+    audio_pattern: [u8; 16],
+    /// XO-CHIP: the playback rate of the audio pattern
+    pitch: u8,
+    /// SCHIP: the RPL user flags that `LD R, Vx` saves registers in, which
+    /// `persistence` keeps between runs
+    flags: [u8; FLAG_COUNT],
+    /// The program's fingerprint (see `library::fingerprint`), which
+    /// `persistence` keeps its data under
+    rom: String,
+
+    /// The instructions decoded so far (see `DecodeCache`)
+    #[serde(skip)]
+    decoded: DecodeCache,
+    /// What recent steps changed, for stepping back (see `Journal`)
+    #[serde(skip)]
+    journal: Journal,
+    /// What sees the program's reads and writes (see `MemoryHook`)
+    #[serde(skip)]
+    hooks: MemoryHooks,
+    /// Where to keep things between runs (see `Persistence`)
+    #[serde(skip)]
+    persistence: SharedPersistence,
 }
 
 impl State {
-    /// Create a new State with the given program.
-    pub fn with_program(program: &[u8]) -> Self {
-        // Program space is from 0x200 to 0xFFF.
-        assert!(program.len() <= (0xFFF - 0x200));
+    /// Create a new State with the given program. Fails if the program
+    /// doesn't fit in memory.
+    pub fn with_program(program: &[u8]) -> Result<Self, Chip8Error> {
+        Self::with_program_for(Variant::Chip8, program)
+    }
+
+    /// Create a new State that runs `variant`, with the given program. The
+    /// program has to fit in the variant's memory, so XO-CHIP programs can be
+    /// bigger than the rest.
+    pub fn with_program_for(variant: Variant, program: &[u8]) -> Result<Self, Chip8Error> {
+        Self::with_program_at(variant, memory::PROGRAM_START, program)
+    }
+
+    /// Create a new State like `with_program_for`, but with the program
+    /// loaded at `address` instead of 0x200, where it starts running. A few
+    /// machines, like the ETI-660, loaded programs at 0x600.
+    pub fn with_program_at(
+        variant: Variant,
+        address: usize,
+        program: &[u8],
+    ) -> Result<Self, Chip8Error> {
+        // Program space is from the load address to the end of memory.
+        let max = variant.memory_size().saturating_sub(address);
+        if program.len() > max {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: program.len(),
+                max,
+            });
+        }
 
-        // Start with 0x200 empty bytes, then add the program at the end
-        let interpreter_area = &[0; 0x200];
-        let memory = [interpreter_area, program].concat();
+        // Start with the interpreter area (including the fonts), then add the
+        // program, then fill the rest of RAM with zeroes
+        let mut memory = vec![0; variant.memory_size()];
+        let font_end = font::FONT_ADDRESS + font::FONT.len();
+        memory[font::FONT_ADDRESS..font_end].copy_from_slice(&font::FONT);
+        let font_end = font::LARGE_FONT_ADDRESS + font::LARGE_FONT.len();
+        memory[font::LARGE_FONT_ADDRESS..font_end].copy_from_slice(&font::LARGE_FONT);
+        let program_end = address + program.len();
+        memory[address..program_end].copy_from_slice(program);
 
-        Self {
+        Ok(Self {
             memory,
+            load_address: address,
+            program_size: program.len(),
             registers: vec![0; 16],
             i: 0,
-            pc: 0x200,
+            pc: address as u16,
             sp: 0,
             stack: vec![0; 16],
-            buffer: ScaledFramebuffer::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            cycles_this_frame: 0,
+            timing: Timing::Flat,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            vip_cycles_this_frame: 0,
+            microstate: Microstate::Running,
+            keypad: [false; 16],
+            collisions: vec![],
+            overwritten: vec![],
+            buffer: Framebuffer::new(),
+            high_resolution: false,
+            variant,
+            quirks: Quirks::default(),
+            planes: 0b01,
+            audio_pattern: [0; 16],
+            // 64 means a playback rate of 4000Hz
+            pitch: 64,
+            flags: [0; FLAG_COUNT],
+            rom: library::fingerprint(program),
+            decoded: DecodeCache::default(),
+            journal: Journal::default(),
+            hooks: MemoryHooks::default(),
+            persistence: SharedPersistence::default(),
+        })
+    }
+
+    /// Use the given CHIP-8 dialect instead of the original CHIP-8. This
+    /// changes how much memory there is, but never so there's less than the
+    /// program (see `with_program_for`).
+    pub fn with_variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        let size = variant
+            .memory_size()
+            .max(self.load_address + self.program_size);
+        self.memory.resize(size, 0);
+        self
+    }
+
+    /// Use the given quirks instead of the COSMAC VIP's.
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Measure frames with the given timing instead of running the same
+    /// number of instructions in every one.
+    pub fn with_timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Run about `hz` instructions a second instead of
+    /// `INSTRUCTIONS_PER_FRAME` every frame, rounded to a whole number of
+    /// instructions in every 60Hz frame (and at least 1). This only matters
+    /// with `Timing::Flat`.
+    pub fn with_clock_hz(mut self, hz: u32) -> Self {
+        self.instructions_per_frame = ((u64::from(hz) + 30) / 60).max(1);
+        self
+    }
+
+    /// Draw the screen at a different size or in different colors. This
+    /// clears the screen, so do it before the program starts.
+    pub fn with_appearance(mut self, appearance: &Appearance) -> Self {
+        self.buffer = Framebuffer::with_appearance(appearance);
+        self
+    }
+
+    /// Let `hook` see (and change) what the program reads from and writes to
+    /// `addresses`, for a peripheral mapped into memory, say.
+    pub fn with_memory_hook(mut self, addresses: Range<usize>, hook: SharedMemoryHook) -> Self {
+        self.hooks.add(addresses, hook);
+        self
+    }
+
+    /// Keep the RPL user flags in `persistence` from now on, starting with
+    /// the ones it has for this program, if any.
+    pub fn with_persistence(
+        mut self,
+        persistence: Rc<RefCell<dyn Persistence>>,
+    ) -> Result<Self, Chip8Error> {
+        self.persistence = SharedPersistence::new(persistence);
+        self.load_flags()?;
+        Ok(self)
+    }
+
+    /// Start with the RPL user flags that `persistence` has for this program.
+    fn load_flags(&mut self) -> Result<(), Chip8Error> {
+        if let Some(flags) = self.persistence.load(&self.rom, FLAGS_KEY)? {
+            let count = flags.len().min(FLAG_COUNT);
+            self.flags[..count].copy_from_slice(&flags[..count]);
+        }
+        Ok(())
+    }
+
+    /// Start `program` from scratch on the same machine: the same load
+    /// address, variant, quirks, timing, speed, screen size and colors, memory
+    /// hooks, and persistence, but everything else as if it had just been
+    /// loaded. Fails
+    /// if the program doesn't fit in memory.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn restarted(&self, program: &[u8]) -> Result<Self, Chip8Error> {
+        let mut state = Self {
+            timing: self.timing,
+            instructions_per_frame: self.instructions_per_frame,
+            hooks: self.hooks.clone(),
+            persistence: self.persistence.clone(),
+            ..Self::with_program_at(self.variant, self.load_address, program)?
+                .with_quirks(self.quirks)
+                .with_appearance(&self.buffer.appearance())
+        };
+        state.load_flags()?;
+        Ok(state)
+    }
+
+    /// Snapshot the whole machine (memory, registers, timers, stack, and
+    /// screen) so it can be written to disk and resumed later.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn serialize(&self) -> Result<Vec<u8>, Chip8Error> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Restore a machine from a snapshot made by `serialize`. Snapshots can
+    /// come from anywhere, like a bundle someone shared, so this fails with
+    /// `CorruptState` instead of giving back a machine that would crash.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        let state: Self = bincode::deserialize(bytes)?;
+        state.check_snapshot()?;
+        Ok(state)
+    }
+
+    /// Check the parts of a snapshot that the rest of the machine counts on,
+    /// since bincode reads any bytes that have the right shape.
+    fn check_snapshot(&self) -> Result<(), Chip8Error> {
+        let corrupt = |message: String| Err(Chip8Error::CorruptState(message));
+        if self.registers.len() != 16 {
+            return corrupt(format!("it has {} registers, not 16", self.registers.len()));
+        }
+        if self.stack.len() != 16 {
+            return corrupt(format!(
+                "its stack is {} levels deep, not 16",
+                self.stack.len()
+            ));
+        }
+        if self.sp as usize > self.stack.len() {
+            return corrupt(format!("its stack pointer is past the top, at {}", self.sp));
+        }
+        if self.memory.len() != self.variant.memory_size() {
+            return corrupt(format!(
+                "it has {} bytes of memory, but {} has {}",
+                self.memory.len(),
+                self.variant,
+                self.variant.memory_size()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Go back to `snapshot`, like a save state or a frame from the rewind
+    /// history, on this machine. Snapshots leave out what isn't part of the
+    /// machine, so this keeps this machine's memory hooks and persistence, and
+    /// the instructions it's decoded where memory is still the same.
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn restore(&mut self, snapshot: State) {
+        let mut decoded = std::mem::take(&mut self.decoded);
+        if self.memory.len() == snapshot.memory.len() {
+            for (address, (now, then)) in self.memory.iter().zip(&snapshot.memory).enumerate() {
+                if now != then {
+                    decoded.invalidate(address, 1);
+                }
+            }
+        } else {
+            decoded = DecodeCache::default();
+        }
+        *self = Self {
+            decoded,
+            hooks: std::mem::take(&mut self.hooks),
+            persistence: std::mem::take(&mut self.persistence),
+            ..snapshot
+        };
+    }
+
+    /// The interpreter area, from 0x000 to 0x1FF, where the fonts live.
+    pub fn font_area(&self) -> MemoryRegion<'_> {
+        MemoryRegion::new(&self.memory, memory::FONT_AREA)
+    }
+
+    /// The program as it is now (it may have modified itself), from where it
+    /// was loaded (usually 0x200) to the end of the program.
+    pub fn program_area(&self) -> MemoryRegion<'_> {
+        let end = self.load_address + self.program_size;
+        MemoryRegion::new(&self.memory, self.load_address..end)
+    }
+
+    /// The RAM after the end of the program, up to 0xFFF (or 0xFFFF with
+    /// XO-CHIP), which programs can use however they like.
+    pub fn free_ram(&self) -> MemoryRegion<'_> {
+        let start = self.load_address + self.program_size;
+        MemoryRegion::new(&self.memory, start..self.memory.len())
+    }
+
+    /// The program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The I register.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// The stack pointer.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// The sound timer. The machine beeps while it's above 0.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Where the last instruction collided, if it was a DRW that turned
+    /// pixels off: their logical (x, y), in the order they were drawn.
+    pub fn collisions(&self) -> &[(usize, usize)] {
+        &self.collisions
+    }
+
+    /// The addresses of the instructions that already ran that the last
+    /// instruction wrote over, so they'll run differently next time: the
+    /// program modifies itself.
+    pub fn overwritten_code(&self) -> &[u16] {
+        &self.overwritten
+    }
+
+    /// The general purpose registers, V0 to VF.
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// A copy of the registers, to compare against after running an
+    /// instruction.
+    fn registers_snapshot(&self) -> [u8; 16] {
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&self.registers);
+        registers
+    }
+
+    /// The whole stack, including the unused slots above the stack pointer.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Which dialect of CHIP-8 this is running.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// All of RAM.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// What's on the screen.
+    pub fn buffer(&self) -> &Framebuffer {
+        &self.buffer
+    }
+
+    /// Everything that's different in `other`, like what an instruction
+    /// changed when `other` is this machine after running it.
+    pub fn diff(&self, other: &State) -> StateDiff {
+        StateDiff::between(self, other)
+    }
+
+    /// What the machine is waiting for, if anything.
+    pub fn microstate(&self) -> &Microstate {
+        &self.microstate
+    }
+
+    /// Which keys are held down, in order from 0 to F.
+    pub fn keypad(&self) -> [bool; 16] {
+        self.keypad
+    }
+
+    /// Set which keys are held down.
+    pub fn set_keypad(&mut self, keypad: [bool; 16]) {
+        self.keypad = keypad;
+    }
+
+    /// The lowest key that's held down, if any.
+    fn pressed_key(&self) -> Option<u8> {
+        self.keypad
+            .iter()
+            .position(|down| *down)
+            .map(|key| key as u8)
+    }
+
+    /// Whether the last frame is over (or there hasn't been one yet), so the
+    /// next cycle will begin a new frame.
+    pub fn between_frames(&self) -> bool {
+        self.cycles_this_frame == 0 || self.frame_is_over()
+    }
+
+    /// Whether the current frame has used up its time: either
+    /// `instructions_per_frame` cycles, or with `Timing::Vip`, as many
+    /// instructions as the VIP could run in a frame.
+    fn frame_is_over(&self) -> bool {
+        match self.timing {
+            Timing::Flat => self.cycles_this_frame == self.instructions_per_frame,
+            Timing::Vip => {
+                self.cycles_this_frame > 0
+                    && self.vip_cycles_this_frame >= timing::VIP_CYCLES_PER_FRAME
+            }
+        }
+    }
+
+    /// Start the next cycle, calling `send` with each thing that did to the
+    /// frame (see `FrameEvent`) as it happens, so that listeners see the
+    /// machine as it was at that point. The last frame ends right before the
+    /// next one begins, and the timers count down at the start of every frame
+    /// (the vertical blank).
+    fn start_cycle(&mut self, mut send: impl FnMut(FrameEvent, &mut State)) {
+        if self.frame_is_over() {
+            send(FrameEvent::EndFrame, self);
+            // Frontends draw at the end of the frame, so start tracking what
+            // changes in the next one
+            self.buffer.mark_drawn();
+            self.cycles_this_frame = 0;
+            self.vip_cycles_this_frame = 0;
+        }
+        if self.cycles_this_frame == 0 {
+            send(FrameEvent::BeginFrame, self);
+            self.delay_timer = self.delay_timer.saturating_sub(1);
+            self.sound_timer = self.sound_timer.saturating_sub(1);
+            send(FrameEvent::TimersTicked, self);
         }
+        self.cycles_this_frame += 1;
     }
 
     /// Set the given register to the given value.
-    fn set_register<U: Into<Register>>(&mut self, unconverted: U, value: u8) {
-        let register = unconverted.into();
-        self.registers[register.0 as usize] = value;
+    fn set_register(&mut self, register: Register, value: u8) {
+        let old_value = self.get_register(register);
+        self.journal.record(Change::Register(register, old_value));
+        self.registers[register.index()] = value;
     }
 
     /// Get the value in the given register.
-    fn get_register<U: Into<Register>>(&self, unconverted: U) -> u8 {
-        let register = unconverted.into();
-        self.registers[register.0 as usize]
+    fn get_register(&self, register: Register) -> u8 {
+        self.registers[register.index()]
     }
 
-    /// Increase I by the value in the given register.
+    /// Increase I by the value in the given register. I is 16 bits, so it
+    /// wraps around past 0xFFFF, which an XO-CHIP long load can get it near.
     fn increase_i(&mut self, register: &Register) {
-        self.i += self.get_register(*register) as u16;
+        self.i = self.i.wrapping_add(self.get_register(*register) as u16);
     }
 
     /// Set the program counter to the given address.
@@ -77,145 +778,1196 @@ impl State {
     }
 
     /// Increment the stack pointer and push a value onto the top of the stack.
-    fn push_onto_stack(&mut self, value: u16) {
+    /// Fails if the stack is already full (16 levels deep).
+    fn push_onto_stack(&mut self, value: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow {
+                pc: self.current_instruction_address(),
+            });
+        }
+        let old_value = self.stack[self.sp as usize];
+        self.journal
+            .record(Change::Stack(self.sp as usize, old_value));
         self.stack[self.sp as usize] = value;
         self.sp += 1;
+        Ok(())
     }
 
     /// Decrement the stack pointer and return the value that it used to point to.
-    fn pop_off_stack(&mut self) -> u16 {
-        if self.sp == 0 {
-            panic!("Cannot decrement stack pointer, already at 0");
-        }
+    /// Fails if the stack is empty.
+    fn pop_off_stack(&mut self) -> Result<u16, Chip8Error> {
+        let value = self
+            .sp
+            .checked_sub(1)
+            .and_then(|top| self.stack.get(top as usize).copied())
+            .ok_or(Chip8Error::StackUnderflow {
+                pc: self.current_instruction_address(),
+            })?;
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(value)
+    }
+
+    /// The `length` bytes of memory starting at `address`, as the program
+    /// reads them, through any `MemoryHook`s. Fails if any of them are past
+    /// the end of memory, like when I points near 0xFFF.
+    pub fn read_mem(&self, address: usize, length: usize) -> Result<Vec<u8>, Chip8Error> {
+        self.check_bounds(address, length)?;
+        let mut bytes = self.memory[address..address + length].to_vec();
+        self.hooks.read(address, &mut bytes);
+        Ok(bytes)
+    }
+
+    /// Write `bytes` to memory starting at `address`, as the program does,
+    /// through any `MemoryHook`s, which might not let some of them be stored.
+    /// Fails (without writing anything) if any of them would go past the end
+    /// of memory.
+    pub fn write_mem(&mut self, address: usize, bytes: &[u8]) -> Result<(), Chip8Error> {
+        self.check_bounds(address, bytes.len())?;
+        if self.journal.is_recording() {
+            let old_bytes = self.memory[address..address + bytes.len()].to_vec();
+            self.journal.record(Change::Memory(address, old_bytes));
+        }
+        let store = self.hooks.write(address, bytes);
+        for ((slot, byte), store) in self.memory[address..].iter_mut().zip(bytes).zip(store) {
+            if store {
+                *slot = *byte;
+            }
+        }
+        let overwritten = self.decoded.invalidate(address, bytes.len());
+        self.overwritten.extend(overwritten);
+        Ok(())
+    }
+
+    fn check_bounds(&self, address: usize, length: usize) -> Result<(), Chip8Error> {
+        if address + length > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                address: address.max(self.memory.len()),
+            });
+        }
+        Ok(())
+    }
+
+    /// The address of the instruction being run. The program counter has
+    /// already moved past it, and CALL, RET, and LD Vx, K are all 2 bytes.
+    fn current_instruction_address(&self) -> u16 {
+        self.pc.wrapping_sub(2)
     }
 
     fn next_chunk(&self) -> Option<u16> {
         let one = self.memory.get(self.pc as usize)?;
-        let two = self.memory.get((self.pc + 1) as usize)?;
+        let two = self.memory.get(self.pc as usize + 1)?;
         Some(u16::from_be_bytes([*one, *two]))
     }
-}
 
-/// Run the entire program, forever.
-pub fn run(state: &mut State, verbosely: bool) -> Result<&mut State, Chip8Error> {
-    let mut display = Display::new(state.buffer.true_width, state.buffer.true_height);
-    let rng = rand::thread_rng();
+    /// Whether the program has stopped for good, the way most programs end:
+    /// the next instruction jumps to itself, and there are no timers left to
+    /// count down and no key being waited for, so nothing can change again.
+    pub fn is_halted(&self) -> bool {
+        self.microstate == Microstate::Running
+            && self.delay_timer == 0
+            && self.sound_timer == 0
+            && matches!(
+                self.next_instruction(),
+                Some(Ok(JP(target))) if target.value() == self.pc
+            )
+    }
+
+    /// Whether the program counter has run off the end of memory, with no
+    /// instruction left to run.
+    fn has_run_off_the_end(&self) -> bool {
+        self.microstate == Microstate::Running && self.next_chunk().is_none()
+    }
 
-    while display.is_running() {
-        match state.next_chunk() {
-            Some(chunk) => {
-                // Advance by 2 bytes since 1 chunk is 2 bytes
-                state.pc += 2;
-                let instruction = Instruction::try_from(chunk)?;
-                execute(state, &instruction, Box::new(rng), verbosely)?;
-                display.draw(&state.buffer);
-                trace!("{}", state.buffer.pretty_print_physical());
+    /// Whether the program stopped itself with EXIT.
+    pub fn has_exited(&self) -> bool {
+        self.microstate == Microstate::Exited
+    }
+
+    /// Decode the instruction at the program counter without running it.
+    /// Returns None if the program counter has run off the end of memory.
+    pub fn next_instruction(&self) -> Option<Result<Instruction, Chip8Error>> {
+        self.next_chunk()?;
+        let pc = self.pc as usize;
+        if !self.hooks.covers(pc..pc + 4) {
+            return Some(Instruction::from_bytes(&self.memory[pc..]));
+        }
+        // Fetch through the hooks, without letting them see the word after
+        // the instruction unless it's part of it
+        let read = |address: usize| {
+            let end = (address + 2).min(self.memory.len());
+            let mut bytes = self.memory[address.min(end)..end].to_vec();
+            self.hooks.read(address, &mut bytes);
+            bytes
+        };
+        let mut bytes = read(pc);
+        if bytes == [0xF0, 0x00] {
+            bytes.extend(read(pc + 2));
+        }
+        Some(Instruction::from_bytes(&bytes))
+    }
+
+    /// The instruction to run this cycle. Usually that's the next one in
+    /// memory (see `advance`), but if the machine is waiting, it's the one
+    /// that's waiting to finish, so that it can check again.
+    fn fetch(&mut self) -> Option<Result<Instruction, Chip8Error>> {
+        match &self.microstate {
+            Microstate::Running => self.advance(),
+            Microstate::WaitingForKey { dest } => Some(Ok(LDKey(*dest))),
+            Microstate::WaitingForVBlank { pending_drw } => Some(Ok(pending_drw.clone())),
+            Microstate::Exited => Some(Ok(EXIT())),
+        }
+    }
+
+    /// Whether this instruction has to wait instead of running this cycle.
+    /// With the display-wait quirk, DRW only runs in the first cycle of a
+    /// frame.
+    /// With `Timing::Vip`, this also counts the instruction's machine cycles
+    /// towards the frame, and waiting uses up the rest of the frame.
+    fn must_wait(&mut self, instruction: &Instruction) -> bool {
+        if self.microstate == Microstate::Exited {
+            self.vip_cycles_this_frame = timing::VIP_CYCLES_PER_FRAME;
+            return true;
+        }
+        if let DRW(..) = instruction {
+            if self.quirks.display_wait && self.cycles_this_frame != 1 {
+                self.microstate = Microstate::WaitingForVBlank {
+                    pending_drw: instruction.clone(),
+                };
+                self.vip_cycles_this_frame = timing::VIP_CYCLES_PER_FRAME;
+                return true;
             }
-            None => break,
+            self.microstate = Microstate::Running;
         }
+        self.vip_cycles_this_frame += timing::vip_cost(instruction);
+        false
     }
-    Ok(state)
-}
 
-// Do one thing in the interpreter (run one instruction) and return the changed state.
-// Useful for testing.
-#[cfg(test)]
-fn tick(state: &mut State, rng: impl RngCore) -> Result<&mut State, Chip8Error> {
-    let chunk = state.next_chunk().unwrap();
-    // Advance by 2 bytes since 1 chunk is 2 bytes
-    state.pc += 2;
-    let instruction = Instruction::try_from(chunk)?;
-    execute(state, &instruction, rng, false)?;
-    Ok(state)
-}
+    /// Decode the instruction at the program counter (or use the one decoded
+    /// there last time, if memory hasn't changed since) and move the program
+    /// counter past it (2 bytes, or 4 for an XO-CHIP long load).
+    /// Instructions at hooked addresses are never cached, since a hook can
+    /// change what's read there at any time.
+    /// Returns None if the program counter has run off the end of memory,
+    /// and fails if the instruction is the last thing in memory.
+    fn advance(&mut self) -> Option<Result<Instruction, Chip8Error>> {
+        let address = self.pc as usize;
+        let hooked = self.hooks.covers(address..address + 4);
+        let cached = if hooked {
+            None
+        } else {
+            self.decoded.get(address).cloned()
+        };
+        let instruction = match cached {
+            Some(instruction) => instruction,
+            None => match self.next_instruction()? {
+                Ok(instruction) => {
+                    if !hooked {
+                        self.decoded.insert(address, instruction.clone());
+                    }
+                    instruction
+                }
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        // On a 64KB machine, the last instruction in memory has nothing after
+        // it to go on to
+        if let Err(e) = self.move_pc_forward(instruction.size()) {
+            return Some(Err(e));
+        }
+        Some(Ok(instruction))
+    }
 
-/// Execute a single instruction and return the changed `State`.
-fn execute<'a>(
-    state: &'a mut State,
-    instruction: &Instruction,
-    mut rng: impl RngCore,
-    verbosely: bool,
-) -> Result<&'a mut State, Chip8Error> {
-    if verbosely {
-        // Subtract 2 to get the value for this instruction, because we add 2 before running `execute`
-        println!("[{:03X}], {}", state.pc - 2, instruction);
+    /// Run one instruction (or check again on the one that's waiting), using
+    /// the machine's own variant and quirks (see `with_quirks`), and report
+    /// what happened. This is what to build on to run the machine some other
+    /// way, like in a web page.
+    /// Fails if the instruction does, or with `MemoryOutOfBounds` if the
+    /// program counter has run off the end of memory.
+    pub fn step(&mut self, rng: impl RngCore) -> Result<StepOutcome, Chip8Error> {
+        self.step_with(rng, false, |_, _| {})
     }
-    match instruction {
-        SYS() => {
-            if verbosely {
-                println!("\tIgnoring");
+
+    /// Run one instruction like `step`, calling `send` with each thing the
+    /// cycle does to the frame (see `start_cycle`), and explaining the
+    /// instruction on stdout if `verbosely` is set. The run loops build on
+    /// this, to tell the display, the input, and so on about frames.
+    fn step_with(
+        &mut self,
+        rng: impl RngCore,
+        verbosely: bool,
+        send: impl FnMut(FrameEvent, &mut State),
+    ) -> Result<StepOutcome, Chip8Error> {
+        self.start_cycle(send);
+        let pc_before = self.pc;
+        let instruction = match self.fetch() {
+            Some(instruction) => instruction?,
+            None => {
+                return Err(Chip8Error::MemoryOutOfBounds {
+                    address: pc_before as usize,
+                })
             }
+        };
+        let mut outcome = StepOutcome {
+            address: self.pc.wrapping_sub(instruction.size() as u16),
+            instruction,
+            pc_before,
+            pc_after: self.pc,
+            waited: false,
+            pixels_changed: 0,
+            collisions: vec![],
+            sound_started: false,
+            call: None,
+            overwritten: vec![],
+        };
+        if self.must_wait(&outcome.instruction) {
+            outcome.waited = true;
+            return Ok(outcome);
         }
-        RET() => {
-            let old_pc = state.pc;
-            state.pc = state.pop_off_stack();
-            if verbosely {
-                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
-            }
+
+        let sound_before = self.sound_timer;
+        let sp_before = self.sp;
+        // Only look at the screen for instructions that can change it, since
+        // most don't
+        let screen_before = if outcome.instruction.changes_screen() {
+            Some(self.logical_screen())
+        } else {
+            None
+        };
+        execute(self, &outcome.instruction, rng, verbosely)?;
+        if let Some(before) = screen_before {
+            let after = self.logical_screen();
+            outcome.pixels_changed = if before.len() == after.len() {
+                before.iter().zip(&after).filter(|(b, a)| b != a).count()
+            } else {
+                before.iter().filter(|planes| **planes != 0).count()
+            };
         }
-        JP(address) => {
-            let old_pc = state.pc;
-            state.set_pc((*address).into());
-            if verbosely {
-                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
-            }
+        outcome.pc_after = self.pc;
+        outcome.collisions = self.collisions.clone();
+        outcome.overwritten = self.overwritten.clone();
+        outcome.sound_started = sound_before == 0 && self.sound_timer > 0;
+        outcome.call = if self.sp > sp_before {
+            Some(CallEvent::Call {
+                from: pc_before,
+                to: self.pc,
+            })
+        } else if self.sp < sp_before {
+            Some(CallEvent::Return { to: self.pc })
+        } else {
+            None
+        };
+        Ok(outcome)
+    }
+
+    /// Start journaling a new step, so that `step_back` can undo everything
+    /// that happens from now until the next `begin_step`. The debugger calls
+    /// this whenever it lets the program go on. Only the last
+    /// `JOURNAL_LENGTH` steps are kept.
+    pub fn begin_step(&mut self) {
+        self.end_step();
+        if self.journal.0.len() == JOURNAL_LENGTH {
+            self.journal.0.pop_front();
         }
-        CALL(address) => {
-            let old_pc = state.pc;
-            state.push_onto_stack(state.pc);
-            if verbosely {
-                println!("\tPushed pc ({:04X}) onto stack", state.pc);
-            }
-            state.set_pc((*address).into());
-            if verbosely {
-                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
-            }
+        self.journal.0.push_back(JournalEntry {
+            pc: self.pc,
+            i: self.i,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            cycles_this_frame: self.cycles_this_frame,
+            vip_cycles_this_frame: self.vip_cycles_this_frame,
+            microstate: self.microstate.clone(),
+            collisions: self.collisions.clone(),
+            high_resolution: self.high_resolution,
+            planes: self.planes,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            flags: self.flags,
+            changes: vec![],
+        });
+        self.buffer.record_changes();
+    }
+
+    /// Add what the screen did to the step that's running.
+    fn end_step(&mut self) {
+        let screen = self.buffer.take_changes();
+        if !screen.is_empty() {
+            self.journal.record(Change::Screen(screen));
         }
-        SEByte(register, byte) => {
-            let register_value = state.get_register(*register);
-            if register_value == *byte {
-                state.pc += 2;
-                if verbosely {
-                    println!("\tSkipping ahead, V{:X} == {:02X}", register.0, byte);
+    }
+
+    /// Undo the last step, putting the machine back the way it was at that
+    /// step's `begin_step`. Returns false if there's no step left to undo.
+    /// The keypad stays as it is, since it's not the program's to change.
+    pub fn step_back(&mut self) -> bool {
+        self.end_step();
+        let entry = match self.journal.0.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for change in entry.changes.into_iter().rev() {
+            match change {
+                Change::Register(register, value) => self.registers[register.index()] = value,
+                Change::Memory(address, bytes) => {
+                    self.memory[address..address + bytes.len()].copy_from_slice(&bytes);
+                    self.decoded.invalidate(address, bytes.len());
                 }
-            } else if verbosely {
-                println!(
-                    "\tNot skipping, V{:X} is {:02X} (would skip if it were {:02X})",
-                    register.0, register_value, byte
-                );
+                Change::Stack(level, address) => self.stack[level] = address,
+                Change::Screen(changes) => self.buffer.undo(changes),
             }
         }
-        SNEByte(register, byte) => {
-            let register_value = state.get_register(*register);
-            if register_value != *byte {
-                state.pc += 2;
-                if verbosely {
-                    println!("\tSkipping ahead, V{:X} != {:02X}", register.0, byte);
-                }
-            } else if verbosely {
-                println!(
-                    "\tNot skipping, V{:X} is {:02X} (would skip if it were not {:02X})",
-                    register.0, register_value, byte
-                );
-            }
+        self.pc = entry.pc;
+        self.i = entry.i;
+        self.sp = entry.sp;
+        self.delay_timer = entry.delay_timer;
+        self.sound_timer = entry.sound_timer;
+        self.cycles_this_frame = entry.cycles_this_frame;
+        self.vip_cycles_this_frame = entry.vip_cycles_this_frame;
+        self.microstate = entry.microstate;
+        self.collisions = entry.collisions;
+        self.high_resolution = entry.high_resolution;
+        self.planes = entry.planes;
+        self.audio_pattern = entry.audio_pattern;
+        self.pitch = entry.pitch;
+        self.flags = entry.flags;
+        true
+    }
+
+    /// Decode the instruction at the start of `bytes` and run it as if it were
+    /// at the program counter, without reading it from memory or waiting for
+    /// the display. Returns the instruction, to find out how many bytes it
+    /// took. Whatever the bytes and the state are, this fails with a
+    /// `Chip8Error` rather than panicking, which is what the fuzz target in
+    /// `fuzz/` checks.
+    pub fn execute_bytes(
+        &mut self,
+        bytes: &[u8],
+        rng: impl RngCore,
+    ) -> Result<Instruction, Chip8Error> {
+        let instruction = Instruction::from_bytes(bytes)?;
+        self.move_pc_forward(instruction.size())?;
+        execute(self, &instruction, rng, false)?;
+        Ok(instruction)
+    }
+
+    /// Which planes are set at each logical pixel, row by row.
+    fn logical_screen(&self) -> Vec<u8> {
+        self.buffer.iter_rows().flatten().copied().collect()
+    }
+
+    /// Skip the next instruction. In XO-CHIP, that might be a 4-byte long
+    /// load, which is skipped entirely. Fails if that's past the end of
+    /// memory.
+    fn skip_next_instruction(&mut self) -> Result<(), Chip8Error> {
+        if self.variant == Variant::XoChip && self.next_chunk() == Some(0xF000) {
+            self.move_pc_forward(4)
+        } else {
+            self.move_pc_forward(2)
+        }
+    }
+
+    /// Move the program counter `bytes` further on. Fails if that's past the
+    /// 64KB that it can point at.
+    fn move_pc_forward(&mut self, bytes: usize) -> Result<(), Chip8Error> {
+        self.pc = self
+            .pc
+            .checked_add(bytes as u16)
+            .ok_or(Chip8Error::MemoryOutOfBounds {
+                address: self.pc as usize + bytes,
+            })?;
+        Ok(())
+    }
+
+    /// The registers from Vx to Vy inclusive, in that order (so if x > y, this
+    /// counts down).
+    fn register_range(x: Register, y: Register) -> Vec<Register> {
+        if x.value() <= y.value() {
+            (x.value()..=y.value()).map(Register::from_nibble).collect()
+        } else {
+            (y.value()..=x.value())
+                .rev()
+                .map(Register::from_nibble)
+                .collect()
+        }
+    }
+
+    /// The registers, timers, top of the stack, and what the machine is
+    /// waiting for (if anything), one line per string. This is everything in
+    /// `Display` except the screen, for showing over the screen itself.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (row, values) in self.registers.chunks(4).enumerate() {
+            let cells = values
+                .iter()
+                .enumerate()
+                .map(|(n, value)| format!("V{:X}={:02X}", row * 4 + n, value))
+                .collect::<Vec<_>>();
+            lines.push(cells.join(" "));
+        }
+        lines.push(format!(
+            "I={:04X} PC={:03X} SP={:X} DT={:02X} ST={:02X}",
+            self.i, self.pc, self.sp, self.delay_timer, self.sound_timer
+        ));
+        lines.push(match self.sp {
+            0 => "Stack: empty".to_string(),
+            sp => format!("Stack: {:03X} (depth {})", self.stack[sp as usize - 1], sp),
+        });
+        match &self.microstate {
+            Microstate::Running => {}
+            Microstate::WaitingForKey { dest } => {
+                lines.push(format!("Waiting for a key to store in V{:X}", dest.value()))
+            }
+            Microstate::WaitingForVBlank { pending_drw } => {
+                lines.push(format!("Waiting for the next frame to run {}", pending_drw))
+            }
+            Microstate::Exited => lines.push("Exited".to_string()),
+        }
+        lines
+    }
+}
+
+/// A compact summary of the machine: the registers in a 4x4 grid, the other
+/// registers, the top of the stack, and a thumbnail of the screen.
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for line in self.summary_lines() {
+            writeln!(f, "{}", line)?;
+        }
+        write!(f, "{}", self.buffer.pretty_print_thumbnail())
+    }
+}
+
+/// How to run a program.
+pub struct RunOptions {
+    /// Explain what every instruction does as it runs
+    pub verbosely: bool,
+    /// Where F5 saves the state to, and F9 loads it from
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub save_state_path: PathBuf,
+    /// Skip unknown instructions instead of stopping
+    pub ignore_unknown: bool,
+    /// Log progress every this many frames (see `Watchdog`)
+    pub progress_every: u64,
+    /// Don't open a window, even if there could be one
+    pub headless: bool,
+    /// Which library opens the window
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub window_backend: WindowBackend,
+    /// Let the window be resized, scaling the screen up to fit
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub resizable: bool,
+    /// Fill the whole screen, scaling the CHIP-8's screen up to fit
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fullscreen: bool,
+    /// Blend away flashing that's too fast, for people who are sensitive to it
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub reduce_flashing: bool,
+    /// Pause after this many seconds of the screen not changing and no keys
+    /// being pressed, or never if 0 (see `IdleDetector`)
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub idle_pause: u64,
+    /// Which keys are the keypad's
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub keymap: Keymap,
+    /// With the wgpu frontend, the shader to draw the screen through
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub shader: Shader,
+    /// How much of its glow a pixel that turned off loses every frame, or None
+    /// to switch it off right away (see `Phosphor`)
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub phosphor: Option<f32>,
+    /// Seeds the random numbers for RND, so a run can be repeated exactly
+    pub seed: u64,
+    /// Write a line of JSON for every instruction that runs to this file (see
+    /// `TraceFile`)
+    pub trace_file: Option<PathBuf>,
+    /// Print the whole machine's state as a line of JSON after every this
+    /// many instructions (see `StateDump`)
+    pub dump_state_every: Option<u64>,
+    /// Record the keypad to this file, to replay later (see `Recorder`)
+    pub record: Option<PathBuf>,
+    /// Play back this input instead of reading the keyboard (see `Player`)
+    pub replay: Option<Replay>,
+    /// Stop after running this many instructions
+    pub max_cycles: Option<u64>,
+    /// Where F2 (or `screenshot_at_cycle`) saves a screenshot to
+    pub screenshot_path: PathBuf,
+    /// Without a window: save a screenshot after running this many
+    /// instructions
+    pub screenshot_at_cycle: Option<u64>,
+}
+
+/// Run the entire program, forever (or until `max_cycles`).
+/// If there's a debugger, it gets to look at the state (and maybe pause) before
+/// every instruction.
+/// The keypad is recorded to `record`, or played back from `replay`, if
+/// they're set.
+/// Without the `window` feature, or with `headless`, there's no window (see
+/// `run_headless`).
+pub fn run<'a>(
+    state: &'a mut State,
+    options: &RunOptions,
+    debugger: Option<&mut Debugger>,
+) -> Result<&'a mut State, Chip8Error> {
+    let mut input = Input::new(
+        options.seed,
+        options.record.is_some(),
+        options.replay.as_ref(),
+    );
+    let result = run_with_input(state, options, debugger, &mut input);
+    // Save the recording even if the program crashed, since that's often
+    // the part worth replaying
+    if let Some(path) = &options.record {
+        input.save_recording(path)?;
+        info!("Recorded the input to {}", path.display());
+    }
+    result
+}
+
+/// Run the program in a window or without one, with input recorded or played
+/// back as `input` says.
+fn run_with_input<'a>(
+    state: &'a mut State,
+    options: &RunOptions,
+    debugger: Option<&mut Debugger>,
+    input: &mut Input,
+) -> Result<&'a mut State, Chip8Error> {
+    #[cfg(feature = "window")]
+    {
+        if !options.headless {
+            return run_in_window(state, options, debugger, input);
+        }
+    }
+    run_headless(state, options, debugger, input)
+}
+
+/// Read a ROM that was dropped onto the window, and start it on the same
+/// machine as `state` (see `State::restarted`). A ROM that can't be read or
+/// doesn't fit in memory is only warned about, so the one that's running
+/// keeps going.
+#[cfg(feature = "window")]
+fn load_dropped_rom(path: &Path, state: &State) -> Option<(Vec<u8>, State)> {
+    let loaded = fs::read(path)
+        .map_err(Chip8Error::from)
+        .and_then(|rom| state.restarted(&rom).map(|restarted| (restarted, rom)));
+    match loaded {
+        Ok((restarted, rom)) => {
+            info!("Loading {}", path.display());
+            Some((rom, restarted))
+        }
+        Err(e) => {
+            warn!("Couldn't load {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Run the program in a window until it's closed.
+/// Press F5 to save the state to `save_state_path`, and F9 to load it again.
+/// Press F3 to start the program over, or drop another ROM on the window to
+/// start that instead.
+/// Unknown instructions are skipped if `ignore_unknown` is set, and otherwise
+/// the user is asked what to do.
+#[cfg(feature = "window")]
+fn run_in_window<'a>(
+    state: &'a mut State,
+    options: &RunOptions,
+    mut debugger: Option<&mut Debugger>,
+    input: &mut Input,
+) -> Result<&'a mut State, Chip8Error> {
+    let mut display = Display::new(
+        options.window_backend,
+        state.buffer.true_width(),
+        state.buffer.true_height(),
+        options.resizable,
+        options.fullscreen,
+    )?;
+    display.set_reduce_flashing(options.reduce_flashing);
+    display.set_idle_pause(options.idle_pause);
+    display.set_keymap(options.keymap);
+    display.set_shader(options.shader);
+    display.set_phosphor(options.phosphor);
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut watchdog = Watchdog::new(options.progress_every);
+    let mut rewind = Rewind::new(rewind::HISTORY_FRAMES);
+    // The frame from the history that's shown while scrubbing, and how many
+    // frames back it is
+    let mut scrubbed: Option<(usize, State)> = None;
+    let save_state_path = &options.save_state_path;
+    // Addresses of unknown instructions that the user said are really data,
+    // so we skip them without asking again
+    let mut data_addresses = HashSet::new();
+    // Whether the program has stopped for good (see `State::is_halted`)
+    let mut halted = false;
+    // The program that F3 starts over, which dropping a ROM on the window
+    // replaces
+    let mut program = state.program_area().bytes.to_vec();
+    let mut log = StepLog::new(options)?;
+    if options.verbosely {
+        print_memory_regions(state);
+    }
+
+    while display.is_running() {
+        if watchdog.out_of_cycles(options.max_cycles) {
+            break;
+        }
+        // Frames (and everything that happens between them, like pausing)
+        // start 60 times a second, whatever the monitor's refresh rate
+        if display.is_paused() || state.between_frames() {
+            display.wait_for_frame();
+        }
+        // The window only reads the keyboard when it draws, at the end of a
+        // frame (or all the time while paused), so only look for hotkeys then
+        if display.is_paused() {
+            match display.scrub(rewind.len()) {
+                0 => {
+                    scrubbed = None;
+                    display.show(state);
+                }
+                // Looking back through the history doesn't change anything
+                // until the program carries on from there
+                frames_back => {
+                    if !matches!(&scrubbed, Some((back, _)) if *back == frames_back) {
+                        scrubbed = rewind.frame(frames_back)?.map(|then| (frames_back, then));
+                    }
+                    if let Some((_, then)) = &scrubbed {
+                        display.show_history(then, frames_back, rewind.len());
+                    }
+                }
+            }
+        }
+        if display.is_paused() || state.between_frames() {
+            display.update_speed();
+            if display.was_pressed(Key::F5) {
+                fs::write(save_state_path, State::serialize(state)?)?;
+                info!("Saved state to {}", save_state_path.display());
+            }
+            if display.was_pressed(Key::F2) {
+                save_screenshot(state, &options.screenshot_path)?;
+            }
+            if display.was_pressed(Key::F9) {
+                // Without a save (or with a broken one), keep playing
+                let loaded = fs::read(save_state_path)
+                    .map_err(Chip8Error::from)
+                    .and_then(|bytes| State::deserialize(&bytes));
+                match loaded {
+                    Ok(loaded) => {
+                        state.restore(loaded);
+                        info!("Loaded state from {}", save_state_path.display());
+                    }
+                    Err(e) => {
+                        let message = format!("Couldn't load {}: {}", save_state_path.display(), e);
+                        warn!("{}", message);
+                        display.set_message(Some(&message));
+                    }
+                }
+            }
+            let dropped = display
+                .dropped_file()
+                .and_then(|path| load_dropped_rom(&path, state));
+            if dropped.is_some() || display.was_pressed(Key::F3) {
+                *state = match dropped {
+                    Some((rom, restarted)) => {
+                        program = rom;
+                        data_addresses.clear();
+                        restarted
+                    }
+                    None => state.restarted(&program)?,
+                };
+                // The history is of the program before the reset, so it can't
+                // be rewound into
+                rewind = Rewind::new(rewind::HISTORY_FRAMES);
+                scrubbed = None;
+                display.stop_scrubbing();
+                display.show(state);
+                info!("Reset the program");
+            }
+            if display.is_rewinding() {
+                // Go back a frame at a time, for as long as the key is held
+                scrubbed = None;
+                display.stop_scrubbing();
+                if let Some(previous) = rewind.rewind()? {
+                    state.restore(previous);
+                }
+                display.show(state);
+                continue;
+            }
+        }
+        if !display.is_paused() || display.step_requested() {
+            if let Some((frames_back, _)) = scrubbed.take() {
+                // Carry on from the frame that was scrubbed to, forgetting the
+                // ones after it
+                if let Some(then) = rewind.go_back(frames_back)? {
+                    state.restore(then);
+                }
+                display.stop_scrubbing();
+            }
+        }
+        if display.is_paused() && !display.step_requested() {
+            continue;
+        }
+        // Once the program halts, running it any more would only burn the
+        // CPU, so keep showing the screen (and handling hotkeys, like F9)
+        // instead. The debugger gets to step through it anyway.
+        let halted_now = debugger.is_none() && state.between_frames() && state.is_halted();
+        if halted_now != halted {
+            halted = halted_now;
+            if halted {
+                let message = format!("Program halted at 0x{:03X}", state.pc);
+                info!("{}", message);
+                display.set_message(Some(&message));
+            } else {
+                display.set_message(None);
+            }
+        }
+        if halted {
+            display.draw(&state.buffer);
+            continue;
+        }
+        if state.has_exited() {
+            info!("Program exited at 0x{:03X}", state.pc);
+            break;
+        }
+        if let Some(debugger) = debugger.as_mut() {
+            if !debugger.before_instruction(state)? {
+                break;
+            }
+        }
+        if state.has_run_off_the_end() {
+            break;
+        }
+        let registers_before = state.registers_snapshot();
+        let stepped = state.step_with(&mut rng, options.verbosely, |event, state| {
+            event.send_to(&mut watchdog, state);
+            event.send_to(&mut display, state);
+            event.send_to(input, state);
+            event.send_to(&mut rewind, state);
+            if let Some(cost) = log.frame_cost.as_mut() {
+                event.send_to(cost, state);
+            }
+        });
+        let outcome = match stepped {
+            Ok(outcome) => outcome,
+            // The program counter has already moved past it, so going on
+            // skips it
+            Err(Chip8Error::UnknownInstruction {
+                pc: address,
+                opcode,
+            }) => {
+                display.decoded(&UNKNOWN(opcode));
+                if options.ignore_unknown {
+                    warn!(
+                        "Skipping unknown instruction {:04X} at {:03X}",
+                        opcode, address
+                    );
+                } else if !data_addresses.contains(&address) {
+                    match ask_about_unknown(&mut display, state, address, opcode) {
+                        UnknownChoice::Skip => {}
+                        UnknownChoice::TreatAsData => {
+                            data_addresses.insert(address);
+                        }
+                        UnknownChoice::Abort => {
+                            return Err(Chip8Error::UnknownInstruction {
+                                pc: address,
+                                opcode,
+                            })
+                        }
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Crashed:\n{}", state);
+                return Err(e);
+            }
+        };
+        display.decoded(&outcome.instruction);
+        if let Some(debugger) = debugger.as_mut() {
+            debugger.after_instruction(&outcome);
+        }
+        log.record(state, &outcome, &registers_before, &mut watchdog)?;
+    }
+    Ok(state)
+}
+
+/// Run the program without a window. Nothing is drawn and there are no hotkeys
+/// (so no save states).
+/// Besides `max_cycles`, this stops when the program jumps to the instruction
+/// it's already at, which is how most programs end: there's nothing else left
+/// to happen. It also stops when the program waits for a key, since there's no
+/// keyboard to press one on, unless a replay still has keys to press.
+/// Unknown instructions are skipped if `ignore_unknown` is set, and otherwise
+/// they stop the program.
+fn run_headless<'a>(
+    state: &'a mut State,
+    options: &RunOptions,
+    mut debugger: Option<&mut Debugger>,
+    input: &mut Input,
+) -> Result<&'a mut State, Chip8Error> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut watchdog = Watchdog::new(options.progress_every);
+    let mut screenshot_at_cycle = options.screenshot_at_cycle;
+    let mut log = StepLog::new(options)?;
+    if options.verbosely {
+        print_memory_regions(state);
+    }
+
+    loop {
+        if screenshot_at_cycle == Some(watchdog.instructions()) {
+            save_screenshot(state, &options.screenshot_path)?;
+            screenshot_at_cycle = None;
+        }
+        if watchdog.out_of_cycles(options.max_cycles) {
+            break;
+        }
+        if let Some(debugger) = debugger.as_mut() {
+            if !debugger.before_instruction(state)? {
+                break;
+            }
+        }
+        if state.is_halted() {
+            info!(
+                "Program halted at 0x{:03X}, which jumps to itself",
+                state.pc
+            );
+            break;
+        }
+        if state.has_exited() {
+            info!("Program exited at 0x{:03X}", state.pc);
+            break;
+        }
+        if state.has_run_off_the_end() {
+            break;
+        }
+        if let Microstate::WaitingForKey { .. } = state.microstate {
+            // There's no keyboard, so no key is ever coming, unless a replay
+            // pressed one or still has one to press
+            if state.pressed_key().is_none() && !input.is_replaying() {
+                info!(
+                    "Stopped at {:03X}, which waits for a key",
+                    state.current_instruction_address()
+                );
+                break;
+            }
+        }
+        let registers_before = state.registers_snapshot();
+        let stepped = state.step_with(&mut rng, options.verbosely, |event, state| {
+            event.send_to(&mut watchdog, state);
+            event.send_to(input, state);
+            if let Some(cost) = log.frame_cost.as_mut() {
+                event.send_to(cost, state);
+            }
+        });
+        match stepped {
+            Ok(outcome) => {
+                if let Some(debugger) = debugger.as_mut() {
+                    debugger.after_instruction(&outcome);
+                }
+                log.record(state, &outcome, &registers_before, &mut watchdog)?;
+            }
+            Err(Chip8Error::UnknownInstruction { pc, opcode }) if options.ignore_unknown => {
+                warn!("Skipping unknown instruction {:04X} at {:03X}", opcode, pc);
+            }
+            Err(e) => {
+                error!("Crashed:\n{}", state);
+                return Err(e);
+            }
+        }
+    }
+    Ok(state)
+}
+
+/// What the run loops write down about each step, besides running it.
+struct StepLog {
+    /// Explaining each instruction includes what it would cost on a VIP
+    frame_cost: Option<FrameCost>,
+    trace_file: Option<TraceFile<BufWriter<File>>>,
+    state_dump: Option<StateDump<Stdout>>,
+    verbosely: bool,
+}
+
+impl StepLog {
+    /// Create the file for `--trace-file`, if there is one, and get ready for
+    /// the rest of what `options` asks for.
+    fn new(options: &RunOptions) -> Result<Self, Chip8Error> {
+        let trace_file = match &options.trace_file {
+            Some(path) => Some(TraceFile::new(BufWriter::new(File::create(path)?))),
+            None => None,
+        };
+        Ok(Self {
+            frame_cost: if options.verbosely {
+                Some(FrameCost::default())
+            } else {
+                None
+            },
+            trace_file,
+            state_dump: options
+                .dump_state_every
+                .map(|every| StateDump::new(io::stdout(), every)),
+            verbosely: options.verbosely,
+        })
+    }
+
+    /// Count a step that a run loop took, and write down what it did: its
+    /// VIP cost, the trace file, the state dump, and (when explaining every
+    /// instruction) any code it wrote over. An instruction that's waiting
+    /// only counts towards the watchdog's instructions, since it hasn't run
+    /// yet.
+    fn record(
+        &mut self,
+        state: &State,
+        outcome: &StepOutcome,
+        registers_before: &[u8],
+        watchdog: &mut Watchdog,
+    ) -> Result<(), Chip8Error> {
+        watchdog.instruction_ran();
+        if outcome.waited {
+            return Ok(());
+        }
+        if let Some(cost) = self.frame_cost.as_mut() {
+            cost.add(&outcome.instruction);
+        }
+        if let Some(trace_file) = self.trace_file.as_mut() {
+            trace_file.record(
+                outcome.address,
+                &outcome.instruction,
+                registers_before,
+                state,
+            )?;
+        }
+        if let Some(state_dump) = self.state_dump.as_mut() {
+            state_dump.step(state)?;
+        }
+        if self.verbosely {
+            print_overwritten_code(state, watchdog.instructions());
+        }
+        trace!("{}", state.buffer.pretty_print_physical());
+        Ok(())
+    }
+}
+
+/// For `trace`, say which instructions that already ran the last one wrote
+/// over, `cycle` instructions into the run, since the program will run
+/// something else there next time.
+fn print_overwritten_code(state: &State, cycle: u64) {
+    for address in state.overwritten_code() {
+        println!(
+            "\tThe instruction at {:03X} was modified at cycle {}",
+            address, cycle
+        );
+    }
+}
+
+/// Save the screen to `path` as a PBM image.
+fn save_screenshot(state: &State, path: &Path) -> Result<(), Chip8Error> {
+    fs::write(path, state.buffer.to_pbm())?;
+    info!("Saved screenshot to {}", path.display());
+    Ok(())
+}
+
+/// Print where the font, the program, and the free RAM are in memory.
+fn print_memory_regions(state: &State) {
+    for (name, region) in &[
+        ("Font area", state.font_area()),
+        ("Program", state.program_area()),
+        ("Free RAM", state.free_ram()),
+    ] {
+        println!(
+            "{}: {:03X} - {:03X} ({} bytes)",
+            name,
+            region.start,
+            region.end() - 1,
+            region.bytes.len()
+        );
+    }
+}
+
+/// What to do about an instruction we don't know how to run.
+#[cfg(feature = "window")]
+enum UnknownChoice {
+    /// Skip over its 2 bytes this time
+    Skip,
+    /// Skip over it every time, because it's data and not an instruction
+    TreatAsData,
+    /// Stop running the program
+    Abort,
+}
+
+/// Pause, show the unknown instruction and where it is, and ask what to do.
+#[cfg(feature = "window")]
+fn ask_about_unknown(
+    display: &mut Display,
+    state: &State,
+    address: u16,
+    bytes: u16,
+) -> UnknownChoice {
+    let message = format!(
+        "Unknown instruction {:04X} at {:03X} - S: skip, D: treat as data, A: abort",
+        bytes, address
+    );
+    println!("{}", message);
+    display.set_message(Some(&message));
+    let choice = match display.wait_for_key(&state.buffer, &[Key::S, Key::D, Key::A]) {
+        Some(Key::S) => UnknownChoice::Skip,
+        Some(Key::D) => UnknownChoice::TreatAsData,
+        _ => UnknownChoice::Abort,
+    };
+    display.set_message(None);
+    choice
+}
+
+// Do one thing in the interpreter (run one instruction, waiting for the next
+// frame if it has to) and return the changed state.
+// Useful for testing.
+#[cfg(test)]
+pub(crate) fn tick(state: &mut State, mut rng: impl RngCore) -> Result<&mut State, Chip8Error> {
+    state.step(&mut rng)?;
+    while let Microstate::WaitingForVBlank { .. } = state.microstate {
+        state.step(&mut rng)?;
+    }
+    Ok(state)
+}
+
+/// A snapshot (see `State::serialize`) of a machine whose stack pointer is
+/// past the top of its stack, which only a corrupt file could have.
+#[cfg(test)]
+pub(crate) fn corrupt_snapshot() -> Vec<u8> {
+    let mut state = State::with_program(&[]).unwrap();
+    state.sp = 17;
+    state.serialize().unwrap()
+}
+
+/// Logical pixels as `(x, y), (x, y), ...`, for explaining collisions.
+fn pretty_pixels(pixels: &[(usize, usize)]) -> String {
+    pixels
+        .iter()
+        .map(|(x, y)| format!("({}, {})", x, y))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Execute a single instruction and return the changed `State`.
+fn execute<'a>(
+    state: &'a mut State,
+    instruction: &Instruction,
+    mut rng: impl RngCore,
+    verbosely: bool,
+) -> Result<&'a mut State, Chip8Error> {
+    if verbosely {
+        // Subtract the size to get the address of this instruction, because
+        // we advance past it before running `execute`
+        let cost = match timing::vip_cycles(instruction) {
+            Some(cycles) => format!(" (~{} VIP cycles)", cycles),
+            None => String::new(),
+        };
+        println!(
+            "[{:03X}], {}{}",
+            state.pc.wrapping_sub(instruction.size() as u16),
+            instruction,
+            cost
+        );
+    }
+    state.collisions.clear();
+    state.overwritten.clear();
+    match instruction {
+        _ if instruction.is_xo_chip() && state.variant != Variant::XoChip => {
+            return Err(Chip8Error::NeedsXoChip {
+                pc: state.pc.wrapping_sub(instruction.size() as u16),
+                instruction: instruction.to_string(),
+            });
+        }
+        SYS(_) => {
+            if verbosely {
+                println!("\tIgnoring");
+            }
+        }
+        CLS() => {
+            state.buffer.clear(state.planes);
+            if verbosely {
+                println!("\tCleared the screen");
+            }
+        }
+        EXIT() => {
+            state.microstate = Microstate::Exited;
+            if verbosely {
+                println!("\tExited");
+            }
+        }
+        RET() => {
+            let old_pc = state.pc;
+            state.pc = state.pop_off_stack()?;
+            if verbosely {
+                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
+            }
+        }
+        JP(address) => {
+            let old_pc = state.pc;
+            state.set_pc(address.value());
+            if verbosely {
+                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
+            }
+        }
+        CALL(address) => {
+            let old_pc = state.pc;
+            state.push_onto_stack(state.pc)?;
+            if verbosely {
+                println!("\tPushed pc ({:04X}) onto stack", state.pc);
+            }
+            state.set_pc(address.value());
+            if verbosely {
+                println!("\tChanged pc from {:04X} -> {:04X}", old_pc, state.pc);
+            }
+        }
+        SEByte(register, byte) => {
+            let register_value = state.get_register(*register);
+            if register_value == *byte {
+                state.skip_next_instruction()?;
+                if verbosely {
+                    println!("\tSkipping ahead, V{:X} == {:02X}", register.value(), byte);
+                }
+            } else if verbosely {
+                println!(
+                    "\tNot skipping, V{:X} is {:02X} (would skip if it were {:02X})",
+                    register.value(),
+                    register_value,
+                    byte
+                );
+            }
+        }
+        SNEByte(register, byte) => {
+            let register_value = state.get_register(*register);
+            if register_value != *byte {
+                state.skip_next_instruction()?;
+                if verbosely {
+                    println!("\tSkipping ahead, V{:X} != {:02X}", register.value(), byte);
+                }
+            } else if verbosely {
+                println!(
+                    "\tNot skipping, V{:X} is {:02X} (would skip if it were not {:02X})",
+                    register.value(),
+                    register_value,
+                    byte
+                );
+            }
         }
         SERegister(register_x, register_y) => {
             let register_x_value = state.get_register(*register_x);
             let register_y_value = state.get_register(*register_y);
             if register_x_value == register_y_value {
-                state.pc += 2;
+                state.skip_next_instruction()?;
                 if verbosely {
                     println!(
                         "\tSkipping ahead, V{:X} == V{:X}",
-                        register_x.0, register_y.0
+                        register_x.value(),
+                        register_y.value()
                     );
                 }
             } else if verbosely {
                 println!(
                     "\tNot skipping, V{:X} is {:02X} (would skip if it were {:02X})",
-                    register_x.0, register_x_value, register_y_value
+                    register_x.value(),
+                    register_x_value,
+                    register_y_value
                 );
             }
         }
@@ -223,24 +1975,26 @@ fn execute<'a>(
             let register_x_value = state.get_register(*register_x);
             let register_y_value = state.get_register(*register_y);
             if register_x_value != register_y_value {
-                state.pc += 2;
+                state.skip_next_instruction()?;
                 if verbosely {
                     println!(
                         "\tSkipping ahead, V{:X} != V{:X}",
-                        register_x.0, register_y.0
+                        register_x.value(),
+                        register_y.value()
                     );
                 }
             } else if verbosely {
                 println!(
                     "\tNot skipping, V{:X} is {:02X} (would skip if it were any other value)",
-                    register_x.0, register_x_value
+                    register_x.value(),
+                    register_x_value
                 );
             }
         }
         LDByte(register, value) => {
             state.set_register(*register, *value);
             if verbosely {
-                println!("\tSet register V{:X} to {:02X}", register.0, value);
+                println!("\tSet register V{:X} to {:02X}", register.value(), value);
             }
         }
         ADDByte(register, addend) => {
@@ -250,7 +2004,39 @@ fn execute<'a>(
             if verbosely {
                 println!(
                     "\tChanged register V{:X} from {:02X} -> {:02X}",
-                    register.0, old_value, new_value
+                    register.value(),
+                    old_value,
+                    new_value
+                );
+            }
+        }
+        LDRegister(register_x, register_y) => {
+            let value = state.get_register(*register_y);
+            state.set_register(*register_x, value);
+            if verbosely {
+                println!(
+                    "\tSet register V{:X} to {:02X} (from V{:X})",
+                    register_x.value(),
+                    value,
+                    register_y.value()
+                );
+            }
+        }
+        OR(register_x, register_y) | AND(register_x, register_y) | XOR(register_x, register_y) => {
+            let value_x = state.get_register(*register_x);
+            let value_y = state.get_register(*register_y);
+            let result = match instruction {
+                OR(..) => value_x | value_y,
+                AND(..) => value_x & value_y,
+                _ => value_x ^ value_y,
+            };
+            state.set_register(*register_x, result);
+            if verbosely {
+                println!(
+                    "\tChanged register V{:X} from {:02X} -> {:02X}",
+                    register_x.value(),
+                    value_x,
+                    result
                 );
             }
         }
@@ -258,27 +2044,93 @@ fn execute<'a>(
             let value_x = state.get_register(*register_x);
             let value_y = state.get_register(*register_y);
             let (result, did_overflow) = value_x.overflowing_add(value_y);
-            if did_overflow {
-                state.set_register(0xF, 1);
+            // VF is set last, so that it's the flag even when it's also Vx
+            state.set_register(*register_x, result);
+            state.set_register(Register::VF, u8::from(did_overflow));
+            if verbosely {
+                println!(
+                    "\tChanged register V{:X} from {:02X} -> {:02X} (VF = {})",
+                    register_x.value(),
+                    value_x,
+                    result,
+                    u8::from(did_overflow)
+                );
             }
+        }
+        SUB(register_x, register_y) | SUBN(register_x, register_y) => {
+            let value_x = state.get_register(*register_x);
+            let value_y = state.get_register(*register_y);
+            let (result, did_borrow) = match instruction {
+                SUB(..) => value_x.overflowing_sub(value_y),
+                _ => value_y.overflowing_sub(value_x),
+            };
             state.set_register(*register_x, result);
+            state.set_register(Register::VF, u8::from(!did_borrow));
             if verbosely {
                 println!(
                     "\tChanged register V{:X} from {:02X} -> {:02X} (VF = {})",
-                    register_x.0,
+                    register_x.value(),
                     value_x,
                     result,
-                    if did_overflow { 1 } else { 0 }
+                    u8::from(!did_borrow)
+                );
+            }
+        }
+        SHR(register_x, register_y) | SHL(register_x, register_y) => {
+            let source = if state.quirks.shift_uses_vy {
+                *register_y
+            } else {
+                *register_x
+            };
+            let value = state.get_register(source);
+            let (result, shifted_out) = match instruction {
+                SHR(..) => (value >> 1, value & 0x1),
+                _ => (value << 1, value >> 7),
+            };
+            state.set_register(*register_x, result);
+            state.set_register(Register::VF, shifted_out);
+            if verbosely {
+                println!(
+                    "\tChanged register V{:X} to {:02X} (shifted V{:X} = {:02X}, VF = {})",
+                    register_x.value(),
+                    result,
+                    source.value(),
+                    value,
+                    shifted_out
                 );
             }
         }
         LDI(address) => {
-            let value = (*address).into();
+            let value = address.value();
             state.i = value;
             if verbosely {
                 println!("\tSet register I to {:04X}", value);
             }
         }
+        JPOffset(address) => {
+            let offset_register = if state.quirks.jump_uses_vx {
+                Register::from_nibble((address.value() >> 8) as u8)
+            } else {
+                Register::from_nibble(0)
+            };
+            let offset = u16::from(state.get_register(offset_register));
+            let target = address
+                .checked_add(offset)
+                .ok_or(Chip8Error::MemoryOutOfBounds {
+                    address: (address.value() + offset) as usize,
+                })?;
+            let old_pc = state.pc;
+            state.set_pc(target.value());
+            if verbosely {
+                println!(
+                    "\tChanged pc from {:04X} -> {:04X} ({} + V{:X})",
+                    old_pc,
+                    state.pc,
+                    address,
+                    offset_register.value()
+                );
+            }
+        }
         RND(register, byte) => {
             let random_value: u8 = rng.gen();
             let new_value = random_value & byte;
@@ -286,135 +2138,1032 @@ fn execute<'a>(
             if verbosely {
                 println!(
                     "\tSet register V{:X} to {:X} (= {:X} & {:X})",
-                    register.0, new_value, random_value, byte
+                    register.value(),
+                    new_value,
+                    random_value,
+                    byte
+                );
+            }
+        }
+        LDKey(register) => match state.pressed_key() {
+            Some(key) => {
+                if verbosely {
+                    println!(
+                        "\tKey {:X} was pressed, setting V{:X}",
+                        key,
+                        register.value()
+                    );
+                }
+                state.set_register(*register, key);
+                state.microstate = Microstate::Running;
+            }
+            None => {
+                state.microstate = Microstate::WaitingForKey { dest: *register };
+            }
+        },
+        SKP(register) | SKNP(register) => {
+            let key = state.get_register(*register) & 0xF;
+            let pressed = state.keypad[key as usize];
+            let held = if pressed { "pressed" } else { "not pressed" };
+            if pressed == matches!(instruction, SKP(_)) {
+                state.skip_next_instruction()?;
+                if verbosely {
+                    println!("\tSkipping ahead, key {:X} is {}", key, held);
+                }
+            } else if verbosely {
+                println!("\tNot skipping, key {:X} is {}", key, held);
+            }
+        }
+        LDFromDT(register) => {
+            state.set_register(*register, state.delay_timer);
+            if verbosely {
+                println!(
+                    "\tSet register V{:X} to the delay timer ({:02X})",
+                    register.value(),
+                    state.delay_timer
                 );
             }
         }
+        LDDT(register) => {
+            state.delay_timer = state.get_register(*register);
+            if verbosely {
+                println!("\tSet the delay timer to {:02X}", state.delay_timer);
+            }
+        }
+        LDST(register) => {
+            state.sound_timer = state.get_register(*register);
+            if verbosely {
+                println!("\tSet the sound timer to {:02X}", state.sound_timer);
+            }
+        }
+        LDF(register) => {
+            let digit = state.get_register(*register) & 0xF;
+            let address = font::FONT_ADDRESS + (digit as usize) * font::FONT_CHARACTER_SIZE;
+            state.i = address as u16;
+            if verbosely {
+                println!("\tSet I to {:04X} (font sprite for {:X})", state.i, digit);
+            }
+        }
+        LDB(register) => {
+            let value = state.get_register(*register);
+            state.write_mem(
+                state.i as usize,
+                &[value / 100, value / 10 % 10, value % 10],
+            )?;
+            if verbosely {
+                println!("\tStored {} in memory at {:04X}", value, state.i);
+            }
+        }
         DRW(register_x, register_y, n) => {
             let x = state.get_register(*register_x);
             let y = state.get_register(*register_y);
-            let slice_start = state.i as usize;
-            let slice_end = slice_start + (*n as usize);
-            let sprite = &state.memory[slice_start..slice_end];
-            let flipped_from_off_to_on =
-                state.buffer.draw_sprite_at(x as usize, y as usize, sprite);
+            // SCHIP: DRW Vx, Vy, 0 draws a 16x16 sprite, which is 32 bytes
+            let sprite_size = if *n == 0 { 32 } else { *n as usize };
+            // XO-CHIP: when drawing to both planes, the sprite for plane 2
+            // comes right after the sprite for plane 1
+            let planes = [0b01, 0b10]
+                .iter()
+                .filter(|p| state.planes & *p != 0)
+                .collect::<Vec<_>>();
+            let clip = state.quirks.clip_sprites;
+            let sprites = state.read_mem(state.i as usize, sprite_size * planes.len())?;
+            let mut collisions = vec![];
+            for (plane, sprite) in planes.into_iter().zip(sprites.chunks(sprite_size)) {
+                let turned_off = if *n == 0 {
+                    state
+                        .buffer
+                        .draw_wide_sprite_at(x as usize, y as usize, sprite, *plane, clip)
+                } else {
+                    state
+                        .buffer
+                        .draw_sprite_at(x as usize, y as usize, sprite, *plane, clip)
+                };
+                // A pixel where both planes collided only counts once
+                for pixel in turned_off {
+                    if !collisions.contains(&pixel) {
+                        collisions.push(pixel);
+                    }
+                }
+            }
             if verbosely || log_enabled!(Debug) {
-                let pretty_sprite = sprite
+                let pretty_sprite = sprites
                     .iter()
                     .map(|byte| format!("\t{:08b}", byte))
                     .collect::<Vec<_>>()
                     .join("\n");
+                let collided = if collisions.is_empty() {
+                    String::from("VF set to 0")
+                } else {
+                    format!("VF set to 1, collided at {}", pretty_pixels(&collisions))
+                };
                 if verbosely {
                     println!(
-                        "\tDrawing at ({}, {}) with sprite data (VF set to {}):\n{}",
-                        x,
-                        y,
-                        if flipped_from_off_to_on { 1 } else { 0 },
-                        pretty_sprite,
+                        "\tDrawing at ({}, {}) with sprite data ({}):\n{}",
+                        x, y, collided, pretty_sprite,
                     );
                 } else if log_enabled!(Debug) {
                     debug!(
-                        "\tDrawing at ({}, {}) with sprite data (VF set to {}):\n{}",
-                        x,
-                        y,
-                        if flipped_from_off_to_on { 1 } else { 0 },
-                        pretty_sprite,
+                        "\tDrawing at ({}, {}) with sprite data ({}):\n{}",
+                        x, y, collided, pretty_sprite,
                     );
                 }
             }
-            if flipped_from_off_to_on {
-                state.set_register(0xF, 1);
+            if collisions.is_empty() {
+                state.set_register(Register::VF, 0);
             } else {
-                state.set_register(0xF, 0);
+                state.set_register(Register::VF, 1);
+            }
+            state.collisions = collisions;
+        }
+        ADDI(register) => {
+            let old_value = state.i;
+            state.increase_i(register);
+            let new_value = state.i;
+            if verbosely {
+                println!("\tChanged I from {:02X} -> {:02X}", old_value, new_value);
+            }
+        }
+        LDIntoMemory(register) => {
+            let start = state.i as usize;
+            let values = (0..=register.value())
+                .map(|x| state.get_register(Register::from_nibble(x)))
+                .collect::<Vec<_>>();
+            state.write_mem(start, &values)?;
+            if state.quirks.load_store_increments_i {
+                state.i = state.i.wrapping_add(u16::from(register.value()) + 1);
+            }
+            if verbosely {
+                println!(
+                    "\tStored V0 - V{:X} in memory at {:04X} (I is now {:04X})",
+                    register.value(),
+                    start,
+                    state.i
+                );
+            }
+        }
+        LDFromMemory(register) => {
+            let start = state.i as usize;
+            let values = state.read_mem(start, register.value() as usize + 1)?;
+            for (x, value) in values.into_iter().enumerate() {
+                state.set_register(Register::from_nibble(x as u8), value);
+            }
+            if state.quirks.load_store_increments_i {
+                state.i = state.i.wrapping_add(u16::from(register.value()) + 1);
             }
+            if verbosely {
+                println!(
+                    "\tLoaded V0 - V{:X} from memory at {:04X} (I is now {:04X})",
+                    register.value(),
+                    start,
+                    state.i
+                );
+            }
+        }
+        SCD(n) => {
+            state.buffer.scroll_down(*n as usize);
+            if verbosely {
+                println!("\tScrolled down {} pixels", n);
+            }
+        }
+        SCR() => {
+            state.buffer.scroll_right(4);
+            if verbosely {
+                println!("\tScrolled right 4 pixels");
+            }
+        }
+        SCL() => {
+            state.buffer.scroll_left(4);
+            if verbosely {
+                println!("\tScrolled left 4 pixels");
+            }
+        }
+        LOW() => {
+            state.high_resolution = false;
+            state
+                .buffer
+                .set_logical_size(display::CHIP8_WIDTH, display::CHIP8_HEIGHT)?;
+            if verbosely {
+                println!("\tSwitched to low resolution (64x32)");
+            }
+        }
+        HIGH() => {
+            state.high_resolution = true;
+            state
+                .buffer
+                .set_logical_size(display::SCHIP_WIDTH, display::SCHIP_HEIGHT)?;
+            if verbosely {
+                println!("\tSwitched to high resolution (128x64)");
+            }
+        }
+        LDHF(register) => {
+            let digit = state.get_register(*register) & 0xF;
+            let address =
+                font::LARGE_FONT_ADDRESS + (digit as usize) * font::LARGE_FONT_CHARACTER_SIZE;
+            state.i = address as u16;
+            if verbosely {
+                println!(
+                    "\tSet I to {:04X} (large font sprite for {:X})",
+                    state.i, digit
+                );
+            }
+        }
+        LDIntoFlags(register) => {
+            for x in 0..=register.value() {
+                state.flags[x as usize] = state.get_register(Register::from_nibble(x));
+            }
+            state
+                .persistence
+                .store(&state.rom, FLAGS_KEY, &state.flags)?;
+            if verbosely {
+                println!(
+                    "\tStored V0 - V{:X} in the RPL user flags",
+                    register.value()
+                );
+            }
+        }
+        LDFromFlags(register) => {
+            for x in 0..=register.value() {
+                state.set_register(Register::from_nibble(x), state.flags[x as usize]);
+            }
+            if verbosely {
+                println!(
+                    "\tLoaded V0 - V{:X} from the RPL user flags",
+                    register.value()
+                );
+            }
+        }
+        SAVE(register_x, register_y) => {
+            let start = state.i as usize;
+            let values = State::register_range(*register_x, *register_y)
+                .into_iter()
+                .map(|register| state.get_register(register))
+                .collect::<Vec<_>>();
+            state.write_mem(start, &values)?;
+            if verbosely {
+                println!(
+                    "\tSaved V{:X} - V{:X} to memory at {:04X}",
+                    register_x.value(),
+                    register_y.value(),
+                    start
+                );
+            }
+        }
+        LOAD(register_x, register_y) => {
+            let start = state.i as usize;
+            let registers = State::register_range(*register_x, *register_y);
+            let values = state.read_mem(start, registers.len())?;
+            for (register, value) in registers.into_iter().zip(values) {
+                state.set_register(register, value);
+            }
+            if verbosely {
+                println!(
+                    "\tLoaded V{:X} - V{:X} from memory at {:04X}",
+                    register_x.value(),
+                    register_y.value(),
+                    start
+                );
+            }
+        }
+        LDILong(address) => {
+            state.i = *address;
+            if verbosely {
+                println!("\tSet register I to {:04X}", address);
+            }
+        }
+        PLANE(n) => {
+            state.planes = *n;
+            if verbosely {
+                println!("\tSelected planes {:02b}", n);
+            }
+        }
+        AUDIO() => {
+            let start = state.i as usize;
+            let pattern = state.read_mem(start, 16)?;
+            state.audio_pattern.copy_from_slice(&pattern);
+            if verbosely {
+                println!("\tLoaded audio pattern from memory at {:04X}", start);
+            }
+        }
+        PITCH(register) => {
+            state.pitch = state.get_register(*register);
+            if verbosely {
+                println!("\tSet pitch to {}", state.pitch);
+            }
+        }
+        UNKNOWN(bytes) => {
+            return Err(Chip8Error::UnknownInstruction {
+                pc: state.pc.wrapping_sub(instruction.size() as u16),
+                opcode: *bytes,
+            });
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::PROFILES;
+    use crate::display;
+    use crate::input::KeypadChange;
+    use crate::instruction::{program, Address};
+    use crate::memory::MemoryHook;
+    use crate::quirks::Profile;
+    use crate::rewind::Rewind;
+
+    // Build a program by inserting encoded instructions at the given address
+    // and address+1, with everything else filled with zeroes.
+    // Note that the program space starts at 0x200, so instructions will be
+    // inserted at 0x200 + your address. This is especially important when
+    // testing JP/RET.
+    // For example: `build_program(vec![(0x300, RET())])` creates a program
+    // with a RET instruction filling the bytes at 0x500 and 0x501.
+    // To insert raw data (like sprites), wrap it in UNKNOWN.
+    fn build_program(addresses_and_instructions: &[(usize, Instruction)]) -> Vec<u8> {
+        let mut program = vec![0; 0xFFF - 0x200];
+        addresses_and_instructions
+            .iter()
+            .for_each(|(address, instruction)| {
+                let bytes = match instruction {
+                    // Data might look like another instruction, so it isn't
+                    // encoded
+                    UNKNOWN(data) => data.to_be_bytes().to_vec(),
+                    instruction => instruction.to_bytes().unwrap(),
+                };
+                program[*address..*address + bytes.len()].copy_from_slice(&bytes);
+            });
+        program
+    }
+
+    fn run(instructions: &[Instruction]) -> State {
+        run_with_variant(Variant::Chip8, instructions)
+    }
+
+    fn run_with_variant(variant: Variant, instructions: &[Instruction]) -> State {
+        let state = build_state_with_program(instructions).with_variant(variant);
+        run_state(state, instructions)
+    }
+
+    fn run_with_quirks(quirks: Quirks, instructions: &[Instruction]) -> State {
+        let state = build_state_with_program(instructions).with_quirks(quirks);
+        run_state(state, instructions)
+    }
+
+    fn run_state(mut state: State, instructions: &[Instruction]) -> State {
+        for _ in instructions {
+            tick(&mut state, testing_rng()).unwrap();
+        }
+        state
+    }
+
+    fn build_state_with_program(instructions: &[Instruction]) -> State {
+        // Most instructions are 2 bytes, so generate 0, 2, 4, etc, but leave
+        // room for any 4-byte ones
+        let addresses = instructions.iter().scan(0, |address, instruction| {
+            let this_address = *address;
+            *address += instruction.size();
+            Some(this_address)
+        });
+        let result = addresses
+            .zip(instructions.iter().cloned())
+            .collect::<Vec<_>>();
+
+        State::with_program(&build_program(result.as_slice())).unwrap()
+    }
+
+    fn build_state_with_program_with_custom_offsets(
+        addresses_and_instructions: &[(usize, Instruction)],
+    ) -> State {
+        State::with_program(&build_program(addresses_and_instructions)).unwrap()
+    }
+
+    // A random-number generator with a pre-determined seed.
+    fn testing_rng() -> impl RngCore {
+        StdRng::seed_from_u64(0)
+    }
+
+    #[test]
+    fn memory_regions() {
+        let state = State::with_program(&[0x12, 0x34, 0x56]).unwrap();
+
+        assert_eq!(state.font_area().start, 0x000);
+        assert_eq!(state.font_area().end(), 0x200);
+        assert_eq!(
+            state.font_area().bytes[font::LARGE_FONT_ADDRESS],
+            font::LARGE_FONT[0]
+        );
+
+        assert_eq!(state.program_area().start, 0x200);
+        assert_eq!(state.program_area().bytes, &[0x12, 0x34, 0x56]);
+
+        assert_eq!(state.free_ram().start, 0x203);
+        assert_eq!(state.free_ram().end(), 0x1000);
+        assert!(state.free_ram().bytes.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn save_and_restore_state() {
+        let state = run_with_variant(Variant::XoChip, &[LDByte(r(0x1), 0x42), CALL(0x300.into())]);
+        let snapshot = state.serialize().unwrap();
+
+        assert_eq!(State::deserialize(&snapshot).unwrap(), state);
+        assert!(State::deserialize(&snapshot[..10]).is_err());
+    }
+
+    #[test]
+    fn corrupt_states_are_an_error() {
+        let corrupt = |change: fn(&mut State)| {
+            let mut state = build_state_with_program(&[CALL(0x300.into())]);
+            change(&mut state);
+            State::deserialize(&state.serialize().unwrap())
+        };
+
+        assert!(matches!(
+            corrupt(|state| state.sp = 17),
+            Err(Chip8Error::CorruptState(_))
+        ));
+        assert!(matches!(
+            corrupt(|state| state.registers.truncate(4)),
+            Err(Chip8Error::CorruptState(_))
+        ));
+        assert!(matches!(
+            corrupt(|state| state.stack.truncate(4)),
+            Err(Chip8Error::CorruptState(_))
+        ));
+        assert!(matches!(
+            corrupt(|state| state.memory.truncate(0x100)),
+            Err(Chip8Error::CorruptState(_))
+        ));
+        assert!(corrupt(|state| state.sp = 16).is_ok());
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let mut state = build_state_with_program(&[LDKey(r(0x3)), LDByte(r(0x4), 0x1)]);
+        tick(&mut state, testing_rng()).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+
+        assert_eq!(state.microstate, Microstate::WaitingForKey { dest: r(0x3) });
+        assert_eq!(state.registers[0x4], 0x0);
+
+        state.keypad[0xB] = true;
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.microstate, Microstate::Running);
+        assert_eq!(state.registers[0x3], 0xB);
+
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.registers[0x4], 0x1);
+    }
+
+    #[test]
+    fn frame_events() {
+        use FrameEvent::*;
+
+        let mut state = State::with_program(&[]).unwrap();
+        state.delay_timer = 2;
+        // Each event, and what the delay timer was when it happened
+        let mut events = vec![];
+        let mut record = |event, state: &mut State| events.push((event, state.delay_timer));
+        for _ in 0..=INSTRUCTIONS_PER_FRAME {
+            state.start_cycle(&mut record);
+        }
+        assert_eq!(
+            events,
+            vec![
+                (BeginFrame, 2),
+                (TimersTicked, 1),
+                (EndFrame, 1),
+                (BeginFrame, 1),
+                (TimersTicked, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn step_reports_what_happened() {
+        let mut state = build_state_with_program(&[
+            LDI(Address::new(font::LARGE_FONT_ADDRESS as u16).unwrap()),
+            DRW(r(0x0), r(0x0), 0x1),
+            LDST(r(0x1)),
+        ]);
+        state.registers[1] = 5;
+        let outcome = state.step(testing_rng()).unwrap();
+        assert_eq!(outcome.pc_before, 0x200);
+        assert_eq!(outcome.pc_after, 0x202);
+        assert_eq!(outcome.pixels_changed, 0);
+
+        // With the display-wait quirk, DRW waits for the next frame
+        let outcome = state.step(testing_rng()).unwrap();
+        assert!(outcome.waited);
+        assert_eq!(outcome.pixels_changed, 0);
+        let outcome = loop {
+            let outcome = state.step(testing_rng()).unwrap();
+            if !outcome.waited {
+                break outcome;
+            }
+        };
+        // The first row of the large 0 is 0x3C: four pixels
+        assert_eq!(outcome.instruction, DRW(r(0x0), r(0x0), 0x1));
+        assert_eq!(outcome.pc_after, 0x204);
+        assert_eq!(outcome.pixels_changed, 4);
+        assert!(!outcome.sound_started);
+
+        assert!(state.step(testing_rng()).unwrap().sound_started);
+    }
+
+    #[test]
+    fn step_reports_where_drw_collided() {
+        let mut state = build_state_with_program(&[
+            LDI(Address::new(font::LARGE_FONT_ADDRESS as u16).unwrap()),
+            DRW(r(0x0), r(0x0), 0x1),
+            DRW(r(0x0), r(0x0), 0x1),
+        ]);
+        let mut drawn = vec![];
+        while drawn.len() < 2 {
+            let outcome = state.step(testing_rng()).unwrap();
+            if let (DRW(..), false) = (&outcome.instruction, outcome.waited) {
+                drawn.push(outcome);
+            }
+        }
+
+        assert_eq!(drawn[0].collisions, vec![]);
+        // Drawing the same row of the large 0 (0x3C) again turns it all off
+        assert_eq!(drawn[1].collisions, vec![(2, 0), (3, 0), (4, 0), (5, 0)]);
+        assert_eq!(state.collisions(), drawn[1].collisions.as_slice());
+        assert_eq!(state.registers[0xF], 1);
+    }
+
+    #[test]
+    fn step_reports_calls_and_returns() {
+        let mut state = build_state_with_program(&[CALL(0x204.into()), JP(0x200.into()), RET()]);
+        let events = (0..3)
+            .map(|_| state.step(testing_rng()).unwrap().call)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                Some(CallEvent::Call {
+                    from: 0x200,
+                    to: 0x204
+                }),
+                Some(CallEvent::Return { to: 0x202 }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn instructions_that_the_program_overwrites_are_decoded_again() {
+        // The program runs ADD V2, 1 at 0x204 once, then writes 7105
+        // (ADD V1, 5) over it and jumps back to it
+        let mut state = build_state_with_program(&[
+            LDByte(r(0x0), 0x71),
+            LDByte(r(0x1), 0x05),
+            ADDByte(r(0x2), 1),
+            LDI(0x204.into()),
+            LDIntoMemory(r(0x1)),
+            JP(0x204.into()),
+        ]);
+        for _ in 0..6 {
+            state.step(testing_rng()).unwrap();
+        }
+        assert_eq!(state.decoded.get(0x204), None);
+
+        let outcome = state.step(testing_rng()).unwrap();
+        assert_eq!(outcome.instruction, ADDByte(r(0x1), 5));
+        assert_eq!(state.registers[0x1], 0x0A);
+        assert_eq!(state.registers[0x2], 1);
+        // Everything else is still decoded, and the cache isn't part of the
+        // machine
+        assert_eq!(state.decoded.get(0x206), Some(&LDI(0x204.into())));
+        assert_eq!(
+            State::deserialize(&state.serialize().unwrap()).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn steps_say_which_instructions_that_ran_they_overwrote() {
+        // The same program: LD [I], V1 writes over the ADD V2, 1 that already
+        // ran, but not over LD V0, 71, which it writes next to
+        let mut state = build_state_with_program(&[
+            LDByte(r(0x0), 0x71),
+            LDByte(r(0x1), 0x05),
+            ADDByte(r(0x2), 1),
+            LDI(0x204.into()),
+            LDIntoMemory(r(0x1)),
+            JP(0x204.into()),
+        ]);
+        for _ in 0..4 {
+            assert_eq!(state.step(testing_rng()).unwrap().overwritten, vec![]);
+        }
+        let outcome = state.step(testing_rng()).unwrap();
+        assert_eq!(outcome.overwritten, vec![0x204]);
+        assert_eq!(state.overwritten_code(), &[0x204]);
+
+        state.step(testing_rng()).unwrap();
+        assert!(state.overwritten_code().is_empty());
+    }
+
+    #[test]
+    fn step_fails_off_the_end_of_memory() {
+        let mut state = build_state_with_program(&[JP(0xFFF.into())]);
+        state.step(testing_rng()).unwrap();
+        assert!(matches!(
+            state.step(testing_rng()),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0xFFF })
+        ));
+    }
+
+    #[test]
+    fn step_fails_off_the_end_of_xo_chip_memory() {
+        // Zeroed memory is SYS 000, which does nothing, all the way to the end
+        let mut state = State::with_program_for(Variant::XoChip, &[]).unwrap();
+        state.pc = 0xFFFC;
+        state.step(testing_rng()).unwrap();
+        assert_eq!(state.pc, 0xFFFE);
+        assert!(matches!(
+            state.step(testing_rng()),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0x10000 })
+        ));
+
+        // Skipping the last instruction in memory goes past the end too
+        let mut state = State::with_program_for(Variant::XoChip, &[]).unwrap();
+        state.pc = 0xFFFC;
+        state.memory[0xFFFC..0xFFFE].copy_from_slice(&[0x30, 0x00]);
+        assert!(matches!(
+            state.step(testing_rng()),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0x10000 })
+        ));
+    }
+
+    #[test]
+    fn clock_hz_sets_how_many_instructions_a_frame_runs() {
+        let program = [ADDByte(r(0x0), 1), JP(0x200.into())];
+        // 700Hz is 11.67 instructions a frame, which rounds to 12
+        let mut state = build_state_with_program(&program).with_clock_hz(700);
+        for _ in 0..11 {
+            state.step(testing_rng()).unwrap();
+            assert!(!state.between_frames());
+        }
+        state.step(testing_rng()).unwrap();
+        assert!(state.between_frames());
+    }
+
+    #[test]
+    fn restarting_keeps_the_machine_but_not_the_program_state() {
+        let appearance = Appearance {
+            scale: 4,
+            foreground: 0xFFB000,
+            background: 0x000000,
+        };
+        let mut state = build_state_with_program(&[ADDByte(r(0x0), 1), JP(0x200.into())])
+            .with_variant(Variant::XoChip)
+            .with_clock_hz(700)
+            .with_appearance(&appearance);
+        state.step(testing_rng()).unwrap();
+
+        let restarted = state.restarted(&[0x60, 0x05]).unwrap();
+        assert_eq!(restarted.pc, 0x200);
+        assert_eq!(restarted.registers[0x0], 0);
+        assert_eq!(restarted.program_area().bytes, &[0x60, 0x05]);
+        assert_eq!(restarted.variant, Variant::XoChip);
+        assert_eq!(restarted.instructions_per_frame, 12);
+        assert_eq!(restarted.buffer.appearance(), appearance);
+    }
+
+    #[test]
+    fn restarting_in_high_resolution_keeps_the_low_resolution_scale() {
+        let mut state = build_state_with_program(&[HIGH()]);
+        state.step(testing_rng()).unwrap();
+        assert_eq!(state.buffer.width(), 128);
+
+        let mut restarted = state.restarted(&program(&[HIGH()])).unwrap();
+        assert_eq!(restarted.buffer.appearance(), Appearance::default());
+        assert_eq!(restarted.buffer.width(), 64);
+        restarted.step(testing_rng()).unwrap();
+        assert_eq!(restarted.buffer.width(), 128);
+        assert_eq!(
+            restarted.buffer.true_width(),
+            Framebuffer::new().true_width()
+        );
+    }
+
+    #[test]
+    fn programs_can_be_loaded_at_another_address() {
+        // LD V0, 05; JP 602
+        let mut state =
+            State::with_program_at(Variant::Chip8, 0x600, &[0x60, 0x05, 0x16, 0x02]).unwrap();
+        assert_eq!(state.pc, 0x600);
+        assert_eq!(state.program_area().start, 0x600);
+        assert_eq!(state.free_ram().start, 0x604);
+        assert_eq!(state.memory[0x200], 0);
+        state.step(testing_rng()).unwrap();
+        assert_eq!(state.registers[0x0], 5);
+
+        let restarted = state.restarted(&[0x00, 0xE0]).unwrap();
+        assert_eq!(restarted.pc, 0x600);
+        assert_eq!(restarted.program_area().bytes, &[0x00, 0xE0]);
+    }
+
+    #[test]
+    fn programs_that_dont_fit_in_memory_are_an_error() {
+        let program = vec![0; 0x1000 - 0x600 + 1];
+        match State::with_program_at(Variant::Chip8, 0x600, &program) {
+            Err(Chip8Error::ProgramTooLarge { size, max }) => {
+                assert_eq!(size, 0xA01);
+                assert_eq!(max, 0xA00);
+            }
+            _ => panic!("Expected the program to be too large"),
+        }
+        assert!(State::with_program(&program).is_ok());
+    }
+
+    #[test]
+    fn xo_chip_has_64kb_of_memory() {
+        // LD I, 2100; LD V0, [I]; and then a byte to load, past 0xFFF
+        let mut program = vec![0; 0x2000];
+        program[..6].copy_from_slice(&[0xF0, 0x00, 0x21, 0x00, 0xF0, 0x65]);
+        program[0x2100 - 0x200] = 0x42;
+        match State::with_program(&program) {
+            Err(Chip8Error::ProgramTooLarge { max, .. }) => assert_eq!(max, 0xE00),
+            _ => panic!("Expected the program to be too large for CHIP-8"),
+        }
+
+        let mut state = State::with_program_for(Variant::XoChip, &program).unwrap();
+        assert_eq!(state.memory().len(), 0x10000);
+        assert_eq!(state.free_ram().start, 0x2200);
+        tick(&mut state, testing_rng()).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.registers[0x0], 0x42);
+        // Switching to XO-CHIP later gets the memory too
+        let state = State::with_program(&[])
+            .unwrap()
+            .with_variant(Variant::XoChip);
+        assert_eq!(state.memory().len(), 0x10000);
+    }
+
+    #[test]
+    fn vip_timing_fits_instructions_into_the_vip_frame() {
+        let program = [ADDByte(r(0x0), 1), JP(0x200.into())];
+        let instructions_per_frame = |timing| {
+            let mut state = build_state_with_program(&program).with_timing(timing);
+            let mut count = 0;
+            loop {
+                state.step(testing_rng()).unwrap();
+                count += 1;
+                if state.between_frames() {
+                    return count;
+                }
+            }
+        };
+
+        assert_eq!(instructions_per_frame(Timing::Flat), INSTRUCTIONS_PER_FRAME);
+        // ADD takes 50 cycles and JP takes 52, so it takes 36 of each to use
+        // up the frame's 3668
+        assert_eq!(instructions_per_frame(Timing::Vip), 72);
+    }
+
+    #[test]
+    fn display_wait_draws_at_the_next_frame() {
+        let drw = DRW(r(0x0), r(0x0), 0x1);
+        let mut state = build_state_with_program(&[
+            LDI(Address::new(font::LARGE_FONT_ADDRESS as u16).unwrap()),
+            drw.clone(),
+        ]);
+        state.step(testing_rng()).unwrap();
+        state.step(testing_rng()).unwrap();
+
+        assert_eq!(
+            state.microstate,
+            Microstate::WaitingForVBlank { pending_drw: drw }
+        );
+        assert_eq!(state.buffer, Framebuffer::new());
+
+        // The first frame started with the first cycle, so the next one
+        // starts after `INSTRUCTIONS_PER_FRAME` cycles
+        for _ in 2..=INSTRUCTIONS_PER_FRAME {
+            state.step(testing_rng()).unwrap();
         }
-        ADDI(register) => {
-            let old_value = state.i;
-            state.increase_i(register);
-            let new_value = state.i;
-            if verbosely {
-                println!("\tChanged I from {:02X} -> {:02X}", old_value, new_value);
+        assert_eq!(state.microstate, Microstate::Running);
+        assert_ne!(state.buffer, Framebuffer::new());
+    }
+
+    #[test]
+    fn save_state_while_waiting() {
+        let mut state = build_state_with_program(&[LDKey(r(0x3))]);
+        tick(&mut state, testing_rng()).unwrap();
+        let mut restored = State::deserialize(&state.serialize().unwrap()).unwrap();
+
+        assert_eq!(restored, state);
+        restored.keypad[0x2] = true;
+        tick(&mut restored, testing_rng()).unwrap();
+        assert_eq!(restored.registers[0x3], 0x2);
+    }
+
+    #[test]
+    fn unknown_instruction_is_an_error() {
+        let mut state = build_state_with_program(&[LDByte(r(0x1), 0x2), UNKNOWN(0x5AB1)]);
+        tick(&mut state, testing_rng()).unwrap();
+
+        match tick(&mut state, testing_rng()) {
+            Err(Chip8Error::UnknownInstruction { pc, opcode }) => {
+                assert_eq!(pc, 0x202);
+                assert_eq!(opcode, 0x5AB1);
             }
+            other => panic!("Expected an unknown instruction error, got {:?}", other),
         }
-        UNKNOWN(bytes) => {
-            panic!("Unknown instruction: {:04X}", bytes);
+    }
+
+    fn headless_options(max_cycles: Option<u64>) -> RunOptions {
+        RunOptions {
+            verbosely: false,
+            save_state_path: PathBuf::new(),
+            ignore_unknown: false,
+            progress_every: 0,
+            headless: true,
+            window_backend: WindowBackend::Minifb,
+            resizable: false,
+            fullscreen: false,
+            reduce_flashing: false,
+            idle_pause: 0,
+            keymap: Keymap::default(),
+            shader: Shader::None,
+            phosphor: None,
+            seed: 0,
+            trace_file: None,
+            dump_state_every: None,
+            record: None,
+            replay: None,
+            max_cycles,
+            screenshot_path: PathBuf::new(),
+            screenshot_at_cycle: None,
         }
     }
-    Ok(state)
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::display;
+    #[test]
+    fn the_same_seed_gives_the_same_random_numbers() {
+        let program = [RND(r(0x0), 0xFF), RND(r(0x1), 0xFF), RND(r(0x2), 0xFF)];
+        let run_with_seed = |seed| {
+            let mut state = build_state_with_program(&program);
+            let options = RunOptions {
+                seed,
+                ..headless_options(Some(3))
+            };
+            super::run(&mut state, &options, None).unwrap();
+            state.registers[..3].to_vec()
+        };
 
-    // Build a program by inserting u16s (as u8s) at the given address and
-    // address+1, with everything else filled with zeroes.
-    // Note that the program space starts at 0x200, so instructions will be
-    // inserted at 0x200 + your address. This is especially important when
-    // testing JP/RET.
-    // For example: `build_program(vec![(0x300, 0x00EE)])` creates a program
-    // with a RET instruction filling the bytes at 0x500 and 0x501.
-    fn build_program(addresses_and_chunks: &[(usize, u16)]) -> Vec<u8> {
-        let mut program = vec![0; 0xFFF - 0x200];
-        addresses_and_chunks
-            .iter()
-            .copied()
-            .for_each(|(address, instruction)| {
-                let [b1, b2] = u16::to_be_bytes(instruction);
-                program[address] = b1;
-                program[address + 1] = b2;
-            });
-        program
+        assert_eq!(run_with_seed(7), run_with_seed(7));
+        assert_ne!(run_with_seed(7), run_with_seed(8));
     }
 
-    fn run(chunks: &[u16]) -> State {
-        let mut state = build_state_with_program(chunks);
-        for _ in chunks {
-            tick(&mut state, testing_rng()).unwrap();
-        }
-        state
+    #[test]
+    fn headless_run_writes_a_trace_file() {
+        let path = std::env::temp_dir().join(format!("chip8-trace-{}.jsonl", std::process::id()));
+        let mut state = build_state_with_program(&[LDByte(r(0x1), 0x5), LDI(0x300.into())]);
+        let options = RunOptions {
+            trace_file: Some(path.clone()),
+            ..headless_options(Some(2))
+        };
+        super::run(&mut state, &options, None).unwrap();
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines = trace.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"changed\":{\"V1\":[0,5]}"));
+        assert!(lines[1].contains("\"pc\":\"202\",\"opcode\":\"A300\""));
     }
 
-    fn build_state_with_program(chunks: &[u16]) -> State {
-        // Every instruction is 2 bytes, so generate 0, 2, 4, etc
-        let addresses = (0..).filter(|x| x % 2 == 0).take(chunks.len());
-        let result = addresses.zip(chunks.iter().copied()).collect::<Vec<_>>();
+    #[test]
+    fn headless_run_replays_and_records_keys() {
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}", std::process::id()));
+        let mut keypad = [false; 16];
+        keypad[0x7] = true;
+        let replay = Replay {
+            seed: 3,
+            changes: vec![KeypadChange { frame: 2, keypad }],
+        };
+        let mut state = build_state_with_program(&[LDKey(r(0x3)), JP(0x202.into())]);
+        let options = RunOptions {
+            seed: 3,
+            record: Some(path.clone()),
+            replay: Some(replay.clone()),
+            ..headless_options(None)
+        };
+        super::run(&mut state, &options, None).unwrap();
+        let recorded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
 
-        State::with_program(&build_program(result.as_slice()))
+        // It waited for the key instead of stopping
+        assert_eq!(state.registers()[0x3], 0x7);
+        assert_eq!(state.pc(), 0x202);
+        assert_eq!(recorded, replay);
     }
 
-    fn build_state_with_program_with_custom_offsets(
-        addresses_and_chunks: &[(usize, u16)],
-    ) -> State {
-        State::with_program(&build_program(addresses_and_chunks))
+    #[test]
+    fn headless_run_stops_when_jumping_to_itself() {
+        let mut state = build_state_with_program(&[LDByte(r(0x1), 0x5), JP(0x202.into())]);
+        super::run(&mut state, &headless_options(None), None).unwrap();
+
+        assert_eq!(state.pc(), 0x202);
+        assert_eq!(state.registers()[1], 0x5);
     }
 
-    // A random-number generator with a pre-determined seed.
-    fn testing_rng() -> impl RngCore {
-        use rand::SeedableRng;
-        rand::rngs::StdRng::seed_from_u64(0)
+    #[test]
+    fn headless_run_counts_the_timers_down_once_a_frame() {
+        let mut state =
+            build_state_with_program(&[LDByte(r(0x1), 60), LDDT(r(0x1)), JP(0x204.into())]);
+        let frames = 10;
+        super::run(
+            &mut state,
+            &headless_options(Some(frames * INSTRUCTIONS_PER_FRAME)),
+            None,
+        )
+        .unwrap();
+
+        // The first frame's timers counted down before LD DT, V1 ran
+        assert_eq!(state.delay_timer, 60 - (frames as u8 - 1));
+    }
+
+    #[test]
+    fn headless_run_waits_for_the_timers_before_halting() {
+        let mut state =
+            build_state_with_program(&[LDByte(r(0x1), 0x3), LDST(r(0x1)), JP(0x204.into())]);
+        super::run(&mut state, &headless_options(None), None).unwrap();
+
+        assert_eq!(state.pc(), 0x204);
+        assert_eq!(state.sound_timer(), 0);
+    }
+
+    #[test]
+    fn halted_means_nothing_can_change() {
+        let mut state = build_state_with_program(&[JP(0x200.into())]);
+        assert!(state.is_halted());
+
+        state.sound_timer = 1;
+        assert!(!state.is_halted());
+        state.sound_timer = 0;
+        state.microstate = Microstate::WaitingForKey { dest: r(0x0) };
+        assert!(!state.is_halted());
+
+        assert!(!build_state_with_program(&[JP(0x202.into())]).is_halted());
+    }
+
+    #[test]
+    fn headless_run_stops_after_max_cycles() {
+        let mut state = build_state_with_program(&[ADDByte(r(0x1), 0x1), JP(0x200.into())]);
+        super::run(&mut state, &headless_options(Some(5)), None).unwrap();
+
+        // ADD, JP, ADD, JP, ADD
+        assert_eq!(state.registers()[1], 0x3);
+    }
+
+    #[test]
+    fn summary() {
+        let state = run(&[LDByte(r(0xA), 0x42), LDI(0x345.into()), CALL(0x300.into())]);
+        let summary = state.to_string();
+        let lines = summary.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "V0=00 V1=00 V2=00 V3=00");
+        assert_eq!(lines[2], "V8=00 V9=00 VA=42 VB=00");
+        assert_eq!(lines[4], "I=0345 PC=300 SP=1 DT=00 ST=00");
+        assert_eq!(lines[5], "Stack: 206 (depth 1)");
+        assert_eq!(lines[6], ".".repeat(32));
+        assert_eq!(lines.len(), 6 + 8);
     }
 
     #[test]
     fn sys_ignored_advances_pc() {
-        let state = run(&[SYS().into()]);
+        let state = run(&[SYS(0x123.into())]);
         assert_eq!(state.pc, 0x202);
     }
 
+    #[test]
+    fn exit_stops_the_program() {
+        let mut state = build_state_with_program(&[EXIT(), LDByte(r(0x0), 0x1)]);
+        super::run(&mut state, &headless_options(Some(10)), None).unwrap();
+        assert!(state.has_exited());
+        assert_eq!(state.registers[0], 0x0);
+        // Stepping any more only waits
+        assert!(state.step(testing_rng()).unwrap().waited);
+        assert_eq!(state.registers[0], 0x0);
+    }
+
     fn r(n: u8) -> Register {
-        Register(n)
+        Register::from_nibble(n)
     }
 
     #[test]
     fn call_subroutine_and_return() {
         let program = &[
             // CALL: Increment SP, put current PC (0x200 + 2 = 0x202) on top of stack, set PC to 0x300
-            (0, CALL(0x300.into()).into()),
+            (0, CALL(0x300.into())),
             // At 0x100 (+ 0x200 = 0x300 in the total program memory), do LD 1, 20
-            (0x100, LDByte(r(0x1), 0x20).into()),
+            (0x100, LDByte(r(0x1), 0x20)),
             // Now RET(urn): Set PC to top of stack (0x202) substract 1 from SP
-            (0x102, RET().into()),
+            (0x102, RET()),
         ];
         let mut state = build_state_with_program_with_custom_offsets(program);
 
@@ -424,24 +3173,69 @@ mod test {
 
         assert_eq!(state.pc, 0x202);
         assert_eq!(state.sp, 0);
-        assert_eq!(state.get_register(0x1), 0x20);
+        assert_eq!(state.get_register(r(0x1)), 0x20);
+    }
+
+    #[test]
+    fn return_without_subroutine() {
+        let mut state = build_state_with_program(&[RET()]);
+
+        match tick(&mut state, testing_rng()) {
+            Err(Chip8Error::StackUnderflow { pc }) => assert_eq!(pc, 0x200),
+            other => panic!("Expected a stack underflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        // A 5-byte sprite at FFE would run 3 bytes past the end of memory
+        let mut state = build_state_with_program(&[LDI(0xFFE.into()), DRW(r(0x0), r(0x0), 5)]);
+        tick(&mut state, testing_rng()).unwrap();
+        match tick(&mut state, testing_rng()) {
+            Err(Chip8Error::MemoryOutOfBounds { address }) => assert_eq!(address, 0x1000),
+            other => panic!("Expected memory out of bounds, got {:?}", other),
+        }
+
+        let mut state = build_state_with_program(&[LDI(0xFFE.into()), LDIntoMemory(r(0x2))]);
+        tick(&mut state, testing_rng()).unwrap();
+        assert!(matches!(
+            tick(&mut state, testing_rng()),
+            Err(Chip8Error::MemoryOutOfBounds { .. })
+        ));
+        // Nothing was written
+        assert_eq!(state.memory[0xFFE..], [0, 0]);
+        assert_eq!(state.read_mem(0xFFE, 2).unwrap(), &[0, 0]);
+    }
+
+    #[test]
+    fn too_many_nested_subroutines() {
+        // Call itself forever
+        let mut state = build_state_with_program(&[CALL(0x200.into())]);
+        for _ in 0..16 {
+            tick(&mut state, testing_rng()).unwrap();
+        }
+
+        match tick(&mut state, testing_rng()) {
+            Err(Chip8Error::StackOverflow { pc }) => assert_eq!(pc, 0x200),
+            other => panic!("Expected a stack overflow, got {:?}", other),
+        }
     }
 
     #[test]
     fn jp_addr() {
-        let state = run(&[JP(0xBCD.into()).into()]);
+        let state = run(&[JP(0xBCD.into())]);
         assert_eq!(state.pc, 0xBCD);
     }
 
     #[test]
     fn ld_vx() {
-        let state = run(&[LDByte(r(0xD), 0x12).into()]);
-        assert_eq!(state.get_register(0xD), 0x12);
+        let state = run(&[LDByte(r(0xD), 0x12)]);
+        assert_eq!(state.get_register(r(0xD)), 0x12);
     }
 
     #[test]
     fn ld_i() {
-        let state = run(&[LDI(0x400.into()).into()]);
+        let state = run(&[LDI(0x400.into())]);
         assert_eq!(state.i, 0x400);
     }
 
@@ -449,87 +3243,87 @@ mod test {
     fn add_byte() {
         #[rustfmt::skip]
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            ADDByte(r(0xD), 0x12).into()
+            LDByte(r(0xD), 0x12),
+            ADDByte(r(0xD), 0x12)
         ]);
-        assert_eq!(state.get_register(0xD), 0x24);
+        assert_eq!(state.get_register(r(0xD)), 0x24);
     }
 
     #[test]
     fn add_byte_with_overflow() {
         #[rustfmt::skip]
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            ADDByte(r(0xD), 0xFF).into()
+            LDByte(r(0xD), 0x12),
+            ADDByte(r(0xD), 0xFF)
         ]);
         // Expect it to wrap around
-        assert_eq!(state.get_register(0xD), 0x11);
+        assert_eq!(state.get_register(r(0xD)), 0x11);
     }
 
     #[test]
     fn sne_byte() {
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            SNEByte(r(0xD), 0x00).into(),
+            LDByte(r(0xD), 0x12),
+            SNEByte(r(0xD), 0x00),
             // This should be skipped
-            LDByte(r(0x1), 0x00).into(),
+            LDByte(r(0x1), 0x00),
             // This one should run
-            LDByte(r(0x1), 0xFF).into(),
+            LDByte(r(0x1), 0xFF),
         ]);
-        assert_eq!(state.get_register(0x1), 0xFF);
+        assert_eq!(state.get_register(r(0x1)), 0xFF);
     }
 
     #[test]
     fn se_byte() {
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            SEByte(r(0xD), 0x12).into(),
+            LDByte(r(0xD), 0x12),
+            SEByte(r(0xD), 0x12),
             // This should be skipped
-            LDByte(r(0x1), 0x00).into(),
+            LDByte(r(0x1), 0x00),
             // This one should run
-            LDByte(r(0x1), 0xFF).into(),
+            LDByte(r(0x1), 0xFF),
         ]);
-        assert_eq!(state.get_register(0x1), 0xFF);
+        assert_eq!(state.get_register(r(0x1)), 0xFF);
     }
 
     #[test]
     fn se_register() {
         let state = run(&[
-            LDByte(r(0xA), 0x12).into(),
-            LDByte(r(0xB), 0x12).into(),
-            SERegister(r(0xA), r(0xB)).into(),
+            LDByte(r(0xA), 0x12),
+            LDByte(r(0xB), 0x12),
+            SERegister(r(0xA), r(0xB)),
             // This should be skipped
-            LDByte(r(0x1), 0x00).into(),
+            LDByte(r(0x1), 0x00),
             // This one should run
-            LDByte(r(0x1), 0xFF).into(),
+            LDByte(r(0x1), 0xFF),
         ]);
-        assert_eq!(state.get_register(0x1), 0xFF);
+        assert_eq!(state.get_register(r(0x1)), 0xFF);
     }
 
     #[test]
     fn sne_register() {
         let state = run(&[
-            LDByte(r(0xA), 0x12).into(),
-            LDByte(r(0xB), 0x12).into(),
-            SNERegister(r(0xA), r(0xC)).into(),
+            LDByte(r(0xA), 0x12),
+            LDByte(r(0xB), 0x12),
+            SNERegister(r(0xA), r(0xC)),
             // This should be skipped
-            LDByte(r(0x1), 0x12).into(),
+            LDByte(r(0x1), 0x12),
             // This should run
-            LDByte(r(0x1), 0xFF).into(),
+            LDByte(r(0x1), 0xFF),
         ]);
-        assert_eq!(state.get_register(0x1), 0xFF);
+        assert_eq!(state.get_register(r(0x1)), 0xFF);
     }
 
     #[test]
     fn rnd() {
         #[rustfmt::skip]
         let state = run(&[
-            LDByte(r(0x1), 0x00).into(),
-            RND(r(0x1), 0xFF).into(),
+            LDByte(r(0x1), 0x00),
+            RND(r(0x1), 0xFF),
         ]);
         // The testing RNG will always generate 0xB2 as its first u8. 0xB2 &
         // 0xFF == 0xB2
-        assert_eq!(state.get_register(0x1), 0xB2);
+        assert_eq!(state.get_register(r(0x1)), 0xB2);
     }
 
     #[test]
@@ -544,23 +3338,23 @@ mod test {
         #[rustfmt::skip]
         let state = run(&[
             // Jump past the sprites
-            JP((0x200 + 4).into()).into(),
+            JP((0x200 + 4).into()),
             // Sprite1 is at 0x200 + 2 and sprite2 is at 0x200 + 3
-            sprites_combined,
-            LDByte(r(0x1), 0x00).into(), // x coordinate to draw at
-            LDByte(r(0x2), 0x00).into(), // y coordinate to draw at
+            UNKNOWN(sprites_combined),
+            LDByte(r(0x1), 0x00), // x coordinate to draw at
+            LDByte(r(0x2), 0x00), // y coordinate to draw at
             // Point I at sprite 1
-            LDI((0x200 + 2).into()).into(),
+            LDI((0x200 + 2).into()),
             // Draw sprite1 at (V1, V2)
-            DRW(r(0x1), r(0x2), 0x01).into(),
+            DRW(r(0x1), r(0x2), 0x01),
             // Point I at sprite 2
-            LDI((0x200 + 3).into()).into(),
+            LDI((0x200 + 3).into()),
             // Draw sprite2 at (V1, V2)
-            DRW(r(0x1), r(0x2), 0x01).into(),
+            DRW(r(0x1), r(0x2), 0x01),
         ]);
 
         // VF flips to 1 because a set pixel was changed to unset
-        assert_eq!(state.get_register(0xF), 0x1);
+        assert_eq!(state.get_register(r(0xF)), 0x1);
         // These pixels stay ON
         assert_eq!(state.buffer.get_pixel(0, 0), display::ON);
         assert_eq!(state.buffer.get_pixel(1, 0), display::ON);
@@ -582,47 +3376,665 @@ mod test {
 
         let state = run(&[
             // Jump past the sprites
-            JP((0x200 + 4).into()).into(),
+            JP((0x200 + 4).into()),
             // Sprite1 is at 0x200 + 2 and sprite2 is at 0x200 + 3
-            sprites_combined,
-            LDByte(r(0x1), 0x00).into(), // x coordinate to draw at
-            LDByte(r(0x2), 0x00).into(), // y coordinate to draw at
+            UNKNOWN(sprites_combined),
+            LDByte(r(0x1), 0x00), // x coordinate to draw at
+            LDByte(r(0x2), 0x00), // y coordinate to draw at
             // Point I at sprite 1
-            LDI((0x200 + 2).into()).into(),
+            LDI((0x200 + 2).into()),
             // Draw sprite (VF stays at 0, pixel changed from unset to set)
-            DRW(r(0x1), r(0x2), 0x01).into(),
+            DRW(r(0x1), r(0x2), 0x01),
             // Draw sprite (VF flips to 1, pixel changed from set to unset)
-            DRW(r(0x1), r(0x2), 0x01).into(),
+            DRW(r(0x1), r(0x2), 0x01),
             // Draw sprite (VF flips to 0, no pixel changed from set to unset)
-            DRW(r(0x1), r(0x2), 0x01).into(),
+            DRW(r(0x1), r(0x2), 0x01),
         ]);
 
-        assert_eq!(state.get_register(0xF), 0x0);
+        assert_eq!(state.get_register(r(0xF)), 0x0);
         assert_eq!(state.buffer.get_pixel(0, 0), display::ON);
         for x in 1..8 {
             assert_eq!(state.buffer.get_pixel(x, 0), display::OFF);
         }
     }
 
+    #[test]
+    fn high_and_low_resolution() {
+        let state = run(&[HIGH()]);
+        assert!(state.high_resolution);
+        assert_eq!(state.buffer.width, display::SCHIP_WIDTH);
+        assert_eq!(state.buffer.height, display::SCHIP_HEIGHT);
+
+        let state = run(&[HIGH(), LOW()]);
+        assert!(!state.high_resolution);
+        assert_eq!(state.buffer.width, display::CHIP8_WIDTH);
+        assert_eq!(state.buffer.height, display::CHIP8_HEIGHT);
+    }
+
+    #[test]
+    fn ld_hf() {
+        let state = run(&[LDByte(r(0x3), 0x2), LDHF(r(0x3))]);
+        assert_eq!(state.i, 0xA0 + 2 * 10);
+        assert_eq!(
+            state.memory[state.i as usize..state.i as usize + 10],
+            font::LARGE_FONT[20..30]
+        );
+    }
+
+    #[test]
+    fn ld_r_saves_registers_for_ld_vx_r() {
+        let state = run(&[
+            LDByte(r(0x0), 0x1),
+            LDByte(r(0x1), 0x2),
+            LDByte(r(0x2), 0x3),
+            LDIntoFlags(r(0x1)),
+            LDByte(r(0x0), 0x0),
+            LDByte(r(0x1), 0x0),
+            LDFromFlags(r(0x2)),
+        ]);
+        assert_eq!(state.flags[..3], [0x1, 0x2, 0x0]);
+        assert_eq!(state.registers[..3], [0x1, 0x2, 0x0]);
+    }
+
+    #[test]
+    fn drw_16x16_in_high_resolution() {
+        #[rustfmt::skip]
+        let program = &[
+            HIGH(),
+            // Point I at the large font "0", so the 16x16 sprite starts with it
+            LDHF(r(0x0)),
+            LDByte(r(0x1), 120), // x coordinate to draw at
+            DRW(r(0x1), r(0x0), 0x0),
+        ];
+
+        for clip_sprites in &[true, false] {
+            let state = run_with_quirks(
+                Quirks {
+                    clip_sprites: *clip_sprites,
+                    ..Quirks::default()
+                },
+                program,
+            );
+
+            // The first byte of "0" is 0b00111100 and the second is 0b01111110
+            assert_eq!(state.buffer.get_pixel(120 + 1, 0), display::OFF);
+            assert_eq!(state.buffer.get_pixel(120 + 2, 0), display::ON);
+            assert_eq!(state.get_register(r(0xF)), 0);
+
+            // The second byte is past the right edge of the 128-pixel-wide
+            // screen, so it's either cut off or wraps around to the left
+            assert_eq!(state.buffer.get_pixel(0, 0), display::OFF);
+            let wrapped = if *clip_sprites {
+                display::OFF
+            } else {
+                display::ON
+            };
+            assert_eq!(state.buffer.get_pixel(1, 0), wrapped);
+            assert_eq!(state.buffer.get_pixel(6, 0), wrapped);
+            assert_eq!(state.buffer.get_pixel(7, 0), display::OFF);
+        }
+    }
+
+    #[test]
+    fn xo_chip_long_ldi() {
+        let state = run_with_variant(Variant::XoChip, &[LDILong(0xBEEF)]);
+        assert_eq!(state.i, 0xBEEF);
+        assert_eq!(state.pc, 0x204);
+    }
+
+    #[test]
+    fn xo_chip_instructions_need_the_xo_chip_variant() {
+        let mut state = build_state_with_program(&[LDILong(0xBEEF)]);
+        match tick(&mut state, testing_rng()) {
+            Err(Chip8Error::NeedsXoChip { pc, instruction }) => {
+                assert_eq!(pc, 0x200);
+                assert_eq!(instruction, LDILong(0xBEEF).to_string());
+            }
+            other => panic!("Expected NeedsXoChip, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn i_wraps_around_instead_of_overflowing() {
+        let state = run_with_variant(
+            Variant::XoChip,
+            &[LDILong(0xFFFF), LDByte(r(0x0), 0x2), ADDI(r(0x0))],
+        );
+        assert_eq!(state.i, 0x1);
+    }
+
+    #[test]
+    fn arbitrary_bytes_fail_without_panicking() {
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..200 {
+            let mut bytes = [0; 64];
+            rng.fill_bytes(&mut bytes);
+            for profile in &PROFILES {
+                let mut state = State::with_program(&[])
+                    .unwrap()
+                    .with_variant(profile.variant())
+                    .with_quirks(profile.quirks());
+                let mut rest = &bytes[..];
+                while !rest.is_empty() {
+                    let size = match state.execute_bytes(rest, &mut rng) {
+                        Ok(instruction) => instruction.size(),
+                        Err(_) => 2,
+                    };
+                    rest = rest.get(size..).unwrap_or(&[]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn xo_chip_skips_over_long_ldi() {
+        let state = run_with_variant(
+            Variant::XoChip,
+            &[
+                SEByte(r(0x0), 0x00),
+                // This should be skipped, all 4 bytes of it
+                LDILong(0xBEEF),
+                // This one should run
+                LDByte(r(0x1), 0xFF),
+            ],
+        );
+        assert_eq!(state.i, 0);
+        assert_eq!(state.get_register(r(0x1)), 0xFF);
+    }
+
+    #[test]
+    fn xo_chip_save_and_load_register_range() {
+        let state = run_with_variant(
+            Variant::XoChip,
+            &[
+                LDByte(r(0x2), 0x22),
+                LDByte(r(0x3), 0x33),
+                LDI(0x400.into()),
+                // Saving in reverse order puts V3 first
+                SAVE(r(0x3), r(0x2)),
+                LOAD(r(0x5), r(0x6)),
+            ],
+        );
+        assert_eq!(state.memory[0x400..0x402], [0x33, 0x22]);
+        assert_eq!(state.get_register(r(0x5)), 0x33);
+        assert_eq!(state.get_register(r(0x6)), 0x22);
+        // I doesn't change
+        assert_eq!(state.i, 0x400);
+    }
+
+    #[test]
+    fn xo_chip_drw_on_both_planes() {
+        let sprites_combined = u16::from_be_bytes([0b10000000, 0b11000000]);
+        let state = run_with_variant(
+            Variant::XoChip,
+            &[
+                // Jump past the sprites
+                JP((0x200 + 4).into()),
+                // The plane 1 sprite is at 0x202, and the plane 2 sprite is
+                // right after it
+                UNKNOWN(sprites_combined),
+                PLANE(0b11),
+                LDI((0x200 + 2).into()),
+                DRW(r(0x0), r(0x0), 0x01),
+            ],
+        );
+        assert_eq!(state.buffer.get_planes(0, 0), 0b11);
+        assert_eq!(state.buffer.get_planes(1, 0), 0b10);
+        assert_eq!(state.buffer.get_planes(2, 0), 0b00);
+    }
+
+    #[test]
+    fn xo_chip_audio_and_pitch() {
+        let state = run_with_variant(
+            Variant::XoChip,
+            &[
+                // The first 16 bytes of the large font make a handy pattern
+                LDHF(r(0x0)),
+                AUDIO(),
+                LDByte(r(0x1), 0x70),
+                PITCH(r(0x1)),
+            ],
+        );
+        assert_eq!(state.audio_pattern, font::LARGE_FONT[0..16]);
+        assert_eq!(state.pitch, 0x70);
+    }
+
+    #[test]
+    fn drw_at_the_edge_of_the_screen() {
+        let sprite: u8 = 0b11000000;
+        let sprites_combined = u16::from_be_bytes([sprite, sprite]);
+        let program = &[
+            // Jump past the sprites
+            JP((0x200 + 4).into()),
+            UNKNOWN(sprites_combined),
+            LDByte(r(0x1), 63), // x coordinate to draw at
+            LDByte(r(0x2), 31), // y coordinate to draw at
+            LDI((0x200 + 2).into()),
+            DRW(r(0x1), r(0x2), 0x01),
+        ];
+
+        let clipped = run_with_quirks(
+            Quirks {
+                clip_sprites: true,
+                ..Quirks::default()
+            },
+            program,
+        );
+        assert_eq!(clipped.buffer.get_pixel(63, 31), display::ON);
+        assert_eq!(clipped.buffer.get_pixel(0, 31), display::OFF);
+
+        let wrapped = run_with_quirks(
+            Quirks {
+                clip_sprites: false,
+                ..Quirks::default()
+            },
+            program,
+        );
+        assert_eq!(wrapped.buffer.get_pixel(63, 31), display::ON);
+        assert_eq!(wrapped.buffer.get_pixel(0, 31), display::ON);
+    }
+
+    #[test]
+    fn shr_with_shift_quirk() {
+        let quirks = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        let state = run_with_quirks(quirks, &[LDByte(r(0x2), 0b0000_0011), SHR(r(0x1), r(0x2))]);
+        assert_eq!(state.get_register(r(0x1)), 0b0000_0001);
+        assert_eq!(state.get_register(r(0xF)), 1);
+    }
+
+    #[test]
+    fn shl_without_shift_quirk() {
+        let quirks = Quirks {
+            shift_uses_vy: false,
+            ..Quirks::default()
+        };
+        let state = run_with_quirks(
+            quirks,
+            &[
+                LDByte(r(0x1), 0b0100_0001),
+                LDByte(r(0x2), 0b1111_1111),
+                SHL(r(0x1), r(0x2)),
+            ],
+        );
+        // Vy is ignored
+        assert_eq!(state.get_register(r(0x1)), 0b1000_0010);
+        assert_eq!(state.get_register(r(0xF)), 0);
+    }
+
+    #[test]
+    fn ld_into_and_from_memory_with_increment_quirk() {
+        let quirks = Quirks {
+            load_store_increments_i: true,
+            ..Quirks::default()
+        };
+        let state = run_with_quirks(
+            quirks,
+            &[
+                LDByte(r(0x0), 0x10),
+                LDByte(r(0x1), 0x11),
+                LDI(0x400.into()),
+                LDIntoMemory(r(0x1)),
+                LDI(0x400.into()),
+                LDFromMemory(r(0x0)),
+            ],
+        );
+        assert_eq!(state.memory[0x400..0x402], [0x10, 0x11]);
+        assert_eq!(state.get_register(r(0x0)), 0x10);
+        assert_eq!(state.i, 0x401);
+    }
+
+    #[test]
+    fn ld_into_memory_without_increment_quirk() {
+        let quirks = Quirks {
+            load_store_increments_i: false,
+            ..Quirks::default()
+        };
+        let state = run_with_quirks(quirks, &[LDI(0x400.into()), LDIntoMemory(r(0x3))]);
+        assert_eq!(state.i, 0x400);
+    }
+
+    /// Reads as 42 everywhere it's mapped, and keeps what's written to it
+    /// instead of storing it in memory.
+    #[derive(Default)]
+    struct Peripheral {
+        written: Vec<(usize, u8)>,
+    }
+
+    impl MemoryHook for Peripheral {
+        fn read(&mut self, _address: usize, _value: u8) -> u8 {
+            0x42
+        }
+
+        fn write(&mut self, address: usize, value: u8) -> bool {
+            self.written.push((address, value));
+            false
+        }
+    }
+
+    #[test]
+    fn memory_hooks_see_what_the_program_reads_and_writes() {
+        let peripheral = Rc::new(RefCell::new(Peripheral::default()));
+        let mut state = build_state_with_program(&[
+            LDByte(r(0x0), 0x10),
+            LDByte(r(0x1), 0x11),
+            LDI(0xF00.into()),
+            LDIntoMemory(r(0x1)),
+            LDI(0xEFF.into()),
+            LDFromMemory(r(0x2)),
+        ])
+        .with_memory_hook(0xF00..0xF02, peripheral.clone());
+        for _ in 0..6 {
+            tick(&mut state, testing_rng()).unwrap();
+        }
+        assert_eq!(
+            peripheral.borrow().written,
+            vec![(0xF00, 0x10), (0xF01, 0x11)]
+        );
+        assert_eq!(state.memory[0xF00..0xF02], [0, 0]);
+        // EFF isn't mapped
+        assert_eq!(state.registers()[..3], [0x00, 0x42, 0x42]);
+    }
+
+    /// Reads as whatever instruction it's been given, like a cartridge
+    /// that can be swapped out.
+    struct Cartridge {
+        opcode: [u8; 2],
+    }
+
+    impl MemoryHook for Cartridge {
+        fn read(&mut self, address: usize, _value: u8) -> u8 {
+            self.opcode[address - 0x200]
+        }
+    }
+
+    #[test]
+    fn instructions_are_fetched_through_memory_hooks_every_time() {
+        let cartridge = Rc::new(RefCell::new(Cartridge {
+            opcode: [0x60, 0x42],
+        }));
+        let mut state = build_state_with_program(&[LDByte(r(0x0), 0x01), JP(0x200.into())])
+            .with_memory_hook(0x200..0x202, cartridge.clone());
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.get_register(r(0x0)), 0x42);
+
+        // Nothing in memory changed, but the next fetch still sees the swap
+        cartridge.borrow_mut().opcode = [0x61, 0x07];
+        tick(&mut state, testing_rng()).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.get_register(r(0x1)), 0x07);
+        assert_eq!(state.decoded.get(0x200), None);
+    }
+
+    /// Keeps blobs in memory, like a browser's local storage might.
+    #[derive(Default)]
+    struct Storage {
+        blobs: Vec<(String, String, Vec<u8>)>,
+    }
+
+    impl Persistence for Storage {
+        fn load(&mut self, rom: &str, key: &str) -> Result<Option<Vec<u8>>, Chip8Error> {
+            Ok(self
+                .blobs
+                .iter()
+                .find(|(r, k, _)| r == rom && k == key)
+                .map(|(_, _, data)| data.clone()))
+        }
+
+        fn store(&mut self, rom: &str, key: &str, data: &[u8]) -> Result<(), Chip8Error> {
+            self.blobs.retain(|(r, k, _)| r != rom || k != key);
+            self.blobs
+                .push((rom.to_string(), key.to_string(), data.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_flags_outlast_the_machine() {
+        let storage = Rc::new(RefCell::new(Storage::default()));
+        let program = [
+            LDByte(r(0x0), 0x2A),
+            LDIntoFlags(r(0x0)),
+            LDFromFlags(r(0x0)),
+        ];
+        let mut state = build_state_with_program(&program)
+            .with_persistence(storage.clone())
+            .unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(storage.borrow().blobs[0].2[0], 0x2A);
+
+        // A new machine with the same program starts with the saved flags
+        let mut state = build_state_with_program(&program)
+            .with_persistence(storage)
+            .unwrap();
+        state.pc += 4;
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(state.registers()[0], 0x2A);
+    }
+
+    #[test]
+    fn the_flags_are_still_saved_after_rewinding() {
+        let storage = Rc::new(RefCell::new(Storage::default()));
+        let mut state = build_state_with_program(&[
+            LDByte(r(0x0), 0x01),
+            LDByte(r(0x0), 0x2A),
+            LDIntoFlags(r(0x0)),
+        ])
+        .with_persistence(storage.clone())
+        .unwrap();
+        let mut rewind = Rewind::new(10);
+        rewind.record(&state).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        rewind.record(&state).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        rewind.record(&state).unwrap();
+
+        state.restore(rewind.rewind().unwrap().unwrap());
+        assert_eq!(state.pc(), 0x202);
+        tick(&mut state, testing_rng()).unwrap();
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(storage.borrow().blobs[0].2[0], 0x2A);
+    }
+
+    #[test]
+    fn ld_into_memory_only_writes_the_registers_it_saves() {
+        let mut state = build_state_with_program(&[
+            LDByte(r(0x0), 0x10),
+            LDByte(r(0x1), 0x11),
+            LDByte(r(0x2), 0x12),
+            LDI(0x400.into()),
+            LDIntoMemory(r(0x1)),
+        ]);
+        for _ in 0..4 {
+            tick(&mut state, testing_rng()).unwrap();
+        }
+        let before = state.clone();
+        tick(&mut state, testing_rng()).unwrap();
+        assert_eq!(
+            before.diff(&state).to_string(),
+            "I: 0400 -> 0402\nPC: 208 -> 20A\nMemory 400-401: 00 00 -> 10 11\n"
+        );
+    }
+
+    #[test]
+    fn jp_offset_with_and_without_jump_quirk() {
+        let program = &[
+            LDByte(r(0x0), 0x01),
+            LDByte(r(0x3), 0x02),
+            JPOffset(0x300.into()),
+        ];
+        let state = run_with_quirks(
+            Quirks {
+                jump_uses_vx: false,
+                ..Quirks::default()
+            },
+            program,
+        );
+        assert_eq!(state.pc, 0x301);
+
+        let state = run_with_quirks(
+            Quirks {
+                jump_uses_vx: true,
+                ..Quirks::default()
+            },
+            program,
+        );
+        assert_eq!(state.pc, 0x302);
+    }
+
+    #[test]
+    fn jp_offset_with_the_schip_profile_uses_the_high_nibble() {
+        // B3A0 is "jump to 3A0 + V3" on the SCHIP, and "jump to 3A0 + V0" on
+        // the COSMAC VIP
+        let program = &[
+            LDByte(r(0x0), 0x01),
+            LDByte(r(0x3), 0x10),
+            JPOffset(0x3A0.into()),
+        ];
+        assert_eq!(run_with_quirks(Profile::Schip.quirks(), program).pc, 0x3B0);
+        assert_eq!(run_with_quirks(Profile::Cosmac.quirks(), program).pc, 0x3A1);
+    }
+
+    #[test]
+    fn jp_offset_past_the_end_of_memory_fails() {
+        let mut state = build_state_with_program(&[LDByte(r(0x0), 0x02), JPOffset(0xFFE.into())]);
+        tick(&mut state, testing_rng()).unwrap();
+        assert!(matches!(
+            tick(&mut state, testing_rng()),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0x1000 })
+        ));
+    }
+
     #[test]
     fn add_registers_without_overflow() {
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            LDByte(r(0xE), 0x20).into(),
-            ADDRegister(r(0xD), r(0xE)).into(),
+            LDByte(r(0xD), 0x12),
+            LDByte(r(0xE), 0x20),
+            ADDRegister(r(0xD), r(0xE)),
         ]);
-        assert_eq!(state.get_register(0xD), 0x12 + 0x20);
-        assert_eq!(state.get_register(0xF), 0);
+        assert_eq!(state.get_register(r(0xD)), 0x12 + 0x20);
+        assert_eq!(state.get_register(r(0xF)), 0);
     }
 
     #[test]
     fn add_registers_with_overflow() {
         let state = run(&[
-            LDByte(r(0xD), 0x12).into(),
-            LDByte(r(0xE), 0xFF).into(),
-            ADDRegister(r(0xD), r(0xE)).into(),
+            LDByte(r(0xD), 0x12),
+            LDByte(r(0xE), 0xFF),
+            ADDRegister(r(0xD), r(0xE)),
+        ]);
+        assert_eq!(state.get_register(r(0xD)), 0x11);
+        assert_eq!(state.get_register(r(0xF)), 1);
+    }
+
+    #[test]
+    fn add_registers_clears_the_carry() {
+        let state = run(&[
+            LDByte(r(0xF), 0x01),
+            LDByte(r(0xD), 0x12),
+            ADDRegister(r(0xD), r(0xD)),
+        ]);
+        assert_eq!(state.get_register(r(0xF)), 0);
+    }
+
+    #[test]
+    fn add_registers_into_vf_keeps_the_carry() {
+        let state = run(&[LDByte(r(0xF), 0x80), ADDRegister(r(0xF), r(0xF))]);
+        assert_eq!(state.get_register(r(0xF)), 1);
+    }
+
+    #[test]
+    fn load_and_combine_registers() {
+        let state = run(&[
+            LDByte(r(0x1), 0b1100),
+            LDByte(r(0x2), 0b1010),
+            LDRegister(r(0x3), r(0x1)),
+            OR(r(0x3), r(0x2)),
+            LDRegister(r(0x4), r(0x1)),
+            AND(r(0x4), r(0x2)),
+            LDRegister(r(0x5), r(0x1)),
+            XOR(r(0x5), r(0x2)),
+        ]);
+        assert_eq!(state.get_register(r(0x3)), 0b1110);
+        assert_eq!(state.get_register(r(0x4)), 0b1000);
+        assert_eq!(state.get_register(r(0x5)), 0b0110);
+    }
+
+    #[test]
+    fn subtract_registers() {
+        let state = run(&[
+            LDByte(r(0x1), 0x30),
+            LDByte(r(0x2), 0x10),
+            SUB(r(0x1), r(0x2)),
         ]);
-        assert_eq!(state.get_register(0xD), 0x11);
-        assert_eq!(state.get_register(0xF), 1);
+        assert_eq!(state.get_register(r(0x1)), 0x20);
+        // No borrow
+        assert_eq!(state.get_register(r(0xF)), 1);
+
+        let state = run(&[
+            LDByte(r(0x1), 0x30),
+            LDByte(r(0x2), 0x10),
+            SUBN(r(0x1), r(0x2)),
+        ]);
+        assert_eq!(state.get_register(r(0x1)), 0xE0);
+        assert_eq!(state.get_register(r(0xF)), 0);
+    }
+
+    #[test]
+    fn skip_if_key_is_pressed_or_not() {
+        let program = [
+            LDByte(r(0x1), 0xB),
+            SKP(r(0x1)),
+            LDByte(r(0x2), 0x1),
+            SKNP(r(0x1)),
+            LDByte(r(0x3), 0x1),
+        ];
+        let mut state = build_state_with_program(&program);
+        state.keypad[0xB] = true;
+        let state = run_state(state, &program[..4]);
+        assert_eq!(state.get_register(r(0x2)), 0);
+        assert_eq!(state.get_register(r(0x3)), 1);
+
+        let state = run(&program);
+        assert_eq!(state.get_register(r(0x2)), 1);
+        assert_eq!(state.get_register(r(0x3)), 0);
+    }
+
+    #[test]
+    fn delay_timer() {
+        let state = run(&[LDByte(r(0x1), 0x3C), LDDT(r(0x1)), LDFromDT(r(0x2))]);
+        assert_eq!(state.delay_timer(), 0x3C);
+        assert_eq!(state.get_register(r(0x2)), 0x3C);
+    }
+
+    #[test]
+    fn ldf_points_at_the_small_font() {
+        let state = run(&[LDByte(r(0x1), 0xA), LDF(r(0x1))]);
+        let address = state.i as usize;
+        assert_eq!(
+            state.memory[address..address + font::FONT_CHARACTER_SIZE],
+            [0xF0, 0x90, 0xF0, 0x90, 0x90]
+        );
+    }
+
+    #[test]
+    fn ldb_stores_the_decimal_digits() {
+        let state = run(&[LDByte(r(0x1), 254), LDI(0x300.into()), LDB(r(0x1))]);
+        assert_eq!(state.memory[0x300..0x303], [2, 5, 4]);
+        assert_eq!(state.i, 0x300);
+    }
+
+    #[test]
+    fn cls_clears_the_selected_planes() {
+        let mut state = State::with_program(&[]).unwrap();
+        state.buffer.xor(true, 1, 1, 0b01);
+        state.buffer.xor(true, 2, 2, 0b11);
+        state.planes = 0b01;
+        execute(&mut state, &CLS(), testing_rng(), false).unwrap();
+        assert_eq!(state.buffer.get_planes(1, 1), 0);
+        assert_eq!(state.buffer.get_planes(2, 2), 0b10);
     }
 }
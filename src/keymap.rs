@@ -0,0 +1,150 @@
+// Only the window reads the keyboard, but the config file's keys are checked
+// either way
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use crate::display::{Key, HOTKEYS, KEYPAD};
+use std::str::FromStr;
+
+/// Which key on the keyboard is each of the CHIP-8's 16 keys, from 0 to F.
+/// It starts out as `KEYPAD`, and any of them can be bound to another letter
+/// or digit, with `--keymap` or the config file's `[keys]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keymap([Key; 16]);
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap(KEYPAD)
+    }
+}
+
+impl Keymap {
+    /// The keyboard keys for 0 to F, in order.
+    pub fn keys(&self) -> &[Key; 16] {
+        &self.0
+    }
+
+    /// Bind the CHIP-8 key `chip8_key` (a hex digit from 0 to F) to the key
+    /// named `key` (see `Key::from_str`).
+    pub fn bind(&mut self, chip8_key: &str, key: &str) -> Result<(), String> {
+        let index = match u8::from_str_radix(chip8_key, 16) {
+            Ok(n) if chip8_key.len() == 1 => n as usize,
+            _ => {
+                return Err(format!(
+                    "The CHIP-8's keys are 0 to F, so there's no key {}",
+                    chip8_key
+                ))
+            }
+        };
+        self.0[index] = key.parse()?;
+        Ok(())
+    }
+
+    /// Check that no key is bound twice, and that none of them are hotkeys,
+    /// since a key like that would do two things at once.
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, key) in self.0.iter().enumerate() {
+            if let Some(other) = self.0[..index].iter().position(|k| k == key) {
+                return Err(format!(
+                    "{:?} is bound to both {:X} and {:X}",
+                    key, other, index
+                ));
+            }
+            if HOTKEYS.contains(key) {
+                return Err(format!(
+                    "{:?} is a hotkey, so it can't be the keypad's {:X}",
+                    key, index
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bindings like `5=W,6=KeyE`, applied over the usual layout, for
+/// `--keymap`.
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keymap = Keymap::default();
+        for binding in s.split(',').filter(|binding| !binding.trim().is_empty()) {
+            let equals = binding
+                .find('=')
+                .ok_or_else(|| format!("Expected a binding like 5=W, got {}", binding))?;
+            keymap.bind(binding[..equals].trim(), binding[equals + 1..].trim())?;
+        }
+        keymap.validate()?;
+        Ok(keymap)
+    }
+}
+
+/// The key for a letter or digit, in either case.
+pub fn key_for_char(c: char) -> Option<Key> {
+    use Key::*;
+
+    const DIGITS: [Key; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    const LETTERS: [Key; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    match c.to_ascii_uppercase() {
+        c @ '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+        c @ 'A'..='Z' => Some(LETTERS[c as usize - 'A' as usize]),
+        _ => None,
+    }
+}
+
+/// A letter or digit, by itself (`W`, `1`) or the way `Key` names it (`KeyW`,
+/// `Key1`).
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefixed = s
+            .get(..3)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("key"));
+        let name = if prefixed && s.len() > 3 { &s[3..] } else { s };
+        let mut chars = name.chars();
+        match (chars.next().and_then(key_for_char), chars.next()) {
+            (Some(key), None) => Ok(key),
+            _ => Err(format!(
+                "Unknown key {} (expected a letter or digit, like W or Key1)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bindings_change_only_their_keys() {
+        let keymap: Keymap = "5=I, 8=k,0=Key0".parse().unwrap();
+        assert_eq!(keymap.keys()[0x5], Key::I);
+        assert_eq!(keymap.keys()[0x8], Key::K);
+        assert_eq!(keymap.keys()[0x0], Key::Key0);
+        assert_eq!(keymap.keys()[0x1], KEYPAD[0x1]);
+    }
+
+    #[test]
+    fn keys_can_only_do_one_thing() {
+        // W is already 5
+        assert_eq!(
+            "6=W".parse::<Keymap>(),
+            Err("W is bound to both 5 and 6".to_string())
+        );
+        assert_eq!(
+            "0=P".parse::<Keymap>(),
+            Err("P is a hotkey, so it can't be the keypad's 0".to_string())
+        );
+        assert!("G=W".parse::<Keymap>().is_err());
+        assert!("1=Enter".parse::<Keymap>().is_err());
+    }
+
+    #[test]
+    fn swapping_keys_is_fine() {
+        let keymap: Keymap = "5=S,8=W".parse().unwrap();
+        assert_eq!((keymap.keys()[0x5], keymap.keys()[0x8]), (Key::S, Key::W));
+    }
+}
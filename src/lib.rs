@@ -0,0 +1,57 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP emulator, with an assembler, an Octo
+//! compiler, a disassembler and a debugger. The `chip8` command line is built
+//! on this library, and so are the fuzz target and the benchmarks. To embed
+//! the emulator, start with `emulator::EmulatorBuilder`.
+
+#[macro_use]
+extern crate log;
+
+pub mod analysis;
+pub mod assembler;
+pub mod avsync;
+pub mod bisect;
+pub mod bundle;
+pub mod cli;
+pub mod config;
+pub mod debugger;
+pub mod decompile;
+pub mod difftest;
+pub mod disasm;
+pub mod display;
+pub mod doctor;
+pub mod emulator;
+pub mod error;
+pub mod expression;
+pub mod flash;
+pub mod font;
+pub mod frame;
+pub mod frontend;
+#[cfg(feature = "wgpu-frontend")]
+pub mod gpu;
+pub mod idle;
+pub mod input;
+pub mod instruction;
+pub mod interpreter;
+pub mod keymap;
+pub mod library;
+pub mod memory;
+pub mod octo;
+pub mod opcodes;
+pub mod persistence;
+pub mod phosphor;
+pub mod profiler;
+pub mod quirks;
+pub mod rewind;
+pub mod romdb;
+#[cfg(feature = "sdl2-frontend")]
+pub mod sdl;
+pub mod sprites;
+pub mod statediff;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+pub mod timing;
+pub mod tracefile;
+pub mod verify;
+pub mod watchdog;
+#[cfg(feature = "wasm")]
+pub mod web;
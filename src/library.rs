@@ -0,0 +1,356 @@
+use crate::{
+    analysis::PROFILES,
+    disasm::{self, Line},
+    error::Chip8Error,
+    memory,
+    quirks::Profile,
+};
+#[cfg(feature = "terminal")]
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+#[cfg(feature = "terminal")]
+use std::io::Stdout;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The files that `scan` counts as ROMs, by extension.
+const ROM_EXTENSIONS: [&str; 2] = ["ch8", "c8"];
+
+/// A ROM in a directory of them, with what can be told about it without
+/// running it.
+#[derive(Debug, PartialEq)]
+pub struct Rom {
+    pub path: PathBuf,
+    /// How many bytes long it is
+    pub size: usize,
+    /// The SHA-1 of its bytes, in lowercase hex, which is how ROM databases
+    /// tell ROMs apart whatever they're called
+    pub sha1: String,
+    /// The oldest machine that has every instruction it can reach
+    pub profile: Profile,
+}
+
+impl Rom {
+    /// Read and fingerprint the ROM at `path`.
+    pub fn load(path: &Path) -> Result<Self, Chip8Error> {
+        let program = fs::read(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: program.len(),
+            sha1: fingerprint(&program),
+            profile: detect_profile(&program)?,
+        })
+    }
+
+    /// The file's name, without the directory it's in.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// One line with the name, size, SHA-1, and machine, for a list of ROMs.
+impl Display for Rom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<32} {:>5} bytes  {}  {}",
+            self.name(),
+            self.size,
+            self.sha1,
+            self.profile
+        )
+    }
+}
+
+/// Every ROM (by its extension) directly in `directory`, sorted by name.
+/// ROMs that can't be read, or are too big to fit in memory, are left out
+/// with a warning, so that one bad file doesn't hide the rest.
+pub fn scan(directory: &Path) -> Result<Vec<Rom>, Chip8Error> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        if ROM_EXTENSIONS.contains(&extension.as_str()) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut roms = vec![];
+    for path in paths {
+        match Rom::load(&path) {
+            Ok(rom) if rom.size > rom.profile.variant().memory_size() - memory::PROGRAM_START => {
+                warn!(
+                    "Skipping {}, which is {} bytes and too big to load",
+                    path.display(),
+                    rom.size
+                )
+            }
+            Ok(rom) => roms.push(rom),
+            Err(e) => warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    Ok(roms)
+}
+
+/// The oldest machine (COSMAC VIP, then SCHIP, then XO-CHIP) that has every
+/// instruction the program can reach, going by its disassembly.
+pub fn detect_profile(program: &[u8]) -> Result<Profile, Chip8Error> {
+    let disassembly = disasm::disassemble(program)?;
+    let instructions = disassembly
+        .lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Code { instruction, .. } => Some(instruction),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    Ok(PROFILES
+        .iter()
+        .copied()
+        .find(|profile| {
+            instructions
+                .iter()
+                .all(|instruction| profile.supports(instruction))
+        })
+        .unwrap_or(Profile::XoChip))
+}
+
+/// Show the ROMs in a list to pick one from with the arrow keys (or j and k),
+/// with the size, machine, and SHA-1 of the highlighted one underneath, and
+/// return which one was picked with Enter. The list starts on `selected`.
+/// None means q or Escape was pressed instead.
+#[cfg(feature = "terminal")]
+pub fn choose(roms: &[Rom], selected: usize) -> Result<Option<usize>, Chip8Error> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode().map_err(|e| Chip8Error::Window(e.to_string()))?;
+    execute!(stdout, EnterAlternateScreen, Hide).map_err(|e| Chip8Error::Window(e.to_string()))?;
+    let chosen = pick(&mut stdout, roms, selected);
+    // Put the terminal back whether or not picking worked
+    let restored =
+        execute!(stdout, Show, LeaveAlternateScreen).and_then(|()| terminal::disable_raw_mode());
+    let chosen = chosen.map_err(|e| Chip8Error::Window(e.to_string()))?;
+    restored.map_err(|e| Chip8Error::Window(e.to_string()))?;
+    Ok(chosen)
+}
+
+#[cfg(feature = "terminal")]
+fn pick(
+    stdout: &mut Stdout,
+    roms: &[Rom],
+    mut selected: usize,
+) -> crossterm::Result<Option<usize>> {
+    loop {
+        let (_, rows) = terminal::size()?;
+        // Leave room for the highlighted ROM's details and the help
+        let visible = (rows as usize).saturating_sub(3).max(1);
+        let first = (selected + 1).saturating_sub(visible);
+        queue!(stdout, Clear(ClearType::All))?;
+        for (row, (index, rom)) in roms
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .enumerate()
+        {
+            queue!(stdout, MoveTo(0, row as u16))?;
+            if index == selected {
+                queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(stdout, Print(rom.name()), SetAttribute(Attribute::Reset))?;
+        }
+        let rom = &roms[selected];
+        queue!(
+            stdout,
+            MoveTo(0, visible as u16 + 1),
+            Print(format!(
+                "{} bytes, {}, SHA-1 {}",
+                rom.size, rom.profile, rom.sha1
+            )),
+            MoveTo(0, visible as u16 + 2),
+            Print("Up and down to choose, Enter to run, q to quit")
+        )?;
+        stdout.flush()?;
+
+        if let Event::Key(KeyEvent { code, modifiers }) = event::read()? {
+            match code {
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => selected = (selected + 1).min(roms.len() - 1),
+                KeyCode::Enter => return Ok(Some(selected)),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+                // Raw mode means Ctrl-C doesn't stop the program by itself
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Without the terminal feature, list the ROMs with a number each and ask for
+/// one. None means q was typed, or stdin ended.
+#[cfg(not(feature = "terminal"))]
+pub fn choose(roms: &[Rom], _selected: usize) -> Result<Option<usize>, Chip8Error> {
+    for (index, rom) in roms.iter().enumerate() {
+        println!("{:>3}. {}", index + 1, rom);
+    }
+    loop {
+        print!("Run which ROM? (1 to {}, or q to quit) ", roms.len());
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        match line.trim() {
+            "q" => return Ok(None),
+            answer => match answer.parse::<usize>() {
+                Ok(number) if (1..=roms.len()).contains(&number) => return Ok(Some(number - 1)),
+                _ => println!("Type a number from 1 to {}", roms.len()),
+            },
+        }
+    }
+}
+
+/// The SHA-1 of a ROM in lowercase hex, which is what the ROM database (see
+/// `romdb`) looks ROMs up by.
+pub fn fingerprint(program: &[u8]) -> String {
+    hex(&sha1(program))
+}
+
+/// The SHA-1 hash of `bytes`, as described in RFC 3174.
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut hash: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    // Pad the message with a 1 bit, then 0s, then its length in bits, to a
+    // multiple of 64 bytes
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = block
+            .chunks(4)
+            .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+            .collect::<Vec<_>>();
+        for i in 16..80 {
+            let word = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+            words.push(word);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = hash;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (total, part) in hash.iter_mut().zip(&[a, b, c, d, e]) {
+            *total = total.wrapping_add(*part);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, part) in digest.chunks_mut(4).zip(&hash) {
+        chunk.copy_from_slice(&part.to_be_bytes());
+    }
+    digest
+}
+
+/// Bytes as lowercase hex, two digits each.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sha1_matches_the_rfc() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // 56 bytes, so the padding needs a second block
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn detects_the_oldest_machine_that_can_run_it() {
+        // LD V0, 05; JP 202
+        assert_eq!(
+            detect_profile(&[0x60, 0x05, 0x12, 0x02]).unwrap(),
+            Profile::Cosmac
+        );
+        // HIGH; JP 202
+        assert_eq!(
+            detect_profile(&[0x00, 0xFF, 0x12, 0x02]).unwrap(),
+            Profile::Schip
+        );
+        // LD I, long 0300; JP 204
+        assert_eq!(
+            detect_profile(&[0xF0, 0x00, 0x03, 0x00, 0x12, 0x04]).unwrap(),
+            Profile::XoChip
+        );
+    }
+
+    #[test]
+    fn scans_only_roms() {
+        let directory = std::env::temp_dir().join(format!("chip8-library-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("b.ch8"), [0x12, 0x00]).unwrap();
+        fs::write(directory.join("a.C8"), [0x00, 0xFF, 0x12, 0x02]).unwrap();
+        fs::write(directory.join("notes.txt"), "not a ROM").unwrap();
+
+        let roms = scan(&directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        let names = roms.iter().map(Rom::name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.C8", "b.ch8"]);
+        assert_eq!(roms[0].profile, Profile::Schip);
+        assert_eq!(roms[1].size, 2);
+    }
+}
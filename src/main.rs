@@ -1,56 +1,517 @@
 #[macro_use]
 extern crate log;
 
-mod cli;
-mod display;
-mod error;
-mod instruction;
-mod interpreter;
-
-use cli::Subcommand::*;
-use error::Chip8Error;
-use instruction::Instruction;
-use interpreter::State;
+use chip8::{
+    analysis, assembler, avsync, bisect, bundle,
+    cli::{self, MachineOptions, SaveStateOptions, Subcommand::*},
+    config,
+    debugger::{self, Debugger},
+    decompile, difftest, disasm, doctor,
+    error::{Chip8Error, ExitCode},
+    input,
+    interpreter::{self, RunOptions, State, Variant},
+    library, memory, octo, opcodes,
+    persistence::FilePersistence,
+    profiler, quirks, romdb, sprites, verify,
+};
 use std::{
-    convert::TryInto,
-    fs::File,
+    cell::RefCell,
+    fs::{self, File},
     io::{BufReader, Read},
+    path::Path,
+    process,
+    rc::Rc,
 };
 use structopt::StructOpt;
 
-fn read_be_u16(input: &mut &[u8]) -> u16 {
-    let (int_bytes, rest) = input.split_at(std::mem::size_of::<u16>());
-    *input = rest;
-    u16::from_be_bytes(int_bytes.try_into().unwrap())
-}
-
-fn main() -> Result<(), Chip8Error> {
+fn main() {
+    // There's no command line on a web page, which runs programs with
+    // `web::Emulator` instead
+    if cfg!(target_arch = "wasm32") {
+        return;
+    }
     let options = cli::Arguments::from_args();
     let mut verbose = options.verbose;
-    cli::install_logger(&mut verbose);
+    cli::install_logger(&mut verbose, options.subcommand.is_headless());
 
-    match options.subcommand {
-        Print { input_file_path } => {
-            let file = BufReader::new(File::open(input_file_path)?);
-            let contents = file.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?;
-            for mut multibytes in contents.as_slice().chunks_exact(2) {
-                let bytes = read_be_u16(&mut multibytes);
-                let instruction: Instruction = bytes.try_into()?;
-                println!("{:04X} => {}", bytes, instruction);
+    let mut subcommand = options.subcommand;
+    let result = fill_in_machine(&mut subcommand).and_then(|()| run_subcommand(subcommand));
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(ExitCode::from(&e) as i32);
+    }
+}
+
+/// Fill in what the command line didn't say about the machine to emulate:
+/// for `run`, from what the ROM database knows about the ROM, and then from
+/// the config file.
+fn fill_in_machine(subcommand: &mut cli::Subcommand) -> Result<(), Chip8Error> {
+    if let Run {
+        input_file_path: Some(path),
+        machine,
+        ..
+    } = subcommand
+    {
+        let sha1 = library::fingerprint(&fs::read(path)?);
+        if let Some(info) = romdb::RomDatabase::load()?.get(&sha1) {
+            info!("Found {} in the ROM database", info);
+            machine.apply_rom_info(info);
+        }
+    }
+    if let Some(machine) = subcommand.machine_mut() {
+        machine.apply_config(&config::Config::load()?);
+    }
+    Ok(())
+}
+
+fn run_subcommand(subcommand: cli::Subcommand) -> Result<(), Chip8Error> {
+    match subcommand {
+        Assemble {
+            input_file_path,
+            output,
+            target,
+            strip_dead_code,
+            size_report,
+            listing,
+        } => {
+            let source = fs::read_to_string(&input_file_path)?;
+            let assembly = assembler::assemble_with_report(&source, target, strip_dead_code)?;
+            let report = &assembly.report;
+            if size_report {
+                print!("{}", report);
+            } else if !report.removed.is_empty() {
+                println!("Removed as unused: {}", report.removed.join(", "));
             }
+            if let Some(listing) = listing {
+                fs::write(listing, &assembly.listing)?;
+            }
+            let output = output.unwrap_or_else(|| input_file_path.with_extension("ch8"));
+            fs::write(output, &assembly.program)?;
         }
-        Trace { input_file_path } => {
-            let file = BufReader::new(File::open(input_file_path)?);
-            let contents = file.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?;
-            let mut state = State::with_program(&contents);
-            interpreter::run(&mut state, true)?;
+        Build {
+            input_file_path,
+            output,
+        } => {
+            let program = octo::compile(&fs::read_to_string(&input_file_path)?)?;
+            let output = output.unwrap_or_else(|| input_file_path.with_extension("ch8"));
+            fs::write(output, program)?;
         }
-        Run { input_file_path } => {
+        Decompile {
+            input_file_path,
+            output,
+        } => {
+            let source = decompile::decompile(&fs::read(&input_file_path)?)?;
+            let output = output.unwrap_or_else(|| input_file_path.with_extension("8o"));
+            fs::write(output, source)?;
+        }
+        Bisect {
+            input_file_path,
+            reference,
+            cycles,
+            machine,
+        } => {
+            let reference = bisect::Reference {
+                cycles,
+                screen: fs::read_to_string(reference)?,
+                seed: machine.seed(),
+            };
+            let program = fs::read(input_file_path)?;
+            let bisection =
+                bisect::bisect(&program, machine.variant(), machine.quirks(), &reference)?;
+            print!("{}", bisection);
+        }
+        Difftest {
+            input_file_path,
+            reference,
+            machine,
+        } => {
+            let reference = difftest::read_reference(&fs::read_to_string(reference)?)?;
+            let comparison = difftest::compare(
+                &fs::read(input_file_path)?,
+                machine.variant(),
+                machine.quirks(),
+                machine.seed(),
+                &reference,
+            )?;
+            print!("{}", comparison);
+            if let Some(divergence) = comparison.divergence {
+                return Err(Chip8Error::Diverged(divergence.cycle));
+            }
+        }
+        AvSync { profile, frames } => print!("{}", avsync::check(profile, frames)?),
+        Check { input_file_path } => {
+            let problems = analysis::check(&fs::read(input_file_path)?)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(Chip8Error::RomProblems(problems.len()));
+            }
+            println!("No problems found");
+        }
+        Hexdump {
+            input_file_path,
+            start,
+            width,
+        } => {
+            let state = State::with_program(&fs::read(input_file_path)?)?;
+            let start = (start as usize).min(state.program_area().end());
+            let region =
+                memory::MemoryRegion::new(state.memory(), start..state.program_area().end());
+            print!("{}", region.hexdump(width, &debugger::pointers(&state)));
+        }
+        Config {
+            command: cli::ConfigCommand::Init { force },
+        } => {
+            let path = config::init(force)?;
+            println!("Wrote {}", path.display());
+        }
+        Browse { directory, machine } => {
+            // The config file isn't applied yet (see `Subcommand::machine_mut`),
+            // so that what's known about each ROM can win over it
+            let config = config::Config::load()?;
+            let database = romdb::RomDatabase::load()?;
+            let roms = library::scan(&directory)?;
+            if roms.is_empty() {
+                println!("Found no .ch8 or .c8 ROMs in {}", directory.display());
+            }
+            let mut selected = 0;
+            while !roms.is_empty() {
+                selected = match library::choose(&roms, selected)? {
+                    Some(index) => index,
+                    None => break,
+                };
+                let rom = &roms[selected];
+                let mut machine = machine.clone();
+                if let Some(info) = database.get(&rom.sha1) {
+                    info!("Found {} in the ROM database", info);
+                    machine.apply_rom_info(info);
+                }
+                // A ROM that only needs the COSMAC VIP runs however the
+                // config file says
+                if rom.profile != quirks::Profile::Cosmac {
+                    machine.default_profile(rom.profile);
+                }
+                machine.apply_config(&config);
+                let save_states = SaveStateOptions::default();
+                let mut state =
+                    build_state(&rom.path, memory::PROGRAM_START, &machine, &save_states)?;
+                interpreter::run(
+                    &mut state,
+                    &run_options(&rom.path, &machine, &save_states),
+                    None,
+                )?;
+            }
+        }
+        Sprites {
+            input_file_path,
+            at,
+            len,
+        } => {
+            let program = fs::read(input_file_path)?;
+            let sprites = match at {
+                Some(address) => vec![sprites::sprite_at(&program, address, len.unwrap_or(15))?],
+                None => sprites::find_sprites(&program)?,
+            };
+            if sprites.is_empty() {
+                println!("Found no LD I, nnn followed by DRW, so try --at");
+            }
+            for sprite in sprites {
+                println!("{}", sprite);
+            }
+        }
+        Verify {
+            source_file_path,
+            rom_file_path,
+        } => {
+            let source = fs::read_to_string(source_file_path)?;
+            let verification = verify::verify(&source, &fs::read(rom_file_path)?)?;
+            print!("{}", verification);
+            if verification.mismatches() > 0 {
+                return Err(Chip8Error::Mismatch(verification.mismatches()));
+            }
+        }
+        Doctor => {
+            let checks = doctor::run_checks();
+            for check in &checks {
+                println!("{}", check);
+            }
+            let failed = checks
+                .iter()
+                .filter(|check| check.status == doctor::Status::Failed)
+                .count();
+            if failed > 0 {
+                return Err(Chip8Error::ChecksFailed(failed));
+            }
+        }
+        ExitCodes => {
+            for code in &ExitCode::ALL {
+                println!("{:>3}  {:?}: {}", *code as i32, code, code.description());
+            }
+        }
+        Opcodes { format } => print!("{}", opcodes::table(format)),
+        Print {
+            input_file_path,
+            all,
+            output,
+            format,
+            data_ranges,
+            load_address,
+        } => match (all, input_file_path) {
+            (Some(directory), _) => {
+                let count = disasm::disassemble_all(&directory, &output)?;
+                println!("Disassembled {} ROMs into {}", count, output.display());
+            }
+            (None, Some(input_file_path)) => {
+                let file = BufReader::new(File::open(input_file_path)?);
+                let contents = file.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?;
+                let disassembly =
+                    disasm::disassemble_with_data(&contents, load_address, &data_ranges)?;
+                print!("{}", disassembly.render(format));
+            }
+            // structopt makes sure there's one or the other
+            (None, None) => unreachable!(),
+        },
+        Trace {
+            input_file_path,
+            trace_file,
+            dump_state_every,
+            load_address,
+            machine,
+            save_states,
+        } => {
+            let mut state = build_state(
+                &input_file_path,
+                load_address as usize,
+                &machine,
+                &save_states,
+            )?;
+            let options = RunOptions {
+                verbosely: true,
+                trace_file,
+                dump_state_every,
+                ..run_options(&input_file_path, &machine, &save_states)
+            };
+            interpreter::run(&mut state, &options, None)?;
+        }
+        Run {
+            input_file_path,
+            bundle,
+            trace_file,
+            record,
+            replay,
+            debug_port,
+            load_address,
+            mut machine,
+            save_states,
+            headless,
+        } => {
+            let (mut state, input_file_path) = match (bundle, input_file_path) {
+                (Some(path), _) => {
+                    let bundle = bundle::Bundle::load(&path)?;
+                    print!("{}", bundle);
+                    machine.seed = Some(bundle.seed);
+                    (bundle.machine(&machine.appearance())?, path)
+                }
+                (None, Some(path)) => (
+                    build_state(&path, load_address as usize, &machine, &save_states)?,
+                    path,
+                ),
+                // structopt makes sure there's one or the other
+                (None, None) => unreachable!(),
+            };
+            let replay = match replay {
+                Some(path) => Some(input::Replay::load(&path)?),
+                None => None,
+            };
+            if let Some(replay) = &replay {
+                machine.seed = Some(replay.seed);
+            }
+            let options = RunOptions {
+                trace_file,
+                record,
+                replay,
+                headless: headless.is_headless(),
+                max_cycles: headless.max_cycles,
+                screenshot_at_cycle: headless.screenshot_at_cycle,
+                ..run_options(&input_file_path, &machine, &save_states)
+            };
+            let mut debugger = match debug_port {
+                Some(port) => Some(Debugger::listen(port)?),
+                None => None,
+            };
+            interpreter::run(&mut state, &options, debugger.as_mut())?;
+            if options.headless {
+                println!("{}", state);
+            }
+            if let Some(path) = headless.dump_display {
+                fs::write(path, state.buffer().pretty_print_logical())?;
+            }
+        }
+        Bundle {
+            input_file_path,
+            output,
+            notes,
+            machine,
+            save_states,
+        } => {
+            // Include the state from `--load-state`, or else the one F5 saved
+            let state_path = match &save_states.load_state {
+                Some(path) => Some(path.clone()),
+                None => {
+                    Some(save_states.save_state_path(&input_file_path)).filter(|path| path.exists())
+                }
+            };
+            let state = match state_path {
+                Some(path) => {
+                    let bytes = fs::read(path)?;
+                    // Make sure it really is a saved state
+                    State::deserialize(&bytes)?;
+                    Some(bytes)
+                }
+                None => None,
+            };
+            let bundle = bundle::Bundle {
+                name: input_file_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                program: fs::read(&input_file_path)?,
+                variant: machine.variant(),
+                quirks: machine.quirks(),
+                timing: machine.timing,
+                seed: machine.seed(),
+                state,
+                notes,
+            };
+            let output = output.unwrap_or_else(|| input_file_path.with_extension("bundle"));
+            bundle.save(&output)?;
+            print!("{}", bundle);
+            println!("Wrote {}", output.display());
+        }
+        Profile {
+            input_file_path,
+            cycles,
+            top,
+            machine,
+        } => {
+            let program = fs::read(input_file_path)?;
+            let profile = profiler::profile(
+                &program,
+                machine.variant(),
+                machine.quirks(),
+                cycles,
+                machine.seed(),
+                top,
+            )?;
+            print!("{}", profile);
+        }
+        Compare {
+            input_file_path,
+            cycles,
+        } => {
             let file = BufReader::new(File::open(input_file_path)?);
             let contents = file.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?;
-            let mut state = State::with_program(&contents);
-            interpreter::run(&mut state, false)?;
+            analysis::compare_profiles(&contents, cycles)?;
+        }
+        Debug {
+            input_file_path,
+            machine,
+            save_states,
+        } => {
+            let mut state = build_state(
+                &input_file_path,
+                memory::PROGRAM_START,
+                &machine,
+                &save_states,
+            )?;
+            let options = run_options(&input_file_path, &machine, &save_states);
+            println!("Paused at the first instruction. Type `help` for commands.");
+            interpreter::run(&mut state, &options, Some(&mut Debugger::new()))?;
         }
     };
     Ok(())
 }
+
+/// How to run the program, with a window and no limits.
+fn run_options(
+    input_file_path: &Path,
+    machine: &MachineOptions,
+    save_states: &SaveStateOptions,
+) -> RunOptions {
+    RunOptions {
+        verbosely: false,
+        save_state_path: save_states.save_state_path(input_file_path),
+        ignore_unknown: machine.ignore_unknown,
+        progress_every: machine.progress_every,
+        headless: false,
+        window_backend: machine.frontend,
+        resizable: machine.resizable,
+        fullscreen: machine.fullscreen,
+        reduce_flashing: machine.reduce_flashing,
+        idle_pause: machine.idle_pause,
+        keymap: machine.keymap.unwrap_or_default(),
+        shader: machine.shader,
+        phosphor: machine.phosphor,
+        seed: machine.seed(),
+        trace_file: None,
+        dump_state_every: None,
+        record: None,
+        replay: None,
+        max_cycles: None,
+        screenshot_path: input_file_path.with_extension("pbm"),
+        screenshot_at_cycle: None,
+    }
+}
+
+/// The machine to run: either resumed from `--load-state` (which keeps the
+/// saved screen's size and colors), or a fresh one with the program loaded at
+/// `load_address`. Either way, it keeps the RPL user flags in files (see
+/// `FilePersistence`).
+fn build_state(
+    input_file_path: &Path,
+    load_address: usize,
+    machine: &MachineOptions,
+    save_states: &SaveStateOptions,
+) -> Result<State, Chip8Error> {
+    let state = match &save_states.load_state {
+        Some(path) => State::deserialize(&fs::read(path)?)?,
+        None => load_program(input_file_path, load_address, machine)?,
+    };
+    match FilePersistence::directory() {
+        Some(directory) => {
+            state.with_persistence(Rc::new(RefCell::new(FilePersistence::new(directory))))
+        }
+        None => Ok(state),
+    }
+}
+
+/// A fresh machine with the program at `input_file_path` loaded at
+/// `load_address`.
+fn load_program(
+    input_file_path: &Path,
+    load_address: usize,
+    machine: &MachineOptions,
+) -> Result<State, Chip8Error> {
+    let file = BufReader::new(File::open(input_file_path)?);
+    let contents = file.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?;
+    let variant = machine.variant();
+    let state = State::with_program_at(variant, load_address, &contents)
+        .map_err(|e| {
+            // Only XO-CHIP has room for ROMs bigger than about 3.5KB
+            let fits_xo_chip = load_address + contents.len() <= memory::XO_CHIP_MEMORY_SIZE;
+            if let Chip8Error::ProgramTooLarge { .. } = e {
+                if variant != Variant::XoChip && fits_xo_chip {
+                    eprintln!("It would fit in XO-CHIP's 64KB of memory, so try --variant xo-chip");
+                }
+            }
+            e
+        })?
+        .with_quirks(machine.quirks())
+        .with_timing(machine.timing)
+        .with_appearance(&machine.appearance());
+    Ok(match machine.clock_hz {
+        Some(hz) => state.with_clock_hz(hz),
+        None => state,
+    })
+}
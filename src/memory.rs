@@ -0,0 +1,216 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, Debug, Formatter},
+    ops::Range,
+    rc::Rc,
+};
+
+/// 4KB = 4096 bytes of RAM.
+pub const MEMORY_SIZE: usize = 0x1000;
+
+/// XO-CHIP has 64KB of RAM, which programs reach with the 16-bit long
+/// `LD I, nnnn`, so its programs can be much bigger.
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+
+/// The first 512 bytes (0x000 to 0x1FF) are for the interpreter and not to be
+/// used by programs. This is where the fonts live.
+pub const FONT_AREA: Range<usize> = 0x000..0x200;
+
+/// Most CHIP-8 programs start at 0x200 = 512, right after the interpreter area.
+pub const PROGRAM_START: usize = FONT_AREA.end;
+
+/// A read-only view of one region of memory that knows where it starts, so
+/// callers don't have to hardcode addresses to make sense of it.
+#[derive(Debug, PartialEq)]
+pub struct MemoryRegion<'a> {
+    /// The address of the first byte in the region
+    pub start: usize,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> MemoryRegion<'a> {
+    /// View `memory[range]`.
+    pub fn new(memory: &'a [u8], range: Range<usize>) -> Self {
+        Self {
+            start: range.start,
+            bytes: &memory[range],
+        }
+    }
+
+    /// The address just past the last byte in the region.
+    pub fn end(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+
+    /// The region as a hex and ASCII dump, `width` bytes to a line. A line
+    /// with a byte that one of `pointers` (like `("PC", 0x202)`) points at
+    /// says so at the end:
+    ///
+    ///     200: 00 E0 A2 2A 60 0C 61 08  |...*`.a.|  <- PC=202
+    ///
+    /// Bytes that aren't printable ASCII are shown as `.`.
+    pub fn hexdump(&self, width: usize, pointers: &[(&str, usize)]) -> String {
+        let mut dump = String::new();
+        for (row, bytes) in self.bytes.chunks(width).enumerate() {
+            let start = self.start + row * width;
+            let hex = bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = bytes
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                })
+                .collect::<String>();
+            let pointed_at = pointers
+                .iter()
+                .filter(|(_, address)| (start..start + bytes.len()).contains(address))
+                .map(|(name, address)| format!("{}={:03X}", name, address))
+                .collect::<Vec<_>>();
+            let line = format!(
+                "{:03X}: {:<hex_width$}  |{:<width$}|",
+                start,
+                hex,
+                ascii,
+                hex_width = width * 3 - 1,
+                width = width
+            );
+            if pointed_at.is_empty() {
+                dump.push_str(&line);
+            } else {
+                dump.push_str(&format!("{}  <- {}", line, pointed_at.join(", ")));
+            }
+            dump.push('\n');
+        }
+        dump
+    }
+}
+
+/// Something outside the machine that sees what the program reads from and
+/// writes to some of memory (see `State::with_memory_hook`), to act like a
+/// peripheral mapped into it, or to log self-modifying code. It's called once
+/// for each byte. Fetching instructions from hooked addresses goes through
+/// hooks too, so a hook can map in code as well as data.
+pub trait MemoryHook {
+    /// The program is reading `value` from `address`. Returns the byte it
+    /// gets instead (by default, the same one).
+    fn read(&mut self, _address: usize, value: u8) -> u8 {
+        value
+    }
+
+    /// The program is writing `value` to `address`. Returns whether to store
+    /// it in memory (by default, yes).
+    fn write(&mut self, _address: usize, _value: u8) -> bool {
+        true
+    }
+}
+
+/// A hook that the machine shares with whoever added it, so they can still
+/// look at it while the program runs.
+pub type SharedMemoryHook = Rc<RefCell<dyn MemoryHook>>;
+
+/// The hooks on a machine, with the addresses each one sees, in the order
+/// they were added. Copies of the machine share the same hooks. They aren't
+/// part of the machine itself: saved states leave them out (so going back to
+/// one keeps the machine's hooks, see `State::restore`), and any two sets of
+/// hooks are equal.
+#[derive(Clone, Default)]
+pub struct MemoryHooks(Vec<(Range<usize>, SharedMemoryHook)>);
+
+impl MemoryHooks {
+    pub fn add(&mut self, addresses: Range<usize>, hook: SharedMemoryHook) {
+        self.0.push((addresses, hook));
+    }
+
+    /// Whether any hook sees any of these addresses.
+    pub fn covers(&self, addresses: Range<usize>) -> bool {
+        self.0
+            .iter()
+            .any(|(hooked, _)| hooked.start < addresses.end && addresses.start < hooked.end)
+    }
+
+    /// Let the hooks see a read of `bytes` from `start`, and change what's
+    /// read.
+    pub fn read(&self, start: usize, bytes: &mut [u8]) {
+        for (addresses, hook) in &self.0 {
+            for (address, byte) in (start..).zip(bytes.iter_mut()) {
+                if addresses.contains(&address) {
+                    *byte = hook.borrow_mut().read(address, *byte);
+                }
+            }
+        }
+    }
+
+    /// Let the hooks see a write of `bytes` to `start`. Returns whether to
+    /// store each byte: only if every hook that sees it agrees.
+    pub fn write(&self, start: usize, bytes: &[u8]) -> Vec<bool> {
+        let mut store = vec![true; bytes.len()];
+        for (addresses, hook) in &self.0 {
+            for ((address, byte), store) in (start..).zip(bytes).zip(store.iter_mut()) {
+                if addresses.contains(&address) {
+                    // Every hook sees the write, even after one refuses it
+                    *store &= hook.borrow_mut().write(address, *byte);
+                }
+            }
+        }
+        store
+    }
+}
+
+impl Debug for MemoryHooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(addresses, _)| addresses))
+            .finish()
+    }
+}
+
+impl PartialEq for MemoryHooks {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Parse an address as hex, with or without a leading "0x", like `0x200` or
+/// `200`.
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Not a hex number: {}", s))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn region_bounds() {
+        let memory = [0, 1, 2, 3, 4, 5];
+        let region = MemoryRegion::new(&memory, 2..5);
+
+        assert_eq!(region.bytes, &[2, 3, 4]);
+        assert_eq!(region.start, 2);
+        assert_eq!(region.end(), 5);
+    }
+
+    #[test]
+    fn hexdump_marks_pointers() {
+        let memory = [0x00, 0xE0, 0x41, 0x42, 0x7F, 0x20];
+        let region = MemoryRegion::new(&memory, 0..6);
+
+        assert_eq!(
+            region.hexdump(4, &[("PC", 0x4), ("I", 0x5)]),
+            "000: 00 E0 41 42  |..AB|\n\
+             004: 7F 20        |.   |  <- PC=004, I=005\n"
+        );
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(parse_address("0x200"), Ok(0x200));
+        assert_eq!(parse_address("2A0"), Ok(0x2A0));
+        assert!(parse_address("xyz").is_err());
+    }
+}
@@ -0,0 +1,844 @@
+use crate::{
+    error::Chip8Error,
+    instruction::{Address, Instruction, Register},
+    memory,
+};
+use std::{collections::HashMap, convert::TryFrom};
+
+/// Compile a program written in John Earnest's Octo language into a ROM that
+/// will be loaded at 0x200. It understands:
+/// - labels, like `: loop`, which `jump`, `jump0`, `i :=`, and `:call` can use,
+///   and which are called just by naming them
+/// - `:const NAME VALUE` and `:alias NAME vX`
+/// - `:org ADDRESS`, and bytes of data, either bare (`0xFF 0x81`) or with `:byte`
+/// - `if ... then` (for one instruction), `if ... begin ... else ... end`, and
+///   `loop ... while ... again`, with `==` and `!=` conditions
+/// - the statements for every instruction that `Instruction` has, like
+///   `v0 := 5`, `i := sprite`, `sprite v0 v1 8`, and `save v1 - v3`
+/// - comments, from `#` to the end of the line
+///
+/// Numbers can be decimal, hex (`0x`), or binary (`0b`). Like Octo, if there's
+/// a `: main` that isn't at 0x200, the program starts with a jump to it.
+/// Statements that need an instruction chip8 doesn't have yet, like
+/// `v0 := v1`, are an error.
+pub fn compile(source: &str) -> Result<Vec<u8>, Chip8Error> {
+    let compiled = Compiler::new(source, memory::PROGRAM_START).run()?;
+    match compiled.labels.get("main") {
+        Some(&main) if usize::from(main) != memory::PROGRAM_START => {
+            // Compile it again with room for the jump, which moves main too
+            let mut compiled = Compiler::new(source, memory::PROGRAM_START + 2).run()?;
+            let main = compiled.labels["main"];
+            let jump = Instruction::JP(Address::new(main)?).to_bytes()?;
+            compiled.rom[..2].copy_from_slice(&jump);
+            Ok(compiled.rom)
+        }
+        _ => Ok(compiled.rom),
+    }
+}
+
+/// What an instruction that uses a label does with it, to make the
+/// instruction once the label's address is known.
+#[derive(Clone, Copy)]
+enum Reference {
+    Jump,
+    JumpOffset,
+    Call,
+    LoadI,
+    LongLoadI,
+}
+
+impl Reference {
+    fn instruction(self, address: u16) -> Result<Instruction, String> {
+        if let Reference::LongLoadI = self {
+            return Ok(Instruction::LDILong(address));
+        }
+        let address = checked_address(address)?;
+        Ok(match self {
+            Reference::Jump => Instruction::JP(address),
+            Reference::JumpOffset => Instruction::JPOffset(address),
+            Reference::Call => Instruction::CALL(address),
+            Reference::LoadI => Instruction::LDI(address),
+            Reference::LongLoadI => unreachable!(),
+        })
+    }
+}
+
+/// An instruction that uses a label before it's defined, to fill in at the
+/// end.
+struct Fixup {
+    line: usize,
+    address: usize,
+    label: String,
+    reference: Reference,
+}
+
+/// Which way an `==`, `!=`, `key`, or `-key` condition goes.
+struct Condition {
+    register: Register,
+    equal: bool,
+    other: Operand,
+}
+
+enum Operand {
+    Register(Register),
+    Byte(u8),
+    /// Whether the key in the register is held down, for `key` (equal) and
+    /// `-key` (not equal)
+    Key,
+}
+
+impl Condition {
+    /// The instruction that skips the next one unless the condition holds,
+    /// for `if ... then`. Octo's conditionals say when to run the next
+    /// instruction, so they're the opposite of the skip instructions.
+    fn then(&self) -> Instruction {
+        match (&self.other, self.equal) {
+            (Operand::Byte(byte), true) => Instruction::SNEByte(self.register, *byte),
+            (Operand::Byte(byte), false) => Instruction::SEByte(self.register, *byte),
+            (Operand::Register(other), true) => Instruction::SNERegister(self.register, *other),
+            (Operand::Register(other), false) => Instruction::SERegister(self.register, *other),
+            (Operand::Key, true) => Instruction::SKNP(self.register),
+            (Operand::Key, false) => Instruction::SKP(self.register),
+        }
+    }
+
+    /// The instruction that skips the next one if the condition holds, to
+    /// jump past a block that only runs when it does.
+    fn unless(self) -> Instruction {
+        Condition {
+            equal: !self.equal,
+            ..self
+        }
+        .then()
+    }
+}
+
+struct Compiler<'a> {
+    /// Every word in the source, with the line it's on
+    tokens: Vec<(usize, &'a str)>,
+    /// Which token is next
+    position: usize,
+    /// The line of the last token read, for errors
+    line: usize,
+    /// The program so far, starting at 0x200
+    rom: Vec<u8>,
+    /// Where the next byte goes
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, Register>,
+    fixups: Vec<Fixup>,
+    /// For each `begin` or `else` that hasn't ended, its line and the jump
+    /// past it, to point at the `else` or `end`
+    branches: Vec<(usize, usize)>,
+    /// For each `loop` that hasn't ended, its line, where it starts, and each
+    /// `while`'s jump out of it
+    loops: Vec<(usize, usize, Vec<usize>)>,
+}
+
+/// Octo's words that can't be the name of a label, a constant, or an alias.
+const KEYWORDS: &[&str] = &[
+    "return",
+    ";",
+    "clear",
+    "exit",
+    "lores",
+    "hires",
+    "scroll-down",
+    "scroll-left",
+    "scroll-right",
+    "audio",
+    "plane",
+    "jump",
+    "jump0",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "while",
+    "again",
+    "i",
+    "buzzer",
+    "delay",
+    "pitch",
+    "key",
+    "-key",
+    "random",
+    "long",
+    "bighex",
+    "hex",
+    "bcd",
+];
+
+impl<'a> Compiler<'a> {
+    fn new(source: &'a str, start: usize) -> Self {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(n, line)| {
+                let code = match line.find('#') {
+                    Some(comment) => &line[..comment],
+                    None => line,
+                };
+                code.split_whitespace().map(move |token| (n + 1, token))
+            })
+            .collect();
+        Self {
+            tokens,
+            position: 0,
+            line: 1,
+            rom: vec![0; start - memory::PROGRAM_START],
+            here: start,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: vec![],
+            branches: vec![],
+            loops: vec![],
+        }
+    }
+
+    /// Compile every statement, then fill in the labels.
+    fn run(mut self) -> Result<Self, Chip8Error> {
+        let error = |line, message| Chip8Error::Assemble { line, message };
+        while self.position < self.tokens.len() {
+            self.statement()
+                .map_err(|message| error(self.line, message))?;
+        }
+        if let Some((line, _)) = self.branches.first() {
+            return Err(error(*line, "This `begin` has no `end`".to_string()));
+        }
+        if let Some((line, _, _)) = self.loops.first() {
+            return Err(error(*line, "This `loop` has no `again`".to_string()));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| error(fixup.line, format!("Unknown label `{}`", fixup.label)))?;
+            let instruction = fixup
+                .reference
+                .instruction(address)
+                .map_err(|message| error(fixup.line, message))?;
+            self.patch(fixup.address, &instruction)
+                .map_err(|message| error(fixup.line, message))?;
+        }
+        Ok(self)
+    }
+
+    fn next_token(&mut self) -> Result<&'a str, String> {
+        let (line, token) = *self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| "The program ends in the middle of a statement".to_string())?;
+        self.position += 1;
+        self.line = line;
+        Ok(token)
+    }
+
+    /// Read the next token, which has to be `expected`.
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next_token()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("Expected `{}`, got `{}`", expected, token)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        use Instruction::*;
+
+        let token = self.next_token()?;
+        match token {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(name) {
+                    return Err(format!("The label `{}` is already defined", name));
+                }
+                let address = u16::try_from(self.here)
+                    .map_err(|_| format!("The label `{}` is past the end of memory", name))?;
+                self.labels.insert(name.to_string(), address);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next_token().and_then(|token| self.number(token))?;
+                self.constants.insert(name.to_string(), value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.next_token().and_then(|token| self.register(token))?;
+                self.aliases.insert(name.to_string(), register);
+            }
+            ":org" => {
+                let address = self.next_token().and_then(|token| self.number(token))?;
+                if address < memory::PROGRAM_START as i64
+                    || address >= memory::XO_CHIP_MEMORY_SIZE as i64
+                {
+                    return Err(format!("Can't put code at {:X}", address));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let byte = self.next_token().and_then(|token| self.byte(token))?;
+                self.emit_bytes(&[byte])?;
+            }
+            ":call" => self.reference(Reference::Call)?,
+            ":breakpoint" => {
+                self.next_token()?;
+            }
+            "return" | ";" => self.emit(RET())?,
+            "clear" => self.emit(CLS())?,
+            "exit" => self.emit(EXIT())?,
+            "lores" => self.emit(LOW())?,
+            "hires" => self.emit(HIGH())?,
+            "scroll-left" => self.emit(SCL())?,
+            "scroll-right" => self.emit(SCR())?,
+            "audio" => self.emit(AUDIO())?,
+            "scroll-down" => {
+                let n = self.next_token().and_then(|token| self.nibble(token))?;
+                self.emit(SCD(n))?;
+            }
+            "plane" => {
+                let n = self.next_token().and_then(|token| self.nibble(token))?;
+                self.emit(PLANE(n))?;
+            }
+            "jump" => self.reference(Reference::Jump)?,
+            "jump0" => self.reference(Reference::JumpOffset)?,
+            "save" | "load" => {
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next_token()?;
+                    let y = self.next_token().and_then(|token| self.register(token))?;
+                    if token == "save" {
+                        SAVE(x, y)
+                    } else {
+                        LOAD(x, y)
+                    }
+                } else if token == "save" {
+                    LDIntoMemory(x)
+                } else {
+                    LDFromMemory(x)
+                };
+                self.emit(instruction)?;
+            }
+            "saveflags" => {
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                self.emit(LDIntoFlags(x))?;
+            }
+            "loadflags" => {
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                self.emit(LDFromFlags(x))?;
+            }
+            "sprite" => {
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                let y = self.next_token().and_then(|token| self.register(token))?;
+                let n = self.next_token().and_then(|token| self.nibble(token))?;
+                self.emit(DRW(x, y, n))?;
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next_token()? {
+                    "then" => self.emit(condition.then())?,
+                    "begin" => {
+                        self.emit(condition.unless())?;
+                        let jump = self.placeholder_jump()?;
+                        self.branches.push((self.line, jump));
+                    }
+                    other => return Err(format!("Expected `then` or `begin`, got `{}`", other)),
+                }
+            }
+            "else" => {
+                let (_, jump) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| "This `else` has no `if ... begin`".to_string())?;
+                let past_else = self.placeholder_jump()?;
+                self.branches.push((self.line, past_else));
+                self.patch(jump, &JP(self.address_here()?))?;
+            }
+            "end" => {
+                let (_, jump) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| "This `end` has no `if ... begin`".to_string())?;
+                self.patch(jump, &JP(self.address_here()?))?;
+            }
+            "loop" => self.loops.push((self.line, self.here, vec![])),
+            "while" => {
+                let condition = self.condition()?;
+                if self.loops.is_empty() {
+                    return Err("This `while` isn't in a `loop`".to_string());
+                }
+                self.emit(condition.unless())?;
+                let jump = self.placeholder_jump()?;
+                if let Some((_, _, exits)) = self.loops.last_mut() {
+                    exits.push(jump);
+                }
+            }
+            "again" => {
+                let (_, start, exits) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| "This `again` has no `loop`".to_string())?;
+                let start = checked_address(start as u16)?;
+                self.emit(JP(start))?;
+                for exit in exits {
+                    self.patch(exit, &JP(self.address_here()?))?;
+                }
+            }
+            "i" => match self.next_token()? {
+                ":=" => match self.peek() {
+                    Some("long") => {
+                        self.next_token()?;
+                        self.reference(Reference::LongLoadI)?;
+                    }
+                    Some("bighex") => {
+                        self.next_token()?;
+                        let x = self.next_token().and_then(|token| self.register(token))?;
+                        self.emit(LDHF(x))?;
+                    }
+                    Some("hex") => {
+                        self.next_token()?;
+                        let x = self.next_token().and_then(|token| self.register(token))?;
+                        self.emit(LDF(x))?;
+                    }
+                    _ => self.reference(Reference::LoadI)?,
+                },
+                "+=" => {
+                    let x = self.next_token().and_then(|token| self.register(token))?;
+                    self.emit(ADDI(x))?;
+                }
+                other => return Err(unsupported(&format!("i {}", other))),
+            },
+            "buzzer" | "delay" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                self.emit(match token {
+                    "buzzer" => LDST(x),
+                    "delay" => LDDT(x),
+                    _ => PITCH(x),
+                })?;
+            }
+            "bcd" => {
+                let x = self.next_token().and_then(|token| self.register(token))?;
+                self.emit(LDB(x))?;
+            }
+            _ => {
+                if let Ok(x) = self.register(token) {
+                    return self.assignment(x);
+                }
+                if let Ok(byte) = self.byte(token) {
+                    return self.emit_bytes(&[byte]);
+                }
+                if KEYWORDS.contains(&token) || token.starts_with(':') {
+                    return Err(unsupported(token));
+                }
+                // Naming a label calls it
+                self.position -= 1;
+                self.reference(Reference::Call)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A statement that starts with a register, like `v0 += 1`.
+    fn assignment(&mut self, x: Register) -> Result<(), String> {
+        use Instruction::*;
+
+        let operator = self.next_token()?;
+        let operand = self.next_token()?;
+        let instruction = match (operator, operand) {
+            (":=", "random") => {
+                let mask = self.next_token().and_then(|token| self.byte(token))?;
+                RND(x, mask)
+            }
+            (":=", "key") => LDKey(x),
+            (":=", "delay") => LDFromDT(x),
+            (":=", value) => match self.register(value) {
+                Ok(y) => LDRegister(x, y),
+                Err(_) => LDByte(x, self.byte(value)?),
+            },
+            ("+=", value) => match self.register(value) {
+                Ok(y) => ADDRegister(x, y),
+                Err(_) => ADDByte(x, self.byte(value)?),
+            },
+            ("|=", value) => OR(x, self.register(value)?),
+            ("&=", value) => AND(x, self.register(value)?),
+            ("^=", value) => XOR(x, self.register(value)?),
+            ("-=", value) => SUB(x, self.register(value)?),
+            ("=-", value) => SUBN(x, self.register(value)?),
+            (">>=", value) => SHR(x, self.register(value)?),
+            ("<<=", value) => SHL(x, self.register(value)?),
+            (operator, operand) => {
+                return Err(unsupported(&format!(
+                    "v{:x} {} {}",
+                    x.value(),
+                    operator,
+                    operand
+                )))
+            }
+        };
+        self.emit(instruction)
+    }
+
+    /// An `==` or `!=` condition, like `v0 == 5` or `v1 != v2`, or whether
+    /// a key is held down, like `v3 key` or `v3 -key`.
+    fn condition(&mut self) -> Result<Condition, String> {
+        let register = self.next_token().and_then(|token| self.register(token))?;
+        let operator = self.next_token()?;
+        let equal = match operator {
+            "==" => true,
+            "!=" => false,
+            "key" | "-key" => {
+                return Ok(Condition {
+                    register,
+                    equal: operator == "key",
+                    other: Operand::Key,
+                })
+            }
+            other => {
+                return Err(unsupported(&format!(
+                    "if v{:x} {}",
+                    register.value(),
+                    other
+                )))
+            }
+        };
+        let operand = self.next_token()?;
+        let other = match self.register(operand) {
+            Ok(other) => Operand::Register(other),
+            Err(_) => Operand::Byte(self.byte(operand)?),
+        };
+        Ok(Condition {
+            register,
+            equal,
+            other,
+        })
+    }
+
+    /// Read an address or a label and emit `reference`'s instruction for it,
+    /// filling the address in later if the label isn't defined yet.
+    fn reference(&mut self, reference: Reference) -> Result<(), String> {
+        let token = self.next_token()?;
+        let address = match self.number(token) {
+            Ok(n) => Some(u16::try_from(n).map_err(|_| format!("{} isn't an address", token))?),
+            Err(_) => self.labels.get(token).copied(),
+        };
+        match address {
+            Some(address) => self.emit(reference.instruction(address)?),
+            None => {
+                let label = self.name_from(token)?.to_string();
+                self.fixups.push(Fixup {
+                    line: self.line,
+                    address: self.here,
+                    label,
+                    reference,
+                });
+                // Take up the room the instruction will need
+                self.emit(reference.instruction(0)?)
+            }
+        }
+    }
+
+    /// A jump whose target isn't known yet. Returns where it is, to `patch`.
+    fn placeholder_jump(&mut self) -> Result<usize, String> {
+        let address = self.here;
+        self.emit(Instruction::JP(checked_address(0)?))?;
+        Ok(address)
+    }
+
+    fn address_here(&self) -> Result<Address, String> {
+        u16::try_from(self.here)
+            .ok()
+            .and_then(|here| Address::new(here).ok())
+            .ok_or_else(|| {
+                format!(
+                    "{:X} is past the end of 4KB of memory, which a jump can't reach",
+                    self.here
+                )
+            })
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), String> {
+        let bytes = instruction.to_bytes().map_err(|e| e.to_string())?;
+        self.emit_bytes(&bytes)
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let end = self.here + bytes.len();
+        if end > memory::XO_CHIP_MEMORY_SIZE {
+            return Err("The program doesn't fit in memory".to_string());
+        }
+        let start = self.here - memory::PROGRAM_START;
+        if self.rom.len() < end - memory::PROGRAM_START {
+            self.rom.resize(end - memory::PROGRAM_START, 0);
+        }
+        self.rom[start..start + bytes.len()].copy_from_slice(bytes);
+        self.here = end;
+        Ok(())
+    }
+
+    /// Write `instruction` over the one at `address`.
+    fn patch(&mut self, address: usize, instruction: &Instruction) -> Result<(), String> {
+        let bytes = instruction.to_bytes().map_err(|e| e.to_string())?;
+        let start = address - memory::PROGRAM_START;
+        self.rom[start..start + bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|(_, token)| *token)
+    }
+
+    /// Read the name being defined by `:`, `:const`, or `:alias`.
+    fn name(&mut self) -> Result<&'a str, String> {
+        let token = self.next_token()?;
+        self.name_from(token)
+    }
+
+    fn name_from(&self, token: &'a str) -> Result<&'a str, String> {
+        if KEYWORDS.contains(&token)
+            || token.starts_with(':')
+            || parse_number(token).is_some()
+            || token.parse::<Register>().is_ok()
+        {
+            return Err(format!("`{}` can't be a name", token));
+        }
+        Ok(token)
+    }
+
+    fn register(&self, token: &str) -> Result<Register, String> {
+        token
+            .parse()
+            .or_else(|e| self.aliases.get(token).copied().ok_or(e))
+    }
+
+    /// A number, or a constant.
+    fn number(&self, token: &str) -> Result<i64, String> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .ok_or_else(|| format!("Expected a number, got `{}`", token))
+    }
+
+    /// A byte, which can be negative, like -1 for 0xFF.
+    fn byte(&self, token: &str) -> Result<u8, String> {
+        let n = self.number(token)?;
+        if !(-128..=255).contains(&n) {
+            return Err(format!("{} doesn't fit in a byte", token));
+        }
+        Ok(n as u8)
+    }
+
+    fn nibble(&self, token: &str) -> Result<u8, String> {
+        let n = self.number(token)?;
+        if !(0..=0xF).contains(&n) {
+            return Err(format!("{} has to be from 0 to 15", token));
+        }
+        Ok(n as u8)
+    }
+}
+
+/// An address for an instruction, or an error if it's past the 4KB that
+/// instructions can reach.
+fn checked_address(n: u16) -> Result<Address, String> {
+    Address::new(n).map_err(|e| e.to_string())
+}
+
+/// The error for a statement that needs an instruction chip8 doesn't have.
+fn unsupported(statement: &str) -> String {
+    format!("chip8 can't compile `{}` yet", statement)
+}
+
+/// Parse a decimal, `0x` hex, or `0b` binary number, which can be negative.
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let n = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -n } else { n })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Instruction::*;
+
+    fn bytes(instructions: &[Instruction]) -> Vec<u8> {
+        instructions
+            .iter()
+            .flat_map(|instruction| instruction.to_bytes().unwrap())
+            .collect()
+    }
+
+    fn r(n: u8) -> Register {
+        Register::from_nibble(n)
+    }
+
+    #[test]
+    fn compiles_statements_constants_and_aliases() {
+        let source = "
+            :const speed 3
+            :alias x v1
+            : main
+              x := speed   # move this fast
+              x += v2
+              i := ball
+              sprite x v2 8
+              save v0 - v3
+              return
+            : ball
+              0xFF 0b10000001 -1
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            [
+                bytes(&[
+                    LDByte(r(0x1), 3),
+                    ADDRegister(r(0x1), r(0x2)),
+                    LDI(0x20C.into()),
+                    DRW(r(0x1), r(0x2), 8),
+                    SAVE(r(0x0), r(0x3)),
+                    RET(),
+                ]),
+                vec![0xFF, 0x81, 0xFF],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn jumps_to_main_if_it_isnt_first() {
+        let source = "
+            : draw
+              sprite v0 v0 1
+              ;
+            : main
+              draw
+              loop again
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            bytes(&[
+                JP(0x206.into()),
+                DRW(r(0x0), r(0x0), 1),
+                RET(),
+                CALL(0x202.into()),
+                JP(0x208.into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn compiles_conditionals_and_loops() {
+        let source = "
+            : main
+              if v0 == 1 then v1 := 2
+              if v0 != v1 begin
+                v2 := 3
+              else
+                v2 := 4
+              end
+              loop
+                v3 += 1
+                while v3 != 10
+              again
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            bytes(&[
+                SNEByte(r(0x0), 1),
+                LDByte(r(0x1), 2),
+                SNERegister(r(0x0), r(0x1)),
+                JP(0x20C.into()),
+                LDByte(r(0x2), 3),
+                JP(0x20E.into()),
+                LDByte(r(0x2), 4),
+                // loop
+                ADDByte(r(0x3), 1),
+                SNEByte(r(0x3), 10),
+                JP(0x216.into()),
+                JP(0x20E.into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn compiles_registers_timers_keys_and_digits() {
+        let source = "
+            : main
+              clear
+              v0 := v1
+              v0 |= v1
+              v0 &= v1
+              v0 ^= v1
+              v0 -= v1
+              v0 =- v1
+              v2 := delay
+              delay := v2
+              if v3 key then v4 := 1
+              if v3 -key then v4 := 2
+              loop
+                while v3 key
+              again
+              i := hex v5
+              bcd v5
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            bytes(&[
+                CLS(),
+                LDRegister(r(0x0), r(0x1)),
+                OR(r(0x0), r(0x1)),
+                AND(r(0x0), r(0x1)),
+                XOR(r(0x0), r(0x1)),
+                SUB(r(0x0), r(0x1)),
+                SUBN(r(0x0), r(0x1)),
+                LDFromDT(r(0x2)),
+                LDDT(r(0x2)),
+                SKNP(r(0x3)),
+                LDByte(r(0x4), 1),
+                SKP(r(0x3)),
+                LDByte(r(0x4), 2),
+                // loop
+                SKP(r(0x3)),
+                JP(0x220.into()),
+                JP(0x21A.into()),
+                LDF(r(0x5)),
+                LDB(r(0x5)),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        match compile(": main\n  jump nowhere") {
+            Err(Chip8Error::Assemble { line, message }) => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "Unknown label `nowhere`");
+            }
+            other => panic!("Expected an unknown label, got {:?}", other),
+        }
+        match compile(":macro") {
+            Err(Chip8Error::Assemble { line, message }) => {
+                assert_eq!(line, 1);
+                assert_eq!(message, "chip8 can't compile `:macro` yet");
+            }
+            other => panic!("Expected an unsupported statement, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{instruction::OPCODES, quirks::Profile, tracefile::json_string};
+use std::{collections::HashMap, str::FromStr};
+
+/// How `chip8 opcodes` prints the instruction set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// A Markdown table, for documentation
+    Markdown,
+    /// One JSON object per opcode per line, like `print --format json`
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown format {} (expected md or json)", s)),
+        }
+    }
+}
+
+/// Everything the documentation says about one opcode.
+#[derive(Debug, PartialEq)]
+pub struct OpcodeDoc {
+    /// Like `8xy6`
+    pub pattern: &'static str,
+    /// How the disassembler writes it, like `SHR Vx, Vy`
+    pub syntax: &'static str,
+    /// The first machine that had it
+    pub machine: Profile,
+    pub description: String,
+    /// The quirks that change what it does, written the way `--quirk` takes
+    /// them, like `shift`
+    pub quirks: Vec<&'static str>,
+}
+
+impl OpcodeDoc {
+    /// Read the documentation from a variant's doc comment lines, in the
+    /// format that `Instruction` describes.
+    fn new(pattern: &'static str, lines: &[&'static str]) -> Self {
+        let (first, rest) = lines
+            .split_first()
+            .expect("Every instruction has a doc comment");
+        let (machine, syntax) = if let Some(syntax) = first.strip_prefix("SCHIP: ") {
+            (Profile::Schip, syntax)
+        } else if let Some(syntax) = first.strip_prefix("XO-CHIP: ") {
+            (Profile::XoChip, syntax)
+        } else {
+            (Profile::Cosmac, *first)
+        };
+        let mut description = vec![];
+        let mut quirks = vec![];
+        for line in rest {
+            match line.strip_prefix("Quirks: ") {
+                Some(names) => quirks.extend(names.split(", ")),
+                None => description.push(*line),
+            }
+        }
+        Self {
+            pattern,
+            syntax,
+            machine,
+            description: description.join(" "),
+            quirks,
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let cell = |s: &str| s.replace('|', "\\|");
+        format!(
+            "| `{}` | `{}` | {} | {} | {} |",
+            self.pattern,
+            cell(self.syntax),
+            self.machine,
+            cell(&self.description),
+            self.quirks.join(", ")
+        )
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"pattern\":{},\"syntax\":{},\"machine\":{},\"description\":{},\"quirks\":[{}]}}",
+            json_string(self.pattern),
+            json_string(self.syntax),
+            json_string(&self.machine.to_string()),
+            json_string(&self.description),
+            self.quirks
+                .iter()
+                .map(|quirk| json_string(quirk))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+/// The doc comment lines of every `Instruction` variant, by its name, read
+/// from the source so the documentation can't drift from the code.
+fn variant_docs() -> HashMap<&'static str, Vec<&'static str>> {
+    let source = include_str!("instruction.rs");
+    let start = source
+        .find("pub enum Instruction {")
+        .expect("instruction.rs has the Instruction enum");
+    let mut docs = HashMap::new();
+    let mut lines = vec![];
+    for line in source[start..].lines().skip(1).map(str::trim) {
+        if line == "}" {
+            break;
+        }
+        if let Some(doc) = line.strip_prefix("///") {
+            lines.push(doc.trim());
+        } else if let Some(paren) = line.find('(') {
+            docs.insert(&line[..paren], std::mem::take(&mut lines));
+        }
+    }
+    docs
+}
+
+/// The documentation for every opcode the decoder knows, in the order of
+/// `OPCODES`.
+pub fn docs() -> Vec<OpcodeDoc> {
+    let variants = variant_docs();
+    OPCODES
+        .iter()
+        .map(|(pattern, name)| OpcodeDoc::new(pattern, &variants[name]))
+        .collect()
+}
+
+/// The whole instruction set, in the given format.
+pub fn table(format: Format) -> String {
+    let docs = docs();
+    let mut lines = vec![];
+    if format == Format::Markdown {
+        lines.push("| Opcode | Instruction | Machine | What it does | Quirks |".to_string());
+        lines.push("| --- | --- | --- | --- | --- |".to_string());
+    }
+    for doc in docs {
+        lines.push(match format {
+            Format::Markdown => doc.to_markdown(),
+            Format::Json => doc.to_json(),
+        });
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analysis::PROFILES, instruction::Instruction};
+    use std::convert::TryFrom;
+
+    /// Whether the opcode fits the pattern, where lowercase letters match
+    /// any nibble.
+    fn matches(pattern: &str, opcode: u16) -> bool {
+        pattern
+            .chars()
+            .take(4)
+            .zip(format!("{:04X}", opcode).chars())
+            .all(|(expected, actual)| expected.is_ascii_lowercase() || expected == actual)
+    }
+
+    #[test]
+    fn every_opcode_the_decoder_knows_is_documented() {
+        let docs = docs();
+        // The earliest machine to have any opcode that fits each pattern
+        let mut earliest = HashMap::new();
+        for opcode in 0..=0xFFFF {
+            let instruction = Instruction::try_from(opcode).unwrap();
+            if let Instruction::UNKNOWN(_) = instruction {
+                continue;
+            }
+            let (index, (pattern, name)) = OPCODES
+                .iter()
+                .enumerate()
+                .find(|(_, (pattern, _))| matches(pattern, opcode))
+                .unwrap_or_else(|| panic!("{:04X} isn't in OPCODES", opcode));
+            // Variants with no fields, like `RET()`, debug-print without
+            // the parentheses
+            let debug = format!("{:?}", instruction);
+            assert!(
+                debug.split('(').next() == Some(*name),
+                "{:04X} fits {} but decodes to {}",
+                opcode,
+                pattern,
+                debug
+            );
+            let machine = PROFILES
+                .iter()
+                .position(|profile| profile.supports(&instruction))
+                .unwrap();
+            let entry = earliest.entry(index).or_insert(machine);
+            *entry = machine.min(*entry);
+        }
+        for (index, doc) in docs.iter().enumerate() {
+            let machine = earliest
+                .get(&index)
+                .unwrap_or_else(|| panic!("Nothing decodes to {}", doc.pattern));
+            assert_eq!(doc.machine, PROFILES[*machine], "{}", doc.pattern);
+        }
+    }
+
+    #[test]
+    fn every_instruction_has_an_opcode() {
+        let mut names = variant_docs().keys().copied().collect::<Vec<_>>();
+        names.retain(|name| *name != "UNKNOWN");
+        names.sort_unstable();
+        let mut documented = OPCODES.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+        documented.sort_unstable();
+        assert_eq!(names, documented);
+    }
+
+    #[test]
+    fn reads_the_doc_comments() {
+        let docs = docs();
+        let shr = docs.iter().find(|doc| doc.pattern == "8xy6").unwrap();
+        assert_eq!(
+            shr.to_markdown(),
+            "| `8xy6` | `SHR Vx, Vy` | cosmac | Shift right by 1 and store the result in Vx. VF is set to the bit that was shifted out. Whether Vx or Vy is shifted depends on the quirks. | shift |"
+        );
+        let save = docs.iter().find(|doc| doc.pattern == "5xy2").unwrap();
+        assert_eq!(save.machine, Profile::XoChip);
+        assert_eq!(save.syntax, "SAVE Vx - Vy");
+        let drw = docs.iter().find(|doc| doc.pattern == "Dxyn").unwrap();
+        assert_eq!(
+            drw.to_json(),
+            "{\"pattern\":\"Dxyn\",\"syntax\":\"DRW Vx, Vy, n\",\"machine\":\"cosmac\",\"description\":\"Display n-byte sprite starting at memory location I at (Vx, Vy). On the SCHIP, a sprite with n = 0 is 16x16.\",\"quirks\":[\"draw\",\"display\"]}"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{config, error::Chip8Error};
+use std::{
+    cell::RefCell,
+    fmt::{self, Debug, Formatter},
+    fs, io,
+    path::PathBuf,
+    rc::Rc,
+};
+
+/// Somewhere to keep small blobs of data between runs, like a program's RPL
+/// user flags (see `Instruction::LDIntoFlags`), so they're still there the
+/// next time. Each blob belongs to one ROM, by its fingerprint (see
+/// `library::fingerprint`), and has a name, like `flags`. The command line
+/// keeps them in files (see `FilePersistence`), but a program that embeds
+/// chip8 can keep them anywhere, like a browser's local storage.
+pub trait Persistence {
+    /// The blob called `key` that was stored for `rom`, if there is one.
+    fn load(&mut self, rom: &str, key: &str) -> Result<Option<Vec<u8>>, Chip8Error>;
+
+    /// Keep `data` as the blob called `key` for `rom`, replacing whatever was
+    /// there.
+    fn store(&mut self, rom: &str, key: &str, data: &[u8]) -> Result<(), Chip8Error>;
+}
+
+/// Keeps each blob in its own file, at `DIRECTORY/ROM/KEY`.
+pub struct FilePersistence {
+    directory: PathBuf,
+}
+
+impl FilePersistence {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Where the command line keeps blobs: `$XDG_CONFIG_HOME/chip8/saves`, or
+    /// `~/.config/chip8/saves`.
+    pub fn directory() -> Option<PathBuf> {
+        Some(config::directory()?.join("saves"))
+    }
+
+    fn path(&self, rom: &str, key: &str) -> PathBuf {
+        self.directory.join(rom).join(key)
+    }
+}
+
+impl Persistence for FilePersistence {
+    fn load(&mut self, rom: &str, key: &str) -> Result<Option<Vec<u8>>, Chip8Error> {
+        match fs::read(self.path(rom, key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, rom: &str, key: &str, data: &[u8]) -> Result<(), Chip8Error> {
+        let path = self.path(rom, key);
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// The machine's `Persistence`, if it has one. Like `MemoryHooks`, copies of
+/// the machine share it, saved states leave it out, and any two are equal.
+#[derive(Clone, Default)]
+pub struct SharedPersistence(Option<Rc<RefCell<dyn Persistence>>>);
+
+impl SharedPersistence {
+    pub fn new(persistence: Rc<RefCell<dyn Persistence>>) -> Self {
+        Self(Some(persistence))
+    }
+
+    /// The blob called `key` for `rom`, or None if there's nowhere to keep it.
+    pub fn load(&self, rom: &str, key: &str) -> Result<Option<Vec<u8>>, Chip8Error> {
+        match &self.0 {
+            Some(persistence) => persistence.borrow_mut().load(rom, key),
+            None => Ok(None),
+        }
+    }
+
+    /// Keep `data` for `rom` as `key`, or do nothing if there's nowhere to
+    /// keep it.
+    pub fn store(&self, rom: &str, key: &str, data: &[u8]) -> Result<(), Chip8Error> {
+        match &self.0 {
+            Some(persistence) => persistence.borrow_mut().store(rom, key, data),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Debug for SharedPersistence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "SharedPersistence(Some(..))"
+        } else {
+            "SharedPersistence(None)"
+        })
+    }
+}
+
+impl PartialEq for SharedPersistence {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn files_keep_each_rom_apart() {
+        let directory =
+            std::env::temp_dir().join(format!("chip8-persistence-{}", std::process::id()));
+        let mut persistence = FilePersistence::new(directory.clone());
+        assert_eq!(persistence.load("abc", "flags").unwrap(), None);
+
+        persistence.store("abc", "flags", &[1, 2, 3]).unwrap();
+        persistence.store("def", "flags", &[4]).unwrap();
+        assert_eq!(
+            persistence.load("abc", "flags").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(persistence.load("def", "flags").unwrap(), Some(vec![4]));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
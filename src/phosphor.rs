@@ -0,0 +1,114 @@
+// Only the window draws with phosphor, but it's tested either way
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use crate::display::Framebuffer;
+
+/// A pixel this dim (from 0 to 1) is as good as off.
+const DARK: f32 = 1.0 / 255.0;
+
+/// Makes pixels that turn off fade out over a few frames, like the phosphor
+/// on a CRT kept glowing after the beam moved on. CHIP-8 programs erase
+/// sprites by drawing them again, so they flicker; on a real TV, the glow
+/// hid most of that.
+/// It works on logical pixels, before the screen is scaled up.
+pub struct Phosphor {
+    /// How much of a pixel's glow fades every frame, from just over 0 (very
+    /// slowly) to 1 (right away, like there's no phosphor at all)
+    decay: f32,
+    /// How brightly each logical pixel is still glowing, from 0 to 1
+    glow: Vec<f32>,
+    /// The color each logical pixel was when it was last on
+    colors: Vec<u32>,
+}
+
+impl Phosphor {
+    pub fn new(decay: f32) -> Self {
+        Self {
+            decay,
+            glow: vec![],
+            colors: vec![],
+        }
+    }
+
+    /// Move on to the next frame: the pixels that are on glow fully, and the
+    /// rest fade a little more. Returns the color of every logical pixel (as
+    /// 0RGB), row by row, to scale up with `Framebuffer::scale_up`.
+    pub fn next_frame(&mut self, buffer: &Framebuffer) -> Vec<u32> {
+        let size = buffer.width * buffer.height;
+        // Switching resolution clears the screen, glow and all
+        if self.glow.len() != size {
+            self.glow = vec![0.0; size];
+            self.colors = vec![0; size];
+        }
+        let background = buffer.appearance().background;
+        let mut pixels = Vec::with_capacity(size);
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let index = y * buffer.width + x;
+                if buffer.get_planes(x, y) != 0 {
+                    self.glow[index] = 1.0;
+                    self.colors[index] = buffer.get_pixel(x, y);
+                } else {
+                    self.glow[index] *= 1.0 - self.decay;
+                    if self.glow[index] < DARK {
+                        self.glow[index] = 0.0;
+                    }
+                }
+                pixels.push(mix(background, self.colors[index], self.glow[index]));
+            }
+        }
+        pixels
+    }
+}
+
+/// The color `amount` (from 0 to 1) of the way from `from` to `to`, one
+/// channel at a time.
+fn mix(from: u32, to: u32, amount: f32) -> u32 {
+    let mut result = [0; 4];
+    for ((channel, from), to) in result
+        .iter_mut()
+        .zip(&from.to_be_bytes())
+        .zip(&to.to_be_bytes())
+    {
+        let (from, to) = (f32::from(*from), f32::from(*to));
+        *channel = (from + (to - from) * amount).round() as u8;
+    }
+    u32::from_be_bytes(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::display::{OFF, ON};
+
+    #[test]
+    fn pixels_fade_out_after_turning_off() {
+        let mut buffer = Framebuffer::new();
+        let mut phosphor = Phosphor::new(0.5);
+        buffer.xor(true, 0, 0, 0b01);
+        assert_eq!(phosphor.next_frame(&buffer)[0], ON);
+
+        buffer.xor(true, 0, 0, 0b01);
+        assert_eq!(phosphor.next_frame(&buffer)[0], 0x80_80_80);
+        assert_eq!(phosphor.next_frame(&buffer)[0], 0x40_40_40);
+        for _ in 0..10 {
+            phosphor.next_frame(&buffer);
+        }
+        assert_eq!(phosphor.next_frame(&buffer)[0], OFF);
+        // The pixels that were never on stay off
+        assert_eq!(phosphor.next_frame(&buffer)[1], OFF);
+    }
+
+    #[test]
+    fn decaying_all_at_once_is_like_no_phosphor() {
+        let mut buffer = Framebuffer::new();
+        let mut phosphor = Phosphor::new(1.0);
+        buffer.xor(true, 3, 2, 0b01);
+        phosphor.next_frame(&buffer);
+        buffer.xor(true, 3, 2, 0b01);
+        assert!(phosphor
+            .next_frame(&buffer)
+            .iter()
+            .all(|pixel| *pixel == OFF));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    analysis,
+    error::Chip8Error,
+    instruction::Instruction,
+    interpreter::{Microstate, State, Variant},
+    quirks::Quirks,
+    timing,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// How many times one address ran, and what's there.
+#[derive(Debug, PartialEq)]
+pub struct HotSpot {
+    pub address: u16,
+    pub count: u64,
+    pub instruction: Instruction,
+}
+
+/// Where a program spends its time: how many times each address and each kind
+/// of instruction ran, and how much of it was drawing.
+/// It runs the program with `State::step` instead of the usual run loop, so
+/// that counting costs nothing when a program isn't being profiled.
+#[derive(Debug, Default)]
+pub struct ExecutionProfile {
+    /// How many instructions finished
+    pub instructions: u64,
+    /// How many cycles were spent waiting, for a key or (with the
+    /// display-wait quirk) for the next frame
+    pub waiting: u64,
+    /// How many times each address ran, and the instruction there
+    addresses: HashMap<u16, (u64, Instruction)>,
+    /// How many times each kind of instruction (see `analysis::kind`) ran
+    kinds: HashMap<String, u64>,
+    /// Roughly how many of the COSMAC VIP's machine cycles the instructions
+    /// would have taken (see `timing::vip_cycles`), and how many of those
+    /// were DRW's
+    vip_cycles: u64,
+    drw_vip_cycles: u64,
+    drws: u64,
+    /// How many instructions to list in the report
+    top: usize,
+    /// Why the run stopped before running out of cycles, if it did
+    pub stopped: Option<String>,
+}
+
+impl ExecutionProfile {
+    /// The `top` addresses that ran the most, most first. Ties go to the
+    /// lower address.
+    pub fn hot_spots(&self) -> Vec<HotSpot> {
+        let mut spots = self
+            .addresses
+            .iter()
+            .map(|(address, (count, instruction))| HotSpot {
+                address: *address,
+                count: *count,
+                instruction: instruction.clone(),
+            })
+            .collect::<Vec<_>>();
+        spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+        spots.truncate(self.top);
+        spots
+    }
+
+    /// Each kind of instruction and how many times it ran, most first.
+    pub fn histogram(&self) -> Vec<(&str, u64)> {
+        let mut kinds = self
+            .kinds
+            .iter()
+            .map(|(kind, count)| (kind.as_str(), *count))
+            .collect::<Vec<_>>();
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        kinds
+    }
+
+    fn add(&mut self, address: u16, instruction: &Instruction) {
+        self.instructions += 1;
+        self.addresses
+            .entry(address)
+            .or_insert_with(|| (0, instruction.clone()))
+            .0 += 1;
+        *self.kinds.entry(analysis::kind(instruction)).or_insert(0) += 1;
+        let cycles = u64::from(timing::vip_cycles(instruction).unwrap_or(0));
+        self.vip_cycles += cycles;
+        if let Instruction::DRW(..) = instruction {
+            self.drws += 1;
+            self.drw_vip_cycles += cycles;
+        }
+    }
+}
+
+/// `part` as a percentage of `whole`.
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl Display for ExecutionProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Ran {} instructions", self.instructions)?;
+        if self.waiting > 0 {
+            write!(f, " (and waited for {} cycles)", self.waiting)?;
+        }
+        match &self.stopped {
+            Some(reason) => writeln!(f, ", then stopped: {}", reason)?,
+            None => writeln!(f)?,
+        }
+
+        writeln!(f, "\nHot spots:")?;
+        for spot in self.hot_spots() {
+            writeln!(
+                f,
+                "  {:03X}  {:>10}  {:>5.1}%  {}",
+                spot.address,
+                spot.count,
+                percent(spot.count, self.instructions),
+                spot.instruction
+            )?;
+        }
+
+        writeln!(f, "\nInstructions:")?;
+        for (kind, count) in self.histogram() {
+            writeln!(
+                f,
+                "  {:<14}{:>10}  {:>5.1}%",
+                kind,
+                count,
+                percent(count, self.instructions)
+            )?;
+        }
+
+        writeln!(
+            f,
+            "\nDRW ran {} times ({:.1}% of instructions), about {:.1}% of the time on a COSMAC VIP",
+            self.drws,
+            percent(self.drws, self.instructions),
+            percent(self.drw_vip_cycles, self.vip_cycles)
+        )
+    }
+}
+
+/// Run the program without a window for `cycles` cycles (or until it halts or
+/// crashes), counting what runs, and list the `top` hot spots. Fails if the
+/// program doesn't fit in memory.
+pub fn profile(
+    program: &[u8],
+    variant: Variant,
+    quirks: Quirks,
+    cycles: u64,
+    seed: u64,
+    top: usize,
+) -> Result<ExecutionProfile, Chip8Error> {
+    let mut state = State::with_program_for(variant, program)?.with_quirks(quirks);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut profile = ExecutionProfile {
+        top,
+        ..ExecutionProfile::default()
+    };
+    // Where the instruction that's waiting is, since the program counter has
+    // already moved past it by the time it finishes
+    let mut waiting_at = None;
+    for _ in 0..cycles {
+        if state.is_halted() {
+            profile.stopped = Some(format!("halted at {:03X}", state.pc()));
+            break;
+        }
+        if state.has_exited() {
+            profile.stopped = Some(format!("exited at {:03X}", state.pc()));
+            break;
+        }
+        match state.step(&mut rng) {
+            Ok(outcome)
+                if outcome.waited
+                    || matches!(state.microstate(), Microstate::WaitingForKey { .. }) =>
+            {
+                profile.waiting += 1;
+                waiting_at.get_or_insert(outcome.pc_before);
+            }
+            Ok(outcome) => {
+                let address = waiting_at.take().unwrap_or(outcome.pc_before);
+                profile.add(address, &outcome.instruction);
+            }
+            Err(e) => {
+                profile.stopped = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Register;
+
+    #[test]
+    fn counts_hot_spots_and_kinds() {
+        // LD I, 0A0; (loop) DRW V0, V0, 1; ADD V1, 1; SE V1, 3; JP 202; JP 20A
+        let program = [
+            0xA0, 0xA0, 0xD0, 0x01, 0x71, 0x01, 0x31, 0x03, 0x12, 0x02, 0x12, 0x0A,
+        ];
+        let profile = profile(&program, Variant::Chip8, Quirks::default(), 1_000, 0, 2).unwrap();
+
+        assert_eq!(profile.stopped, Some("halted at 20A".to_string()));
+        // LD I, then 3 times around the loop, the last of which skips the JP
+        assert_eq!(profile.instructions, 1 + 4 + 4 + 3);
+        assert_eq!(
+            profile.hot_spots(),
+            vec![
+                HotSpot {
+                    address: 0x202,
+                    count: 3,
+                    instruction: Instruction::DRW(
+                        Register::from_nibble(0),
+                        Register::from_nibble(0),
+                        1
+                    ),
+                },
+                HotSpot {
+                    address: 0x204,
+                    count: 3,
+                    instruction: Instruction::ADDByte(Register::from_nibble(1), 1),
+                },
+            ]
+        );
+        assert_eq!(profile.histogram()[0], ("ADDByte", 3));
+        // With the display-wait quirk, every DRW waits for the next frame
+        assert!(profile.waiting > 0);
+        assert!(profile.to_string().contains("DRW ran 3 times"));
+    }
+}
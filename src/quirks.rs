@@ -0,0 +1,273 @@
+use crate::{instruction::Instruction, interpreter::Variant};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Formatter},
+    str::FromStr,
+};
+
+/// The ways that CHIP-8 dialects disagree about what instructions do.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quirks {
+    /// SHR/SHL (8xy6/8xyE): if true, shift Vy and store the result in Vx, like
+    /// the COSMAC VIP. If false, shift Vx in place and ignore Vy.
+    pub shift_uses_vy: bool,
+    /// LD [I], Vx and LD Vx, [I] (Fx55/Fx65): if true, leave I pointing just
+    /// past the last register that was stored or loaded, like the COSMAC VIP.
+    /// If false, leave I alone.
+    pub load_store_increments_i: bool,
+    /// JP V0, nnn (Bnnn): if true, jump to nnn + Vx, where x is the highest
+    /// nibble of nnn, like the CHIP-48 and SCHIP. If false, jump to nnn + V0.
+    pub jump_uses_vx: bool,
+    /// DRW: if true, the parts of a sprite that go past the edge of the screen
+    /// are cut off. If false, they wrap around to the other side.
+    pub clip_sprites: bool,
+    /// DRW: if true, wait for the start of the next frame (the vertical
+    /// blank) before drawing, like the COSMAC VIP. If false, draw right away.
+    pub display_wait: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Profile::Cosmac.quirks()
+    }
+}
+
+/// Every quirk, written the way `--quirk` takes it, like
+/// `shift=vy load-store=increment jump=v0 draw=clip display=wait`.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let settings = QuirkSetting::all_of(self)
+            .iter()
+            .map(|setting| setting.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", settings.join(" "))
+    }
+}
+
+/// A machine whose quirks we know, to use as a starting point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Cosmac,
+    Schip,
+    XoChip,
+}
+
+impl Profile {
+    /// The quirks of this machine.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Profile::Cosmac => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                clip_sprites: true,
+                display_wait: true,
+            },
+            Profile::Schip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            Profile::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                clip_sprites: false,
+                display_wait: false,
+            },
+        }
+    }
+
+    /// Whether this machine has the instruction. Each machine has everything
+    /// the one before it had: the SCHIP added to the COSMAC VIP's
+    /// instructions, and XO-CHIP added to the SCHIP's.
+    pub fn supports(self, instruction: &Instruction) -> bool {
+        match self {
+            Profile::Cosmac => !instruction.is_schip() && !instruction.is_xo_chip(),
+            Profile::Schip => !instruction.is_xo_chip(),
+            Profile::XoChip => true,
+        }
+    }
+
+    /// Which dialect this machine speaks.
+    pub fn variant(self) -> Variant {
+        match self {
+            Profile::XoChip => Variant::XoChip,
+            Profile::Cosmac | Profile::Schip => Variant::Chip8,
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cosmac" => Ok(Profile::Cosmac),
+            "schip" => Ok(Profile::Schip),
+            "xo-chip" | "xochip" => Ok(Profile::XoChip),
+            _ => Err(format!(
+                "Unknown profile {} (expected cosmac, schip, or xo-chip)",
+                s
+            )),
+        }
+    }
+}
+
+/// The same names that `from_str` accepts.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Cosmac => write!(f, "cosmac"),
+            Profile::Schip => write!(f, "schip"),
+            Profile::XoChip => write!(f, "xo-chip"),
+        }
+    }
+}
+
+/// A single quirk override from the command line, like `shift=vy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuirkSetting {
+    ShiftUsesVy(bool),
+    LoadStoreIncrementsI(bool),
+    JumpUsesVx(bool),
+    ClipSprites(bool),
+    DisplayWait(bool),
+}
+
+impl QuirkSetting {
+    /// Change the given quirks to use this setting.
+    pub fn apply(self, quirks: &mut Quirks) {
+        match self {
+            QuirkSetting::ShiftUsesVy(value) => quirks.shift_uses_vy = value,
+            QuirkSetting::LoadStoreIncrementsI(value) => quirks.load_store_increments_i = value,
+            QuirkSetting::JumpUsesVx(value) => quirks.jump_uses_vx = value,
+            QuirkSetting::ClipSprites(value) => quirks.clip_sprites = value,
+            QuirkSetting::DisplayWait(value) => quirks.display_wait = value,
+        }
+    }
+
+    /// How each quirk is set in `quirks`, in the order they're printed.
+    pub fn all_of(quirks: &Quirks) -> [QuirkSetting; 5] {
+        [
+            QuirkSetting::ShiftUsesVy(quirks.shift_uses_vy),
+            QuirkSetting::LoadStoreIncrementsI(quirks.load_store_increments_i),
+            QuirkSetting::JumpUsesVx(quirks.jump_uses_vx),
+            QuirkSetting::ClipSprites(quirks.clip_sprites),
+            QuirkSetting::DisplayWait(quirks.display_wait),
+        ]
+    }
+
+    /// The same quirk, set the other way.
+    pub fn flipped(self) -> Self {
+        match self {
+            QuirkSetting::ShiftUsesVy(value) => QuirkSetting::ShiftUsesVy(!value),
+            QuirkSetting::LoadStoreIncrementsI(value) => QuirkSetting::LoadStoreIncrementsI(!value),
+            QuirkSetting::JumpUsesVx(value) => QuirkSetting::JumpUsesVx(!value),
+            QuirkSetting::ClipSprites(value) => QuirkSetting::ClipSprites(!value),
+            QuirkSetting::DisplayWait(value) => QuirkSetting::DisplayWait(!value),
+        }
+    }
+}
+
+/// The way `from_str` takes it, like `shift=vy`.
+impl fmt::Display for QuirkSetting {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pick = |value: bool, yes: &'static str, no: &'static str| if value { yes } else { no };
+        match self {
+            QuirkSetting::ShiftUsesVy(value) => write!(f, "shift={}", pick(*value, "vy", "vx")),
+            QuirkSetting::LoadStoreIncrementsI(value) => {
+                write!(f, "load-store={}", pick(*value, "increment", "unchanged"))
+            }
+            QuirkSetting::JumpUsesVx(value) => write!(f, "jump={}", pick(*value, "vx", "v0")),
+            QuirkSetting::ClipSprites(value) => write!(f, "draw={}", pick(*value, "clip", "wrap")),
+            QuirkSetting::DisplayWait(value) => {
+                write!(f, "display={}", pick(*value, "wait", "immediate"))
+            }
+        }
+    }
+}
+
+impl FromStr for QuirkSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shift=vy" => Ok(QuirkSetting::ShiftUsesVy(true)),
+            "shift=vx" => Ok(QuirkSetting::ShiftUsesVy(false)),
+            "load-store=increment" => Ok(QuirkSetting::LoadStoreIncrementsI(true)),
+            "load-store=unchanged" => Ok(QuirkSetting::LoadStoreIncrementsI(false)),
+            "jump=vx" => Ok(QuirkSetting::JumpUsesVx(true)),
+            "jump=v0" => Ok(QuirkSetting::JumpUsesVx(false)),
+            "draw=clip" => Ok(QuirkSetting::ClipSprites(true)),
+            "draw=wrap" => Ok(QuirkSetting::ClipSprites(false)),
+            "display=wait" => Ok(QuirkSetting::DisplayWait(true)),
+            "display=immediate" => Ok(QuirkSetting::DisplayWait(false)),
+            _ => Err(format!(
+                "Unknown quirk {} (expected one of shift=vy, shift=vx, \
+                 load-store=increment, load-store=unchanged, jump=vx, jump=v0, \
+                 draw=clip, draw=wrap, display=wait, display=immediate)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn settings_override_profile() {
+        let mut quirks = Profile::Schip.quirks();
+        "shift=vy"
+            .parse::<QuirkSetting>()
+            .unwrap()
+            .apply(&mut quirks);
+        "draw=wrap"
+            .parse::<QuirkSetting>()
+            .unwrap()
+            .apply(&mut quirks);
+
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                clip_sprites: false,
+                display_wait: false,
+            }
+        );
+    }
+
+    #[test]
+    fn quirks_print_as_settings() {
+        let quirks = Profile::Schip.quirks();
+        assert_eq!(
+            quirks.to_string(),
+            "shift=vx load-store=unchanged jump=vx draw=clip display=immediate"
+        );
+
+        let mut parsed = Quirks::default();
+        for setting in quirks.to_string().split(' ') {
+            setting.parse::<QuirkSetting>().unwrap().apply(&mut parsed);
+        }
+        assert_eq!(parsed, quirks);
+    }
+
+    #[test]
+    fn flipped_settings() {
+        let settings = QuirkSetting::all_of(&Profile::Cosmac.quirks());
+        assert_eq!(settings[0].to_string(), "shift=vy");
+        assert_eq!(settings[0].flipped().to_string(), "shift=vx");
+        assert_eq!(settings[4].flipped(), QuirkSetting::DisplayWait(false));
+    }
+
+    #[test]
+    fn unknown_setting() {
+        assert!("shift=sideways".parse::<QuirkSetting>().is_err());
+    }
+}
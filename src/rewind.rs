@@ -0,0 +1,252 @@
+// Only the window can rewind, but the history is tested either way
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use crate::{error::Chip8Error, frame::FrameListener, interpreter::State};
+use std::collections::VecDeque;
+
+/// How many frames back the window can rewind: 10 seconds at 60 frames a
+/// second.
+pub const HISTORY_FRAMES: usize = 600;
+
+/// The bytes that changed between two serialized states (see
+/// `State::serialize`). Most of the machine, especially memory and the
+/// framebuffer, is the same from one frame to the next, so this is much smaller
+/// than a whole state.
+#[derive(Debug, PartialEq)]
+struct Delta {
+    /// How long the other state is
+    length: usize,
+    /// Runs of bytes that are different in the other state, and where each
+    /// run starts
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// What to change in `from` to get `to`.
+    fn between(from: &[u8], to: &[u8]) -> Self {
+        let mut runs: Vec<(usize, Vec<u8>)> = vec![];
+        for (index, byte) in to.iter().enumerate() {
+            if from.get(index) == Some(byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == index => bytes.push(*byte),
+                _ => runs.push((index, vec![*byte])),
+            }
+        }
+        Self {
+            length: to.len(),
+            runs,
+        }
+    }
+
+    /// Change `bytes` into the other state.
+    fn apply(&self, bytes: &mut Vec<u8>) {
+        bytes.resize(self.length, 0);
+        for (start, run) in &self.runs {
+            bytes[*start..*start + run.len()].copy_from_slice(run);
+        }
+    }
+
+    /// Roughly how many bytes this takes up.
+    #[cfg(test)]
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, run)| run.len() + 8).sum()
+    }
+}
+
+/// A history of the last few frames, for rewinding like a modern emulator.
+/// Only the newest state is kept whole. Every older one is a `Delta` from the
+/// state after it, so rewinding a frame means undoing one delta.
+pub struct Rewind {
+    /// The newest state, serialized
+    latest: Option<Vec<u8>>,
+    /// The deltas that step back from each state to the one before it, oldest
+    /// first
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` frames of history.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// How many frames back it can go.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Whether there's nowhere to go back to.
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Add the state to the history, forgetting the oldest frame if it's full.
+    /// A state that's the same as the newest one (like the one just rewound
+    /// to) isn't added again.
+    pub fn record(&mut self, state: &State) -> Result<(), Chip8Error> {
+        let bytes = state.serialize()?;
+        if self.latest.as_ref() == Some(&bytes) {
+            return Ok(());
+        }
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&bytes, &latest));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(bytes);
+        Ok(())
+    }
+
+    /// Go back one frame and return the state from then, or None if there's
+    /// no more history.
+    pub fn rewind(&mut self) -> Result<Option<State>, Chip8Error> {
+        self.go_back(1)
+    }
+
+    /// Go back `frames` frames, forgetting the ones after it, and return the
+    /// state from then. Returns None (and forgets nothing) if the history
+    /// isn't that long.
+    pub fn go_back(&mut self, frames: usize) -> Result<Option<State>, Chip8Error> {
+        if frames == 0 || frames > self.deltas.len() {
+            return Ok(None);
+        }
+        let latest = match self.latest.as_mut() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        for _ in 0..frames {
+            if let Some(delta) = self.deltas.pop_back() {
+                delta.apply(latest);
+            }
+        }
+        Ok(Some(State::deserialize(latest)?))
+    }
+
+    /// The state from `frames` frames ago (0 is the newest), without
+    /// forgetting anything, for looking back through the history. Returns None
+    /// if the history isn't that long.
+    pub fn frame(&self, frames: usize) -> Result<Option<State>, Chip8Error> {
+        let mut bytes = match &self.latest {
+            Some(latest) if frames <= self.deltas.len() => latest.clone(),
+            _ => return Ok(None),
+        };
+        for delta in self.deltas.iter().rev().take(frames) {
+            delta.apply(&mut bytes);
+        }
+        Ok(Some(State::deserialize(&bytes)?))
+    }
+}
+
+/// Every frame goes into the history when it ends.
+impl FrameListener for Rewind {
+    fn end_frame(&mut self, state: &State) {
+        if let Err(e) = self.record(state) {
+            warn!("Couldn't record a frame to rewind to: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::tick;
+
+    #[test]
+    fn delta_between_different_lengths() {
+        let mut bytes = vec![1, 2, 3];
+        let delta = Delta::between(&bytes, &[1, 5, 6, 4, 7]);
+        assert_eq!(delta.runs, vec![(1, vec![5, 6, 4, 7])]);
+
+        delta.apply(&mut bytes);
+        assert_eq!(bytes, vec![1, 5, 6, 4, 7]);
+        Delta::between(&bytes, &[1]).apply(&mut bytes);
+        assert_eq!(bytes, vec![1]);
+    }
+
+    #[test]
+    fn rewinds_one_frame_at_a_time() {
+        // ADD V0, 01 over and over
+        let program = [0x70, 0x01].repeat(4);
+        let mut state = State::with_program(&program).unwrap();
+        let mut rewind = Rewind::new(10);
+        rewind.record(&state).unwrap();
+        for _ in 0..3 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+            rewind.record(&state).unwrap();
+        }
+        assert_eq!(state.registers()[0], 3);
+
+        for expected in (0..3).rev() {
+            let previous = rewind.rewind().unwrap().unwrap();
+            assert_eq!(previous.registers()[0], expected);
+        }
+        assert!(rewind.rewind().unwrap().is_none());
+    }
+
+    #[test]
+    fn looks_back_without_forgetting() {
+        let mut state = State::with_program(&[0x70, 0x01].repeat(8)).unwrap();
+        let mut rewind = Rewind::new(10);
+        rewind.record(&state).unwrap();
+        for _ in 0..5 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+            rewind.record(&state).unwrap();
+        }
+
+        assert_eq!(rewind.frame(0).unwrap().unwrap().registers()[0], 5);
+        assert_eq!(rewind.frame(3).unwrap().unwrap().registers()[0], 2);
+        assert!(rewind.frame(6).unwrap().is_none());
+        assert_eq!(rewind.len(), 5);
+
+        assert!(rewind.go_back(6).unwrap().is_none());
+        assert_eq!(rewind.go_back(3).unwrap().unwrap().registers()[0], 2);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.rewind().unwrap().unwrap().registers()[0], 1);
+    }
+
+    #[test]
+    fn doesnt_record_the_same_state_twice() {
+        let mut state = State::with_program(&[0x70, 0x01].repeat(2)).unwrap();
+        let mut rewind = Rewind::new(10);
+        rewind.record(&state).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        rewind.record(&state).unwrap();
+
+        let previous = rewind.rewind().unwrap().unwrap();
+        rewind.record(&previous).unwrap();
+        assert_eq!(rewind.len(), 0);
+    }
+
+    #[test]
+    fn forgets_the_oldest_frames() {
+        let mut state = State::with_program(&[0x70, 0x01].repeat(8)).unwrap();
+        let mut rewind = Rewind::new(2);
+        rewind.record(&state).unwrap();
+        for _ in 0..5 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+            rewind.record(&state).unwrap();
+        }
+
+        assert_eq!(rewind.len(), 2);
+        rewind.rewind().unwrap();
+        assert_eq!(rewind.rewind().unwrap().unwrap().registers()[0], 3);
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let mut state = State::with_program(&[0x70, 0x01]).unwrap();
+        let before = state.serialize().unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        let delta = Delta::between(&state.serialize().unwrap(), &before);
+
+        assert!(delta.size() < before.len() / 100);
+    }
+}
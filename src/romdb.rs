@@ -0,0 +1,219 @@
+use crate::{config, error::Chip8Error, keymap::Keymap, quirks::Profile};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The ROMs that chip8 knows about out of the box, in the same format as the
+/// user's `roms.toml`: a `[sha1]` heading for each ROM, then what's known
+/// about it.
+const BUILT_IN: &str = r#"
+# test_opcode.ch8, corax89's chip8-test-rom, which is in this repository
+[f1cfcffe1937ed6dd6eeed1a7f85dfc777bda700]
+name = "chip8-test-rom"
+profile = "cosmac"
+"#;
+
+/// What the ROM database knows about one ROM. Anything it doesn't know is
+/// None.
+#[derive(Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub name: Option<String>,
+    /// The machine it was written for
+    pub profile: Option<Profile>,
+    /// How fast it's meant to run, in instructions a second
+    pub clock_hz: Option<u32>,
+    /// Key bindings that suit it better than the usual ones
+    pub keymap: Option<Keymap>,
+}
+
+impl RomInfo {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "name" => self.name = Some(value.to_string()),
+            "profile" => self.profile = Some(value.parse()?),
+            "clock_hz" => {
+                self.clock_hz = Some(value.parse().map_err(|_| {
+                    format!(
+                        "clock_hz must be a number of instructions a second, not {}",
+                        value
+                    )
+                })?)
+            }
+            "keys" => self.keymap = Some(value.parse()?),
+            _ => return Err(format!("Unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// What it sets, like `chip8-test-rom (cosmac, 600 instructions a second)`.
+impl Display for RomInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut settings = vec![];
+        if let Some(profile) = self.profile {
+            settings.push(profile.to_string());
+        }
+        if let Some(hz) = self.clock_hz {
+            settings.push(format!("{} instructions a second", hz));
+        }
+        if self.keymap.is_some() {
+            settings.push("its own keys".to_string());
+        }
+        write!(f, "{}", self.name.as_deref().unwrap_or("an unnamed ROM"))?;
+        if !settings.is_empty() {
+            write!(f, " ({})", settings.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// What's known about particular ROMs, by their SHA-1 (see
+/// `library::fingerprint`), so that `run` can pick the right machine for them
+/// without being told.
+#[derive(Debug, Default, PartialEq)]
+pub struct RomDatabase(HashMap<String, RomInfo>);
+
+impl RomDatabase {
+    /// Where the user's additions to the database are:
+    /// `$XDG_CONFIG_HOME/chip8/roms.toml`, or `~/.config/chip8/roms.toml`.
+    pub fn path() -> Option<PathBuf> {
+        Some(config::directory()?.join("roms.toml"))
+    }
+
+    /// The built-in database, with the user's file on top. A ROM that's in
+    /// both gets the user's settings.
+    pub fn load() -> Result<Self, Chip8Error> {
+        let mut database = Self::built_in();
+        if let Some(path) = Self::path() {
+            database.0.extend(Self::load_from(&path)?.0);
+        }
+        Ok(database)
+    }
+
+    fn built_in() -> Self {
+        Self::parse(BUILT_IN).expect("The built-in ROM database is broken")
+    }
+
+    fn load_from(path: &Path) -> Result<Self, Chip8Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|(line, message)| Chip8Error::Config {
+                path: path.display().to_string(),
+                line,
+                message,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parse a database: a `[sha1]` heading for each ROM, followed by
+    /// `name`, `profile`, `clock_hz`, and `keys` (like `--keymap`) settings in
+    /// the config file's format.
+    /// Fails with the line number and what's wrong with it.
+    pub fn parse(text: &str) -> Result<Self, (usize, String)> {
+        let mut roms = HashMap::new();
+        // The SHA-1 of the ROM whose settings these are
+        let mut current: Option<String> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if let Some(heading) = trimmed.strip_prefix('[') {
+                let sha1 = heading
+                    .strip_suffix(']')
+                    .filter(|sha1| sha1.len() == 40 && sha1.chars().all(|c| c.is_ascii_hexdigit()))
+                    .ok_or_else(|| {
+                        (
+                            line_number,
+                            format!(
+                                "Expected a ROM's SHA-1 in hex, like [{}], got {}",
+                                "0".repeat(40),
+                                trimmed
+                            ),
+                        )
+                    })?
+                    .to_lowercase();
+                roms.insert(sha1.clone(), RomInfo::default());
+                current = Some(sha1);
+                continue;
+            }
+            let (key, value) = match config::parse_line(line).map_err(|e| (line_number, e))? {
+                Some(setting) => setting,
+                None => continue,
+            };
+            let info = match &current {
+                Some(sha1) => roms.get_mut(sha1).expect("Every heading has an entry"),
+                None => {
+                    return Err((
+                        line_number,
+                        format!("`{}` needs to be under a ROM's [sha1] heading", key),
+                    ))
+                }
+            };
+            info.set(key, value)
+                .map_err(|message| (line_number, message))?;
+        }
+        Ok(Self(roms))
+    }
+
+    /// What's known about the ROM with this SHA-1, if anything.
+    pub fn get(&self, sha1: &str) -> Option<&RomInfo> {
+        self.0.get(sha1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library;
+
+    #[test]
+    fn knows_the_test_rom() {
+        let program = fs::read("test_opcode.ch8").unwrap();
+        let database = RomDatabase::built_in();
+        let info = database.get(&library::fingerprint(&program)).unwrap();
+        assert_eq!(info.profile, Some(Profile::Cosmac));
+    }
+
+    #[test]
+    fn parses_entries() {
+        let database = RomDatabase::parse(
+            "# A game\n[ABCDEF0123456789ABCDEF0123456789ABCDEF01]\nname = \"Game\"\nprofile = \"schip\"\nclock_hz = 1000\nkeys = \"5=I\"\n",
+        )
+        .unwrap();
+        let info = database
+            .get("abcdef0123456789abcdef0123456789abcdef01")
+            .unwrap();
+        assert_eq!(
+            info,
+            &RomInfo {
+                name: Some("Game".to_string()),
+                profile: Some(Profile::Schip),
+                clock_hz: Some(1000),
+                keymap: Some("5=I".parse().unwrap()),
+            }
+        );
+        assert_eq!(
+            info.to_string(),
+            "Game (schip, 1000 instructions a second, its own keys)"
+        );
+    }
+
+    #[test]
+    fn mistakes_say_which_line() {
+        assert!(matches!(RomDatabase::parse("[not-a-hash]\n"), Err((1, _))));
+        assert_eq!(
+            RomDatabase::parse("name = \"Game\"\n"),
+            Err((
+                1,
+                "`name` needs to be under a ROM's [sha1] heading".to_string()
+            ))
+        );
+        assert!(matches!(
+            RomDatabase::parse(&format!("[{}]\n\nspeed = 1\n", "0".repeat(40))),
+            Err((3, _))
+        ));
+    }
+}
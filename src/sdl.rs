@@ -0,0 +1,283 @@
+use crate::{
+    display::{Backend, Key, TITLE},
+    error::Chip8Error,
+};
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    event::Event,
+    keyboard::Scancode,
+    pixels::PixelFormatEnum,
+    render::Canvas,
+    video::Window,
+    EventPump,
+};
+use std::{collections::HashSet, path::PathBuf};
+
+/// The beep is a square wave at this pitch, in Hz.
+const BEEP_PITCH: f32 = 440.0;
+const BEEP_VOLUME: f32 = 0.1;
+/// While fast-forwarding, only every this many frames is actually shown, since
+/// vsync would otherwise hold it to the monitor's refresh rate.
+const TURBO_FRAMES_PER_PRESENT: u32 = 10;
+
+/// A window opened with SDL2. Unlike minifb, it can beep, and it waits for the
+/// monitor's vertical sync before showing a frame, so the screen doesn't tear.
+pub struct Sdl2 {
+    canvas: Canvas<Window>,
+    events: EventPump,
+    audio: Option<AudioDevice<SquareWave>>,
+    open: bool,
+    /// The keys pressed since the last update, not counting key repeats
+    pressed: HashSet<Scancode>,
+    turbo: bool,
+    /// Frames since the last one that was shown, while fast-forwarding
+    skipped_frames: u32,
+    /// The file most recently dropped onto the window, until it's taken
+    dropped: Option<PathBuf>,
+}
+
+impl Sdl2 {
+    /// Open the audio device and close it again, to see whether there can be
+    /// a beep.
+    pub fn check_audio() -> Result<(), String> {
+        let sdl = sdl2::init()?;
+        SquareWave::open(&sdl).map(|_| ())
+    }
+
+    pub fn open(
+        width: usize,
+        height: usize,
+        resizable: bool,
+        fullscreen: bool,
+    ) -> Result<Self, Chip8Error> {
+        let sdl = sdl2::init().map_err(Chip8Error::Window)?;
+        let video = sdl.video().map_err(Chip8Error::Window)?;
+        let mut window = video.window(TITLE, width as u32, height as u32);
+        window.position_centered();
+        if resizable {
+            window.resizable();
+        }
+        if fullscreen {
+            // At the desktop's resolution, rather than changing it
+            window.fullscreen_desktop();
+        }
+        let window = window
+            .build()
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        let mut canvas = window
+            .into_canvas()
+            .present_vsync()
+            .build()
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        // When the window is resized, scale the screen up a whole number of
+        // times, with black bars around it
+        canvas
+            .set_logical_size(width as u32, height as u32)
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        canvas
+            .set_integer_scale(true)
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        let events = sdl.event_pump().map_err(Chip8Error::Window)?;
+
+        // Without sound the program can still run, so just warn about it
+        let audio = match SquareWave::open(&sdl) {
+            Ok(audio) => Some(audio),
+            Err(e) => {
+                warn!("Couldn't open the audio device, so there's no beep: {}", e);
+                None
+            }
+        };
+
+        Ok(Self {
+            canvas,
+            events,
+            audio,
+            open: true,
+            pressed: HashSet::new(),
+            turbo: false,
+            skipped_frames: 0,
+            dropped: None,
+        })
+    }
+
+    fn scancode(key: Key) -> Scancode {
+        match key {
+            Key::Key0 => Scancode::Num0,
+            Key::Key1 => Scancode::Num1,
+            Key::Key2 => Scancode::Num2,
+            Key::Key3 => Scancode::Num3,
+            Key::Key4 => Scancode::Num4,
+            Key::Key5 => Scancode::Num5,
+            Key::Key6 => Scancode::Num6,
+            Key::Key7 => Scancode::Num7,
+            Key::Key8 => Scancode::Num8,
+            Key::Key9 => Scancode::Num9,
+            Key::A => Scancode::A,
+            Key::B => Scancode::B,
+            Key::C => Scancode::C,
+            Key::D => Scancode::D,
+            Key::E => Scancode::E,
+            Key::F => Scancode::F,
+            Key::G => Scancode::G,
+            Key::H => Scancode::H,
+            Key::I => Scancode::I,
+            Key::J => Scancode::J,
+            Key::K => Scancode::K,
+            Key::L => Scancode::L,
+            Key::M => Scancode::M,
+            Key::N => Scancode::N,
+            Key::O => Scancode::O,
+            Key::P => Scancode::P,
+            Key::Q => Scancode::Q,
+            Key::R => Scancode::R,
+            Key::S => Scancode::S,
+            Key::T => Scancode::T,
+            Key::U => Scancode::U,
+            Key::V => Scancode::V,
+            Key::W => Scancode::W,
+            Key::X => Scancode::X,
+            Key::Y => Scancode::Y,
+            Key::Z => Scancode::Z,
+            Key::Escape => Scancode::Escape,
+            Key::Period => Scancode::Period,
+            Key::Tab => Scancode::Tab,
+            Key::Space => Scancode::Space,
+            Key::Backspace => Scancode::Backspace,
+            Key::Left => Scancode::Left,
+            Key::Right => Scancode::Right,
+            Key::F1 => Scancode::F1,
+            Key::F2 => Scancode::F2,
+            Key::F3 => Scancode::F3,
+            Key::F5 => Scancode::F5,
+            Key::F9 => Scancode::F9,
+        }
+    }
+
+    /// Read the keys pressed since last time, and whether the window was
+    /// closed.
+    fn read_events(&mut self) {
+        self.pressed.clear();
+        for event in self.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => self.open = false,
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } => {
+                    self.pressed.insert(scancode);
+                }
+                Event::DropFile { filename, .. } => self.dropped = Some(PathBuf::from(filename)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Draw the pixels (as 0RGB) to the window, which waits for vsync.
+    fn present(&mut self, pixels: &[u32], width: usize, height: usize) -> Result<(), String> {
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB888, width as u32, height as u32)
+            .map_err(|e| e.to_string())?;
+        let bytes = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes().to_vec())
+            .collect::<Vec<u8>>();
+        texture
+            .update(None, &bytes, width * 4)
+            .map_err(|e| e.to_string())?;
+        self.canvas.copy(&texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl Backend for Sdl2 {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.events
+            .keyboard_state()
+            .is_scancode_pressed(Self::scancode(key))
+    }
+
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&Self::scancode(key))
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            warn!("Couldn't set the window's title: {}", e);
+        }
+    }
+
+    fn update(&mut self, pixels: &[u32], width: usize, height: usize) {
+        self.read_events();
+        if self.turbo && self.skipped_frames < TURBO_FRAMES_PER_PRESENT {
+            self.skipped_frames += 1;
+            return;
+        }
+        self.skipped_frames = 0;
+        if let Err(e) = self.present(pixels, width, height) {
+            warn!("Couldn't draw to the window: {}", e);
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.read_events();
+    }
+
+    fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    fn beep(&mut self, on: bool) {
+        if let Some(audio) = &self.audio {
+            if on {
+                audio.resume();
+            } else {
+                audio.pause();
+            }
+        }
+    }
+}
+
+/// Makes the beep. SDL calls `callback` whenever it needs more sound, and the
+/// device is paused whenever the machine isn't beeping.
+struct SquareWave {
+    /// How far through one wave we are, from 0 to 1
+    phase: f32,
+    /// How far through a wave each sample goes
+    phase_step: f32,
+}
+
+impl SquareWave {
+    fn open(sdl: &sdl2::Sdl) -> Result<AudioDevice<Self>, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+        sdl.audio()?.open_playback(None, &desired, |spec| Self {
+            phase: 0.0,
+            phase_step: BEEP_PITCH / spec.freq as f32,
+        })
+    }
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            };
+            self.phase = (self.phase + self.phase_step) % 1.0;
+        }
+    }
+}
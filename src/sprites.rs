@@ -0,0 +1,171 @@
+use crate::{
+    disasm::{self, Line},
+    error::Chip8Error,
+    instruction::Instruction,
+    memory,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// Some bytes of a ROM drawn the way DRW would draw them, one byte per row
+/// (see `Framebuffer::draw_sprite_at`).
+#[derive(Debug, PartialEq)]
+pub struct Sprite {
+    /// Where the sprite starts in memory
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// With auto-detection, the DRW instructions that draw it
+    pub drawn_at: Vec<u16>,
+}
+
+impl Display for Sprite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Sprite at {:03X} ({} bytes",
+            self.address,
+            self.bytes.len()
+        )?;
+        if !self.drawn_at.is_empty() {
+            let drawn_at = self
+                .drawn_at
+                .iter()
+                .map(|address| format!("{:03X}", address))
+                .collect::<Vec<_>>();
+            write!(f, ", drawn at {}", drawn_at.join(", "))?;
+        }
+        writeln!(f, "):")?;
+        for (offset, byte) in self.bytes.iter().enumerate() {
+            let row = (0..8)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect::<String>();
+            writeln!(
+                f,
+                "    {:03X}: {:02X}  {}",
+                self.address as usize + offset,
+                byte,
+                row
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The `length` bytes at `address` in a program loaded at 0x200. Fails with
+/// `MemoryOutOfBounds` if any of them aren't in the program.
+pub fn sprite_at(program: &[u8], address: u16, length: usize) -> Result<Sprite, Chip8Error> {
+    let start = (address as usize)
+        .checked_sub(memory::PROGRAM_START)
+        .ok_or(Chip8Error::MemoryOutOfBounds {
+            address: address as usize,
+        })?;
+    let bytes = program
+        .get(start..start + length)
+        .ok_or(Chip8Error::MemoryOutOfBounds {
+            address: memory::PROGRAM_START + program.len(),
+        })?;
+    Ok(Sprite {
+        address,
+        bytes: bytes.to_vec(),
+        drawn_at: vec![],
+    })
+}
+
+/// Find the sprites a program draws without running it: wherever `LD I, nnn`
+/// is followed by a DRW (before anything else changes I or the code jumps
+/// away), the DRW's height is how many bytes at nnn are a sprite. A 16x16
+/// SCHIP sprite (`DRW Vx, Vy, 0`) is 32 bytes, 2 per row, so it shows up as 32
+/// rows of 8.
+/// Sprites that are only drawn with an I worked out at runtime, like with
+/// `ADD I, Vx`, can't be found this way.
+pub fn find_sprites(program: &[u8]) -> Result<Vec<Sprite>, Chip8Error> {
+    let disassembly = disasm::disassemble(program)?;
+    // The address and height of each sprite, and where it's drawn
+    let mut found: BTreeMap<u16, (usize, Vec<u16>)> = BTreeMap::new();
+    let mut i = None;
+    for line in &disassembly.lines {
+        let (address, instruction) = match line {
+            Line::Code {
+                address,
+                instruction,
+                ..
+            } => (*address, instruction),
+            _ => continue,
+        };
+        match instruction {
+            Instruction::LDI(target) => i = Some(target.value()),
+            Instruction::DRW(_, _, height) => {
+                if let Some(target) = i {
+                    let length = if *height == 0 { 32 } else { *height as usize };
+                    let (longest, drawn_at) = found.entry(target).or_default();
+                    *longest = length.max(*longest);
+                    drawn_at.push(address);
+                }
+            }
+            Instruction::ADDI(_)
+            | Instruction::LDHF(_)
+            | Instruction::LDILong(_)
+            | Instruction::JP(_)
+            | Instruction::JPOffset(_)
+            | Instruction::RET() => i = None,
+            _ => {}
+        }
+    }
+
+    Ok(found
+        .into_iter()
+        .filter_map(|(address, (length, drawn_at))| {
+            let sprite = sprite_at(program, address, length).ok()?;
+            Some(Sprite { drawn_at, ..sprite })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::{program, Register};
+
+    #[test]
+    fn draws_each_byte_as_a_row() {
+        let sprite = sprite_at(&[0x00, 0x00, 0x3C, 0x24, 0x3C], 0x202, 3).unwrap();
+        assert_eq!(
+            sprite.to_string(),
+            "Sprite at 202 (3 bytes):\n    202: 3C  ..####..\n    203: 24  ..#..#..\n    204: 3C  ..####..\n"
+        );
+    }
+
+    #[test]
+    fn sprites_must_be_in_the_program() {
+        assert!(matches!(
+            sprite_at(&[0x3C], 0x200, 2),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0x201 })
+        ));
+        assert!(matches!(
+            sprite_at(&[0x3C], 0x100, 1),
+            Err(Chip8Error::MemoryOutOfBounds { address: 0x100 })
+        ));
+    }
+
+    #[test]
+    fn finds_sprites_from_ld_i_and_drw() {
+        // LD I, 206; DRW V0, V0, 2; JP 204; then a 2-byte sprite
+        let mut rom = program(&[
+            Instruction::LDI(0x206.into()),
+            Instruction::DRW(Register::from_nibble(0x0), Register::from_nibble(0x0), 2),
+            Instruction::JP(0x204.into()),
+        ]);
+        rom.extend(&[0xF0, 0x90]);
+
+        assert_eq!(
+            find_sprites(&rom).unwrap(),
+            vec![Sprite {
+                address: 0x206,
+                bytes: vec![0xF0, 0x90],
+                drawn_at: vec![0x202],
+            }]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::interpreter::State;
+use std::fmt::{self, Display, Formatter};
+
+/// How many changed pixels `StateDiff` lists before it just counts the rest.
+const PIXELS_SHOWN: usize = 8;
+
+/// A run of bytes in memory that changed.
+#[derive(Debug, PartialEq)]
+pub struct MemoryChange {
+    pub start: usize,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+/// Everything that's different between two machines (see `State::diff`), as
+/// pairs of the old and the new value. Only the registers, memory, stack, and
+/// screen are compared, not the keypad or anything the frontend keeps.
+#[derive(Debug, Default, PartialEq)]
+pub struct StateDiff {
+    /// Each V register that changed, by its number
+    pub registers: Vec<(usize, u8, u8)>,
+    pub i: Option<(u16, u16)>,
+    pub pc: Option<(u16, u16)>,
+    pub sp: Option<(u8, u8)>,
+    pub delay_timer: Option<(u8, u8)>,
+    pub sound_timer: Option<(u8, u8)>,
+    pub memory: Vec<MemoryChange>,
+    /// Each stack entry that changed, by its depth
+    pub stack: Vec<(usize, u16, u16)>,
+    /// The logical width and height, if the resolution changed. The pixels
+    /// aren't compared then, since they don't line up.
+    pub resolution: Option<((usize, usize), (usize, usize))>,
+    /// Each logical pixel that changed, as `(x, y, planes before, planes
+    /// after)`, row by row
+    pub pixels: Vec<(usize, usize, u8, u8)>,
+}
+
+/// What changed from `a` to `b`, if anything.
+fn changed<T: PartialEq>(a: T, b: T) -> Option<(T, T)> {
+    if a == b {
+        None
+    } else {
+        Some((a, b))
+    }
+}
+
+impl StateDiff {
+    pub fn between(a: &State, b: &State) -> Self {
+        let registers = a
+            .registers()
+            .iter()
+            .zip(b.registers())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(n, (before, after))| (n, *before, *after))
+            .collect();
+        let stack = a
+            .stack()
+            .iter()
+            .zip(b.stack())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(n, (before, after))| (n, *before, *after))
+            .collect();
+        let (before, after) = (a.buffer(), b.buffer());
+        let resolution = changed((before.width, before.height), (after.width, after.height));
+        let mut pixels = vec![];
+        if resolution.is_none() {
+            for (y, (row_before, row_after)) in
+                before.iter_rows().zip(after.iter_rows()).enumerate()
+            {
+                for (x, (pixel_before, pixel_after)) in row_before.iter().zip(row_after).enumerate()
+                {
+                    if pixel_before != pixel_after {
+                        pixels.push((x, y, *pixel_before, *pixel_after));
+                    }
+                }
+            }
+        }
+        Self {
+            registers,
+            i: changed(a.i(), b.i()),
+            pc: changed(a.pc(), b.pc()),
+            sp: changed(a.sp(), b.sp()),
+            delay_timer: changed(a.delay_timer(), b.delay_timer()),
+            sound_timer: changed(a.sound_timer(), b.sound_timer()),
+            memory: memory_changes(a.memory(), b.memory()),
+            stack,
+            resolution,
+            pixels,
+        }
+    }
+
+    /// Whether the two machines are the same.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The runs of bytes that are different between `a` and `b`.
+fn memory_changes(a: &[u8], b: &[u8]) -> Vec<MemoryChange> {
+    let mut changes: Vec<MemoryChange> = vec![];
+    for (address, (before, after)) in a.iter().zip(b).enumerate() {
+        if before == after {
+            continue;
+        }
+        match changes.last_mut() {
+            Some(change) if change.start + change.after.len() == address => {
+                change.before.push(*before);
+                change.after.push(*after);
+            }
+            _ => changes.push(MemoryChange {
+                start: address,
+                before: vec![*before],
+                after: vec![*after],
+            }),
+        }
+    }
+    changes
+}
+
+/// Bytes in hex, separated by spaces.
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// One line for each thing that changed, like:
+///
+///     V3: 04 -> 05
+///     I: 0300 -> 0302
+///     Memory 300-301: 00 00 -> 04 05
+///     Pixels: (2, 0) 0 -> 1, (3, 0) 0 -> 1
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Nothing changed");
+        }
+        for (n, before, after) in &self.registers {
+            writeln!(f, "V{:X}: {:02X} -> {:02X}", n, before, after)?;
+        }
+        if let Some((before, after)) = self.i {
+            writeln!(f, "I: {:04X} -> {:04X}", before, after)?;
+        }
+        if let Some((before, after)) = self.pc {
+            writeln!(f, "PC: {:03X} -> {:03X}", before, after)?;
+        }
+        if let Some((before, after)) = self.sp {
+            writeln!(f, "SP: {} -> {}", before, after)?;
+        }
+        if let Some((before, after)) = self.delay_timer {
+            writeln!(f, "DT: {} -> {}", before, after)?;
+        }
+        if let Some((before, after)) = self.sound_timer {
+            writeln!(f, "ST: {} -> {}", before, after)?;
+        }
+        for change in &self.memory {
+            writeln!(
+                f,
+                "Memory {:03X}-{:03X}: {} -> {}",
+                change.start,
+                change.start + change.after.len() - 1,
+                hex_bytes(&change.before),
+                hex_bytes(&change.after)
+            )?;
+        }
+        for (depth, before, after) in &self.stack {
+            writeln!(f, "Stack {}: {:03X} -> {:03X}", depth, before, after)?;
+        }
+        if let Some(((width, height), (new_width, new_height))) = self.resolution {
+            writeln!(
+                f,
+                "Resolution: {}x{} -> {}x{}",
+                width, height, new_width, new_height
+            )?;
+        }
+        if !self.pixels.is_empty() {
+            let mut shown = self
+                .pixels
+                .iter()
+                .take(PIXELS_SHOWN)
+                .map(|(x, y, before, after)| format!("({}, {}) {} -> {}", x, y, before, after))
+                .collect::<Vec<_>>();
+            if self.pixels.len() > PIXELS_SHOWN {
+                shown.push(format!("and {} more", self.pixels.len() - PIXELS_SHOWN));
+            }
+            writeln!(f, "Pixels: {}", shown.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::tick;
+
+    #[test]
+    fn nothing_changes_between_the_same_machine() {
+        let state = State::with_program(&[0x6A, 0x02]).unwrap();
+        let diff = state.diff(&state.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "Nothing changed\n");
+    }
+
+    #[test]
+    fn shows_everything_that_changed() {
+        // LD V0, 04; LD V1, 05; LD I, 300; LD [I], V1; CALL 20C; (nothing);
+        // LD I, 0A0 (the large font's 0); DRW V0, V0, 1
+        let mut state = State::with_program(&[
+            0x60, 0x04, 0x61, 0x05, 0xA3, 0x00, 0xF1, 0x55, 0x22, 0x0C, 0x00, 0x00, 0xA0, 0xA0,
+            0xD0, 0x01,
+        ])
+        .unwrap();
+        let before = state.clone();
+        for _ in 0..7 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+        }
+        let diff = before.diff(&state);
+        assert_eq!(diff.registers, vec![(0, 0x00, 0x04), (1, 0x00, 0x05)]);
+        assert_eq!(
+            diff.memory,
+            vec![MemoryChange {
+                start: 0x300,
+                before: vec![0x00, 0x00],
+                after: vec![0x04, 0x05],
+            }]
+        );
+        assert_eq!(diff.stack, vec![(0, 0x000, 0x20A)]);
+        assert_eq!(diff.pixels.len(), 4);
+        assert_eq!(
+            diff.to_string(),
+            "V0: 00 -> 04\n\
+             V1: 00 -> 05\n\
+             I: 0000 -> 00A0\n\
+             PC: 200 -> 210\n\
+             SP: 0 -> 1\n\
+             Memory 300-301: 00 00 -> 04 05\n\
+             Stack 0: 000 -> 20A\n\
+             Pixels: (6, 4) 0 -> 1, (7, 4) 0 -> 1, (8, 4) 0 -> 1, (9, 4) 0 -> 1\n"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    display::{Backend, Key, TITLE},
+    error::Chip8Error,
+    keymap,
+};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Stdout, Write},
+    time::Duration,
+};
+
+/// Terminals say when a key is pressed (and keep repeating it while it's held
+/// down), but never when it's let go. So a key counts as held down for this
+/// many frames after the last time the terminal said it was pressed, which is
+/// longer than the usual gap between key repeats.
+const HOLD_FRAMES: u32 = 6;
+
+/// Draws the display in the terminal, two pixels to a character with the
+/// upper half block `▀`: its foreground color is the top pixel and its
+/// background color is the bottom one. It needs no windowing library, so it
+/// works over SSH.
+pub struct Terminal {
+    stdout: Stdout,
+    open: bool,
+    /// How many more frames each key counts as held down
+    held: HashMap<Key, u32>,
+    /// The keys pressed since the last update, not counting key repeats
+    pressed: HashSet<Key>,
+    title: String,
+}
+
+impl Terminal {
+    pub fn open() -> Result<Self, Chip8Error> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode().map_err(|e| Chip8Error::Window(e.to_string()))?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))
+            .map_err(|e| Chip8Error::Window(e.to_string()))?;
+        Ok(Self {
+            stdout,
+            open: true,
+            held: HashMap::new(),
+            pressed: HashSet::new(),
+            title: TITLE.to_string(),
+        })
+    }
+
+    /// How many columns and rows the terminal has. Fails if there's no
+    /// terminal, like when the output is piped somewhere.
+    pub fn size() -> Result<(u16, u16), Chip8Error> {
+        terminal::size().map_err(|e| Chip8Error::Window(e.to_string()))
+    }
+
+    fn key(code: KeyCode) -> Option<Key> {
+        let key = match code {
+            KeyCode::Char('.') => Key::Period,
+            KeyCode::Char(' ') => Key::Space,
+            KeyCode::Char(c) => keymap::key_for_char(c)?,
+            KeyCode::Esc => Key::Escape,
+            KeyCode::Tab => Key::Tab,
+            KeyCode::Backspace => Key::Backspace,
+            KeyCode::Left => Key::Left,
+            KeyCode::Right => Key::Right,
+            KeyCode::F(1) => Key::F1,
+            KeyCode::F(2) => Key::F2,
+            KeyCode::F(3) => Key::F3,
+            KeyCode::F(5) => Key::F5,
+            KeyCode::F(9) => Key::F9,
+            _ => return None,
+        };
+        Some(key)
+    }
+
+    /// Count down the held keys, and read the keys pressed since last time.
+    fn read_keys(&mut self) -> crossterm::Result<()> {
+        self.pressed.clear();
+        for frames in self.held.values_mut() {
+            *frames -= 1;
+        }
+        self.held.retain(|_, frames| *frames > 0);
+
+        while event::poll(Duration::from_secs(0))? {
+            if let Event::Key(KeyEvent { code, modifiers }) = event::read()? {
+                // Raw mode means Ctrl-C doesn't stop the program by itself
+                if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+                    self.open = false;
+                    continue;
+                }
+                if let Some(key) = Self::key(code) {
+                    if self.held.insert(key, HOLD_FRAMES).is_none() {
+                        self.pressed.insert(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw the pixels, with the title on the line underneath them. It uses
+    /// 128x64 pixels if the terminal is big enough, and 64x32 otherwise,
+    /// picking the nearest of the framebuffer's pixels for each.
+    fn draw(&mut self, pixels: &[u32], width: usize, height: usize) -> crossterm::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let (columns, pixel_rows) = if columns >= 128 && rows > 64 / 2 {
+            (128, 64)
+        } else {
+            (64, 32)
+        };
+        let pixel = |x: usize, y: usize| {
+            let [_, r, g, b] =
+                pixels[(y * height / pixel_rows) * width + x * width / columns].to_be_bytes();
+            Color::Rgb { r, g, b }
+        };
+
+        for row in 0..pixel_rows / 2 {
+            queue!(self.stdout, MoveTo(0, row as u16))?;
+            for x in 0..columns {
+                queue!(
+                    self.stdout,
+                    SetForegroundColor(pixel(x, row * 2)),
+                    SetBackgroundColor(pixel(x, row * 2 + 1)),
+                    Print('▀')
+                )?;
+            }
+        }
+        queue!(
+            self.stdout,
+            ResetColor,
+            MoveTo(0, (pixel_rows / 2) as u16),
+            Clear(ClearType::CurrentLine),
+            Print(&self.title)
+        )?;
+        self.stdout.flush()
+    }
+}
+
+impl Backend for Terminal {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.held.contains_key(&key)
+    }
+
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    fn update(&mut self, pixels: &[u32], width: usize, height: usize) {
+        if let Err(e) = self.read_keys() {
+            warn!("Couldn't read the keyboard: {}", e);
+        }
+        if let Err(e) = self.draw(pixels, width, height) {
+            warn!("Couldn't draw to the terminal: {}", e);
+        }
+    }
+
+    /// The terminal keeps showing what was drawn last by itself.
+    fn refresh(&mut self) {
+        if let Err(e) = self.read_keys() {
+            warn!("Couldn't read the keyboard: {}", e);
+        }
+    }
+}
+
+/// Put the terminal back the way it was.
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
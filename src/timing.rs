@@ -0,0 +1,278 @@
+use crate::{frame::FrameListener, instruction::Instruction, interpreter::State};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Formatter},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The COSMAC VIP's 1802 runs at about 1.76 MHz, and a machine cycle is 8 clock
+/// cycles, so this many machine cycles go by in each 60Hz frame. Some of them
+/// are spent drawing the screen, so a program gets fewer than this.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// The VIP's interpreter spends about this many machine cycles fetching and
+/// decoding every instruction, before running it.
+const FETCH_CYCLES: u32 = 40;
+/// How long each 60Hz frame lasts, going by the wall clock.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// When the window gets more than this many frames behind the wall clock (say
+/// because it was being dragged around, or the debugger was waiting for a
+/// command), it gives up on catching up instead of rushing through them all.
+const MAX_FRAMES_BEHIND: u32 = 5;
+
+/// How the interpreter decides when a 60Hz frame is over.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Timing {
+    /// Every frame runs the same number of instructions (see
+    /// `State::with_clock_hz`), however long they'd take on real hardware.
+    Flat,
+    /// Every frame runs as many instructions as fit in the COSMAC VIP's
+    /// machine cycles (see `vip_cycles`), so a frame with lots of drawing runs
+    /// fewer instructions, like on the real machine. Some programs were tuned
+    /// for that, and run too fast or too slow otherwise.
+    Vip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Timing::Flat),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("Unknown timing {} (expected flat or vip)", s)),
+        }
+    }
+}
+
+/// The same names that `from_str` accepts.
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Timing::Flat => write!(f, "flat"),
+            Timing::Vip => write!(f, "vip"),
+        }
+    }
+}
+
+/// Roughly how many machine cycles the original COSMAC VIP interpreter takes to
+/// run `instruction`, including fetching it. These come from reading the VIP's
+/// interpreter, so they're estimates: the real cost can depend on the operands
+/// (like where a sprite is drawn) and on whether a skip is taken.
+/// Returns None for instructions the VIP didn't have (like the SCHIP and
+/// XO-CHIP ones), and for SYS, which runs machine code that could take any
+/// amount of time.
+pub fn vip_cycles(instruction: &Instruction) -> Option<u32> {
+    use Instruction::*;
+
+    let cycles = match instruction {
+        // Most of the time goes into clearing each byte of the display
+        CLS() => 24 + 3054,
+        RET() => 10,
+        JP(_) => 12,
+        CALL(_) => 26,
+        SEByte(..) | SNEByte(..) => 10,
+        SERegister(..) | SNERegister(..) => 14,
+        LDByte(..) => 6,
+        ADDByte(..) => 10,
+        LDRegister(..) | OR(..) | AND(..) | XOR(..) | ADDRegister(..) | SUB(..) | SUBN(..)
+        | SHR(..) | SHL(..) => 44,
+        LDI(_) => 12,
+        JPOffset(_) => 22,
+        RND(..) => 36,
+        // Most of the time goes into drawing each row of the sprite
+        DRW(_, _, rows) => 26 + 46 * *rows as u32,
+        // Not counting the wait for a key
+        SKP(_) | SKNP(_) => 14,
+        LDFromDT(_) => 10,
+        LDKey(_) => 8,
+        LDDT(_) | LDST(_) => 6,
+        ADDI(_) => 12,
+        LDF(_) => 16,
+        // Working out each digit takes a pass per hundred and per ten
+        LDB(_) => 80,
+        LDIntoMemory(register) | LDFromMemory(register) => 14 + 14 * (register.value() as u32 + 1),
+        SYS(_) | SCD(_) | SCR() | SCL() | EXIT() | LOW() | HIGH() | LDHF(_) | LDIntoFlags(_)
+        | LDFromFlags(_) | SAVE(..) | LOAD(..) | LDILong(_) | PLANE(_) | AUDIO() | PITCH(_)
+        | UNKNOWN(_) => return None,
+    };
+    Some(FETCH_CYCLES + cycles)
+}
+
+/// How many machine cycles `instruction` uses up from a frame with
+/// `Timing::Vip`. Instructions the VIP didn't have count as cheap ones.
+pub fn vip_cost(instruction: &Instruction) -> u32 {
+    vip_cycles(instruction).unwrap_or(FETCH_CYCLES)
+}
+
+/// Adds up the VIP cycles (see `vip_cycles`) of every instruction in a frame,
+/// and prints the total when the frame ends. `trace` uses this to show how
+/// close a program would come to running out of time on the real machine.
+#[derive(Default)]
+pub struct FrameCost {
+    cycles: u32,
+    /// How many instructions this frame had no estimate
+    unknown: usize,
+}
+
+impl FrameCost {
+    /// Count an instruction that just ran.
+    pub fn add(&mut self, instruction: &Instruction) {
+        match vip_cycles(instruction) {
+            Some(cycles) => self.cycles += cycles,
+            None => self.unknown += 1,
+        }
+    }
+
+    /// A line summarizing the frame so far.
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "Frame took ~{} of {} VIP cycles",
+            self.cycles, VIP_CYCLES_PER_FRAME
+        );
+        if self.cycles > VIP_CYCLES_PER_FRAME {
+            summary.push_str(" (too slow for a real VIP)");
+        }
+        if self.unknown > 0 {
+            summary.push_str(&format!(
+                ", plus {} instructions the VIP didn't have",
+                self.unknown
+            ));
+        }
+        summary
+    }
+}
+
+impl FrameListener for FrameCost {
+    fn end_frame(&mut self, _state: &State) {
+        println!("{}", self.summary());
+        *self = Self::default();
+    }
+}
+
+/// Starts the window's frames exactly 60 times a second by the wall clock,
+/// however often the monitor refreshes and however long drawing takes, so the
+/// timers count down at 60Hz and programs run at the same speed everywhere.
+/// Each frame is due a `FRAME` after the one before was due, not after it
+/// actually started, so oversleeping is made up for by the next frame.
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub struct FramePacer {
+    /// When the next frame is due to start
+    next_frame: Instant,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+impl FramePacer {
+    pub fn new() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Sleep until the next frame is due, if it isn't yet. Returns how many
+    /// whole frames late it's starting, so that frames that are only catching
+    /// up don't have to be drawn.
+    pub fn wait(&mut self) -> u32 {
+        let (sleep, late) = self.advance(Instant::now());
+        if sleep > Duration::from_secs(0) {
+            thread::sleep(sleep);
+        }
+        late
+    }
+
+    /// Count from now, instead of when the next frame was due, because frames
+    /// were running as fast as possible.
+    pub fn restart(&mut self) {
+        self.next_frame = Instant::now();
+    }
+
+    /// Schedule the frame after the next one, and return how long to wait
+    /// until the next one (at `now`), and how many whole frames late it is.
+    fn advance(&mut self, now: Instant) -> (Duration, u32) {
+        let late = now.saturating_duration_since(self.next_frame);
+        let mut frames_late = (late.as_nanos() / FRAME.as_nanos()) as u32;
+        if frames_late > MAX_FRAMES_BEHIND {
+            self.next_frame = now;
+            frames_late = 0;
+        }
+        let sleep = self.next_frame.saturating_duration_since(now);
+        self.next_frame += FRAME;
+        (sleep, frames_late)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Register;
+
+    #[test]
+    fn frames_start_sixty_times_a_second_by_the_clock() {
+        let start = Instant::now();
+        let mut pacer = FramePacer { next_frame: start };
+        let zero = Duration::from_secs(0);
+        assert_eq!(pacer.advance(start), (zero, 0));
+        // Drawing the first frame took a quarter of a frame
+        assert_eq!(pacer.advance(start + FRAME / 4), (FRAME - FRAME / 4, 0));
+        // The third frame was due at 2 frames in, so it's a whole frame late
+        assert_eq!(pacer.advance(start + FRAME * 3), (zero, 1));
+        // That's made up for by not waiting for the fourth
+        assert_eq!(pacer.advance(start + FRAME * 3), (zero, 0));
+        assert_eq!(pacer.advance(start + FRAME * 3), (FRAME, 0));
+    }
+
+    #[test]
+    fn frames_that_are_too_late_are_given_up_on() {
+        let start = Instant::now();
+        let mut pacer = FramePacer { next_frame: start };
+        let later = start + FRAME * 60;
+        assert_eq!(pacer.advance(later), (Duration::from_secs(0), 0));
+        assert_eq!(pacer.advance(later), (FRAME, 0));
+    }
+
+    #[test]
+    fn vip_cycles_include_fetching() {
+        assert_eq!(vip_cycles(&Instruction::JP(0x200.into())), Some(52));
+        assert_eq!(
+            vip_cycles(&Instruction::DRW(
+                Register::from_nibble(0),
+                Register::from_nibble(1),
+                5
+            )),
+            Some(40 + 26 + 46 * 5)
+        );
+        assert_eq!(vip_cycles(&Instruction::HIGH()), None);
+    }
+
+    #[test]
+    fn timing() {
+        let drw = Instruction::DRW(Register::from_nibble(0), Register::from_nibble(1), 5);
+        assert_eq!(vip_cost(&drw), vip_cycles(&drw).unwrap());
+        assert_eq!(vip_cost(&Instruction::HIGH()), FETCH_CYCLES);
+        assert_eq!("vip".parse(), Ok(Timing::Vip));
+        assert_eq!(Timing::Flat.to_string(), "flat");
+    }
+
+    #[test]
+    fn frame_cost_summary() {
+        let mut cost = FrameCost::default();
+        cost.add(&Instruction::LDByte(Register::from_nibble(0), 1));
+        cost.add(&Instruction::SCR());
+        assert_eq!(
+            cost.summary(),
+            "Frame took ~46 of 3668 VIP cycles, plus 1 instructions the VIP didn't have"
+        );
+
+        for _ in 0..100 {
+            cost.add(&Instruction::LDByte(Register::from_nibble(0), 1));
+        }
+        assert!(cost.summary().contains("too slow"));
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{instruction::Instruction, interpreter::State, library};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Formatter},
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// Writes a line of JSON for every instruction that runs, so that a run can
+/// be analyzed (or diffed against another run) later. Each line looks like:
+///
+///     {"step":1,"pc":"200","opcode":"6A02","mnemonic":"LD VA, 02","changed":{"VA":[0,2]},"i":"0000","dt":0,"st":0}
+///
+/// `changed` has the registers that the instruction changed, with their old
+/// and new values. `i`, `dt`, and `st` are the I register and the timers after
+/// it ran. A DRW that collides also has `"collisions":[[x,y],...]`, the logical
+/// pixels it turned off.
+pub struct TraceFile<W: Write> {
+    writer: W,
+    steps: u64,
+}
+
+impl<W: Write> TraceFile<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, steps: 0 }
+    }
+
+    /// Write the record for the instruction at `address`, which just ran.
+    /// `registers_before` are the registers from before it ran.
+    pub fn record(
+        &mut self,
+        address: u16,
+        instruction: &Instruction,
+        registers_before: &[u8],
+        state: &State,
+    ) -> io::Result<()> {
+        self.steps += 1;
+        let opcode = state
+            .memory()
+            .iter()
+            .skip(address as usize)
+            .take(instruction.size())
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>();
+        let changed = registers_before
+            .iter()
+            .zip(state.registers())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(n, (before, after))| format!("\"V{:X}\":[{},{}]", n, before, after))
+            .collect::<Vec<_>>();
+        let collisions = if state.collisions().is_empty() {
+            String::new()
+        } else {
+            let pixels = state
+                .collisions()
+                .iter()
+                .map(|(x, y)| format!("[{},{}]", x, y))
+                .collect::<Vec<_>>();
+            format!(",\"collisions\":[{}]", pixels.join(","))
+        };
+        writeln!(
+            self.writer,
+            "{{\"step\":{},\"pc\":\"{:03X}\",\"opcode\":\"{}\",\"mnemonic\":{},\"changed\":{{{}}},\"i\":\"{:04X}\",\"dt\":{},\"st\":{}{}}}",
+            self.steps,
+            address,
+            opcode,
+            json_string(&instruction.to_string()),
+            changed.join(","),
+            state.i(),
+            state.delay_timer(),
+            state.sound_timer(),
+            collisions
+        )
+    }
+}
+
+/// Writes a line of JSON with the whole machine's state (a `Snapshot`) every
+/// so many instructions, for `trace --dump-state-every`, so that a run can be
+/// compared against another emulator's, instruction by instruction.
+pub struct StateDump<W: Write> {
+    writer: W,
+    /// Write a line after every this many instructions
+    every: u64,
+    steps: u64,
+}
+
+impl<W: Write> StateDump<W> {
+    pub fn new(writer: W, every: u64) -> Self {
+        Self {
+            writer,
+            every,
+            steps: 0,
+        }
+    }
+
+    /// Count an instruction that just ran, and write the state if it's time.
+    pub fn step(&mut self, state: &State) -> io::Result<()> {
+        self.steps += 1;
+        if self.every == 0 || !self.steps.is_multiple_of(self.every) {
+            return Ok(());
+        }
+        writeln!(self.writer, "{}", Snapshot::of(self.steps, state))
+    }
+}
+
+/// The machine's state after some number of instructions, as `StateDump`
+/// writes it and `difftest` reads it back. As JSON, it looks like:
+///
+///     {"cycle":2,"pc":"206","i":"0000","v":[0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0],"dt":0,"st":0,"sp":1,"stack":["204"],"screen":"7f4ed2..."}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// How many instructions had run
+    pub cycle: u64,
+    pub pc: u16,
+    pub i: u16,
+    pub registers: Vec<u8>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub sp: u8,
+    /// Only the addresses below the stack pointer
+    pub stack: Vec<u16>,
+    /// The SHA-1 of the logical pixels, one byte per pixel (its planes, so 0
+    /// or 1 outside of XO-CHIP), row by row, which another emulator can
+    /// compute too
+    pub screen: String,
+}
+
+impl Snapshot {
+    pub fn of(cycle: u64, state: &State) -> Self {
+        Self {
+            cycle,
+            pc: state.pc(),
+            i: state.i(),
+            registers: state.registers().to_vec(),
+            delay_timer: state.delay_timer(),
+            sound_timer: state.sound_timer(),
+            sp: state.sp(),
+            stack: state
+                .stack()
+                .iter()
+                .take(state.sp() as usize)
+                .copied()
+                .collect(),
+            screen: screen_hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let registers = self
+            .registers
+            .iter()
+            .map(|register| register.to_string())
+            .collect::<Vec<_>>();
+        let stack = self
+            .stack
+            .iter()
+            .map(|address| format!("\"{:03X}\"", address))
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{{\"cycle\":{},\"pc\":\"{:03X}\",\"i\":\"{:04X}\",\"v\":[{}],\"dt\":{},\"st\":{},\"sp\":{},\"stack\":[{}],\"screen\":{}}}",
+            self.cycle,
+            self.pc,
+            self.i,
+            registers.join(","),
+            self.delay_timer,
+            self.sound_timer,
+            self.sp,
+            stack.join(","),
+            json_string(&self.screen)
+        )
+    }
+}
+
+/// Read a snapshot back from its JSON line. The fields can be in any order,
+/// but it has to have all of them.
+impl FromStr for Snapshot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |key: &str| -> Result<u64, String> {
+            field(s, key)?
+                .parse::<u64>()
+                .map_err(|_| format!("\"{}\" isn't a number", key))
+        };
+        let byte = |key: &str| -> Result<u8, String> {
+            u8::try_from(number(key)?).map_err(|_| format!("\"{}\" is more than 255", key))
+        };
+        let address = |value: &str| -> Result<u16, String> {
+            let hex = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .ok_or_else(|| format!("{} isn't a string", value))?;
+            u16::from_str_radix(hex, 16).map_err(|_| format!("{} isn't a hex address", value))
+        };
+        let registers = list(field(s, "v")?)
+            .iter()
+            .map(|register| {
+                register
+                    .parse::<u8>()
+                    .map_err(|_| format!("V register {} isn't a byte", register))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if registers.len() != 16 {
+            return Err(format!("\"v\" has {} registers, not 16", registers.len()));
+        }
+        let screen = field(s, "screen")?;
+        Ok(Self {
+            cycle: number("cycle")?,
+            pc: address(field(s, "pc")?)?,
+            i: address(field(s, "i")?)?,
+            registers,
+            delay_timer: byte("dt")?,
+            sound_timer: byte("st")?,
+            sp: byte("sp")?,
+            stack: list(field(s, "stack")?)
+                .into_iter()
+                .map(address)
+                .collect::<Result<_, _>>()?,
+            screen: screen.trim_matches('"').to_string(),
+        })
+    }
+}
+
+/// The value of `key` in a line of flat JSON (where the only things nested are
+/// lists of numbers or strings), as written: a list with its brackets, or a
+/// string with its quotes.
+fn field<'a>(json: &'a str, key: &str) -> Result<&'a str, String> {
+    let name = format!("\"{}\":", key);
+    let start = json
+        .find(&name)
+        .map(|start| start + name.len())
+        .ok_or_else(|| format!("There's no \"{}\"", key))?;
+    let rest = json[start..].trim_start();
+    let end = if rest.starts_with('[') {
+        rest.find(']').map(|end| end + 1)
+    } else if let Some(string) = rest.strip_prefix('"') {
+        string.find('"').map(|end| end + 2)
+    } else {
+        rest.find([',', '}'])
+    };
+    end.map(|end| rest[..end].trim())
+        .ok_or_else(|| format!("\"{}\" doesn't end", key))
+}
+
+/// The items in a list field, like `[1,2]`.
+fn list(value: &str) -> Vec<&str> {
+    let items = value.trim_start_matches('[').trim_end_matches(']').trim();
+    if items.is_empty() {
+        vec![]
+    } else {
+        items.split(',').map(str::trim).collect()
+    }
+}
+
+/// The SHA-1 of the screen's logical pixels, as `Snapshot` describes.
+fn screen_hash(state: &State) -> String {
+    let buffer = state.buffer();
+    let mut planes = Vec::with_capacity(buffer.width * buffer.height);
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            planes.push(buffer.get_planes(x, y));
+        }
+    }
+    library::fingerprint(&planes)
+}
+
+/// Quote a string for JSON.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::tick;
+
+    #[test]
+    fn records_what_changed() {
+        // LD VA, 02
+        let mut state = State::with_program(&[0x6A, 0x02]).unwrap();
+        let before = state.registers().to_vec();
+        let instruction = state.next_instruction().unwrap().unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+
+        let mut trace = TraceFile::new(vec![]);
+        trace.record(0x200, &instruction, &before, &state).unwrap();
+        assert_eq!(
+            String::from_utf8(trace.writer).unwrap(),
+            "{\"step\":1,\"pc\":\"200\",\"opcode\":\"6A02\",\"mnemonic\":\"LD VA, 02\",\"changed\":{\"VA\":[0,2]},\"i\":\"0000\",\"dt\":0,\"st\":0}\n"
+        );
+    }
+
+    #[test]
+    fn records_where_drw_collided() {
+        // LD I, 0A0 (the large font's 0); DRW V0, V0, 1; DRW V0, V0, 1
+        let mut state = State::with_program(&[0xA0, 0xA0, 0xD0, 0x01, 0xD0, 0x01]).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        let before = state.registers().to_vec();
+        let instruction = state.next_instruction().unwrap().unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+
+        let mut trace = TraceFile::new(vec![]);
+        trace.record(0x204, &instruction, &before, &state).unwrap();
+        assert!(String::from_utf8(trace.writer)
+            .unwrap()
+            .ends_with(",\"collisions\":[[2,0],[3,0],[4,0],[5,0]]}\n"));
+    }
+
+    #[test]
+    fn dumps_the_state_every_so_often() {
+        // LD VA, 02; CALL 206; JP 204; RET
+        let mut state =
+            State::with_program(&[0x6A, 0x02, 0x22, 0x06, 0x12, 0x04, 0x00, 0xEE]).unwrap();
+        let mut dump = StateDump::new(vec![], 2);
+        for _ in 0..3 {
+            tick(&mut state, rand::thread_rng()).unwrap();
+            dump.step(&state).unwrap();
+        }
+        let blank = library::fingerprint(&[0; 64 * 32]);
+        assert_eq!(
+            String::from_utf8(dump.writer).unwrap(),
+            format!("{{\"cycle\":2,\"pc\":\"206\",\"i\":\"0000\",\"v\":[0,0,0,0,0,0,0,0,0,0,2,0,0,0,0,0],\"dt\":0,\"st\":0,\"sp\":1,\"stack\":[\"204\"],\"screen\":\"{}\"}}\n", blank)
+        );
+    }
+
+    #[test]
+    fn reads_snapshots_back() {
+        // LD VA, 02; CALL 206; JP 204; RET
+        let mut state =
+            State::with_program(&[0x6A, 0x02, 0x22, 0x06, 0x12, 0x04, 0x00, 0xEE]).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        let snapshot = Snapshot::of(2, &state);
+        assert_eq!(
+            snapshot.to_string().parse::<Snapshot>(),
+            Ok(snapshot.clone())
+        );
+        // Other emulators might put the fields in another order, with spaces
+        let reordered = format!(
+            "{{ \"screen\": \"{}\", \"stack\": [\"204\"], \"sp\": 1, \"st\": 0, \"dt\": 0, \"v\": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0], \"i\": \"0000\", \"pc\": \"206\", \"cycle\": 2 }}",
+            snapshot.screen
+        );
+        assert_eq!(reordered.parse::<Snapshot>(), Ok(snapshot));
+    }
+
+    #[test]
+    fn says_whats_wrong_with_a_snapshot() {
+        assert_eq!(
+            "{\"cycle\":1}".parse::<Snapshot>(),
+            Err("There's no \"v\"".to_string())
+        );
+        assert_eq!(
+            "{\"cycle\":1,\"v\":[1,2]}".parse::<Snapshot>(),
+            Err("\"v\" has 2 registers, not 16".to_string())
+        );
+    }
+
+    #[test]
+    fn the_screen_hash_changes_with_the_pixels() {
+        // LD I, 0A0; DRW V0, V0, 1
+        let mut state = State::with_program(&[0xA0, 0xA0, 0xD0, 0x01]).unwrap();
+        tick(&mut state, rand::thread_rng()).unwrap();
+        let blank = screen_hash(&state);
+        tick(&mut state, rand::thread_rng()).unwrap();
+        assert_ne!(screen_hash(&state), blank);
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a \"b\" \\ \n"), "\"a \\\"b\\\" \\\\ \\u000a\"");
+    }
+}
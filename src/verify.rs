@@ -0,0 +1,238 @@
+use crate::{
+    assembler,
+    disasm::{self, Line},
+    error::Chip8Error,
+    instruction::{Instruction, Register},
+    memory,
+};
+use std::fmt::{self, Display, Formatter};
+
+/// One place where the assembled source doesn't match the ROM.
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub address: usize,
+    /// What the ROM has there
+    pub rom: String,
+    /// What the source assembled to there
+    pub source: String,
+    /// Whether they do the same thing, even though their bytes are different
+    pub equivalent: bool,
+}
+
+/// How close the source is to rebuilding the ROM.
+#[derive(Debug, PartialEq)]
+pub struct Verification {
+    /// How many of the ROM's bytes the source rebuilt exactly
+    pub matching_bytes: usize,
+    pub rom_length: usize,
+    pub source_length: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl Verification {
+    /// How many differences actually change what the program does.
+    pub fn mismatches(&self) -> usize {
+        self.differences
+            .iter()
+            .filter(|difference| !difference.equivalent)
+            .count()
+    }
+
+    pub fn is_byte_exact(&self) -> bool {
+        self.differences.is_empty() && self.rom_length == self.source_length
+    }
+}
+
+/// Compare what the source assembles to with the ROM, to track how close a
+/// reconstruction is to rebuilding it byte for byte.
+///
+/// The ROM is split into code and data the same way `chip8 print` does it.
+/// Its code is compared an instruction at a time, and instructions that are
+/// encoded differently but do the same thing (like `SE V1, V2` and
+/// `SE V2, V1`) are equivalent, not mismatches. Its data is compared a run of
+/// bytes at a time.
+pub fn verify(source: &str, rom: &[u8]) -> Result<Verification, Chip8Error> {
+    let assembled = assembler::assemble(source)?;
+    let disassembly = disasm::disassemble(rom)?;
+    let start = memory::PROGRAM_START;
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut differences = vec![];
+
+    for line in &disassembly.lines {
+        match line {
+            Line::Label(_) => {}
+            Line::Code {
+                address,
+                instruction,
+                ..
+            } => {
+                let offset = *address as usize - start;
+                let size = instruction.size();
+                let rom_bytes = &rom[offset..offset + size];
+                let source_bytes = assembled.get(offset..offset + size);
+                if source_bytes == Some(rom_bytes) {
+                    continue;
+                }
+                let theirs = source_bytes.map(Instruction::from_bytes).transpose()?;
+                differences.push(Difference {
+                    address: *address as usize,
+                    rom: format!("{} ({})", hex(rom_bytes), instruction),
+                    source: match (&theirs, source_bytes) {
+                        (Some(theirs), Some(bytes)) => format!("{} ({})", hex(bytes), theirs),
+                        _ => "nothing".to_string(),
+                    },
+                    equivalent: match &theirs {
+                        Some(theirs) => normalize(theirs) == normalize(instruction),
+                        None => false,
+                    },
+                });
+            }
+            Line::Data { address, bytes } => {
+                let offset = *address as usize - start;
+                let source_bytes = assembled.get(offset..).unwrap_or(&[]);
+                let source_bytes = &source_bytes[..bytes.len().min(source_bytes.len())];
+                if source_bytes != bytes.as_slice() {
+                    differences.push(Difference {
+                        address: *address as usize,
+                        rom: hex(bytes),
+                        source: match source_bytes {
+                            [] => "nothing".to_string(),
+                            bytes => hex(bytes),
+                        },
+                        equivalent: false,
+                    });
+                }
+            }
+        }
+    }
+    if assembled.len() > rom.len() {
+        differences.push(Difference {
+            address: start + rom.len(),
+            rom: "nothing".to_string(),
+            source: match assembled.len() - rom.len() {
+                1 => "1 more byte".to_string(),
+                extra => format!("{} more bytes", extra),
+            },
+            equivalent: false,
+        });
+    }
+
+    Ok(Verification {
+        matching_bytes: rom
+            .iter()
+            .zip(&assembled)
+            .filter(|(rom, source)| rom == source)
+            .count(),
+        rom_length: rom.len(),
+        source_length: assembled.len(),
+        differences,
+    })
+}
+
+/// The instruction, written one particular way out of the ways that do the
+/// same thing.
+fn normalize(instruction: &Instruction) -> Instruction {
+    let ordered = |x: &Register, y: &Register| {
+        if x.value() <= y.value() {
+            (*x, *y)
+        } else {
+            (*y, *x)
+        }
+    };
+    match instruction {
+        // Comparing two registers doesn't care which comes first
+        Instruction::SERegister(x, y) => {
+            let (x, y) = ordered(x, y);
+            Instruction::SERegister(x, y)
+        }
+        Instruction::SNERegister(x, y) => {
+            let (x, y) = ordered(x, y);
+            Instruction::SNERegister(x, y)
+        }
+        _ => instruction.clone(),
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(
+                f,
+                "{:03X}: ROM has {}, source has {}{}",
+                difference.address,
+                difference.rom,
+                difference.source,
+                if difference.equivalent {
+                    " (equivalent)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        if self.is_byte_exact() {
+            return writeln!(f, "Byte-exact: all {} bytes match", self.rom_length);
+        }
+        let percent = match self.rom_length {
+            0 => 0.0,
+            length => self.matching_bytes as f64 * 100.0 / length as f64,
+        };
+        writeln!(
+            f,
+            "{} of {} bytes match ({:.1}%), {} equivalent, {} mismatched",
+            self.matching_bytes,
+            self.rom_length,
+            percent,
+            self.differences.len() - self.mismatches(),
+            self.mismatches()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn byte_exact() {
+        let rom = assembler::assemble("LD V1, 02\nJP 202").unwrap();
+        let verification = verify("LD V1, 02\nJP 202", &rom).unwrap();
+
+        assert!(verification.is_byte_exact());
+        assert_eq!(verification.to_string(), "Byte-exact: all 4 bytes match\n");
+    }
+
+    #[test]
+    fn equivalent_encodings_arent_mismatches() {
+        let rom = assembler::assemble("SE V1, V2\nJP 200").unwrap();
+        let verification = verify("SE V2, V1\nJP 200", &rom).unwrap();
+
+        assert!(!verification.is_byte_exact());
+        assert_eq!(verification.mismatches(), 0);
+        assert_eq!(
+            verification.to_string(),
+            "200: ROM has 51 20 (SE V1, V2), source has 52 10 (SE V2, V1) (equivalent)\n\
+             2 of 4 bytes match (50.0%), 1 equivalent, 0 mismatched\n"
+        );
+    }
+
+    #[test]
+    fn reports_code_and_data_mismatches() {
+        let rom = assembler::assemble("LD I, 206\nLD V1, 02\nJP 204\ndb FF 81").unwrap();
+        let verification = verify("LD I, 206\nLD V1, 03\nJP 204\ndb FF 00 01", &rom).unwrap();
+
+        assert_eq!(verification.mismatches(), 3);
+        assert_eq!(
+            verification.to_string(),
+            "202: ROM has 61 02 (LD V1, 02), source has 61 03 (LD V1, 03)\n\
+             206: ROM has FF 81, source has FF 00\n\
+             208: ROM has nothing, source has 1 more byte\n\
+             6 of 8 bytes match (75.0%), 0 equivalent, 3 mismatched\n"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{frame::FrameListener, interpreter::State};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// Keeps count of how far a run has got, and logs a progress line every so
+/// often so that long unattended runs can be seen to be moving (or not).
+pub struct Watchdog {
+    /// Log progress every this many frames, or never if it's 0
+    report_every: u64,
+    instructions: u64,
+    frames: u64,
+    /// How many more frames until the next report
+    until_report: u64,
+    /// When we last reported, and how many instructions had run by then
+    last_report: (Instant, u64),
+}
+
+impl Watchdog {
+    pub fn new(report_every: u64) -> Self {
+        Self {
+            report_every,
+            instructions: 0,
+            frames: 0,
+            until_report: report_every,
+            last_report: (Instant::now(), 0),
+        }
+    }
+
+    /// How many frames have gone by.
+    #[cfg(test)]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// How many instructions have run.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Whether we've run `max_cycles` instructions (if there's a limit).
+    pub fn out_of_cycles(&self, max_cycles: Option<u64>) -> bool {
+        match max_cycles {
+            Some(max_cycles) if self.instructions >= max_cycles => {
+                info!("Stopped after {} cycles", self.instructions);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Count an instruction that just ran (or waited).
+    pub fn instruction_ran(&mut self) {
+        self.instructions += 1;
+    }
+}
+
+impl FrameListener for Watchdog {
+    /// Count the frame, and log progress if it's time.
+    fn end_frame(&mut self, state: &State) {
+        self.frames += 1;
+        if self.report_every == 0 {
+            return;
+        }
+        self.until_report -= 1;
+        if self.until_report > 0 {
+            return;
+        }
+        self.until_report = self.report_every;
+        let now = Instant::now();
+        let (last_time, last_instructions) = self.last_report;
+        info!(
+            "{}",
+            progress(
+                self.frames,
+                self.instructions - last_instructions,
+                now - last_time,
+                state
+            )
+        );
+        self.last_report = (now, self.instructions);
+    }
+}
+
+/// A hash of what's on the screen, so it's easy to see whether it changed.
+pub fn screen_hash(state: &State) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.buffer().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A progress line, like `frame 600: 6000 instructions/s, screen 1a2b3c4d5e6f7a8b`.
+fn progress(frames: u64, instructions: u64, elapsed: Duration, state: &State) -> String {
+    let per_second = instructions as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    format!(
+        "frame {}: {:.0} instructions/s, screen {:016x}",
+        frames,
+        per_second,
+        screen_hash(state)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_frames() {
+        let state = State::with_program(&[]).unwrap();
+        let mut watchdog = Watchdog::new(0);
+        for _ in 0..25 {
+            watchdog.instruction_ran();
+        }
+        watchdog.end_frame(&state);
+        watchdog.end_frame(&state);
+
+        assert_eq!(watchdog.instructions(), 25);
+        assert_eq!(watchdog.frames(), 2);
+    }
+
+    #[test]
+    fn progress_line() {
+        let state = State::with_program(&[]).unwrap();
+        let line = progress(600, 3000, Duration::from_millis(500), &state);
+
+        assert!(line.starts_with("frame 600: 6000 instructions/s, screen "));
+        assert_eq!(screen_hash(&state), screen_hash(&state.clone()));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{display::Framebuffer, emulator, frontend::Frontend, interpreter::State};
+use wasm_bindgen::{prelude::*, Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+
+/// The keys for the CHIP-8's keypad, in order from 0 to F, as the `code` of a
+/// browser `KeyboardEvent`. They're laid out like the window's keys (see
+/// `display::KEYPAD`), so they're in the same place no matter the keyboard's
+/// language.
+const KEYPAD_CODES: [&str; 16] = [
+    "KeyX", "Digit1", "Digit2", "Digit3", "KeyQ", "KeyW", "KeyE", "KeyA", "KeyS", "KeyD", "KeyZ",
+    "KeyC", "Digit4", "KeyR", "KeyF", "KeyV",
+];
+
+/// It knows how to draw a `Framebuffer` to an HTML canvas, and which
+/// keys the page says are held down.
+pub struct Canvas {
+    context: CanvasRenderingContext2d,
+    keypad: [bool; 16],
+}
+
+impl Canvas {
+    /// Draw on the canvas with this id, resizing it to fit the screen.
+    fn find(id: &str, buffer: &Framebuffer) -> Result<Self, JsValue> {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(id))
+            .ok_or_else(|| JsValue::from_str(&format!("There's no element with the id `{}`", id)))?
+            .dyn_into::<HtmlCanvasElement>()?;
+        canvas.set_width(buffer.true_width() as u32);
+        canvas.set_height(buffer.true_height() as u32);
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("The canvas can't draw in 2D"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        Ok(Self {
+            context,
+            keypad: [false; 16],
+        })
+    }
+
+    /// Hold a key down, or let it go, if it's one of the keypad's keys.
+    fn set_key(&mut self, code: &str, down: bool) -> bool {
+        match KEYPAD_CODES.iter().position(|key| *key == code) {
+            Some(key) => {
+                self.keypad[key] = down;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Frontend for Canvas {
+    fn keypad(&self) -> [bool; 16] {
+        self.keypad
+    }
+
+    fn show(&mut self, state: &State) {
+        let buffer = state.buffer();
+        // The canvas keeps showing what was drawn last
+        if buffer.dirty_region().is_none() {
+            return;
+        }
+        // The canvas wants RGBA bytes, and the scaled pixels are 0RGB words
+        let rgba = buffer
+            .scaled_pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let [_, r, g, b] = pixel.to_be_bytes();
+                vec![r, g, b, 0xFF]
+            })
+            .collect::<Vec<u8>>();
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&rgba),
+            buffer.true_width() as u32,
+            buffer.true_height() as u32,
+        );
+        match image.and_then(|image| self.context.put_image_data(&image, 0.0, 0.0)) {
+            Ok(()) => {}
+            Err(e) => warn!("Couldn't draw to the canvas: {:?}", e),
+        }
+    }
+
+    /// The page stops the program by not asking for any more frames.
+    fn is_running(&self) -> bool {
+        true
+    }
+}
+
+/// A CHIP-8 machine on a web page. The page calls `run_frame` 60 times a
+/// second (with `requestAnimationFrame`), and passes its key events along:
+///
+///     const emulator = new Emulator("screen", rom);
+///     document.addEventListener("keydown", e => emulator.key_down(e.code));
+///     document.addEventListener("keyup", e => emulator.key_up(e.code));
+///     const frame = () => { emulator.run_frame(); requestAnimationFrame(frame); };
+///     requestAnimationFrame(frame);
+///
+/// See `web/index.html` for a whole page.
+#[wasm_bindgen]
+pub struct Emulator {
+    emulator: emulator::Emulator<Canvas>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Load the ROM, and draw it on the canvas with the id `canvas_id`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas_id: &str, rom: &[u8]) -> Result<Emulator, JsValue> {
+        let canvas = Canvas::find(canvas_id, &Framebuffer::new())?;
+        let emulator = emulator::Emulator::builder()
+            .rom(rom)
+            .frontend(canvas)
+            .build()
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(Self { emulator })
+    }
+
+    /// Run the instructions in one frame, and then draw the screen.
+    pub fn run_frame(&mut self) -> Result<(), JsValue> {
+        self.emulator
+            .run_frame()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// A key was pressed. Returns whether it's one of the keypad's keys, so the
+    /// page can stop the browser from doing anything else with it.
+    pub fn key_down(&mut self, code: &str) -> bool {
+        self.emulator.frontend_mut().set_key(code, true)
+    }
+
+    /// A key was let go. Returns whether it's one of the keypad's keys.
+    pub fn key_up(&mut self, code: &str) -> bool {
+        self.emulator.frontend_mut().set_key(code, false)
+    }
+}
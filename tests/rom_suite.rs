@@ -0,0 +1,224 @@
+//! Runs well-known test ROMs headlessly, and compares the screen each one ends
+//! up showing with the golden one recorded in `tests/golden`. When they're
+//! different, the test fails with the pixels that don't match.
+//!
+//! corax89's opcode test (`test_opcode.ch8`) is in this repository, so it
+//! always runs. The ROMs from the community CHIP-8 test suite
+//! (https://github.com/Timendus/chip8-test-suite) aren't, so their test only
+//! runs when asked for: point `CHIP8_TEST_SUITE` at the suite's `bin`
+//! directory and run `cargo test --test rom_suite -- --ignored`.
+//!
+//! Set `CHIP8_BLESS=1` to record the screens instead of comparing them, after
+//! checking by eye that they show every test passing.
+
+use chip8::{
+    display::Framebuffer, emulator::Emulator, frontend::Frontend, interpreter::State,
+    memory::MemoryHook, quirks::Profile,
+};
+use std::{
+    cell::RefCell,
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// A frontend with no window, for running ROMs in tests: it holds down no
+/// keys, keeps the last screen it was shown, and stops after a set number of
+/// frames.
+struct TestHarnessDisplay {
+    frames_left: usize,
+    screen: Option<Framebuffer>,
+}
+
+impl TestHarnessDisplay {
+    fn new(frames: usize) -> Self {
+        Self {
+            frames_left: frames,
+            screen: None,
+        }
+    }
+}
+
+impl Frontend for TestHarnessDisplay {
+    fn keypad(&self) -> [bool; 16] {
+        [false; 16]
+    }
+
+    fn show(&mut self, state: &State) {
+        self.screen = Some(state.buffer().clone());
+        self.frames_left = self.frames_left.saturating_sub(1);
+    }
+
+    fn is_running(&self) -> bool {
+        self.frames_left > 0
+    }
+}
+
+/// Reads as which machine to test. The quirks ROM reads this from 0x1FF
+/// instead of asking: 1 for CHIP-8, 2 for SCHIP, and 3 for XO-CHIP.
+struct Platform(u8);
+
+impl MemoryHook for Platform {
+    fn read(&mut self, _address: usize, _value: u8) -> u8 {
+        self.0
+    }
+}
+
+/// One ROM, and how to run it.
+struct Case {
+    rom: &'static str,
+    /// What to call the golden screen, since some ROMs are run more than once
+    name: &'static str,
+    profile: Profile,
+    /// What the ROM reads at 0x1FF, if anything (see `Platform`)
+    platform: Option<u8>,
+    /// How many frames to run before looking at the screen
+    frames: usize,
+}
+
+/// The keypad ROM waits for keys, and the beep and scrolling ROMs have to be
+/// looked at (or listened to), so they aren't here.
+const TEST_SUITE: [Case; 7] = [
+    Case {
+        rom: "1-chip8-logo.ch8",
+        name: "1-chip8-logo",
+        profile: Profile::Cosmac,
+        platform: None,
+        frames: 100,
+    },
+    Case {
+        rom: "2-ibm-logo.ch8",
+        name: "2-ibm-logo",
+        profile: Profile::Cosmac,
+        platform: None,
+        frames: 100,
+    },
+    Case {
+        rom: "3-corax+.ch8",
+        name: "3-corax+",
+        profile: Profile::Cosmac,
+        platform: None,
+        frames: 1_000,
+    },
+    Case {
+        rom: "4-flags.ch8",
+        name: "4-flags",
+        profile: Profile::Cosmac,
+        platform: None,
+        frames: 1_000,
+    },
+    Case {
+        rom: "5-quirks.ch8",
+        name: "5-quirks-cosmac",
+        profile: Profile::Cosmac,
+        platform: Some(1),
+        frames: 10_000,
+    },
+    Case {
+        rom: "5-quirks.ch8",
+        name: "5-quirks-schip",
+        profile: Profile::Schip,
+        platform: Some(2),
+        frames: 10_000,
+    },
+    Case {
+        rom: "5-quirks.ch8",
+        name: "5-quirks-xo-chip",
+        profile: Profile::XoChip,
+        platform: Some(3),
+        frames: 10_000,
+    },
+];
+
+/// Run the ROM for the case's number of frames, and return the last screen.
+fn run(case: &Case, program: &[u8]) -> Result<Framebuffer, String> {
+    let mut builder = Emulator::builder()
+        .rom(program)
+        .profile(case.profile)
+        .seed(0)
+        .frontend(TestHarnessDisplay::new(case.frames));
+    if let Some(platform) = case.platform {
+        builder = builder.memory_hook(0x1FF..0x200, Rc::new(RefCell::new(Platform(platform))));
+    }
+    let mut emulator = builder.build().map_err(|e| e.to_string())?;
+    let mut frame = 0;
+    while emulator.is_running() {
+        emulator
+            .run_frame()
+            .map_err(|e| format!("crashed after {} frames: {}", frame, e))?;
+        frame += 1;
+    }
+    emulator
+        .frontend_mut()
+        .screen
+        .take()
+        .ok_or_else(|| "never drew anything".to_string())
+}
+
+/// Run each case's ROM from `rom_dir`, and fail with every screen that's
+/// different from its golden one.
+fn check(cases: &[Case], rom_dir: &Path) {
+    let bless = env::var_os("CHIP8_BLESS").is_some();
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+
+    let mut failures = vec![];
+    for case in cases {
+        let program = fs::read(rom_dir.join(case.rom))
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", case.rom, e));
+        let screen = match run(case, &program) {
+            Ok(screen) => screen,
+            Err(message) => {
+                failures.push(format!("{} {}", case.name, message));
+                continue;
+            }
+        };
+        let golden_path = golden_dir.join(case.name).with_extension("txt");
+        if bless {
+            fs::create_dir_all(&golden_dir).unwrap();
+            fs::write(&golden_path, screen.to_logical_bitstring()).unwrap();
+            continue;
+        }
+        let golden = match fs::read_to_string(&golden_path) {
+            Ok(golden) => Framebuffer::from_logical_bitstring(&golden)
+                .unwrap_or_else(|e| panic!("{} isn't a screen: {}", golden_path.display(), e)),
+            Err(_) => {
+                failures.push(format!(
+                    "{} has no golden screen yet (run with CHIP8_BLESS=1 to record it)",
+                    case.name
+                ));
+                continue;
+            }
+        };
+        if let Some(diff) = screen.diff(&golden) {
+            failures.push(format!(
+                "{} drew a different screen than {}. {}",
+                case.name,
+                golden_path.display(),
+                diff
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn corax_opcode_test() {
+    check(
+        &[Case {
+            rom: "test_opcode.ch8",
+            name: "test_opcode",
+            profile: Profile::Cosmac,
+            platform: None,
+            frames: 100,
+        }],
+        Path::new(env!("CARGO_MANIFEST_DIR")),
+    );
+}
+
+#[test]
+#[ignore] // Needs CHIP8_TEST_SUITE, see the top of this file
+fn community_test_suite() {
+    let suite = env::var_os("CHIP8_TEST_SUITE")
+        .expect("Set CHIP8_TEST_SUITE to the test suite's bin directory to run it");
+    check(&TEST_SUITE, Path::new(&suite));
+}